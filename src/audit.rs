@@ -0,0 +1,154 @@
+use crate::dots::civil_date;
+use anyhow::Result;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUDIT_LOG_FILE: &str = "secrets_audit.log";
+
+/// Appends a line to the secret audit log every time a secret is decrypted, rendered or
+/// printed, enabled with `audit_secrets = true`. The log only holds secret names, never
+/// their value : `2026-10-14T09:00:00Z rendered secret=pass file=/dotfiles/ssh/config dot=ssh`
+#[derive(Debug, Clone)]
+pub(crate) struct SecretAudit {
+    path: PathBuf,
+    /// Source of the dot consuming the secrets, `None` outside of a dot
+    dot: Option<String>,
+}
+
+impl SecretAudit {
+    /// The audit log in `$XDG_STATE_HOME/bombadil/secrets_audit.log`
+    pub(crate) fn new() -> Result<Self> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| SecretAudit::at(&dir.join("bombadil").join(AUDIT_LOG_FILE)))
+            .ok_or_else(|| anyhow!("$XDG_STATE_HOME does not exist"))
+    }
+
+    pub(crate) fn at(path: &Path) -> Self {
+        SecretAudit {
+            path: path.to_path_buf(),
+            dot: None,
+        }
+    }
+
+    /// The same log, recording entries as consumed by the dot with the given `source`
+    pub(crate) fn for_dot(&self, source: &Path) -> Self {
+        SecretAudit {
+            path: self.path.clone(),
+            dot: Some(source.display().to_string()),
+        }
+    }
+
+    pub(crate) fn decrypted(&self, key: &str) {
+        self.record(&format!("decrypted secret={}", key));
+    }
+
+    /// The secret `key` was rendered in the template `file`
+    pub(crate) fn rendered(&self, key: &str, file: &Path) {
+        self.record(&format!("rendered secret={} file={}", key, file.display()));
+    }
+
+    pub(crate) fn printed(&self, key: &str) {
+        self.record(&format!("printed secret={}", key));
+    }
+
+    /// Failing to audit is reported but does not fail the command using the secret
+    fn record(&self, event: &str) {
+        let entry = match &self.dot {
+            Some(dot) => entry(now(), &format!("{} dot={}", event, dot)),
+            None => entry(now(), event),
+        };
+
+        if let Err(err) = self.append(&entry) {
+            warn!(
+                "Unable to write the secret audit log {:?} : {}",
+                self.path, err
+            );
+        }
+    }
+
+    /// The log is only ever appended to, a single write per entry keeps the entries of
+    /// concurrent renders whole
+    fn append(&self, entry: &str) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut options = OpenOptions::new();
+        options.append(true).create(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut log = options.open(&self.path)?;
+        log.write_all(entry.as_bytes())?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// The log line of `event` at `secs` since the epoch
+fn entry(secs: u64, event: &str) -> String {
+    let (year, month, day) = civil_date((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z {}\n",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        event
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::{entry, SecretAudit};
+    use anyhow::Result;
+    use std::fs;
+    use std::path::Path;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_append_audit_entries() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let path = temp.join("state/secrets_audit.log");
+        let audit = SecretAudit::at(&path);
+
+        // Act
+        audit.decrypted("pass");
+        audit
+            .for_dot(Path::new("ssh"))
+            .rendered("pass", Path::new("/dotfiles/ssh/config"));
+        audit.printed("token");
+
+        // Assert
+        let log = fs::read_to_string(&path)?;
+        let events: Vec<&str> = log
+            .lines()
+            .filter_map(|line| line.split_once(' ').map(|(_, event)| event))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                "decrypted secret=pass",
+                "rendered secret=pass file=/dotfiles/ssh/config dot=ssh",
+                "printed secret=token",
+            ]
+        );
+        assert_eq!(
+            entry(1_791_968_400, "decrypted secret=pass"),
+            "2026-10-14T09:00:00Z decrypted secret=pass\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Rendered autostart entries live in `.dots/.autostart`, apart from the rendered dots
+const AUTOSTART_COPY_DIR: &str = ".autostart";
+
+/// An XDG autostart entry, generated to `$XDG_CONFIG/autostart/<key>.desktop`.
+/// Profiles use the same table to override the fields of an entry or disable it.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Autostart {
+    /// Command started with the session, may contain template variables
+    #[serde(default)]
+    pub exec: Option<String>,
+
+    /// Name displayed by session settings, defaults to the entry key
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[serde(default)]
+    pub comment: Option<String>,
+
+    #[serde(default)]
+    pub icon: Option<String>,
+
+    /// Desktops starting the entry, ex: `["sway", "GNOME"]`, every desktop when empty
+    #[serde(default)]
+    pub only_show_in: Vec<String>,
+
+    /// Seconds to wait before starting the command, honored by GNOME and KDE
+    #[serde(default)]
+    pub delay: Option<u32>,
+
+    /// Set to `false` in a profile to stop generating the entry
+    #[serde(default)]
+    pub enabled: Option<bool>,
+}
+
+impl Autostart {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    /// Override the fields set in a profile entry
+    pub(crate) fn merge(&mut self, other: &Autostart) {
+        let merge = |field: &mut Option<String>, other: &Option<String>| {
+            if other.is_some() {
+                field.clone_from(other);
+            }
+        };
+
+        merge(&mut self.exec, &other.exec);
+        merge(&mut self.name, &other.name);
+        merge(&mut self.comment, &other.comment);
+        merge(&mut self.icon, &other.icon);
+        if !other.only_show_in.is_empty() {
+            self.only_show_in.clone_from(&other.only_show_in);
+        }
+        if other.delay.is_some() {
+            self.delay = other.delay;
+        }
+        if other.enabled.is_some() {
+            self.enabled = other.enabled;
+        }
+    }
+
+    /// The `.desktop` file content, template variables are rendered afterward
+    pub(crate) fn desktop_entry(&self, key: &str) -> Result<String> {
+        let exec = self
+            .exec
+            .as_ref()
+            .ok_or_else(|| anyhow!("no `exec` command"))?;
+
+        let mut lines = vec![
+            "[Desktop Entry]".to_string(),
+            "Type=Application".to_string(),
+            format!("Name={}", escape(self.name.as_deref().unwrap_or(key))),
+            format!("Exec={}", escape(exec)),
+        ];
+        if let Some(comment) = &self.comment {
+            lines.push(format!("Comment={}", escape(comment)));
+        }
+        if let Some(icon) = &self.icon {
+            lines.push(format!("Icon={}", escape(icon)));
+        }
+        if !self.only_show_in.is_empty() {
+            lines.push(format!("OnlyShowIn={};", self.only_show_in.join(";")));
+        }
+        if let Some(delay) = self.delay {
+            lines.push(format!("X-GNOME-Autostart-Delay={}", delay));
+        }
+        lines.push("X-Bombadil-Generated=true".to_string());
+
+        Ok(lines.join("\n") + "\n")
+    }
+}
+
+/// Where the entry is rendered before being linked
+pub(crate) fn copy_path(dotfiles_dir: &Path, key: &str) -> PathBuf {
+    dotfiles_dir
+        .join(".dots")
+        .join(AUTOSTART_COPY_DIR)
+        .join(format!("{}.desktop", key))
+}
+
+/// `$XDG_CONFIG/autostart/<key>.desktop`
+pub(crate) fn target_path(key: &str) -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|config| config.join("autostart").join(format!("{}.desktop", key)))
+        .ok_or_else(|| anyhow!("$XDG_CONFIG does not exist"))
+}
+
+/// Desktop entry values are single lines
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::autostart::Autostart;
+
+    #[test]
+    fn should_generate_desktop_entry_with_profile_overrides() -> anyhow::Result<()> {
+        // Arrange
+        let mut entry = Autostart {
+            exec: Some("nm-applet --indicator".to_string()),
+            only_show_in: vec!["sway".to_string()],
+            enabled: Some(false),
+            ..Default::default()
+        };
+        let laptop = Autostart {
+            comment: Some("Network tray icon".to_string()),
+            delay: Some(3),
+            enabled: Some(true),
+            ..Default::default()
+        };
+
+        // Act
+        entry.merge(&laptop);
+        let desktop_entry = entry.desktop_entry("nm-applet")?;
+
+        // Assert
+        assert!(entry.is_enabled());
+        assert_eq!(
+            desktop_entry,
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=nm-applet\n\
+             Exec=nm-applet --indicator\n\
+             Comment=Network tray icon\n\
+             OnlyShowIn=sway;\n\
+             X-GNOME-Autostart-Delay=3\n\
+             X-Bombadil-Generated=true\n"
+        );
+        assert!(Autostart::default().desktop_entry("empty").is_err());
+        Ok(())
+    }
+}
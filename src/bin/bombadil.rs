@@ -2,16 +2,55 @@ use clap::{App, AppSettings, Arg, Shell, SubCommand};
 use std::io::BufRead;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use toml_bombadil::settings::Settings;
-use toml_bombadil::{Bombadil, MetadataType, Mode};
+use std::sync::mpsc::RecvTimeoutError;
+use toml_bombadil::logger;
+use toml_bombadil::settings::{self, Overlay, Settings};
+use toml_bombadil::shell::{dynamic_completions, InitShell, PROFILES_PLACEHOLDER};
+use toml_bombadil::systemd;
+use toml_bombadil::{Bombadil, DotFilter, MetadataType, Mode};
 
 const LINK: &str = "link";
 const UNLINK: &str = "unlink";
+const EJECT: &str = "eject";
+const GC: &str = "gc";
+const ROLLBACK: &str = "rollback";
+const CLEAN: &str = "clean";
+const RESUME: &str = "resume";
+const WATCH: &str = "watch";
+const EDIT: &str = "edit";
+const ADD: &str = "add";
+const REMOVE: &str = "remove";
+const SAVE: &str = "save";
+const STATUS: &str = "status";
+const CHECK: &str = "check";
+const DOCTOR: &str = "doctor";
+const MIGRATE: &str = "migrate";
+const DIFF: &str = "diff";
+const ADOPT_CHANGES: &str = "adopt-changes";
+const UPDATE: &str = "update";
+const SYNC: &str = "sync";
 const INSTALL: &str = "install";
 const CLONE: &str = "clone";
 const ADD_SECRET: &str = "add-secret";
 const GET: &str = "get";
+const PROFILE: &str = "profile";
+const PROFILE_LIST: &str = "list";
+const THEME: &str = "theme";
+const THEME_LIST: &str = "list";
+const THEME_GET: &str = "get";
+const THEME_SET: &str = "set";
+const SHOWCASE: &str = "showcase";
+const CONFIG: &str = "config";
+const CONFIG_GET: &str = "get";
 const GENERATE_COMPLETIONS: &str = "generate-completions";
+const COMPLETE: &str = "complete";
+const GENERATE: &str = "generate";
+const GENERATE_SYSTEMD: &str = "systemd";
+const SHELL_INIT: &str = "shell-init";
+const IMPORT: &str = "import";
+const IMPORT_STOW: &str = "stow";
+const IMPORT_CHEZMOI: &str = "chezmoi";
+const IMPORT_DOTBOT: &str = "dotbot";
 
 macro_rules! fatal {
     ($($tt:tt)*) => {{
@@ -45,12 +84,47 @@ where
         .about("A dotfile template manager")
         .long_about("Toml is a dotfile template manager, written in rust. \
         For more info on how to configure it please go to https://github.com/oknozor/toml-bombadil")
+        .arg(Arg::with_name("config")
+            .help("Configuration file, or directory containing one, to use instead of XDG_CONFIG_DIR/bombadil.toml")
+            .long("config")
+            .value_name("CONFIG")
+            .takes_value(true)
+            .global(true))
+        .arg(Arg::with_name("verbose")
+            .help("Show how each dot is rendered and linked, twice to show every rendered file")
+            .short("v")
+            .long("verbose")
+            .multiple(true))
+        .arg(Arg::with_name("quiet")
+            .help("Only show errors and command results")
+            .short("q")
+            .long("quiet")
+            .conflicts_with("verbose"))
+        .arg(Arg::with_name("plain")
+            .help("Plain ASCII output without colors, for screen readers : OK, CHANGED and FAILED tell the outcome of status, diff and link lines")
+            .long("plain")
+            .global(true))
         .subcommand(SubCommand::with_name(INSTALL)
             .settings(subcommand_settings)
             .about("Link a given dotfile directory config to XDG_CONFIG_DIR/bombadil.toml")
+            .long_about("Link a given dotfile directory config to XDG_CONFIG_DIR/bombadil.toml. \
+            If CONFIG is a git remote, the repository is cloned first and the dotfiles are linked.")
             .arg(Arg::with_name("CONFIG")
-                .help("Path to your dotfile directory")
+                .help("Path to your dotfile directory or address of a remote git repository")
                 .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("path")
+                .help("Clone destination when installing from a git remote, repository name by default")
+                .short("t")
+                .long("target")
+                .takes_value(true)
+                .required(false))
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate when installing from a git remote")
+                .short("p")
+                .long("profiles")
+                .takes_value(true)
+                .multiple(true)
                 .required(false)))
         .subcommand(SubCommand::with_name(CLONE)
             .settings(subcommand_settings)
@@ -82,10 +156,261 @@ where
                 .possible_values(profile_names.as_slice())
                 .takes_value(true)
                 .multiple(true)
+                .required(false))
+            .arg(Arg::with_name("default")
+                .help("Link the default profile only and forget the profiles enabled by the last link")
+                .long("default")
+                .short("d")
+                .conflicts_with("profiles")
+                .takes_value(false))
+            .arg(Arg::with_name("interactive")
+                .help("Choose the profiles to enable from an interactive list")
+                .long("interactive")
+                .short("i")
+                .conflicts_with_all(&["profiles", "default"])
+                .takes_value(false))
+            .arg(Arg::with_name("overlay")
+                .help("Profile applied on top of the enabled profiles for this link only, `-` reads it from stdin")
+                .long("overlay")
+                .value_name("OVERLAY_FILE")
+                .takes_value(true))
+            .arg(Arg::with_name("frozen-vars")
+                .help("Render with the variable values recorded by the last link")
+                .long("frozen-vars")
+                .takes_value(false))
+            .arg(Arg::with_name("explain")
+                .help("Print the resolved set of profiles, including extra profiles, before linking")
+                .long("explain")
+                .takes_value(false))
+            .arg(Arg::with_name("force")
+                .help("Overwrite the rendered files edited since the last link")
+                .long("force")
+                .short("f")
+                .takes_value(false))
+            .arg(Arg::with_name("allow-inside-dotfiles")
+                .help("Link the dots whose target is inside a dotfiles directory")
+                .long("allow-inside-dotfiles")
+                .takes_value(false))
+            .arg(Arg::with_name("atomic")
+                .help("Render every dot before changing anything, the link fails and leaves the previous one in place if a dot does not render")
+                .long("atomic")
+                .conflicts_with("skip-secrets")
+                .takes_value(false))
+            .arg(Arg::with_name("write-protect")
+                .help("Compare the dotfiles directory before and after the link, fail if a file was modified, created or removed")
+                .long("write-protect")
+                .takes_value(false))
+            .arg(Arg::with_name("rerender")
+                .help("Render every dot, even those whose variables and sources did not change since the last link")
+                .long("rerender")
+                .takes_value(false))
+            .arg(Arg::with_name("only-render")
+                .help("Only render the dots to `.dots`, without touching their targets nor running the hooks")
+                .long("only-render")
+                .conflicts_with_all(&["atomic", "only-link"])
+                .takes_value(false))
+            .arg(Arg::with_name("only-link")
+                .help("Only link the dots already rendered to `.dots`, ex: copied from a machine running `--only-render`")
+                .long("only-link")
+                .conflicts_with("atomic")
+                .takes_value(false))
+            .arg(Arg::with_name("skip-secrets")
+                .help("Don't decrypt secrets, the dots using them are not linked")
+                .long("skip-secrets")
+                .takes_value(false))
+            .arg(Arg::with_name("degraded")
+                .help("Don't decrypt secrets, render them as the `secret_sentinel` and link every dot, `bombadil status` lists the degraded dots")
+                .long("degraded")
+                .conflicts_with("skip-secrets")
+                .takes_value(false))
+            .arg(Arg::with_name("profile-render")
+                .help("Print the render time of every template, slowest first, and optionally write them as folded stacks for flamegraph tools")
+                .long("profile-render")
+                .value_name("FOLDED_FILE")
+                .takes_value(true)
+                .min_values(0)))
+        .subcommand(SubCommand::with_name(STATUS)
+            .settings(subcommand_settings)
+            .about("List the dots that are not linked, broken, or edited since the last link, and the outdated submodules")
+            .arg(Arg::with_name("profiles")
+                .help("Profiles to check, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false))
+            .arg(Arg::with_name("json")
+                .help("Print the status as json")
+                .long("json")
+                .takes_value(false)))
+        .subcommand(SubCommand::with_name(CHECK)
+            .settings(subcommand_settings)
+            .about("Validate your configuration, its imports, dot sources, var files, targets and profiles, reporting every problem at once")
+            .arg(Arg::with_name("mock-secrets")
+                .help("Also render every dot with the secrets of this unencrypted var file instead of decrypting them, to check the templates in CI without gpg keys")
+                .long("mock-secrets")
+                .value_name("FILE")
+                .takes_value(true))
+            .arg(Arg::with_name("profiles")
+                .help("Profiles to render with `--mock-secrets`")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .requires("mock-secrets")))
+        .subcommand(SubCommand::with_name(MIGRATE)
+            .settings(subcommand_settings)
+            .about("Upgrade a configuration written for an older bombadil to the current schema, keeping a backup of the original"))
+        .subcommand(SubCommand::with_name(DOCTOR)
+            .settings(subcommand_settings)
+            .about("Diagnose the environment : gpg keys, target permissions, broken symlinks in `.dots`, the config symlink and the git state, with suggestions for each problem"))
+        .subcommand(SubCommand::with_name(DIFF)
+            .settings(subcommand_settings)
+            .about("Show what linking would change in the rendered dots, with the configured `diff_tool` if any")
+            .arg(Arg::with_name("profiles")
+                .help("Profiles to render, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(ADOPT_CHANGES)
+            .settings(subcommand_settings)
+            .about("Copy the edits made to rendered files back to their dot source")
+            .arg(Arg::with_name("profiles")
+                .help("Profiles of the edited dots, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
                 .required(false)))
+        .subcommand(SubCommand::with_name(WATCH)
+            .settings(subcommand_settings)
+            .about("Relink dots whenever their source or vars change")
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(EDIT)
+            .settings(subcommand_settings)
+            .about("Open the source of a dot in $EDITOR, then relink it and run post install hooks")
+            .arg(Arg::with_name("dot")
+                .help("Name of the dot to edit")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(ADD)
+            .settings(subcommand_settings)
+            .about("Move an existing file or directory to your dotfiles, add its dot to bombadil.toml and link it back")
+            .arg(Arg::with_name("path")
+                .help("Path of the file or directory to add, ex: ~/.config/alacritty")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("source")
+                .help("Path of the dot source relative to the dotfiles directory, defaults to the file name without its leading dot")
+                .long("source")
+                .takes_value(true))
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(REMOVE)
+            .settings(subcommand_settings)
+            .about("Remove a dot from bombadil.toml and replace its links with a copy of the rendered files")
+            .arg(Arg::with_name("dot")
+                .help("Name of the dot to remove")
+                .required(true)
+                .takes_value(true))
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(UPDATE)
+            .settings(subcommand_settings)
+            .about("Pull your dotfiles repository and its submodules, then relink the changed dots and run post install hooks")
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false)))
+        .subcommand(SubCommand::with_name(SYNC)
+            .settings(subcommand_settings)
+            .about("Periodically fetch your dotfiles repository and notify when upstream commits change the active dots, press Enter to apply them")
+            .arg(Arg::with_name("profiles")
+                .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                .short("p")
+                .long("profiles")
+                .possible_values(profile_names.as_slice())
+                .takes_value(true)
+                .multiple(true)
+                .required(false))
+            .arg(Arg::with_name("interval")
+                .help("Minutes between two fetches")
+                .long("interval")
+                .takes_value(true)
+                .default_value("30"))
+            .arg(Arg::with_name("once")
+                .help("Fetch and notify once, then exit")
+                .long("once")
+                .takes_value(false)))
+        .subcommand(SubCommand::with_name(SAVE)
+            .settings(subcommand_settings)
+            .about("Commit every change in your dotfiles repository, and push it if `auto_push` is enabled")
+            .arg(Arg::with_name("message")
+                .help("Commit message")
+                .takes_value(true)
+                .default_value("bombadil: save dotfiles")))
         .subcommand(SubCommand::with_name(UNLINK)
             .settings(subcommand_settings)
             .about("Remove all symlinks defined in your bombadil.toml"))
+        .subcommand(SubCommand::with_name(ROLLBACK)
+            .settings(subcommand_settings)
+            .about("Undo the last link, restoring the dots rendered and the files replaced before it"))
+        .subcommand(SubCommand::with_name(GC)
+            .settings(subcommand_settings)
+            .about("Discard the previous links and replaced files outside of the `[retention]` policy"))
+        .subcommand(SubCommand::with_name(CLEAN)
+            .settings(subcommand_settings)
+            .about("Remove the links to dots that left your bombadil.toml and their rendered files in `.dots`")
+            .arg(Arg::with_name("yes")
+                .help("Remove them without asking for confirmation")
+                .short("y")
+                .long("yes")))
+        .subcommand(SubCommand::with_name(EJECT)
+            .settings(subcommand_settings)
+            .about("Replace every symlink with the rendered file, then remove `.dots` and the bombadil config link")
+            .long_about("Replace every symlink created by the last link with a plain copy of the rendered file, \
+            then remove the `.dots` directories and the bombadil config link. \
+            The dotfiles stay in place and no longer depend on bombadil, run `bombadil install` to manage them again."))
+        .subcommand(SubCommand::with_name(RESUME)
+            .settings(subcommand_settings)
+            .about("Link the dots that failed or were not processed during the last link"))
         .subcommand(SubCommand::with_name(ADD_SECRET)
             .settings(subcommand_settings)
             .about("Add a secret var to bombadil environment")
@@ -129,7 +454,171 @@ where
                 .multiple(true)
                 .help("Get metadata for specific profiles")
             )
+            .arg(Arg::with_name("unlinked")
+                .long("unlinked")
+                .help("Only list dots whose target does not exist"))
+            .arg(Arg::with_name("broken")
+                .long("broken")
+                .help("Only list dots whose target is a broken symlink or points somewhere unexpected"))
+            .arg(Arg::with_name("edited")
+                .long("edited")
+                .help("Only list dots whose rendered files were edited since the last link"))
+            .arg(Arg::with_name("degraded")
+                .long("degraded")
+                .help("Only list dots linked with `--degraded` whose secrets were rendered with a sentinel"))
+            .arg(Arg::with_name("json")
+                .long("json")
+                .help("Print the metadata as json"))
         )
+        .subcommand(SubCommand::with_name(PROFILE)
+            .settings(subcommand_settings)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Inspect the profiles defined in your bombadil.toml")
+            .subcommand(SubCommand::with_name(PROFILE_LIST)
+                .settings(subcommand_settings)
+                .about("List profiles with their description, the dots they change and whether they are active")))
+        .subcommand(SubCommand::with_name(THEME)
+            .settings(subcommand_settings)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Switch between the themes defined in your bombadil.toml")
+            .subcommand(SubCommand::with_name(THEME_LIST)
+                .settings(subcommand_settings)
+                .about("List themes with their description and var files"))
+            .subcommand(SubCommand::with_name(THEME_GET)
+                .settings(subcommand_settings)
+                .about("Print the active theme"))
+            .subcommand(SubCommand::with_name(THEME_SET)
+                .settings(subcommand_settings)
+                .about("Make a theme active, render the dots using its vars again and run post install hooks")
+                .arg(Arg::with_name("theme")
+                    .help("Name of the theme")
+                    .required(true)
+                    .takes_value(true))
+                .arg(Arg::with_name("profiles")
+                    .help("A list of comma separated profiles to activate, defaults to the profiles of the last link")
+                    .short("p")
+                    .long("profiles")
+                    .possible_values(profile_names.as_slice())
+                    .takes_value(true)
+                    .multiple(true)
+                    .required(false))))
+        .subcommand(SubCommand::with_name(SHOWCASE)
+            .settings(subcommand_settings)
+            .about("Describe your profiles, their dots and color palettes to embed in your dotfiles README")
+            .arg(Arg::with_name("html")
+                .long("html")
+                .help("Render html instead of markdown"))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .help("Write the showcase to this file, between its showcase markers when it already exists")))
+        .subcommand(SubCommand::with_name(CONFIG)
+            .settings(subcommand_settings)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Query the configuration as resolved by bombadil")
+            .subcommand(SubCommand::with_name(CONFIG_GET)
+                .settings(subcommand_settings)
+                .about("Print a resolved setting, lists are printed one item per line")
+                .arg(Arg::with_name("key")
+                    .help("dotfiles_dir, render_dir, gpg_user_id, profiles, dots, dots.<dot>.source, dots.<dot>.target or vars.<var>")
+                    .required(true)
+                    .takes_value(true))
+                .arg(Arg::with_name("profiles")
+                    .short("p")
+                    .long("profiles")
+                    .takes_value(true)
+                    .possible_values(profile_names.as_slice())
+                    .multiple(true)
+                    .help("Resolve the setting with these profiles instead of those a bare `bombadil link` enables"))
+                .arg(Arg::with_name("json")
+                    .long("json")
+                    .help("Print the value as json"))))
+        .subcommand(SubCommand::with_name(GENERATE)
+            .settings(subcommand_settings)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Generate integration files")
+            .subcommand(SubCommand::with_name(GENERATE_SYSTEMD)
+                .settings(subcommand_settings)
+                .about("Generate a systemd user unit running `bombadil watch`, or `bombadil link` at login")
+                .arg(Arg::with_name("link")
+                    .help("Run `bombadil link` once at login and notify failures instead of watching")
+                    .long("link")
+                    .takes_value(false))
+                .arg(Arg::with_name("profiles")
+                    .help("Profiles passed to bombadil, defaults to the profiles of the last link")
+                    .short("p")
+                    .long("profiles")
+                    .possible_values(profile_names.as_slice())
+                    .takes_value(true)
+                    .multiple(true)
+                    .required(false))
+                .arg(Arg::with_name("install")
+                    .help("Write the unit to $XDG_CONFIG/systemd/user instead of printing it")
+                    .long("install")
+                    .takes_value(false))
+                .arg(Arg::with_name("enable")
+                    .help("Enable and start the unit after installing it")
+                    .long("enable")
+                    .requires("install")
+                    .takes_value(false))))
+        .subcommand(SubCommand::with_name(SHELL_INIT)
+            .settings(subcommand_settings)
+            .about("Print shell functions to switch profiles with `theme <profile>` and edit dots with `dotedit <dot>`")
+            .long_about("Print shell functions to switch profiles with `theme <profile>` and edit dots with `dotedit <dot>`, \
+            completing the profile and dot names of your configuration. Evaluate it in your shell configuration to keep it in sync, \
+            ex: `eval \"$(bombadil shell-init zsh)\"`")
+            .arg(Arg::with_name("shell")
+                .possible_values(&["bash", "zsh", "fish"])
+                .required(true)
+                .takes_value(true)))
+        .subcommand(SubCommand::with_name(IMPORT)
+            .settings(subcommand_settings)
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .about("Generate dots in your bombadil.toml from another dotfiles manager layout")
+            .subcommand(SubCommand::with_name(IMPORT_STOW)
+                .settings(subcommand_settings)
+                .about("Add a dot for every file GNU Stow would link from a stow directory")
+                .arg(Arg::with_name("dir")
+                    .help("The stow directory, containing one directory per package, it must be inside your dotfiles directory")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("target")
+                    .help("The stow target directory, defaults to $HOME")
+                    .short("t")
+                    .long("target")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("dotfiles")
+                    .help("Replace `dot-` prefixes with `.` like `stow --dotfiles`")
+                    .long("dotfiles")
+                    .takes_value(false))
+                .arg(Arg::with_name("dry-run")
+                    .help("Print the generated dots instead of adding them to bombadil.toml")
+                    .long("dry-run")
+                    .takes_value(false)))
+            .subcommand(SubCommand::with_name(IMPORT_CHEZMOI)
+                .settings(subcommand_settings)
+                .about("Add a dot for every target of a chezmoi source directory, flagging what needs a manual conversion")
+                .arg(Arg::with_name("source-dir")
+                    .help("The chezmoi source directory, it must be inside your dotfiles directory")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("dry-run")
+                    .help("Print the generated dots instead of adding them to bombadil.toml")
+                    .long("dry-run")
+                    .takes_value(false)))
+            .subcommand(SubCommand::with_name(IMPORT_DOTBOT)
+                .settings(subcommand_settings)
+                .about("Convert the links and shell commands of a dotbot configuration to an imported dotbot.toml")
+                .arg(Arg::with_name("file")
+                    .help("The dotbot configuration, ex: install.conf.yaml, it must be inside your dotfiles directory")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("dry-run")
+                    .help("Print the generated configuration instead of writing it")
+                    .long("dry-run")
+                    .takes_value(false))))
         .subcommand(SubCommand::with_name(GENERATE_COMPLETIONS)
             .settings(subcommand_settings)
             .about("Generate shell completions")
@@ -140,10 +629,147 @@ where
                 .help("Type of completions to generate")
             )
         )
+        .subcommand(SubCommand::with_name(COMPLETE)
+            .settings(subcommand_settings)
+            .setting(AppSettings::Hidden)
+            .about("List the profile or dot names for shell completions, one per line")
+            .arg(Arg::with_name("kind")
+                .possible_values(&["profiles", "dots"])
+                .required(true)
+                .takes_value(true)))
+}
+
+/// Strings without quotes and lists one item per line, for shell scripts
+fn print_plain(value: &serde_json::Value) {
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::String(value) => println!("{}", value),
+        serde_json::Value::Array(items) => items.iter().for_each(print_plain),
+        value => println!("{}", value),
+    }
+}
+
+/// Remote addresses, as opposed to local dotfiles directories
+fn is_git_remote(config: &str) -> bool {
+    ["https://", "http://", "ssh://", "git://", "file://", "git@"]
+        .iter()
+        .any(|scheme| config.starts_with(scheme))
+        || (config.ends_with(".git") && !Path::new(config).exists())
+}
+
+/// Default clone destination : the repository name without its `.git` suffix
+fn repo_name(remote: &str) -> &str {
+    let repo_name = remote
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or(remote);
+    repo_name.strip_suffix(".git").unwrap_or(repo_name)
+}
+
+/// Pull the dotfiles repository, then relink the changed dots of the configuration `config`
+/// with the explicit or last profiles
+fn update(explicit_profiles: Option<&Vec<&str>>, config: Option<&Path>) {
+    let changed = Bombadil::from_settings(Mode::NoGpg, config)
+        .and_then(|bombadil| bombadil.pull())
+        .unwrap_or_else(|err| fatal!("{}", err));
+
+    // The pull may have changed the configuration, reload it before relinking
+    let mut bombadil =
+        Bombadil::from_settings(Mode::Gpg, config).unwrap_or_else(|err| fatal!("{}", err));
+    let profiles = profiles_or_last(&bombadil, explicit_profiles);
+
+    if !profiles.is_empty() {
+        bombadil
+            .enable_profiles(profiles.iter().map(String::as_str).collect())
+            .unwrap_or_else(|err| fatal!("{}", err));
+    }
+
+    bombadil
+        .relink_changed(&changed)
+        .unwrap_or_else(|err| fatal!("{}", err));
+}
+
+/// The explicit profiles if any, otherwise the profiles of the last link and the profiles
+/// matching this machine
+fn profiles_or_last(bombadil: &Bombadil, explicit_profiles: Option<&Vec<&str>>) -> Vec<String> {
+    match explicit_profiles {
+        Some(profiles) => profiles.iter().map(|profile| profile.to_string()).collect(),
+        None => {
+            let mut profiles = bombadil.last_profiles();
+            for profile in bombadil.matching_profiles() {
+                if !profiles.contains(&profile) {
+                    profiles.push(profile);
+                }
+            }
+            profiles
+        }
+    }
+}
+
+/// `--config` is read before parsing the arguments since the profile names accepted by
+/// the subcommands come from the configuration
+fn config_arg() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    None
+}
+
+/// `--plain` is read before parsing the arguments, like `--config`, so the warnings of the
+/// configuration loaded to build the cli are plain too
+fn plain_arg() -> bool {
+    std::env::args_os().skip(1).any(|arg| arg == "--plain")
+}
+
+/// `-v`, `-vv` and `-q` come before the subcommand, they are read before parsing the
+/// arguments to show the warnings of the configuration loaded to build the cli
+fn verbosity_arg() -> i8 {
+    let mut verbosity = 0i8;
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--config") => {
+                args.next();
+            }
+            Some("--verbose") => verbosity = verbosity.saturating_add(1),
+            Some("--quiet") => verbosity = -1,
+            Some(flags) if flags.starts_with('-') && !flags.starts_with("--") => {
+                for flag in flags.chars().skip(1) {
+                    match flag {
+                        'v' => verbosity = verbosity.saturating_add(1),
+                        'q' => verbosity = -1,
+                        _ => {}
+                    }
+                }
+            }
+            Some(flag) if flag.starts_with('-') => {}
+            // The subcommand, its own flags are not ours
+            _ => break,
+        }
+    }
+
+    verbosity
 }
 
 fn main() {
-    let profiles = Settings::get()
+    logger::init(verbosity_arg());
+    if plain_arg() {
+        logger::plain();
+    }
+
+    // Absolute so that bombadil commands run by hooks use the same configuration
+    let config = config_arg().map(|config| config.canonicalize().unwrap_or(config));
+
+    let profiles = Settings::get_from(config.as_deref())
         .map(|settings| settings.profiles)
         .unwrap_or_default();
 
@@ -158,22 +784,35 @@ fn main() {
         match subcommand {
             INSTALL => {
                 let install_command = matches.subcommand_matches(INSTALL).unwrap();
-                let config_path = install_command.value_of("CONFIG").map(PathBuf::from);
+                let config = install_command.value_of("CONFIG");
+
+                match config {
+                    Some(remote) if is_git_remote(remote) => {
+                        let path = install_command
+                            .value_of("path")
+                            .unwrap_or_else(|| repo_name(remote));
+                        let profiles: Option<Vec<&str>> = install_command
+                            .values_of("profiles")
+                            .map(|profiles| profiles.collect());
 
-                Bombadil::link_self_config(config_path).unwrap_or_else(|err| fatal!("{}", err));
+                        println!("Cloning {} in {}", remote, path);
+                        Bombadil::install_from_remote(remote, PathBuf::from(path), profiles)
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    _ => {
+                        let config_path = config.map(PathBuf::from);
+                        Bombadil::link_self_config(config_path)
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                }
             }
 
             CLONE => {
                 let clone_command = matches.subcommand_matches(CLONE).unwrap();
                 let remote = clone_command.value_of("remote").unwrap();
-                let path = match clone_command.value_of("path") {
-                    None => {
-                        let repo_name = remote.split('/').last().unwrap();
-                        let repo_name = repo_name.strip_suffix(".git").unwrap();
-                        repo_name
-                    }
-                    Some(path) => path,
-                };
+                let path = clone_command
+                    .value_of("path")
+                    .unwrap_or_else(|| repo_name(remote));
 
                 let target_pathbuf = PathBuf::from_str(path).unwrap();
                 println!("Cloning {} in {}", remote, path);
@@ -188,25 +827,461 @@ fn main() {
             }
 
             LINK => {
-                let mut bombadil =
-                    Bombadil::from_settings(Mode::Gpg).unwrap_or_else(|err| fatal!("{}", err));
-
                 let link_command = matches.subcommand_matches(LINK).unwrap();
+                let mode = if link_command.is_present("skip-secrets") {
+                    Mode::SkipSecrets
+                } else if link_command.is_present("degraded") {
+                    Mode::Degraded
+                } else {
+                    Mode::Gpg
+                };
+                let mut bombadil = Bombadil::from_settings(mode, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let matching = bombadil.matching_profiles();
+                let last_profiles = if link_command.is_present("default") {
+                    bombadil
+                        .save_profiles(&[])
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                    vec![]
+                } else {
+                    bombadil.last_profiles()
+                };
+
+                let picked_profiles = if link_command.is_present("interactive") {
+                    match bombadil
+                        .pick_profiles()
+                        .unwrap_or_else(|err| fatal!("{}", err))
+                    {
+                        Some(profiles) => Some(profiles),
+                        None => {
+                            println!("Aborted, nothing linked");
+                            return;
+                        }
+                    }
+                } else {
+                    None
+                };
+
+                let explicit_profiles: Option<Vec<&str>> = match &picked_profiles {
+                    Some(profiles) => Some(profiles.iter().map(String::as_str).collect()),
+                    None => link_command
+                        .values_of("profiles")
+                        .map(|profiles| profiles.collect()),
+                };
+
+                let mut profiles: Vec<&str> = if let Some(profiles) = &explicit_profiles {
+                    profiles.clone()
+                } else {
+                    if !last_profiles.is_empty() {
+                        println!(
+                            "Enabling profiles from the last link : {}",
+                            last_profiles.join(", ")
+                        );
+                    }
+                    if !matching.is_empty() {
+                        println!(
+                            "Enabling profiles matching this machine : {}",
+                            matching.join(", ")
+                        );
+                    }
+                    let mut profiles: Vec<&str> =
+                        last_profiles.iter().map(String::as_str).collect();
+                    profiles.extend(
+                        matching
+                            .iter()
+                            .map(String::as_str)
+                            .filter(|profile| !last_profiles.iter().any(|last| last == profile)),
+                    );
+                    profiles
+                };
 
-                if link_command.is_present("profiles") {
-                    let profiles: Vec<_> = link_command.values_of("profiles").unwrap().collect();
-                    let _command_result = bombadil
+                if let Some(overlay) = link_command.value_of("overlay") {
+                    let overlay =
+                        Overlay::read(Path::new(overlay)).unwrap_or_else(|err| fatal!("{}", err));
+                    bombadil.add_overlay(overlay);
+                    profiles.push(settings::OVERLAY_PROFILE);
+                }
+
+                if link_command.is_present("explain") {
+                    bombadil
+                        .explain_profiles(&profiles)
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                if !profiles.is_empty() {
+                    bombadil
                         .enable_profiles(profiles)
                         .unwrap_or_else(|err| fatal!("{}", err));
                 }
 
+                if link_command.is_present("force") {
+                    bombadil.force();
+                }
+
+                if link_command.is_present("allow-inside-dotfiles") {
+                    bombadil.allow_inside_dotfiles();
+                }
+
+                if link_command.is_present("atomic") {
+                    bombadil.atomic();
+                }
+
+                if link_command.is_present("write-protect") {
+                    bombadil.write_protect();
+                }
+
+                if link_command.is_present("rerender") {
+                    bombadil.rerender();
+                }
+
+                if link_command.is_present("only-render") {
+                    bombadil.only_render();
+                }
+
+                if link_command.is_present("only-link") {
+                    bombadil.only_link();
+                }
+
+                if link_command.is_present("frozen-vars") {
+                    bombadil
+                        .freeze_vars()
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                if link_command.is_present("profile-render") {
+                    bombadil.profile_render();
+                }
+
                 bombadil.install().unwrap_or_else(|err| fatal!("{}", err));
+
+                bombadil
+                    .print_render_timings(link_command.value_of("profile-render").map(Path::new))
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                if let Some(profiles) = explicit_profiles {
+                    bombadil
+                        .save_profiles(&profiles)
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+            }
+            WATCH => {
+                let watch_command = matches.subcommand_matches(WATCH).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = watch_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+
+                // Reload the configuration each time it changes
+                loop {
+                    let mut bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+
+                    let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+
+                    if !profiles.is_empty() {
+                        bombadil
+                            .enable_profiles(profiles.iter().map(String::as_str).collect())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+
+                    bombadil.watch().unwrap_or_else(|err| fatal!("{}", err));
+                }
+            }
+            EDIT => {
+                let edit_command = matches.subcommand_matches(EDIT).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = edit_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+                let mut bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                if !profiles.is_empty() {
+                    bombadil
+                        .enable_profiles(profiles.iter().map(String::as_str).collect())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                bombadil
+                    .edit(edit_command.value_of("dot").unwrap())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            ADD => {
+                let add_command = matches.subcommand_matches(ADD).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = add_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+                let mut bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                if !profiles.is_empty() {
+                    bombadil
+                        .enable_profiles(profiles.iter().map(String::as_str).collect())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                bombadil
+                    .add(
+                        Path::new(add_command.value_of("path").unwrap()),
+                        add_command.value_of("source").map(Path::new),
+                    )
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            REMOVE => {
+                let remove_command = matches.subcommand_matches(REMOVE).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = remove_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+                let mut bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                if !profiles.is_empty() {
+                    bombadil
+                        .enable_profiles(profiles.iter().map(String::as_str).collect())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                bombadil
+                    .remove(remove_command.value_of("dot").unwrap())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            UPDATE => {
+                let update_command = matches.subcommand_matches(UPDATE).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = update_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+
+                update(explicit_profiles.as_ref(), config.as_deref());
+            }
+            SYNC => {
+                let sync_command = matches.subcommand_matches(SYNC).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = sync_command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+                let minutes: u64 = sync_command
+                    .value_of("interval")
+                    .unwrap()
+                    .parse()
+                    .unwrap_or_else(|_| fatal!("--interval expects a number of minutes"));
+                let interval = std::time::Duration::from_secs(minutes.max(1) * 60);
+                let once = sync_command.is_present("once");
+
+                // Enter presses, the channel is closed when stdin is not interactive
+                let (keys, pressed) = std::sync::mpsc::channel();
+                if !once {
+                    std::thread::spawn(move || {
+                        for _ in std::io::stdin().lock().lines().map_while(Result::ok) {
+                            if keys.send(()).is_err() {
+                                return;
+                            }
+                        }
+                    });
+                }
+
+                let mut notified: Option<String> = None;
+                loop {
+                    let mut bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                    let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                    if !profiles.is_empty() {
+                        bombadil
+                            .enable_profiles(profiles.iter().map(String::as_str).collect())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+
+                    let pending = match bombadil.fetch_upstream() {
+                        Ok(Some(upstream)) => {
+                            if notified.as_ref() != Some(&upstream.head) {
+                                bombadil.notify_upstream(&upstream);
+                                notified = Some(upstream.head);
+                            }
+                            true
+                        }
+                        Ok(None) => false,
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            false
+                        }
+                    };
+
+                    if once {
+                        break;
+                    }
+
+                    if pending {
+                        println!("Press Enter to apply the update");
+                    }
+
+                    match pressed.recv_timeout(interval) {
+                        Ok(()) if pending => {
+                            update(explicit_profiles.as_ref(), config.as_deref());
+                            notified = None;
+                        }
+                        Ok(()) | Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => std::thread::sleep(interval),
+                    }
+                }
+            }
+            RESUME => {
+                let mut bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let profiles = bombadil
+                    .interrupted_run_profiles()
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                if !profiles.is_empty() {
+                    bombadil
+                        .enable_profiles(profiles.iter().map(String::as_str).collect())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                bombadil.resume().unwrap_or_else(|err| fatal!("{}", err));
+            }
+            SAVE => {
+                let save_command = matches.subcommand_matches(SAVE).unwrap();
+                let message = save_command.value_of("message").unwrap();
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil
+                    .save(message)
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            STATUS | DIFF | ADOPT_CHANGES => {
+                let command = matches.subcommand_matches(subcommand).unwrap();
+                let explicit_profiles: Option<Vec<&str>> = command
+                    .values_of("profiles")
+                    .map(|profiles| profiles.collect());
+                // Secrets are rendered to compare with the linked dots
+                let mode = match subcommand {
+                    DIFF => Mode::Gpg,
+                    _ => Mode::NoGpg,
+                };
+                let mut bombadil = Bombadil::from_settings(mode, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+
+                if !profiles.is_empty() {
+                    bombadil
+                        .enable_profiles(profiles.iter().map(String::as_str).collect())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+
+                match subcommand {
+                    STATUS if command.is_present("json") => {
+                        let status = bombadil
+                            .status_json()
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                        println!("{}", status);
+                    }
+                    STATUS => {
+                        bombadil.print_metadata(MetadataType::UnhealthyDots(DotFilter {
+                            unlinked: true,
+                            broken: true,
+                            edited: true,
+                            degraded: true,
+                        }));
+                        bombadil
+                            .print_submodules()
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    DIFF => bombadil.diff().unwrap_or_else(|err| fatal!("{}", err)),
+                    _ => bombadil
+                        .adopt_changes()
+                        .unwrap_or_else(|err| fatal!("{}", err)),
+                }
+            }
+            CHECK => {
+                let check_command = matches.subcommand_matches(CHECK).unwrap();
+                Bombadil::check(config.as_deref()).unwrap_or_else(|err| fatal!("{}", err));
+                if let Some(fixture) = check_command.value_of("mock-secrets") {
+                    let mode = Mode::MockSecrets(PathBuf::from(fixture));
+                    let mut bombadil = Bombadil::from_settings(mode, config.as_deref())
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                    if let Some(profiles) = check_command.values_of("profiles") {
+                        bombadil
+                            .enable_profiles(profiles.collect())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    bombadil
+                        .check_render()
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                }
+            }
+            MIGRATE => Bombadil::migrate(config.as_deref()).unwrap_or_else(|err| fatal!("{}", err)),
+            DOCTOR => {
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil.doctor().unwrap_or_else(|err| fatal!("{}", err));
             }
             UNLINK => {
-                let bombadil =
-                    Bombadil::from_settings(Mode::NoGpg).unwrap_or_else(|err| fatal!("{}", err));
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
                 bombadil.uninstall().unwrap_or_else(|err| fatal!("{}", err));
             }
+            ROLLBACK => {
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil.rollback().unwrap_or_else(|err| fatal!("{}", err));
+            }
+            GC => {
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil.gc().unwrap_or_else(|err| fatal!("{}", err));
+            }
+            CLEAN => {
+                let mut bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                let profiles = profiles_or_last(&bombadil, None);
+                bombadil
+                    .enable_profiles(profiles.iter().map(String::as_str).collect())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+
+                let plan = bombadil
+                    .clean_plan()
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                if plan.is_empty() {
+                    println!("Nothing to clean");
+                    return;
+                }
+
+                if !plan.orphaned_links.is_empty() {
+                    println!("Links to dots no longer in the configuration :");
+                    plan.orphaned_links
+                        .iter()
+                        .for_each(|link| println!("\t{}", link.display()));
+                }
+                if !plan.stale_renders.is_empty() {
+                    println!("Rendered files no dot uses anymore :");
+                    plan.stale_renders
+                        .iter()
+                        .for_each(|render| println!("\t{}", render.display()));
+                }
+
+                let confirmed = matches.subcommand_matches(CLEAN).unwrap().is_present("yes") || {
+                    print!("Remove them ? [y/N] ");
+                    std::io::Write::flush(&mut std::io::stdout()).ok();
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer).ok();
+                    matches!(answer.trim(), "y" | "Y" | "yes")
+                };
+                if !confirmed {
+                    println!("Aborted, nothing removed");
+                    return;
+                }
+
+                bombadil
+                    .clean(&plan)
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            EJECT => {
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil.eject().unwrap_or_else(|err| fatal!("{}", err));
+                Bombadil::unlink_self_config().unwrap_or_else(|err| fatal!("{}", err));
+            }
             ADD_SECRET => {
                 let add_secret_subcommand = matches.subcommand_matches(ADD_SECRET).unwrap();
                 let key = add_secret_subcommand.value_of("key").unwrap();
@@ -235,8 +1310,8 @@ fn main() {
                     )
                 }
 
-                let bombadil =
-                    Bombadil::from_settings(Mode::Gpg).unwrap_or_else(|err| fatal!("{}", err));
+                let bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
 
                 bombadil
                     .add_secret(key, &value, var_file)
@@ -244,8 +1319,20 @@ fn main() {
             }
             GET => {
                 let get_subcommand = matches.subcommand_matches(GET).unwrap();
+                let filter = DotFilter {
+                    unlinked: get_subcommand.is_present("unlinked"),
+                    broken: get_subcommand.is_present("broken"),
+                    edited: get_subcommand.is_present("edited"),
+                    degraded: get_subcommand.is_present("degraded"),
+                };
+                let filtered = filter.unlinked || filter.broken || filter.edited || filter.degraded;
+
                 let metadata_type = match get_subcommand.value_of("value").unwrap() {
+                    "dots" if filtered => MetadataType::UnhealthyDots(filter),
                     "dots" => MetadataType::Dots,
+                    _ if filtered => {
+                        fatal!("--unlinked, --broken, --edited and --degraded only apply to dots")
+                    }
                     "prehooks" => MetadataType::PreHooks,
                     "posthooks" => MetadataType::PostHooks,
                     "path" => MetadataType::Path,
@@ -256,34 +1343,252 @@ fn main() {
                 };
 
                 let mut bombadil = match metadata_type {
-                    MetadataType::Secrets => Bombadil::from_settings(Mode::Gpg),
-                    _ => Bombadil::from_settings(Mode::NoGpg),
+                    MetadataType::Secrets => Bombadil::from_settings(Mode::Gpg, config.as_deref()),
+                    _ => Bombadil::from_settings(Mode::NoGpg, config.as_deref()),
                 }
                 .unwrap_or_else(|err| fatal!("{}", err));
 
                 if get_subcommand.is_present("profiles") {
                     let profiles: Vec<_> = get_subcommand.values_of("profiles").unwrap().collect();
-                    let _command_result = bombadil
+                    bombadil
                         .enable_profiles(profiles)
                         .unwrap_or_else(|err| fatal!("{}", err));
                 }
 
-                bombadil.print_metadata(metadata_type);
+                if get_subcommand.is_present("json") {
+                    let metadata = bombadil
+                        .metadata_json(metadata_type)
+                        .unwrap_or_else(|err| fatal!("{}", err));
+                    println!("{}", metadata);
+                } else {
+                    bombadil.print_metadata(metadata_type);
+                }
+            }
+            PROFILE => {
+                let profile_command = matches.subcommand_matches(PROFILE).unwrap();
+                match profile_command.subcommand_name() {
+                    Some(PROFILE_LIST) => {
+                        let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                        bombadil.print_profiles();
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            THEME => {
+                let theme_command = matches.subcommand_matches(THEME).unwrap();
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                match theme_command.subcommand() {
+                    (THEME_LIST, _) => bombadil.print_themes(),
+                    (THEME_GET, _) => {
+                        if let Some(theme) = bombadil.active_theme() {
+                            println!("{}", theme);
+                        }
+                    }
+                    (THEME_SET, Some(set_command)) => {
+                        bombadil
+                            .set_theme(set_command.value_of("theme").unwrap())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+
+                        // Reload with the vars of the new theme
+                        let explicit_profiles: Option<Vec<&str>> = set_command
+                            .values_of("profiles")
+                            .map(|profiles| profiles.collect());
+                        let mut bombadil = Bombadil::from_settings(Mode::Gpg, config.as_deref())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                        let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                        if !profiles.is_empty() {
+                            bombadil
+                                .enable_profiles(profiles.iter().map(String::as_str).collect())
+                                .unwrap_or_else(|err| fatal!("{}", err));
+                        }
+
+                        bombadil
+                            .relink_theme()
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            SHOWCASE => {
+                let showcase_command = matches.subcommand_matches(SHOWCASE).unwrap();
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                bombadil
+                    .showcase(
+                        showcase_command.is_present("html"),
+                        showcase_command.value_of("output").map(Path::new),
+                    )
+                    .unwrap_or_else(|err| fatal!("{}", err));
+            }
+            CONFIG => {
+                let config_command = matches.subcommand_matches(CONFIG).unwrap();
+                match config_command.subcommand() {
+                    (CONFIG_GET, Some(get_command)) => {
+                        let mut bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+
+                        let explicit_profiles: Option<Vec<&str>> = get_command
+                            .values_of("profiles")
+                            .map(|profiles| profiles.collect());
+                        let profiles = profiles_or_last(&bombadil, explicit_profiles.as_ref());
+                        bombadil
+                            .enable_profiles(profiles.iter().map(String::as_str).collect())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+
+                        let value = bombadil
+                            .config_value(get_command.value_of("key").unwrap())
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                        if get_command.is_present("json") {
+                            println!("{}", value);
+                        } else {
+                            print_plain(&value);
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            GENERATE => {
+                let generate_command = matches.subcommand_matches(GENERATE).unwrap();
+                match generate_command.subcommand() {
+                    (GENERATE_SYSTEMD, Some(systemd_command)) => {
+                        let bombadil_path = std::env::current_exe()
+                            .unwrap_or_else(|err| fatal!("Unable to find bombadil path : {}", err));
+                        let profiles: Vec<&str> = systemd_command
+                            .values_of("profiles")
+                            .map(|profiles| profiles.collect())
+                            .unwrap_or_default();
+
+                        let (name, unit) = if systemd_command.is_present("link") {
+                            (
+                                systemd::LINK_UNIT,
+                                systemd::link_unit(&bombadil_path, &profiles),
+                            )
+                        } else {
+                            (
+                                systemd::WATCH_UNIT,
+                                systemd::watch_unit(&bombadil_path, &profiles),
+                            )
+                        };
+                        if systemd_command.is_present("install") {
+                            systemd::install_unit(
+                                name,
+                                &unit,
+                                systemd_command.is_present("enable"),
+                            )
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                        } else {
+                            print!("{}", unit);
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            SHELL_INIT => {
+                let shell = matches
+                    .subcommand_matches(SHELL_INIT)
+                    .and_then(|shell_init| shell_init.value_of("shell"))
+                    .map(InitShell::from_str)
+                    .unwrap()
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                let script = bombadil
+                    .shell_init(shell)
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                print!("{}", script);
+            }
+            IMPORT => {
+                let import_command = matches.subcommand_matches(IMPORT).unwrap();
+                let bombadil = Bombadil::from_settings(Mode::NoGpg, config.as_deref())
+                    .unwrap_or_else(|err| fatal!("{}", err));
+                match import_command.subcommand() {
+                    (IMPORT_STOW, Some(stow_command)) => {
+                        let stow_dir = PathBuf::from(stow_command.value_of("dir").unwrap());
+                        let target_dir = match stow_command.value_of("target") {
+                            Some(target) => PathBuf::from(target),
+                            None => dirs::home_dir()
+                                .unwrap_or_else(|| fatal!("$HOME directory not found")),
+                        };
+
+                        bombadil
+                            .import_stow(
+                                &stow_dir,
+                                &target_dir,
+                                stow_command.is_present("dotfiles"),
+                                stow_command.is_present("dry-run"),
+                            )
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    (IMPORT_CHEZMOI, Some(chezmoi_command)) => {
+                        let source_dir =
+                            PathBuf::from(chezmoi_command.value_of("source-dir").unwrap());
+                        let home_dir =
+                            dirs::home_dir().unwrap_or_else(|| fatal!("$HOME directory not found"));
+
+                        bombadil
+                            .import_chezmoi(
+                                &source_dir,
+                                &home_dir,
+                                chezmoi_command.is_present("dry-run"),
+                            )
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    (IMPORT_DOTBOT, Some(dotbot_command)) => {
+                        let config = PathBuf::from(dotbot_command.value_of("file").unwrap());
+                        bombadil
+                            .import_dotbot(&config, dotbot_command.is_present("dry-run"))
+                            .unwrap_or_else(|err| fatal!("{}", err));
+                    }
+                    _ => unreachable!(),
+                }
             }
             GENERATE_COMPLETIONS => {
                 let generate_subcommand = matches.subcommand_matches(GENERATE_COMPLETIONS).unwrap();
-                let for_shell = match generate_subcommand.value_of("type").unwrap() {
+                let shell = generate_subcommand.value_of("type").unwrap();
+                let for_shell = match shell {
                     "bash" => Shell::Bash,
                     "elvish" => Shell::Elvish,
                     "fish" => Shell::Fish,
                     "zsh" => Shell::Zsh,
                     _ => unreachable!(),
                 };
-                build_cli(profile_names).gen_completions_to(
+
+                // Profiles are listed when completing, the placeholder marks where
+                let mut script = vec![];
+                build_cli(vec![PROFILES_PLACEHOLDER]).gen_completions_to(
                     "bombadil",
                     for_shell,
-                    &mut std::io::stdout(),
+                    &mut script,
                 );
+                let script = String::from_utf8_lossy(&script);
+                print!("{}", dynamic_completions(&script, shell));
+            }
+            COMPLETE => {
+                let complete_subcommand = matches.subcommand_matches(COMPLETE).unwrap();
+                let settings = match Settings::get_from(config.as_deref()) {
+                    Ok(settings) => settings,
+                    Err(_) => return,
+                };
+
+                let mut names: Vec<&String> = match complete_subcommand.value_of("kind") {
+                    Some("profiles") => settings.profiles.keys().collect(),
+                    _ => settings
+                        .settings
+                        .dots
+                        .keys()
+                        .chain(
+                            settings
+                                .profiles
+                                .values()
+                                .flat_map(|profile| profile.dots.keys()),
+                        )
+                        .collect(),
+                };
+                names.sort();
+                names.dedup();
+                names.iter().for_each(|name| println!("{}", name));
             }
             _ => unreachable!(),
         }
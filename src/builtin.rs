@@ -0,0 +1,218 @@
+use anyhow::Result;
+use colored::*;
+use std::fs;
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Hook commands starting with this prefix run a built-in action instead of a command
+pub(crate) const BUILTIN_PREFIX: &str = "builtin:";
+
+const BUILTINS: [&str; 4] = ["reload-sway", "restart-waybar", "fc-cache", "tmux-source"];
+
+/// Run the built-in action named `name`, without the `builtin:` prefix
+pub(crate) fn run(name: &str) -> Result<()> {
+    match name {
+        #[cfg(unix)]
+        "reload-sway" => {
+            let socket = std::env::var_os("SWAYSOCK")
+                .ok_or_else(|| anyhow!("$SWAYSOCK is not set, is sway running ?"))?;
+            reload_sway(Path::new(&socket))
+        }
+        #[cfg(unix)]
+        "restart-waybar" => restart_waybar(),
+        #[cfg(not(unix))]
+        "reload-sway" | "restart-waybar" => Err(anyhow!(
+            "Built-in hook `{}{}` requires a Unix system",
+            BUILTIN_PREFIX,
+            name
+        )),
+        "fc-cache" => fc_cache(),
+        "tmux-source" => tmux_source(),
+        _ => Err(anyhow!(
+            "Unknown built-in hook `{}{}`, available built-ins : {}",
+            BUILTIN_PREFIX,
+            name,
+            BUILTINS.join(", ")
+        )),
+    }
+}
+
+/// Sway IPC `RUN_COMMAND` message type
+const SWAY_RUN_COMMAND: u32 = 0;
+const SWAY_IPC_MAGIC: &[u8; 6] = b"i3-ipc";
+
+#[cfg(unix)]
+fn reload_sway(socket: &Path) -> Result<()> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|err| anyhow!("Unable to connect to sway at {:?} : {}", socket, err))?;
+
+    stream.write_all(&sway_message(SWAY_RUN_COMMAND, "reload"))?;
+
+    let mut header = [0u8; 14];
+    stream.read_exact(&mut header)?;
+    if &header[..6] != SWAY_IPC_MAGIC {
+        return Err(anyhow!("Unexpected answer from sway at {:?}", socket));
+    }
+
+    let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]) as usize;
+    let mut payload = vec![0u8; length];
+    stream.read_exact(&mut payload)?;
+    let payload = String::from_utf8_lossy(&payload);
+
+    if payload.contains("\"success\":true") || payload.contains("\"success\": true") {
+        Ok(())
+    } else {
+        Err(anyhow!("sway reload failed : {}", payload))
+    }
+}
+
+fn sway_message(message_type: u32, payload: &str) -> Vec<u8> {
+    let mut message = SWAY_IPC_MAGIC.to_vec();
+    message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+    message.extend_from_slice(&message_type.to_ne_bytes());
+    message.extend_from_slice(payload.as_bytes());
+    message
+}
+
+/// Waybar reloads its configuration and style on SIGUSR2
+#[cfg(unix)]
+fn restart_waybar() -> Result<()> {
+    let pids = processes_named("waybar");
+    if pids.is_empty() {
+        return Err(anyhow!("waybar is not running"));
+    }
+
+    for pid in pids {
+        if unsafe { libc::kill(pid, libc::SIGUSR2) } != 0 {
+            return Err(anyhow!(
+                "Unable to signal waybar ({}) : {}",
+                pid,
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Pids of the processes of the current user named `name`
+#[cfg(unix)]
+fn processes_named(name: &str) -> Vec<libc::pid_t> {
+    let uid = unsafe { libc::getuid() };
+    let entries = match fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<libc::pid_t>().ok()?;
+            let comm = fs::read_to_string(entry.path().join("comm")).ok()?;
+            let owner = std::os::unix::fs::MetadataExt::uid(&entry.metadata().ok()?);
+            (comm.trim() == name && owner == uid).then_some(pid)
+        })
+        .collect()
+}
+
+fn fc_cache() -> Result<()> {
+    let output = Command::new("fc-cache")
+        .arg("-f")
+        .output()
+        .map_err(|err| anyhow!("Unable to run fc-cache, is fontconfig installed ? {}", err))?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "fc-cache failed : {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn tmux_source() -> Result<()> {
+    let config = tmux_config().ok_or_else(|| anyhow!("No tmux configuration found"))?;
+    let output = Command::new("tmux")
+        .arg("source-file")
+        .arg(&config)
+        .output()
+        .map_err(|err| anyhow!("Unable to run tmux, is it installed ? {}", err))?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if output.status.success() {
+        Ok(())
+    } else if stderr.contains("no server running") {
+        // The configuration will be read when tmux starts
+        info!("{}", "tmux is not running, nothing to reload".yellow());
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "tmux source-file {} failed : {}",
+            config.display(),
+            stderr.trim()
+        ))
+    }
+}
+
+fn tmux_config() -> Option<PathBuf> {
+    let xdg = dirs::config_dir().map(|config| config.join("tmux").join("tmux.conf"));
+    let legacy = dirs::home_dir().map(|home| home.join(".tmux.conf"));
+
+    vec![xdg, legacy]
+        .into_iter()
+        .flatten()
+        .find(|path| path.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builtin::{reload_sway, run, sway_message, SWAY_RUN_COMMAND};
+    use anyhow::Result;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_reload_sway_over_ipc() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let socket = temp.join("sway.sock");
+        let listener = UnixListener::bind(&socket)?;
+        let sway = std::thread::spawn(move || -> Vec<u8> {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 20];
+            stream.read_exact(&mut request).unwrap();
+            let mut answer = b"i3-ipc".to_vec();
+            let payload = "[{\"success\":true}]";
+            answer.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+            answer.extend_from_slice(&SWAY_RUN_COMMAND.to_ne_bytes());
+            answer.extend_from_slice(payload.as_bytes());
+            stream.write_all(&answer).unwrap();
+            request.to_vec()
+        });
+
+        // Act
+        let result = reload_sway(&socket);
+
+        // Assert
+        assert!(result.is_ok());
+        assert_eq!(
+            sway.join().unwrap(),
+            sway_message(SWAY_RUN_COMMAND, "reload")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_on_unknown_builtin() {
+        // Act
+        let result = run("reload-everything");
+
+        // Assert
+        assert!(result.unwrap_err().to_string().contains("reload-sway"));
+    }
+}
@@ -0,0 +1,431 @@
+use crate::dots::DotVar;
+use crate::mimeapps::{self, Defaults};
+use crate::settings::Settings;
+use crate::templating::{self, VarFormat};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// A configuration problem, with the configuration file and line it comes from when known
+#[derive(Debug, PartialEq)]
+pub(crate) struct Problem {
+    pub location: Option<(PathBuf, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.location {
+            Some((file, line)) => write!(f, "{}:{}: {}", file.display(), line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Check the merged settings : dot sources, var files, targets and profile references.
+/// Every problem is returned instead of stopping at the first one.
+pub(crate) fn check(settings: &Settings) -> Vec<Problem> {
+    let files = ConfigFiles::read(&settings.files);
+    let mut problems: Vec<Problem> = settings
+        .warnings
+        .iter()
+        .map(|warning| Problem {
+            location: None,
+            message: warning.clone(),
+        })
+        .collect();
+
+    let dotfiles_dir = match settings.get_dotfiles_path() {
+        Ok(dotfiles_dir) => dotfiles_dir,
+        Err(err) => {
+            problems.push(Problem {
+                location: files.find_key("dotfiles_dir"),
+                message: err.to_string(),
+            });
+            return problems;
+        }
+    };
+
+    for var_path in settings.settings.vars.iter() {
+        problems.extend(check_var_file(
+            &dotfiles_dir.join(var_path),
+            var_path,
+            &files,
+        ));
+    }
+
+    let mut dots: Vec<_> = settings.settings.dots.iter().collect();
+    dots.sort_by_key(|(key, _)| key.as_str());
+    for (key, dot) in dots {
+        let location = || files.find_table(&["settings", "dots", key]);
+        let dot_dir = dot.dotfiles_dir(&dotfiles_dir);
+        if is_interpolated(&dot.source) {
+            debug!(
+                "dot `{}` : source {:?} depends on variables",
+                key, dot.source
+            );
+        } else if !dot_dir.join(&dot.source).exists() {
+            problems.push(Problem {
+                location: location(),
+                message: format!("dot `{}` : source {:?} not found", key, dot.source),
+            });
+        } else if !dot.is_default_var_path()
+            && dot
+                .find_from_source(&dot_dir, &dot.source, &dot.vars)
+                .is_none()
+        {
+            problems.push(Problem {
+                location: location(),
+                message: format!("dot `{}` : var file {:?} not found", key, dot.vars),
+            });
+        }
+
+        if escapes_home(&dot.target) {
+            problems.push(Problem {
+                location: location(),
+                message: format!(
+                    "dot `{}` : target {:?} is outside of $HOME",
+                    key, dot.target
+                ),
+            });
+        }
+    }
+
+    let mut autostart: Vec<_> = settings.settings.autostart.iter().collect();
+    autostart.sort_by_key(|(key, _)| key.as_str());
+    for (key, entry) in autostart {
+        if entry.exec.is_none() {
+            problems.push(Problem {
+                location: files.find_table(&["settings", "autostart", key]),
+                message: format!("autostart `{}` : no `exec` command", key),
+            });
+        }
+    }
+
+    let application_dirs = mimeapps::application_dirs();
+    problems.extend(check_defaults(
+        &settings.settings.defaults,
+        "",
+        &application_dirs,
+        || files.find_table(&["settings", "defaults"]),
+    ));
+
+    let mut profiles: Vec<_> = settings.profiles.iter().collect();
+    profiles.sort_by_key(|(name, _)| name.as_str());
+    for (name, profile) in profiles {
+        let location = || files.find_table(&["profiles", name]);
+
+        problems.extend(check_defaults(
+            &profile.defaults,
+            &format!("profile `{}` : ", name),
+            &application_dirs,
+            || files.find_table(&["profiles", name, "defaults"]),
+        ));
+
+        let mut autostart: Vec<_> = profile.autostart.iter().collect();
+        autostart.sort_by_key(|(key, _)| key.as_str());
+        for (key, entry) in autostart {
+            if entry.exec.is_none() && !settings.settings.autostart.contains_key(key) {
+                problems.push(Problem {
+                    location: files.find_table(&["profiles", name, "autostart", key]),
+                    message: format!(
+                        "profile `{}` : autostart `{}` overrides no entry and has no `exec` command",
+                        name, key
+                    ),
+                });
+            }
+        }
+
+        for extra in profile.extra_profiles.iter() {
+            if !settings.profiles.contains_key(extra) {
+                problems.push(Problem {
+                    location: files.find_value(extra).or_else(location),
+                    message: format!("profile `{}` : unknown extra profile `{}`", name, extra),
+                });
+            }
+        }
+
+        for excluded in profile.dots_exclude.iter() {
+            let exists = settings.settings.dots.contains_key(excluded)
+                || settings
+                    .profiles
+                    .values()
+                    .any(|profile| profile.dots.contains_key(excluded));
+            if !exists {
+                problems.push(Problem {
+                    location: files.find_value(excluded).or_else(location),
+                    message: format!(
+                        "profile `{}` : excluded dot `{}` does not exist",
+                        name, excluded
+                    ),
+                });
+            }
+        }
+
+        for var_path in profile.vars.iter() {
+            problems.extend(check_var_file(
+                &dotfiles_dir.join(var_path),
+                var_path,
+                &files,
+            ));
+        }
+
+        let mut dots: Vec<_> = profile.dots.iter().collect();
+        dots.sort_by_key(|(key, _)| key.as_str());
+        for (key, dot) in dots {
+            let location = || files.find_table(&["profiles", name, "dots", key]);
+            let dot_dir = dot.repository.as_ref().unwrap_or(&dotfiles_dir);
+            let source = dot
+                .source
+                .as_ref()
+                .or_else(|| settings.settings.dots.get(key).map(|dot| &dot.source));
+
+            match source {
+                None => problems.push(Problem {
+                    location: location(),
+                    message: format!(
+                        "profile `{}` : dot `{}` overrides no dot and has no source",
+                        name, key
+                    ),
+                }),
+                Some(source) if is_interpolated(source) => {}
+                Some(source) if !dot_dir.join(source).exists() => problems.push(Problem {
+                    location: location(),
+                    message: format!(
+                        "profile `{}` : dot `{}` source {:?} not found",
+                        name, key, source
+                    ),
+                }),
+                Some(source) => {
+                    let vars = dot.vars.as_ref().filter(|_| !dot.is_default_var_path());
+                    if let Some(vars) = vars {
+                        if dot.find_from_source(dot_dir, source, vars).is_none() {
+                            problems.push(Problem {
+                                location: location(),
+                                message: format!(
+                                    "profile `{}` : dot `{}` var file {:?} not found",
+                                    name, key, vars
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
+
+            if let Some(target) = dot.target.as_ref().filter(|target| escapes_home(target)) {
+                problems.push(Problem {
+                    location: location(),
+                    message: format!(
+                        "profile `{}` : dot `{}` target {:?} is outside of $HOME",
+                        name, key, target
+                    ),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Every default application must be an installed `.desktop` file
+fn check_defaults(
+    defaults: &Defaults,
+    prefix: &str,
+    application_dirs: &[PathBuf],
+    location: impl Fn() -> Option<(PathBuf, usize)>,
+) -> Vec<Problem> {
+    defaults
+        .desktop_files()
+        .into_iter()
+        .filter(|desktop_file| !mimeapps::is_installed(desktop_file, application_dirs))
+        .map(|desktop_file| Problem {
+            location: location(),
+            message: format!(
+                "{}default application {} is not installed",
+                prefix, desktop_file
+            ),
+        })
+        .collect()
+}
+
+/// A var file must exist and be a valid toml table of strings, `.env`, json or yaml file
+fn check_var_file(path: &Path, declared: &Path, files: &ConfigFiles) -> Option<Problem> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Some(Problem {
+                location: files.find_value(&declared.to_string_lossy()),
+                message: format!("var file {:?} : {}", path, err),
+            })
+        }
+    };
+
+    let problem = |line: Option<usize>, message: String| Problem {
+        location: line.map(|line| (path.to_path_buf(), line)),
+        message: format!("var file {:?} : {}", path, message),
+    };
+
+    match VarFormat::of(path) {
+        VarFormat::Env => templating::parse_env(&content)
+            .err()
+            .map(|err| problem(Some(err.line), err.message)),
+        VarFormat::Json => templating::parse_json(&content)
+            .err()
+            .map(|err| problem(Some(err.line()), err.to_string())),
+        VarFormat::Yaml => templating::parse_yaml(&content)
+            .err()
+            .map(|err| problem(Some(err.marker().line()), err.to_string())),
+        VarFormat::Toml => toml::from_str::<HashMap<String, String>>(&content)
+            .err()
+            .map(|err| {
+                let line = err
+                    .span()
+                    .map(|span| content[..span.start].matches('\n').count() + 1);
+                problem(line, err.message().to_string())
+            }),
+    }
+}
+
+/// Sources using `%var%` placeholders, they are only known once the variables are read
+fn is_interpolated(source: &Path) -> bool {
+    source.to_string_lossy().matches('%').count() >= 2
+}
+
+/// Relative targets climbing above $HOME with `..`, absolute targets are deliberate
+fn escapes_home(target: &Path) -> bool {
+    let mut depth = 0;
+    for component in target.components() {
+        match component {
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::RootDir | Component::Prefix(_) => return false,
+            Component::CurDir => {}
+        }
+    }
+
+    false
+}
+
+/// Configuration files content, to locate problems
+pub(crate) struct ConfigFiles {
+    files: Vec<(PathBuf, String)>,
+}
+
+impl ConfigFiles {
+    pub(crate) fn read(paths: &[PathBuf]) -> Self {
+        let files = paths
+            .iter()
+            .filter_map(|path| {
+                let content = fs::read_to_string(path).ok()?;
+                Some((
+                    path.canonicalize().unwrap_or_else(|_| path.clone()),
+                    content,
+                ))
+            })
+            .collect();
+
+        ConfigFiles { files }
+    }
+
+    /// First line matching `predicate`, one based
+    fn find(&self, predicate: impl Fn(&str) -> bool) -> Option<(PathBuf, usize)> {
+        self.files.iter().find_map(|(path, content)| {
+            content
+                .lines()
+                .position(|line| predicate(line.trim()))
+                .map(|index| (path.clone(), index + 1))
+        })
+    }
+
+    /// A quoted string value
+    pub(crate) fn find_value(&self, value: &str) -> Option<(PathBuf, usize)> {
+        let double = format!("\"{}\"", value);
+        let single = format!("'{}'", value);
+        self.find(|line| line.contains(&double) || line.contains(&single))
+    }
+
+    /// A `key = ` or `key: ` entry
+    pub(crate) fn find_key(&self, key: &str) -> Option<(PathBuf, usize)> {
+        let prefixes = [
+            format!("{} ", key),
+            format!("{}=", key),
+            format!("{}:", key),
+            format!("\"{}\"", key),
+        ];
+        self.find(|line| {
+            prefixes
+                .iter()
+                .any(|prefix| line.starts_with(prefix.as_str()))
+        })
+    }
+
+    /// A `[a.b.c]` toml table, or the `c` entry for inline tables and other formats
+    pub(crate) fn find_table(&self, keys: &[&str]) -> Option<(PathBuf, usize)> {
+        let header = format!("[{}]", keys.join("."));
+        let nested = format!("[{}.", keys.join("."));
+        self.find(|line| line.starts_with(&header) || line.starts_with(&nested))
+            .or_else(|| keys.last().and_then(|key| self.find_key(key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check::{check, escapes_home};
+    use crate::settings::Settings;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn should_detect_targets_outside_of_home() {
+        // Assert
+        assert!(escapes_home(Path::new("../other_user/.zshrc")));
+        assert!(escapes_home(Path::new(".config/../../etc")));
+        assert!(!escapes_home(Path::new(".config/../.zshrc")));
+        assert!(!escapes_home(Path::new("/etc/hosts")));
+    }
+
+    #[test]
+    fn should_report_every_problem() -> anyhow::Result<()> {
+        // Arrange
+        let dotfiles_dir = PathBuf::from("tests/check").canonicalize()?;
+        let config = dotfiles_dir.join("bombadil.toml");
+        let mut settings: Settings = toml::from_str(&fs::read_to_string(&config)?)?;
+        settings.dotfiles_dir = dotfiles_dir.clone();
+        settings.files.push(config.clone());
+
+        // Act
+        let problems = check(&settings);
+
+        // Assert
+        let messages: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.message.as_str())
+            .collect();
+        let lines: Vec<usize> = problems
+            .iter()
+            .filter_map(|problem| problem.location.as_ref())
+            .map(|(file, line)| {
+                assert_eq!(file, &config);
+                *line
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                format!(
+                    "var file {:?} : No such file or directory (os error 2)",
+                    dotfiles_dir.join("missing_vars.toml")
+                )
+                .as_str(),
+                "dot `missing` : source \"missing\" not found",
+                "dot `outside` : target \"../.zshrc\" is outside of $HOME",
+                "profile `work` : unknown extra profile `personal`",
+                "profile `work` : dot `new` overrides no dot and has no source",
+            ]
+        );
+        assert_eq!(lines, vec![4, 10, 14, 19, 21]);
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Previously linked `candidates` that still link into one of the `copy_dirs` but are not
+/// among the `current` targets
+pub(crate) fn orphaned_links(
+    candidates: &[PathBuf],
+    copy_dirs: &[PathBuf],
+    current: &[PathBuf],
+) -> Vec<PathBuf> {
+    let mut links: Vec<PathBuf> = candidates
+        .iter()
+        .filter(|target| !current.contains(target))
+        .filter(|target| {
+            fs::read_link(target)
+                .is_ok_and(|destination| copy_dirs.iter().any(|dir| destination.starts_with(dir)))
+        })
+        .cloned()
+        .collect();
+
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// Entries of `copy_dir` that are neither one of the `kept` paths nor contain one
+pub(crate) fn stale_renders(copy_dir: &Path, kept: &[PathBuf]) -> Vec<PathBuf> {
+    let mut stale = vec![];
+    collect_stale_renders(copy_dir, kept, &mut stale);
+    stale
+}
+
+fn collect_stale_renders(dir: &Path, kept: &[PathBuf], stale: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match dir.read_dir() {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+
+    for entry in entries {
+        if kept.contains(&entry) {
+            continue;
+        }
+
+        let is_dir = fs::symlink_metadata(&entry).is_ok_and(|metadata| metadata.is_dir());
+        if is_dir && kept.iter().any(|path| path.starts_with(&entry)) {
+            collect_stale_renders(&entry, kept, stale);
+        } else {
+            stale.push(entry);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clean::{orphaned_links, stale_renders};
+    use anyhow::Result;
+    use std::fs;
+    use std::os::unix;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_find_orphaned_links_and_stale_renders() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dots = temp.join(".dots");
+        fs::create_dir_all(dots.join("wm/sway"))?;
+        fs::create_dir_all(dots.join("wm/i3"))?;
+        fs::write(dots.join("zshrc"), "")?;
+        fs::write(dots.join("previous_state.toml"), "")?;
+        unix::fs::symlink(dots.join("wm/sway"), temp.join("sway"))?;
+        unix::fs::symlink(dots.join("wm/i3"), temp.join("i3"))?;
+        unix::fs::symlink(temp.join("elsewhere"), temp.join("user_link"))?;
+        let kept = vec![dots.join("wm/sway"), dots.join("previous_state.toml")];
+
+        // Act
+        let copy_dirs = vec![dots.clone()];
+        let links = orphaned_links(
+            &[temp.join("sway"), temp.join("i3"), temp.join("user_link")],
+            &copy_dirs,
+            &[temp.join("sway")],
+        );
+        let stale = stale_renders(&dots, &kept);
+
+        // Assert
+        assert_eq!(links, vec![temp.join("i3")]);
+        assert_eq!(stale, vec![dots.join("wm/i3"), dots.join("zshrc")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::Result;
+use colored::*;
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+/// Above this many line comparisons files are only reported as different
+const MAX_COMPARISONS: usize = 10_000_000;
+
+/// Relative paths of the files that differ between two rendered dots,
+/// `old` or `new` may not exist
+pub(crate) fn changed_files(old: &Path, new: &Path) -> Vec<PathBuf> {
+    let mut files = vec![];
+    collect_files(old, Path::new(""), &mut files);
+    collect_files(new, Path::new(""), &mut files);
+    files.sort();
+    files.dedup();
+
+    files
+        .into_iter()
+        .filter(|file| fs::read(old.join(file)).ok() != fs::read(new.join(file)).ok())
+        .collect()
+}
+
+fn collect_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) {
+    let path = root.join(relative);
+    // Symlinks kept inside a directory dot may loop, they are compared as files
+    let is_link = !relative.as_os_str().is_empty()
+        && fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.is_symlink());
+    if path.is_dir() && !is_link {
+        if let Ok(entries) = path.read_dir() {
+            entries
+                .filter_map(Result::ok)
+                .for_each(|entry| collect_files(root, &relative.join(entry.file_name()), files));
+        }
+    } else if path.exists() {
+        files.push(relative.to_path_buf());
+    }
+}
+
+/// A unified diff of `old` and `new`, missing files are treated as empty
+pub(crate) fn unified(old: &Path, new: &Path) -> String {
+    let header = format!("--- {}\n+++ {}\n", label(old), label(new));
+    let read = |path: &Path| match fs::read(path) {
+        Ok(content) => String::from_utf8(content).ok(),
+        Err(_) => Some(String::new()),
+    };
+
+    match (read(old), read(new)) {
+        (Some(old_content), Some(new_content)) => {
+            let old_lines: Vec<&str> = old_content.lines().collect();
+            let new_lines: Vec<&str> = new_content.lines().collect();
+            match hunks(&old_lines, &new_lines) {
+                Some(hunks) => format!("{}{}", header, hunks),
+                None => format!("{}Files are too large to compare\n", header),
+            }
+        }
+        _ => format!("Binary files {} and {} differ\n", label(old), label(new)),
+    }
+}
+
+fn label(path: &Path) -> String {
+    if path.exists() {
+        path.display().to_string()
+    } else {
+        "/dev/null".to_string()
+    }
+}
+
+fn hunks(old: &[&str], new: &[&str]) -> Option<String> {
+    let edits = edits(old, new)?;
+    let changes: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, (kind, _))| *kind != ' ')
+        .map(|(idx, _)| idx)
+        .collect();
+
+    // Group the changes closer than twice the context
+    let mut groups: Vec<(usize, usize)> = vec![];
+    for change in changes {
+        match groups.last_mut() {
+            Some((_, last)) if change <= *last + 2 * CONTEXT => *last = change,
+            _ => groups.push((change, change)),
+        }
+    }
+
+    let mut output = String::new();
+    for (first, last) in groups {
+        let start = first.saturating_sub(CONTEXT);
+        let end = (last + CONTEXT + 1).min(edits.len());
+        let count = |range: &[(char, &str)], skipped: char| {
+            range.iter().filter(|(kind, _)| *kind != skipped).count()
+        };
+
+        let old_start = count(&edits[..start], '+');
+        let new_start = count(&edits[..start], '-');
+        let old_len = count(&edits[start..end], '+');
+        let new_len = count(&edits[start..end], '-');
+        let position = |start: usize, len: usize| if len == 0 { start } else { start + 1 };
+
+        output.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            position(old_start, old_len),
+            old_len,
+            position(new_start, new_len),
+            new_len
+        ));
+        for (kind, line) in edits[start..end].iter() {
+            output.push_str(&format!("{}{}\n", kind, line));
+        }
+    }
+
+    Some(output)
+}
+
+/// Line edits turning `old` into `new`, from their longest common subsequence
+fn edits<'a>(old: &[&'a str], new: &[&'a str]) -> Option<Vec<(char, &'a str)>> {
+    let (n, m) = (old.len(), new.len());
+    if n.saturating_mul(m) > MAX_COMPARISONS {
+        return None;
+    }
+
+    // lcs[i][j] : length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    let at = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[at(i, j)] = if old[i] == new[j] {
+                lcs[at(i + 1, j + 1)] + 1
+            } else {
+                lcs[at(i + 1, j)].max(lcs[at(i, j + 1)])
+            };
+        }
+    }
+
+    let (mut i, mut j) = (0, 0);
+    let mut edits = vec![];
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            edits.push((' ', old[i]));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[at(i + 1, j)] >= lcs[at(i, j + 1)]) {
+            edits.push(('-', old[i]));
+            i += 1;
+        } else {
+            edits.push(('+', new[j]));
+            j += 1;
+        }
+    }
+
+    Some(edits)
+}
+
+/// Colorize a unified diff, for terminals
+pub(crate) fn colorize(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            if line.starts_with("+++") || line.starts_with("---") {
+                line.bold().to_string()
+            } else if line.starts_with('+') {
+                line.green().to_string()
+            } else if line.starts_with('-') {
+                line.red().to_string()
+            } else if line.starts_with("@@") {
+                line.cyan().to_string()
+            } else {
+                line.to_string()
+            }
+        })
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+pub(crate) fn is_terminal() -> bool {
+    std::io::stdout().is_terminal()
+}
+
+/// Tools comparing two files instead of reading a unified diff on stdin
+const FILE_TOOLS: [&str; 3] = ["difft", "vimdiff", "meld"];
+
+/// A user configured diff tool, ex: `delta`, `difft` or `code --diff {old} {new}`
+pub(crate) struct DiffTool<'a> {
+    args: Vec<&'a str>,
+}
+
+impl<'a> DiffTool<'a> {
+    pub(crate) fn new(command: &'a str) -> Result<Self> {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        if args.is_empty() {
+            return Err(anyhow!("`diff_tool` is empty"));
+        }
+
+        Ok(DiffTool { args })
+    }
+
+    /// Tools taking `{old}` and `{new}` placeholders, or known to compare files, are run
+    /// once per changed file, the others read the whole unified diff on their stdin
+    pub(crate) fn compares_files(&self) -> bool {
+        FILE_TOOLS.contains(&self.args[0])
+            || self
+                .args
+                .iter()
+                .any(|arg| arg.contains("{old}") || arg.contains("{new}"))
+    }
+
+    /// Run the tool on two files, the terminal is left to the tool
+    pub(crate) fn compare(&self, old: &Path, new: &Path) -> Result<()> {
+        let old = label(old);
+        let new = label(new);
+        let has_placeholders = self
+            .args
+            .iter()
+            .any(|arg| arg.contains("{old}") || arg.contains("{new}"));
+
+        let mut args: Vec<String> = self
+            .args
+            .iter()
+            .map(|arg| arg.replace("{old}", &old).replace("{new}", &new))
+            .collect();
+        if !has_placeholders {
+            args.push(old);
+            args.push(new);
+        }
+
+        // Diff tools exit with a non zero code when files differ, only spawn errors are failures
+        Command::new(&args[0])
+            .args(&args[1..])
+            .status()
+            .map(|_| ())
+            .map_err(|err| anyhow!("Unable to run diff tool `{}` : {}", args[0], err))
+    }
+
+    /// Pipe a unified diff to the tool
+    pub(crate) fn page(&self, diff: &str) -> Result<()> {
+        let mut child = Command::new(self.args[0])
+            .args(&self.args[1..])
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|err| anyhow!("Unable to run diff tool `{}` : {}", self.args[0], err))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // The tool may exit before reading everything, when quitting a pager for instance
+            let _ = stdin.write_all(diff.as_bytes());
+        }
+
+        child.wait().map(|_| ()).map_err(|err| anyhow!(err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{changed_files, hunks, unified, DiffTool};
+    use anyhow::Result;
+    use std::fs;
+    use std::path::PathBuf;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_build_unified_hunks() {
+        // Arrange
+        let old = vec!["a", "b", "c", "d", "e", "f", "g", "h", "i", "j"];
+        let new = vec!["a", "B", "c", "d", "e", "f", "g", "h", "i", "j", "k"];
+
+        // Act
+        let hunks = hunks(&old, &new).unwrap();
+
+        // Assert
+        assert_eq!(
+            hunks,
+            "@@ -1,5 +1,5 @@\n a\n-b\n+B\n c\n d\n e\n@@ -8,3 +8,4 @@\n h\n i\n j\n+k\n"
+        );
+    }
+
+    #[test]
+    fn should_diff_rendered_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let old = temp.join("old");
+        let new = temp.join("new");
+        fs::create_dir_all(old.join("sub"))?;
+        fs::create_dir_all(&new)?;
+        fs::write(old.join("config"), "font = 12\n")?;
+        fs::write(new.join("config"), "font = 14\n")?;
+        fs::write(old.join("same"), "same")?;
+        fs::write(new.join("same"), "same")?;
+        fs::write(old.join("sub/removed"), "gone")?;
+
+        // Act
+        let changed = changed_files(&old, &new);
+        let diff = unified(&old.join("sub/removed"), &new.join("sub/removed"));
+
+        // Assert
+        assert_eq!(
+            changed,
+            vec![PathBuf::from("config"), PathBuf::from("sub/removed")]
+        );
+        assert!(diff.ends_with("+++ /dev/null\n@@ -1,1 +0,0 @@\n-gone\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_file_comparing_tools() -> Result<()> {
+        // Assert
+        assert!(!DiffTool::new("delta --side-by-side")?.compares_files());
+        assert!(DiffTool::new("difft")?.compares_files());
+        assert!(DiffTool::new("code --wait --diff {old} {new}")?.compares_files());
+        assert!(DiffTool::new("").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+use crate::git;
+use crate::gpg::{self, GpgStatus};
+use crate::preflight;
+use crate::settings;
+use colored::*;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Level {
+    Ok,
+    Warning,
+    Error,
+}
+
+/// The result of a single environment check, with a suggestion to fix it
+#[derive(Debug, PartialEq)]
+pub(crate) struct Finding {
+    pub level: Level,
+    pub message: String,
+    pub suggestion: Option<String>,
+}
+
+impl Finding {
+    pub(crate) fn ok(message: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Ok,
+            message: message.into(),
+            suggestion: None,
+        }
+    }
+
+    pub(crate) fn warning(message: impl Into<String>, suggestion: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Warning,
+            message: message.into(),
+            suggestion: Some(suggestion.into()),
+        }
+    }
+
+    pub(crate) fn error(message: impl Into<String>, suggestion: impl Into<String>) -> Self {
+        Finding {
+            level: Level::Error,
+            message: message.into(),
+            suggestion: Some(suggestion.into()),
+        }
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self.level {
+            Level::Ok => format!("[ok] {}", self.message).green(),
+            Level::Warning => format!("[warning] {}", self.message).yellow(),
+            Level::Error => format!("[error] {}", self.message).red(),
+        };
+        write!(f, "{}", message)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n\t{}", suggestion)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The gpg binary must run, and hold a secret key for `user_id` to decrypt secrets
+pub(crate) fn check_gpg(user_id: Option<&str>, has_secrets: bool) -> Vec<Finding> {
+    let user_id = match user_id {
+        Some(user_id) => user_id,
+        None if has_secrets => {
+            return vec![Finding::error(
+                "Encrypted variables found but no `gpg_user_id` is configured",
+                "Set `gpg_user_id` in bombadil.toml to the key used with `bombadil add-secret`",
+            )]
+        }
+        None => return vec![],
+    };
+
+    match gpg::status(user_id) {
+        GpgStatus::Ready(version) => vec![
+            Finding::ok(version),
+            Finding::ok(format!("Secret key found for {}", user_id)),
+        ],
+        GpgStatus::NotInstalled => vec![Finding::error(
+            "Unable to run `gpg --version`",
+            "Install gnupg and make sure `gpg` is in your $PATH",
+        )],
+        GpgStatus::MissingKey => vec![Finding::error(
+            format!("No secret key found for `gpg_user_id` {}", user_id),
+            format!(
+                "Import it with `gpg --import <key file>`, or check it is listed by `gpg --list-secret-keys {}`",
+                user_id
+            ),
+        )],
+    }
+}
+
+/// Every dot target must be writable, or creatable in its closest existing parent
+pub(crate) fn check_targets(targets: &[(String, PathBuf)]) -> Vec<Finding> {
+    let findings: Vec<Finding> = targets
+        .iter()
+        .filter_map(|(key, target)| match preflight::existing_ancestor(target) {
+            Some(ancestor) if preflight::is_writable(ancestor) => None,
+            Some(ancestor) => Some(Finding::error(
+                format!(
+                    "{} : cannot write {}, {} is not writable",
+                    key,
+                    target.display(),
+                    ancestor.display()
+                ),
+                format!("Fix the permissions of {}", ancestor.display()),
+            )),
+            None => Some(Finding::error(
+                format!(
+                    "{} : no existing parent directory for {}",
+                    key,
+                    target.display()
+                ),
+                "Check the dot target path",
+            )),
+        })
+        .collect();
+
+    if findings.is_empty() {
+        vec![Finding::ok(format!(
+            "{} dot targets are writable",
+            targets.len()
+        ))]
+    } else {
+        findings
+    }
+}
+
+/// Symlinks under `dir` pointing to a path that does not exist
+pub(crate) fn broken_links(dir: &Path) -> Vec<PathBuf> {
+    let mut links = vec![];
+    collect_broken_links(dir, &mut links);
+    links.sort();
+    links
+}
+
+fn collect_broken_links(dir: &Path, links: &mut Vec<PathBuf>) {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_symlink() && !path.exists() => links.push(path),
+            Ok(metadata) if metadata.file_type().is_symlink() => {}
+            Ok(metadata) if metadata.is_dir() => collect_broken_links(&path, links),
+            _ => {}
+        }
+    }
+}
+
+pub(crate) fn check_copy_dirs(copy_dirs: &[PathBuf]) -> Vec<Finding> {
+    let links: Vec<PathBuf> = copy_dirs.iter().flat_map(|dir| broken_links(dir)).collect();
+    if links.is_empty() {
+        return vec![Finding::ok("No broken symlinks in .dots")];
+    }
+
+    links
+        .iter()
+        .map(|link| {
+            Finding::warning(
+                format!("Broken symlink {}", link.display()),
+                "Run `bombadil link` to render the dots again",
+            )
+        })
+        .collect()
+}
+
+/// `xdg_config` must be a symlink to the configuration of `dotfiles_dir`
+pub(crate) fn check_self_config(xdg_config: &Path, dotfiles_dir: &Path) -> Finding {
+    let suggestion = format!("Run `bombadil install {}`", dotfiles_dir.display());
+    let expected = settings::config_file(dotfiles_dir);
+
+    match fs::symlink_metadata(xdg_config) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            match (xdg_config.canonicalize(), expected.canonicalize()) {
+                (Ok(actual), Ok(expected)) if actual == expected => Finding::ok(format!(
+                    "{} links to {}",
+                    xdg_config.display(),
+                    expected.display()
+                )),
+                (Ok(actual), _) => Finding::error(
+                    format!(
+                        "{} links to {} instead of {}",
+                        xdg_config.display(),
+                        actual.display(),
+                        expected.display()
+                    ),
+                    suggestion,
+                ),
+                (Err(_), _) => Finding::error(
+                    format!("{} is a broken symlink", xdg_config.display()),
+                    suggestion,
+                ),
+            }
+        }
+        Ok(_) => Finding::warning(
+            format!("{} is a regular file, not a symlink", xdg_config.display()),
+            format!(
+                "Move it to {} and run `bombadil install {}`",
+                expected.display(),
+                dotfiles_dir.display()
+            ),
+        ),
+        Err(_) => Finding::error(
+            format!("{} does not exist", xdg_config.display()),
+            suggestion,
+        ),
+    }
+}
+
+pub(crate) fn check_git(dotfiles_dir: &Path) -> Vec<Finding> {
+    let summary = match git::summary(dotfiles_dir) {
+        Ok(summary) => summary,
+        Err(_) => {
+            return vec![Finding::warning(
+                format!("{} is not a git repository", dotfiles_dir.display()),
+                "Run `git init` in the dotfiles directory to version your dotfiles",
+            )]
+        }
+    };
+
+    let mut findings = vec![];
+    match &summary.branch {
+        Some(branch) => findings.push(Finding::ok(format!("On branch {}", branch))),
+        None => findings.push(Finding::warning(
+            "HEAD is detached or has no commit",
+            "Checkout a branch with `git checkout <branch>`",
+        )),
+    }
+
+    if summary.changed > 0 {
+        findings.push(Finding::warning(
+            format!("{} uncommitted changes", summary.changed),
+            "Commit them with `bombadil save`",
+        ));
+    }
+
+    match summary.ahead_behind {
+        Some((ahead, behind)) => {
+            if behind > 0 {
+                findings.push(Finding::warning(
+                    format!("{} commits behind upstream", behind),
+                    "Pull them with `bombadil update`",
+                ));
+            }
+            if ahead > 0 {
+                findings.push(Finding::warning(
+                    format!("{} commits ahead of upstream", ahead),
+                    "Push them with `bombadil save`",
+                ));
+            }
+        }
+        None if summary.branch.is_some() => findings.push(Finding::warning(
+            "The current branch has no upstream",
+            "Set one with `git branch --set-upstream-to <remote>/<branch>`",
+        )),
+        None => {}
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::doctor::{broken_links, check_self_config, Level};
+    use anyhow::Result;
+    use std::fs;
+    use std::os::unix;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_find_broken_links_and_misplaced_config() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let dots = temp.join(".dots");
+        fs::create_dir_all(dots.join("sway"))?;
+        fs::write(dots.join("zshrc"), "")?;
+        unix::fs::symlink(dots.join("zshrc"), dots.join("valid"))?;
+        unix::fs::symlink(temp.join("missing"), dots.join("sway/broken"))?;
+
+        let dotfiles_dir = temp.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir)?;
+        fs::write(dotfiles_dir.join("bombadil.toml"), "")?;
+        fs::write(temp.join("other.toml"), "")?;
+        unix::fs::symlink(temp.join("other.toml"), temp.join("bombadil.toml"))?;
+
+        // Act
+        let links = broken_links(&dots);
+        let finding = check_self_config(&temp.join("bombadil.toml"), &dotfiles_dir);
+
+        // Assert
+        assert_eq!(links, vec![dots.join("sway/broken")]);
+        assert_eq!(finding.level, Level::Error);
+        assert_eq!(
+            finding.suggestion,
+            Some(format!("Run `bombadil install {}`", dotfiles_dir.display()))
+        );
+        Ok(())
+    }
+}
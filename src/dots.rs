@@ -1,14 +1,23 @@
+use crate::facts::{
+    expand_windows_vars, is_wsl, on_windows_drive, termux_path, termux_prefix, wsl_windows_home,
+};
 use crate::gpg::Gpg;
-use crate::templating::Variables;
-use crate::unlink;
+use crate::logger;
+use crate::owner::Owner;
+use crate::parallel;
+use crate::templating::{SkippedSecret, Variables};
+use crate::{symlink, symlink_or_copy, unlink, PROFILES_VAR, WSL_WINDOWS_HOME_VAR};
 use anyhow::Result;
 use colored::*;
 use dirs::home_dir;
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::fs;
 use std::fs::File;
 use std::io::Write;
-use std::os::unix;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 /// Represent a link between a `source` dotfile in the user defined dotfiles directory
 /// and the XDG `target` path where it should be linked
@@ -16,7 +25,9 @@ use std::path::{Path, PathBuf};
 pub struct Dot {
     /// Path relative to user defined dotfile
     pub source: PathBuf,
-    /// Target path either relative to $HOME or absolute
+    /// Target path either relative to $HOME or absolute, or a table of targets by operating
+    /// system resolved when loading the configuration, empty when none matches
+    #[serde(deserialize_with = "deserialize_target")]
     pub target: PathBuf,
     /// Glob pattern of files to ignore when creating symlinks
     #[serde(default)]
@@ -26,6 +37,60 @@ pub struct Dot {
     #[serde(default = "Dot::default_vars")]
     #[serde(skip_serializing)]
     pub vars: PathBuf,
+    /// For directory dots, link each file inside the target directory instead of
+    /// replacing the whole directory, unmanaged files in the target are preserved
+    #[serde(default)]
+    #[serde(skip_serializing)]
+    pub merge: bool,
+    /// Dotfiles directory of the `[[source]]` repository declaring the dot,
+    /// `None` for the main dotfiles directory
+    #[serde(skip)]
+    pub(crate) repository: Option<PathBuf>,
+    /// Comment syntax of the provenance header, ex: "//" or "/* */",
+    /// guessed from the file extension if not set, an empty string disables the header
+    #[serde(default)]
+    #[serde(skip_serializing)]
+    pub comment: Option<String>,
+    /// Mode of the rendered files instead of the mode of their source, ex: `chmod = "600"`
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    #[serde(skip_serializing)]
+    pub chmod: Option<u32>,
+    /// Mode of the rendered directories of a directory dot, ex: `dir_mode = "700"`
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    #[serde(skip_serializing)]
+    pub dir_mode: Option<u32>,
+    /// Owner of the rendered files and of the links, ex: `chown = "alice:staff"`, see [`Owner`]
+    #[serde(default)]
+    #[serde(skip_serializing)]
+    pub chown: Option<String>,
+    /// Render the variables of the dot files, `false` copies them byte for byte
+    #[serde(default = "Dot::default_template")]
+    #[serde(skip_serializing)]
+    pub template: bool,
+    /// How a directory dot is linked, `link = "children"` is the same as `merge = true`
+    #[serde(default)]
+    #[serde(skip_serializing)]
+    pub link: Option<LinkMode>,
+}
+
+impl Default for Dot {
+    /// A dot with the defaults of its configuration fields, `source` and `target` are empty
+    fn default() -> Self {
+        Dot {
+            source: PathBuf::new(),
+            target: PathBuf::new(),
+            ignore: vec![],
+            vars: Dot::default_vars(),
+            merge: false,
+            repository: None,
+            comment: None,
+            chmod: None,
+            dir_mode: None,
+            chown: None,
+            template: Dot::default_template(),
+            link: None,
+        }
+    }
 }
 
 /// Same as dot but source and target are optionals
@@ -33,54 +98,255 @@ pub struct Dot {
 pub struct DotOverride {
     /// Path relative to user defined dotfile
     pub source: Option<PathBuf>,
-    /// Target path either relative to $HOME or absolute
+    /// Target path either relative to $HOME or absolute, or a table of targets by operating
+    /// system, empty when none matches so the overridden target is kept
+    #[serde(default, deserialize_with = "deserialize_target_override")]
     pub target: Option<PathBuf>,
     /// Glob pattern of files to ignore when creating symlinks
     #[serde(default)]
     pub ignore: Vec<String>,
     // A single var file attached to the dot
     pub vars: Option<PathBuf>,
+    /// Link the directory content into the target instead of replacing it
+    pub merge: Option<bool>,
+    /// Dotfiles directory of the `[[source]]` repository declaring the override
+    #[serde(skip)]
+    pub(crate) repository: Option<PathBuf>,
+    /// Comment syntax of the provenance header
+    pub comment: Option<String>,
+    /// Mode of the rendered files
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub chmod: Option<u32>,
+    /// Mode of the rendered directories
+    #[serde(default, deserialize_with = "deserialize_mode")]
+    pub dir_mode: Option<u32>,
+    /// Owner of the rendered files and of the links
+    pub chown: Option<String>,
+    /// Render the variables of the dot files
+    pub template: Option<bool>,
+    /// How a directory dot is linked
+    pub link: Option<LinkMode>,
+}
+
+/// How the target of a directory dot is linked
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkMode {
+    /// The target is a symlink to the rendered directory
+    Directory,
+    /// Each file is linked inside the target directory, other files in it are left untouched
+    Children,
+}
+
+/// A dot target, either a path or a table of paths by operating system :
+/// `target = { linux = ".config/alacritty", macos = "Library/Application Support/alacritty" }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TargetEntry {
+    Path(PathBuf),
+    PerOs(HashMap<String, PathBuf>),
+}
+
+impl TargetEntry {
+    /// The target of `os`, as named by [`std::env::consts::OS`], or the `default` one
+    fn resolve_for(self, os: &str) -> Option<PathBuf> {
+        match self {
+            TargetEntry::Path(target) => Some(target),
+            TargetEntry::PerOs(mut targets) => {
+                targets.remove(os).or_else(|| targets.remove("default"))
+            }
+        }
+    }
+}
+
+fn deserialize_target<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    TargetEntry::deserialize(deserializer)
+        .map(|target| target.resolve_for(std::env::consts::OS).unwrap_or_default())
+}
+
+fn deserialize_target_override<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<PathBuf>, D::Error> {
+    Option::<TargetEntry>::deserialize(deserializer).map(|target| {
+        target.map(|target| target.resolve_for(std::env::consts::OS).unwrap_or_default())
+    })
+}
+
+/// An octal file mode, ex: `"600"` or `"0o755"`
+fn deserialize_mode<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    let mode = String::deserialize(deserializer)?;
+    u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .map(Some)
+        .ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid file mode \"{}\", expected an octal mode like \"600\"",
+                mode
+            ))
+        })
+}
+
+/// State of a dot target in the filesystem
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum LinkStatus {
+    Linked,
+    /// The target does not exist
+    Unlinked,
+    /// The target is a symlink to a missing file
+    Broken,
+    /// The target is a symlink to another path, or a file not managed by bombadil
+    Unexpected(Option<PathBuf>),
+}
+
+/// The files of a dot to render and its variables, see [`Dot::prepare_render`]
+pub(crate) struct Rendering<'a> {
+    dot: &'a Dot,
+    vars: Variables,
+    /// The dot source is a file, failing to render it fails the dot
+    single_file: bool,
+    /// Source files and where to render them
+    files: Vec<(PathBuf, PathBuf)>,
+    /// Relative symlinks of a directory dot staying inside of it, recreated as is by
+    /// [`Dot::collect_files`] : the link and its destination
+    links: Vec<(PathBuf, PathBuf)>,
+    /// Owner of the rendered files, resolved from [`Dot::chown`]
+    owner: Option<Owner>,
 }
 
 impl Dot {
-    pub(crate) fn install(
+    /// Render the dot to `copy_path`, usually [`Dot::copy_path`]
+    pub(crate) fn render(
         &self,
         dotfile_dir: &Path,
+        copy_path: &Path,
         vars: &Variables,
         auto_ignored: Vec<PathBuf>,
         gpg: Option<&Gpg>,
     ) -> Result<()> {
+        let rendering = self.prepare_render(dotfile_dir, copy_path, vars, auto_ignored, gpg)?;
+        render_all(std::slice::from_ref(&rendering)).remove(0)
+    }
+
+    /// Resolve the variables of the dot and create the directories of `copy_path`, the files
+    /// are rendered with [`render_all`]
+    pub(crate) fn prepare_render(
+        &self,
+        dotfile_dir: &Path,
+        copy_path: &Path,
+        vars: &Variables,
+        auto_ignored: Vec<PathBuf>,
+        gpg: Option<&Gpg>,
+    ) -> Result<Rendering<'_>> {
         let source = &self.source_path(dotfile_dir)?;
-        let copy_path = &self.copy_path(dotfile_dir);
         let source_str = source.to_str().unwrap_or_default();
         let mut ignored_paths = self.get_ignored_paths(source_str)?;
         ignored_paths.extend_from_slice(&auto_ignored);
+        debug!(
+            "Rendering {:?} to {:?}, {} ignored paths",
+            source,
+            copy_path,
+            ignored_paths.len()
+        );
 
         // Add local vars to the global ones
         let mut vars = vars.clone();
 
         if let Some(local_vars_path) = self.resolve_var_path(dotfile_dir) {
+            debug!(
+                "{:?} : using the dot var file {:?}",
+                source, local_vars_path
+            );
             let local_vars = Dot::load_local_vars(&local_vars_path, gpg);
+            if let Some(audit) = &vars.secret_audit {
+                let mut keys: Vec<&String> = local_vars.secrets.keys().collect();
+                keys.sort();
+                let audit = audit.for_dot(&self.source);
+                keys.iter().for_each(|key| audit.decrypted(key));
+            }
             vars.extend(local_vars);
         }
 
         // Resolve % reference
         vars.resolve_ref();
+        vars.secret_audit = vars.secret_audit.map(|audit| audit.for_dot(&self.source));
 
-        // Recursively copy dotfile to .dots directory
-        self.traverse_and_copy(source, copy_path, ignored_paths.as_slice(), &vars)
+        self.rendering(source, copy_path, ignored_paths.as_slice(), vars)
+    }
+
+    fn rendering(
+        &self,
+        source: &Path,
+        target: &Path,
+        ignored: &[PathBuf],
+        vars: Variables,
+    ) -> Result<Rendering<'_>> {
+        let owner = self.owner()?;
+        let mut files = vec![];
+        let mut links = vec![];
+        self.collect_files(source, source, target, ignored, &mut files, &mut links)?;
+        // Rendered files are owned once written, see `Rendering::render_file`
+        if let Some(owner) = &owner {
+            if fs::symlink_metadata(target).is_ok() {
+                owner.apply_recursively(target)?;
+            }
+        }
+
+        Ok(Rendering {
+            dot: self,
+            vars,
+            single_file: source.is_file(),
+            files,
+            links,
+            owner,
+        })
+    }
+
+    fn default_template() -> bool {
+        true
+    }
+
+    /// The owner set with `chown`, if any
+    pub(crate) fn owner(&self) -> Result<Option<Owner>> {
+        self.chown
+            .as_deref()
+            .map(Owner::parse)
+            .transpose()
+            .map_err(|err| anyhow!("{:?} : {}", self.source, err))
     }
 
     pub(crate) fn symlink(&self, dotfile_dir: &Path) -> Result<()> {
         let copy_path = &self.copy_path(dotfile_dir);
         let target = &self.target_path()?;
 
-        // Link
-        unix::fs::symlink(copy_path, target)
-            .map(|_result| {
-                let source = format!("{:?}", copy_path).blue();
-                let dest = format!("{:?}", target).green();
-                println!("{} => {}", source, dest)
+        if self.is_merged(dotfile_dir) {
+            debug!(
+                "{:?} is an existing directory, merging the dot files into it",
+                target
+            );
+            return self.symlink_merged(dotfile_dir);
+        }
+
+        // The target can be renamed, ex: `wezterm/work.lua` linked to `.config/wezterm/wezterm.lua`
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        // Link, or copy if the target filesystem does not support symlinks
+        symlink_or_copy(copy_path, target)
+            .and_then(|copied| {
+                if let Some(owner) = self.owner()? {
+                    owner.apply_recursively(target)?;
+                }
+                Ok(copied)
+            })
+            .map(|copied| {
+                let linked = logger::linked(copy_path, target);
+                if copied {
+                    info!("{} (copied, symlinks not supported)", linked)
+                } else {
+                    info!("{}", linked)
+                }
             })
             .map_err(|err| {
                 let source = format!("{:?}", copy_path).blue();
@@ -88,33 +354,197 @@ impl Dot {
                 let err = format!("{}", err).red().bold();
                 anyhow!("{} => {} : {}", source, dest, err)
             })
-            .unwrap_or_else(|err| eprintln!("{}", err));
+    }
+
+    /// Link every file of a merged directory dot, creating the target directories as needed
+    fn symlink_merged(&self, dotfile_dir: &Path) -> Result<()> {
+        let entries = self.merged_entries(dotfile_dir)?;
+
+        for (copy_path, target) in entries.iter() {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            unlink(target)?;
+            symlink_or_copy(copy_path, target).map_err(|err| {
+                let source = format!("{:?}", copy_path).blue();
+                let dest = format!("{:?}", &target).red();
+                let err = format!("{}", err).red().bold();
+                anyhow!("{} => {} : {}", source, dest, err)
+            })?;
+            if let Some(owner) = self.owner()? {
+                owner.apply_recursively(target)?;
+            }
+        }
 
+        let linked = logger::linked(&self.copy_path(dotfile_dir), &self.target_path()?);
+        info!("{} (merged {} files)", linked, entries.len());
         Ok(())
     }
 
+    /// Remove the dot target, merged directories are kept since they can contain unmanaged files
     pub(crate) fn unlink(&self) -> Result<()> {
         let target = &self.target_path()?;
+        let is_symlink = fs::symlink_metadata(target)
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false);
+
+        if self.links_children() && target.is_dir() && !is_symlink {
+            return Ok(());
+        }
+
         unlink(target)
     }
 
-    /// Return the target path of a dot entry either absolute or relative to $HOME
-    pub(crate) fn target_path(&self) -> Result<PathBuf> {
-        if self.target.is_absolute() {
-            Ok(self.target.clone())
+    /// Paths created in the filesystem when linking this dot : the target itself,
+    /// or every linked file for a merged directory dot
+    pub(crate) fn targets(&self, dotfile_dir: &Path) -> Result<Vec<PathBuf>> {
+        if self.is_merged(dotfile_dir) {
+            Ok(self
+                .merged_entries(dotfile_dir)?
+                .into_iter()
+                .map(|(_, target)| target)
+                .collect())
+        } else {
+            Ok(vec![self.target_path()?])
+        }
+    }
+
+    /// Rendered files in `.dots` and the path they are linked to, every linked file for a
+    /// merged directory dot
+    pub(crate) fn links(&self, dotfile_dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        if self.is_merged(dotfile_dir) {
+            self.merged_entries(dotfile_dir)
+        } else {
+            Ok(vec![(self.copy_path(dotfile_dir), self.target_path()?)])
+        }
+    }
+
+    /// The dotfiles directory the dot source is relative to
+    pub(crate) fn dotfiles_dir(&self, main_dotfiles_dir: &Path) -> PathBuf {
+        self.repository
+            .clone()
+            .unwrap_or_else(|| main_dotfiles_dir.to_path_buf())
+    }
+
+    /// Check the targets against the rendered dot, for merged directory dots the first
+    /// target that is not linked gives the status
+    pub(crate) fn link_status(&self, dotfile_dir: &Path) -> LinkStatus {
+        let links = if self.is_merged(dotfile_dir) {
+            self.merged_entries(dotfile_dir).unwrap_or_default()
         } else {
-            home_dir()
-                .map(|home| home.join(&self.target))
+            match self.target_path() {
+                Ok(target) => vec![(self.copy_path(dotfile_dir), target)],
+                Err(_) => return LinkStatus::Unlinked,
+            }
+        };
+
+        links
+            .iter()
+            .map(|(copy_path, target)| link_status(copy_path, target))
+            .find(|status| *status != LinkStatus::Linked)
+            .unwrap_or(LinkStatus::Linked)
+    }
+
+    fn is_merged(&self, dotfile_dir: &Path) -> bool {
+        self.links_children() && self.copy_path(dotfile_dir).is_dir()
+    }
+
+    /// Files are linked one by one, `link` takes precedence over `merge`
+    fn links_children(&self) -> bool {
+        match self.link {
+            Some(mode) => mode == LinkMode::Children,
+            None => self.merge,
+        }
+    }
+
+    /// Pairs of rendered file in `.dots` and the corresponding path in the target directory
+    fn merged_entries(&self, dotfile_dir: &Path) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let copy_path = self.copy_path(dotfile_dir);
+        let target = self.target_path()?;
+        let mut entries = vec![];
+        let mut directories = vec![copy_path.clone()];
+
+        while let Some(directory) = directories.pop() {
+            for entry in directory.read_dir()? {
+                let path = entry?.path();
+                // Internal symlinks are linked as is, their destination is linked already
+                if is_real_dir(&path) {
+                    directories.push(path);
+                } else {
+                    let relative = path.strip_prefix(&copy_path)?.to_path_buf();
+                    entries.push((path, target.join(relative)));
+                }
+            }
+        }
+
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Move the target under `prefix`, absolute targets are re-rooted inside it.
+    /// Targets in the WSL Windows home or starting with a Windows variable are left untouched.
+    pub(crate) fn prefix_target(&mut self, prefix: &Path) {
+        let windows_home_placeholder = format!("__[{}]__", WSL_WINDOWS_HOME_VAR);
+        if self.target.starts_with(&windows_home_placeholder)
+            || self.target.to_string_lossy().starts_with('%')
+        {
+            return;
+        }
+
+        let relative = self.target.strip_prefix("/").unwrap_or(&self.target);
+        self.target = prefix.join(relative);
+    }
+
+    /// Link a relative target in `base` instead of $HOME.
+    /// Targets in the WSL Windows home or starting with a Windows variable are left untouched.
+    pub(crate) fn root_target(&mut self, base: &Path) {
+        let windows_home_placeholder = format!("__[{}]__", WSL_WINDOWS_HOME_VAR);
+        if self.target.is_absolute()
+            || self.target.starts_with(&windows_home_placeholder)
+            || self.target.to_string_lossy().starts_with('%')
+        {
+            return;
+        }
+
+        self.target = base.join(&self.target);
+    }
+
+    /// Return the target path of a dot entry either absolute or relative to $HOME.
+    /// Under WSL, targets can start with `__[wsl.windows_home]__` to point to the Windows home directory.
+    /// `%NAME%` environment variables are expanded, ex: `%APPDATA%/alacritty` on Windows.
+    pub(crate) fn target_path(&self) -> Result<PathBuf> {
+        let windows_home_placeholder = format!("__[{}]__", WSL_WINDOWS_HOME_VAR);
+        if let Ok(relative) = self.target.strip_prefix(&windows_home_placeholder) {
+            return wsl_windows_home()
+                .map(|home| home.join(relative))
                 .ok_or_else(|| {
-                    let err = format!("Unable to find dot path : {:?}", &self.target).red();
+                    let err = format!(
+                        "Unable to find the Windows home directory for {:?}, is this WSL ?",
+                        &self.target
+                    )
+                    .red();
                     anyhow!(err)
-                })
+                });
+        }
+
+        let target = expand_windows_vars(&self.target, |name| std::env::var(name).ok());
+        if target.is_absolute() {
+            match termux_prefix() {
+                Some(prefix) => Ok(termux_path(&prefix, &target)),
+                None => Ok(target),
+            }
+        } else {
+            home_dir().map(|home| home.join(&target)).ok_or_else(|| {
+                let err = format!("Unable to find dot path : {:?}", &self.target).red();
+                anyhow!(err)
+            })
         }
     }
 
     fn load_local_vars(source: &Path, gpg: Option<&Gpg>) -> Variables {
         Variables::from_toml(source, gpg).unwrap_or_else(|err| {
-            eprintln!("{}", err.to_string().yellow());
+            warn!("{}", err.to_string().yellow());
             Variables::default()
         })
     }
@@ -123,54 +553,173 @@ impl Dot {
         Ok(
             globwalk::GlobWalkerBuilder::from_patterns(source_str, self.ignore.as_slice())
                 .build()?
-                .into_iter()
                 .filter_map(Result::ok)
                 .map(|entry| entry.path().to_path_buf())
                 .collect(),
         )
     }
 
+    /// Render `source` to `target` without the dot var file nor ignore patterns
+    #[cfg(test)]
     fn traverse_and_copy(
         &self,
         source: &Path,
         target: &Path,
         ignored: &[PathBuf],
         vars: &Variables,
+    ) -> Result<()> {
+        let rendering = self.rendering(source, target, ignored, vars.clone())?;
+        render_all(std::slice::from_ref(&rendering)).remove(0)
+    }
+
+    /// Create the directories and internal symlinks of `source` in `target` and collect the
+    /// files to render, `root` is the source of the dot. Other symlinks are followed.
+    fn collect_files(
+        &self,
+        root: &Path,
+        source: &Path,
+        target: &Path,
+        ignored: &[PathBuf],
+        files: &mut Vec<(PathBuf, PathBuf)>,
+        links: &mut Vec<(PathBuf, PathBuf)>,
     ) -> Result<()> {
         if ignored.contains(&PathBuf::from(source)) {
+            trace!("{:?} : ignored", source);
             return Ok(());
         }
 
-        // Single file : inject vars and write to .dots/
-        if source.is_file() {
-            fs::create_dir_all(&target.parent().unwrap())?;
-            if let Ok(content) = vars.to_dot(source) {
-                let permissions = fs::metadata(source)?.permissions();
-                let mut dot_copy = File::create(&target)?;
-                dot_copy.write_all(content.as_bytes())?;
-                dot_copy.set_permissions(permissions)?;
-            } else {
-                // Something went wrong parsing or reading the source path,
-                // We just copy the file in place
-                fs::copy(source, target)?;
+        if let Some(destination) = internal_link(root, source) {
+            unlink(target)?;
+            match symlink(&destination, target) {
+                Ok(()) => {
+                    trace!("{:?} : linked to {:?}", source, destination);
+                    links.push((target.to_path_buf(), destination));
+                    return Ok(());
+                }
+                Err(err) => debug!("{:?} : link not recreated, {}", source, err),
             }
+        }
+
+        if source.is_file() {
+            fs::create_dir_all(target.parent().unwrap())?;
+            files.push((source.to_path_buf(), target.to_path_buf()));
         } else if source.is_dir() {
+            // A link recreated by a previous render
+            if fs::symlink_metadata(target).is_ok_and(|metadata| metadata.is_symlink()) {
+                unlink(target)?;
+            }
             fs::create_dir_all(target)?;
+            if let Some(mode) = self.dir_mode {
+                set_mode(target, mode)?;
+            }
             for entry in source.read_dir()? {
                 let entry_path = &entry?.path();
                 let entry_name = entry_path.file_name().unwrap().to_str().unwrap();
-                self.traverse_and_copy(
+                let result = self.collect_files(
+                    root,
                     &source.join(entry_name),
                     &target.join(entry_name),
                     ignored,
-                    vars,
-                )
-                .unwrap_or_else(|err| eprintln!("{}", err));
+                    files,
+                    links,
+                );
+
+                if let Err(err) = result {
+                    error!("{}", err);
+                }
             }
         }
         Ok(())
     }
 
+    /// A comment telling where the rendered `file` comes from, `None` if the comment syntax
+    /// is unknown or disabled for this dot
+    fn provenance_header(&self, file: &Path, content: &str, vars: &Variables) -> Option<String> {
+        let excluded = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| vars.provenance_exclude.iter().any(|e| e == extension))
+            .unwrap_or(false);
+        if excluded {
+            return None;
+        }
+
+        let syntax = match &self.comment {
+            Some(comment) => comment.as_str(),
+            None => default_comment(file)?,
+        };
+
+        let mut delimiters = syntax.split_whitespace();
+        let open = delimiters.next()?;
+        let close = delimiters
+            .next()
+            .map(|close| format!(" {}", close))
+            .unwrap_or_default();
+
+        let profiles = match vars.variables.get(PROFILES_VAR) {
+            Some(profiles) if !profiles.is_empty() => {
+                format!(" with profiles [{}]", profiles.replace(',', ", "))
+            }
+            _ => String::new(),
+        };
+
+        Some(format!(
+            "{} Generated by bombadil from {}{} on {}, do not edit {}{}{}\n",
+            open,
+            file.display(),
+            profiles,
+            today(),
+            HASH_MARKER,
+            content_hash(content),
+            close
+        ))
+    }
+
+    /// Rendered files of this dot whose content changed since they were rendered,
+    /// only files with a provenance header can be checked
+    pub(crate) fn edited_files(&self, dotfile_dir: &Path) -> Vec<PathBuf> {
+        let mut edited = vec![];
+        collect_edited(&self.copy_path(dotfile_dir), &mut edited);
+        edited
+    }
+
+    /// Copy the edits of rendered files back to their source, sources containing
+    /// template variables are left for a manual merge. Returns the adopted sources.
+    pub(crate) fn adopt_changes(&self, dotfile_dir: &Path) -> Result<Vec<PathBuf>> {
+        let copy_path = self.copy_path(dotfile_dir);
+        let mut adopted = vec![];
+
+        for edited in self.edited_files(dotfile_dir) {
+            let source = self.source_path(dotfile_dir)?;
+            let source = match edited.strip_prefix(&copy_path) {
+                Ok(relative) if !relative.as_os_str().is_empty() => source.join(relative),
+                _ => source,
+            };
+
+            let source_content = fs::read_to_string(&source).unwrap_or_default();
+            if source_content.contains("__[") {
+                let warning = format!(
+                    "{} contains template variables, merge the edits of {} by hand",
+                    source.display(),
+                    edited.display()
+                )
+                .yellow();
+                warn!("{}", warning);
+                continue;
+            }
+
+            let content = fs::read_to_string(&edited)?;
+            if let Some((rendered, _)) = split_header(&content) {
+                fs::write(&source, &rendered)?;
+                // The copy is now up to date with its source
+                fs::write(&edited, refresh_hash(&content, &rendered))?;
+                adopted.push(source);
+            }
+        }
+
+        Ok(adopted)
+    }
+
     /// Resolve dot source copy path ({dotfiles/dotsource) against user defined dotfile directory
     /// Check if file exists
     fn source_path(&self, dotfile_dir: &Path) -> Result<PathBuf> {
@@ -192,7 +741,401 @@ impl Dot {
     }
 }
 
+impl Rendering<'_> {
+    /// Inject vars in `source` and write it to `target`
+    fn render_file(&self, source: &Path, target: &Path) -> Result<()> {
+        let rendered = match self.dot.template {
+            true => self.vars.to_dot(source),
+            false => Err(anyhow!("templating disabled")),
+        };
+
+        match rendered {
+            Ok(mut content) => {
+                if self.vars.provenance_header {
+                    if let Some(header) = self.dot.provenance_header(source, &content, &self.vars) {
+                        content = with_header(&content, &header);
+                    }
+                }
+
+                // The target may be hard linked to the previous generation, see `reuse`
+                unlink(target)?;
+                let mut dot_copy = match self.dot.chmod {
+                    Some(mode) => create_with_mode(target, mode)?,
+                    None => File::create(target)?,
+                };
+                dot_copy.write_all(content.as_bytes())?;
+                match self.dot.chmod {
+                    Some(mode) => set_mode(target, mode)?,
+                    None => dot_copy.set_permissions(fs::metadata(source)?.permissions())?,
+                }
+                trace!("{:?} : rendered to {:?}", source, target);
+            }
+            Err(err) if err.is::<SkippedSecret>() => return Err(err),
+            Err(err) => {
+                // Something went wrong parsing or reading the source path,
+                // We just copy the file in place
+                debug!("{:?} : copied without rendering, {}", source, err);
+                unlink(target)?;
+                fs::copy(source, target)?;
+                if let Some(mode) = self.dot.chmod {
+                    set_mode(target, mode)?;
+                }
+            }
+        }
+
+        if let Some(owner) = &self.owner {
+            owner.apply(target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Rendering again gives the same files when the inputs don't change : commands, render
+    /// profiling and degraded secrets need the templates to be rendered
+    pub(crate) fn is_reproducible(&self) -> bool {
+        !self.vars.allow_commands
+            && self.vars.render_profiler.is_none()
+            && self.vars.degraded.is_none()
+    }
+
+    /// Hash of the dot, its variables and its source files. Targets are relative to
+    /// `copy_dir`, so rendering to a staging directory hashes the same.
+    pub(crate) fn input_hash(&self, copy_dir: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(format!("{:?}\n{}\n", self.dot, self.vars.fingerprint()));
+        for (source, target) in &self.sorted_files() {
+            let content = fs::read(source)?;
+            let mode = permissions_mode(&fs::metadata(source)?.permissions());
+            let target = target.strip_prefix(copy_dir).unwrap_or(target);
+            hasher.update(format!(
+                "{:?} {:?} {:o} {}\n",
+                source,
+                target,
+                mode,
+                content.len()
+            ));
+            hasher.update(content);
+        }
+        for (link, destination) in &self.links {
+            let link = link.strip_prefix(copy_dir).unwrap_or(link);
+            hasher.update(format!("{:?} -> {:?}\n", link, destination));
+        }
+
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Hash of the files rendered to `copy_dir`, read from `rendered_dir`
+    pub(crate) fn output_hash(&self, copy_dir: &Path, rendered_dir: &Path) -> Result<String> {
+        let mut hasher = Sha256::new();
+        for (_, target) in &self.sorted_files() {
+            let target = target.strip_prefix(copy_dir)?;
+            let content = fs::read(rendered_dir.join(target))?;
+            hasher.update(format!("{:?} {}\n", target, content.len()));
+            hasher.update(content);
+        }
+
+        Ok(hex(&hasher.finalize()))
+    }
+
+    /// Link the files rendered to `copy_dir` by a previous link, they are in `rendered_dir`,
+    /// instead of rendering them again. Files are copied where they can't be hard linked.
+    pub(crate) fn reuse(&self, copy_dir: &Path, rendered_dir: &Path) -> Result<()> {
+        if copy_dir == rendered_dir {
+            return Ok(());
+        }
+
+        for (_, target) in &self.files {
+            let rendered = rendered_dir.join(target.strip_prefix(copy_dir)?);
+            unlink(target)?;
+            if fs::hard_link(&rendered, target).is_err() {
+                fs::copy(&rendered, target)?;
+                if let Some(owner) = &self.owner {
+                    owner.apply(target)?;
+                }
+            }
+            trace!("{:?} : reused {:?}", target, rendered);
+        }
+
+        Ok(())
+    }
+
+    fn sorted_files(&self) -> Vec<&(PathBuf, PathBuf)> {
+        let mut files: Vec<&(PathBuf, PathBuf)> = self.files.iter().collect();
+        files.sort();
+        files
+    }
+}
+
+/// Destination of `source` if it is a relative symlink resolving inside the dot `root`
+fn internal_link(root: &Path, source: &Path) -> Option<PathBuf> {
+    let is_symlink = fs::symlink_metadata(source).is_ok_and(|metadata| metadata.is_symlink());
+    if !is_symlink {
+        return None;
+    }
+
+    let destination = fs::read_link(source).ok()?;
+    let mut depth = source
+        .parent()?
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .count();
+    for component in destination.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Normal(_) => depth += 1,
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return None,
+        }
+    }
+
+    Some(destination)
+}
+
+/// A directory and not a symlink to one
+fn is_real_dir(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Create `path` with the octal `mode`, a file is never readable by others while it is written
+#[cfg(unix)]
+fn create_with_mode(path: &Path, mode: u32) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_with_mode(path: &Path, _mode: u32) -> std::io::Result<File> {
+    File::create(path)
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// Only the owner write bit of `mode` has an equivalent, the read-only flag
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn permissions_mode(permissions: &fs::Permissions) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    permissions.mode()
+}
+
+#[cfg(not(unix))]
+fn permissions_mode(permissions: &fs::Permissions) -> u32 {
+    match permissions.readonly() {
+        true => 0o444,
+        false => 0o644,
+    }
+}
+
+/// Render the files of every dot in parallel and return the result of each dot. Errors
+/// rendering a file of a directory are reported and the other files are still rendered,
+/// except for skipped secrets : a dot using one is not linked at all.
+pub(crate) fn render_all(renderings: &[Rendering]) -> Vec<Result<()>> {
+    let files: Vec<(usize, &PathBuf, &PathBuf)> = renderings
+        .iter()
+        .enumerate()
+        .flat_map(|(index, rendering)| {
+            rendering
+                .files
+                .iter()
+                .map(move |(source, target)| (index, source, target))
+        })
+        .collect();
+
+    let rendered = parallel::map(&files, |(index, source, target)| {
+        renderings[*index].render_file(source, target)
+    });
+
+    let mut results: Vec<Result<()>> = renderings.iter().map(|_| Ok(())).collect();
+    for ((index, _, _), result) in files.iter().zip(rendered) {
+        match result {
+            Err(err) if err.is::<SkippedSecret>() || renderings[*index].single_file => {
+                if results[*index].is_ok() {
+                    results[*index] = Err(err);
+                }
+            }
+            Err(err) => error!("{}", err),
+            Ok(()) => {}
+        }
+    }
+
+    results
+}
+
+/// Comment syntax guessed from the file extension, files without extension
+/// are usually shell-like configs
+fn default_comment(file: &Path) -> Option<&'static str> {
+    let extension = match file.extension().and_then(|extension| extension.to_str()) {
+        Some(extension) => extension,
+        None => return Some("#"),
+    };
+
+    match extension {
+        "sh" | "bash" | "zsh" | "fish" | "py" | "rb" | "pl" | "toml" | "yml" | "yaml" | "conf"
+        | "cfg" | "ini" | "nix" | "tmux" => Some("#"),
+        "lua" | "sql" | "hs" => Some("--"),
+        "vim" => Some("\""),
+        "js" | "ts" | "rs" | "c" | "h" | "cpp" | "go" | "java" | "jsonc" | "kdl" | "scss" => {
+            Some("//")
+        }
+        "css" => Some("/* */"),
+        "html" | "xml" | "svg" => Some("<!-- -->"),
+        "el" | "lisp" => Some(";"),
+        _ => None,
+    }
+}
+
+/// Separates the provenance header from the hash of the content it was rendered with
+const HASH_MARKER: &str = "sha256:";
+
+fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Split a rendered file in its content without the provenance header and the recorded
+/// content hash, `None` if the file has no header
+fn split_header(content: &str) -> Option<(String, String)> {
+    let mut lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let position = lines.iter().take(2).position(|line| {
+        line.contains("Generated by bombadil from ") && line.contains(HASH_MARKER)
+    })?;
+
+    let header = lines.remove(position);
+    let hash = header
+        .split(HASH_MARKER)
+        .nth(1)?
+        .chars()
+        .take_while(|c| c.is_ascii_hexdigit())
+        .collect();
+
+    Some((lines.concat(), hash))
+}
+
+fn refresh_hash(content: &str, rendered: &str) -> String {
+    match split_header(content) {
+        Some((_, hash)) => content.replacen(
+            &format!("{}{}", HASH_MARKER, hash),
+            &format!("{}{}", HASH_MARKER, content_hash(rendered)),
+            1,
+        ),
+        None => content.to_string(),
+    }
+}
+
+fn collect_edited(path: &Path, edited: &mut Vec<PathBuf>) {
+    if is_real_dir(path) {
+        if let Ok(entries) = path.read_dir() {
+            let mut entries: Vec<PathBuf> =
+                entries.filter_map(Result::ok).map(|e| e.path()).collect();
+            entries.sort();
+            entries
+                .iter()
+                .for_each(|entry| collect_edited(entry, edited));
+        }
+    } else if let Ok(content) = fs::read_to_string(path) {
+        if let Some((rendered, hash)) = split_header(&content) {
+            if content_hash(&rendered) != hash {
+                edited.push(path.to_path_buf());
+            }
+        }
+    }
+}
+
+/// Insert `header` at the top of `content`, after the shebang or the xml declaration if any
+fn with_header(content: &str, header: &str) -> String {
+    if content.starts_with("#!") || content.starts_with("<?xml") {
+        let (first_line, rest) = match content.find('\n') {
+            Some(idx) => content.split_at(idx + 1),
+            None => (content, ""),
+        };
+        let separator = if first_line.ends_with('\n') { "" } else { "\n" };
+        format!("{}{}{}{}", first_line, separator, header, rest)
+    } else {
+        format!("{}{}", header, content)
+    }
+}
+
+/// Current UTC date formatted as `YYYY-MM-DD`
+fn today() -> String {
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() / 86_400)
+        .unwrap_or_default() as i64;
+
+    let (year, month, day) = civil_date(days);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Year, month and day of the `days` since 1970-01-01,
+/// see http://howardhinnant.github.io/date_algorithms.html
+pub(crate) fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn link_status(copy_path: &Path, target: &Path) -> LinkStatus {
+    let metadata = match fs::symlink_metadata(target) {
+        Ok(metadata) => metadata,
+        Err(_) => return LinkStatus::Unlinked,
+    };
+
+    if !metadata.file_type().is_symlink() {
+        // Dots targeting a Windows drive are copied instead of linked
+        return if is_wsl() && on_windows_drive(target) {
+            LinkStatus::Linked
+        } else {
+            LinkStatus::Unexpected(None)
+        };
+    }
+
+    match fs::read_link(target) {
+        Ok(destination) if destination == copy_path && target.exists() => LinkStatus::Linked,
+        Ok(destination) if destination != copy_path && target.exists() => {
+            LinkStatus::Unexpected(Some(destination))
+        }
+        _ => LinkStatus::Broken,
+    }
+}
+
 impl DotOverride {
+    /// The target for the current operating system
+    pub(crate) fn target(&self) -> Option<&PathBuf> {
+        self.target
+            .as_ref()
+            .filter(|target| !target.as_os_str().is_empty())
+    }
+
     pub(crate) fn resolve_var_path(
         &self,
         dotfile_dir: &Path,
@@ -232,6 +1175,13 @@ pub(crate) trait DotVar {
         source: &Path,
         path: &Path,
     ) -> Option<PathBuf> {
+        self.find_from_source(dotfile_dir, source, path)
+            // Warning is emitted only if the path is not "vars.toml"
+            .or_else(|| self.vars_path_not_found(dotfile_dir, source, path))
+    }
+
+    /// Same as `resolve_from_source` without warning when the var file is not found
+    fn find_from_source(&self, dotfile_dir: &Path, source: &Path, path: &Path) -> Option<PathBuf> {
         let relative_to_dot = dotfile_dir.join(source).join(path);
         let relative_to_dotfile_dir = dotfile_dir.join(path);
         // FIXME : we should not try to look for path like this
@@ -244,11 +1194,10 @@ pub(crate) trait DotVar {
             } else if relative_to_dotfile_dir.exists() && !self.is_default_var_path() {
                 Some(relative_to_dotfile_dir)
             } else {
-                self.vars_path_not_found(dotfile_dir, source, path)
+                None
             }
         } else {
-            // Warning is emitted only if the path is not "vars.toml"
-            self.vars_path_not_found(dotfile_dir, source, path)
+            None
         }
     }
 
@@ -259,7 +1208,7 @@ pub(crate) trait DotVar {
         path: &Path,
     ) -> Option<PathBuf> {
         if !self.is_default_var_path() {
-            eprintln!(
+            warn!(
                 "{} {:?} {} {:?} {} {:?}",
                 "WARNING: Variable path".yellow(),
                 path,
@@ -295,10 +1244,12 @@ impl DotVar for DotOverride {
 
 #[cfg(test)]
 mod tests {
-    use crate::dots::{Dot, DotVar};
+    use crate::dots::{render_all, Dot, DotOverride, LinkStatus, TargetEntry};
     use crate::templating::Variables;
     use anyhow::Result;
     use std::fs;
+    use std::os::unix;
+    use std::os::unix::fs::PermissionsExt;
     use std::path::PathBuf;
     use temp_testdir::TempDir;
 
@@ -310,8 +1261,7 @@ mod tests {
         let dot = Dot {
             source: Default::default(),
             target: PathBuf::from(".config/sway"),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         // Act
@@ -330,8 +1280,7 @@ mod tests {
         let dot = Dot {
             source: Default::default(),
             target: PathBuf::from("/etc/profile"),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         // Act
@@ -344,6 +1293,32 @@ mod tests {
         assert_eq!(result.unwrap(), expected);
     }
 
+    #[test]
+    fn should_resolve_target_per_os() -> Result<()> {
+        // Arrange
+        let alacritty = r#"
+            source = "alacritty"
+            target = { linux = ".config/alacritty", macos = "Library/Application Support/alacritty" }
+        "#;
+        let git = "linux = \".config/git\"\ndefault = \".gitconfig\"";
+
+        // Act
+        let dot: Dot = toml::from_str(alacritty)?;
+        let dot_override: DotOverride = toml::from_str(r#"target = { freebsd = ".alacritty" }"#)?;
+        let macos = toml::from_str::<TargetEntry>(git)?.resolve_for("macos");
+        let windows =
+            toml::from_str::<TargetEntry>("linux = \".config/git\"")?.resolve_for("windows");
+
+        // Assert
+        if cfg!(target_os = "linux") {
+            assert_eq!(dot.target, PathBuf::from(".config/alacritty"));
+            assert_eq!(dot_override.target(), None);
+        }
+        assert_eq!(macos, Some(PathBuf::from(".gitconfig")));
+        assert_eq!(windows, None);
+        Ok(())
+    }
+
     #[test]
     fn symlink_ok() -> Result<()> {
         // Arrange
@@ -354,13 +1329,12 @@ mod tests {
         let target = PathBuf::from("target_dot");
 
         fs::create_dir(temp.join(".dots"))?;
-        fs::write(&temp.join(".dots").join(&source), "Hello Tom")?;
+        fs::write(temp.join(".dots").join(&source), "Hello Tom")?;
 
         let dot = Dot {
             source,
             target,
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         // Act
@@ -372,7 +1346,125 @@ mod tests {
         assert!(target.exists());
         assert_eq!(fs::read_to_string(target)?, "Hello Tom");
 
-        Ok(dot.unlink()?)
+        dot.unlink()
+    }
+
+    #[test]
+    fn should_render_the_files_of_every_dot() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        fs::create_dir_all(temp.join("sway/conf.d"))?;
+        for index in 0..50 {
+            fs::write(temp.join(format!("sway/conf.d/{}", index)), "bar __[red]__")?;
+        }
+        fs::write(temp.join("zshrc"), "PS1=__[red]__")?;
+        let dot = |source: &str| Dot {
+            source: PathBuf::from(source),
+            target: PathBuf::from(source),
+            ..Default::default()
+        };
+        let (sway, zshrc, missing) = (dot("sway"), dot("zshrc"), dot("missing"));
+        let mut vars = Variables::default();
+        vars.insert("red", "#ff0000");
+
+        // Act
+        let renderings = vec![
+            sway.prepare_render(temp, &sway.copy_path(temp), &vars, vec![], None)?,
+            zshrc.prepare_render(temp, &zshrc.copy_path(temp), &vars, vec![], None)?,
+        ];
+        let results = render_all(&renderings);
+
+        // Assert
+        assert!(results.iter().all(Result::is_ok));
+        for index in 0..50 {
+            let rendered = fs::read_to_string(temp.join(format!(".dots/sway/conf.d/{}", index)))?;
+            assert_eq!(rendered, "bar #ff0000");
+        }
+        assert_eq!(fs::read_to_string(temp.join(".dots/zshrc"))?, "PS1=#ff0000");
+        assert!(missing
+            .prepare_render(temp, &missing.copy_path(temp), &vars, vec![], None)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_render_with_dot_modes() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        fs::create_dir_all(temp.join("ssh/config.d"))?;
+        fs::write(temp.join("ssh/config"), "Host *")?;
+        fs::write(temp.join("ssh/config.d/work"), "Host work")?;
+        let dot: Dot = toml::from_str(
+            r#"
+            source = "ssh"
+            target = ".ssh"
+            chmod = "600"
+            dir_mode = "0o700"
+        "#,
+        )?;
+        let mode = |path: &str| -> Result<u32> {
+            Ok(fs::metadata(temp.join(".dots").join(path))?
+                .permissions()
+                .mode()
+                & 0o7777)
+        };
+
+        // Act
+        dot.render(
+            temp,
+            &dot.copy_path(temp),
+            &Variables::default(),
+            vec![],
+            None,
+        )?;
+        let invalid = toml::from_str::<Dot>("source = \"ssh\"\ntarget = \".ssh\"\nchmod = \"rw\"");
+
+        // Assert
+        assert_eq!(mode("ssh")?, 0o700);
+        assert_eq!(mode("ssh/config.d")?, 0o700);
+        assert_eq!(mode("ssh/config")?, 0o600);
+        assert_eq!(mode("ssh/config.d/work")?, 0o600);
+        assert!(invalid.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_executable_bits_and_internal_symlinks() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        fs::create_dir_all(temp.join("bin/lib"))?;
+        fs::write(temp.join("bin/backup"), "#!/bin/sh\nrsync")?;
+        fs::set_permissions(temp.join("bin/backup"), fs::Permissions::from_mode(0o755))?;
+        fs::write(temp.join("bin/lib/common.sh"), "set -e")?;
+        fs::write(temp.join("outside"), "outside")?;
+        unix::fs::symlink("backup", temp.join("bin/bk"))?;
+        unix::fs::symlink("../lib", temp.join("bin/lib/self"))?;
+        unix::fs::symlink("../outside", temp.join("bin/outside"))?;
+        unix::fs::symlink(temp.join("outside"), temp.join("bin/absolute"))?;
+        let dot: Dot = toml::from_str("source = \"bin\"\ntarget = \".local/bin\"")?;
+        let copy = dot.copy_path(temp);
+
+        // Act
+        dot.render(temp, &copy, &Variables::default(), vec![], None)?;
+        let is_symlink = |path: &str| -> Result<bool> {
+            Ok(fs::symlink_metadata(copy.join(path))?.is_symlink())
+        };
+
+        // Assert
+        let mode = fs::metadata(copy.join("backup"))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(fs::read_link(copy.join("bk"))?, PathBuf::from("backup"));
+        assert_eq!(
+            fs::read_link(copy.join("lib/self"))?,
+            PathBuf::from("../lib")
+        );
+        assert!(!is_symlink("outside")?);
+        assert!(!is_symlink("absolute")?);
+        assert_eq!(fs::read_to_string(copy.join("absolute"))?, "outside");
+        Ok(())
     }
 
     #[test]
@@ -384,7 +1476,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         fs::write(absolute_source_path.join("file"), "Hello Tom")?;
@@ -402,8 +1494,7 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         let absolute_source_path = dot.source_path(temp)?;
@@ -412,7 +1503,7 @@ mod tests {
         dot.traverse_and_copy(
             &absolute_source_path,
             &dot.copy_path(temp),
-            &vec![],
+            &[],
             &Variables::default(),
         )?;
 
@@ -439,20 +1530,19 @@ mod tests {
         let source = &PathBuf::from("ferris.png");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
         fs::copy("tests/dotfiles_non_utf8/ferris.png", absolute_source_path)?;
 
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         dot.traverse_and_copy(
-            &absolute_source_path,
+            absolute_source_path,
             &dot.copy_path(temp),
-            &vec![],
+            &[],
             &Variables::default(),
         )?;
 
@@ -461,6 +1551,62 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn should_copy_binary_files_verbatim() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        fs::create_dir_all(temp.join("fonts"))?;
+        let font = b"__[name]__\0\x01glyphs".to_vec();
+        fs::write(temp.join("fonts/icons.ttf"), &font)?;
+        fs::write(temp.join("fonts/fonts.conf"), "__[name]__")?;
+        let dot: Dot = toml::from_str("source = \"fonts\"\ntarget = \".local/share/fonts\"")?;
+        let mut vars = Variables {
+            provenance_header: true,
+            ..Default::default()
+        };
+        vars.variables
+            .insert("name".to_string(), "icons".to_string());
+
+        // Act
+        dot.render(temp, &dot.copy_path(temp), &vars, vec![], None)?;
+
+        // Assert
+        let copy = dot.copy_path(temp);
+        assert_eq!(fs::read(copy.join("icons.ttf"))?, font);
+        assert!(fs::read_to_string(copy.join("fonts.conf"))?.ends_with("icons"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_copy_untemplated_dots_verbatim() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        let content = "date_format = \"%Y-%m-%d\"\nurl = \"a%20b\"\nname = \"__[name]__\"\n";
+        fs::write(temp.join("config.toml"), content)?;
+        let dot: Dot = toml::from_str(
+            r#"
+            source = "config.toml"
+            target = ".config/app/config.toml"
+            template = false
+        "#,
+        )?;
+        let mut vars = Variables {
+            provenance_header: true,
+            ..Default::default()
+        };
+        vars.variables
+            .insert("name".to_string(), "bombadil".to_string());
+
+        // Act
+        dot.render(temp, &dot.copy_path(temp), &vars, vec![], None)?;
+
+        // Assert
+        assert_eq!(fs::read_to_string(dot.copy_path(temp))?, content);
+        Ok(())
+    }
+
     #[test]
     fn copy_with_ignore() -> Result<()> {
         // Arrange
@@ -470,7 +1616,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         fs::write(absolute_source_path.join("file.md"), "Not Hello Tom")?;
@@ -497,7 +1643,7 @@ mod tests {
             source: source.clone(),
             target: target.clone(),
             ignore: vec!["*.md".to_string()],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         let absolute_source_path = dot.source_path(temp)?;
@@ -508,7 +1654,7 @@ mod tests {
         dot.traverse_and_copy(
             &absolute_source_path,
             &dot.copy_path(temp),
-            &vec![ignored_one, ignored_two],
+            &[ignored_one, ignored_two],
             &Variables::default(),
         )?;
 
@@ -543,13 +1689,12 @@ mod tests {
         let target = PathBuf::from("target_dot");
 
         fs::create_dir(temp.join(".dots"))?;
-        fs::write(&temp.join(".dots").join(&source), "Hello Tom")?;
+        fs::write(temp.join(".dots").join(&source), "Hello Tom")?;
 
         let dot = Dot {
             source,
             target,
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         dot.symlink(temp)?;
@@ -565,6 +1710,49 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn should_link_renamed_targets_in_missing_directories() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = &temp.to_path_buf();
+        fs::create_dir_all(temp.join("wezterm"))?;
+        fs::write(temp.join("wezterm/work.lua"), "return {}")?;
+        fs::create_dir_all(temp.join("nvim-work"))?;
+        fs::write(temp.join("nvim-work/init.lua"), "vim.opt.number = true")?;
+
+        let dot = |source: &str, target: &str| Dot {
+            source: PathBuf::from(source),
+            target: temp.join(target),
+            ..Default::default()
+        };
+        let wezterm = dot("wezterm/work.lua", "home/.config/wezterm/wezterm.lua");
+        let nvim = dot("nvim-work", "home/.config/nvim");
+
+        // Act
+        for dot in [&wezterm, &nvim] {
+            dot.render(
+                temp,
+                &dot.copy_path(temp),
+                &Variables::default(),
+                vec![],
+                None,
+            )?;
+            dot.symlink(temp)?;
+        }
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(temp.join("home/.config/wezterm/wezterm.lua"))?,
+            "return {}"
+        );
+        assert_eq!(
+            fs::read_link(temp.join("home/.config/nvim"))?,
+            temp.join(".dots/nvim-work")
+        );
+        assert!(temp.join("home/.config/nvim/init.lua").exists());
+        Ok(())
+    }
+
     #[test]
     fn install() -> Result<()> {
         // Arrange
@@ -574,7 +1762,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         fs::write(absolute_source_path.join("file"), "Hello Tom")?;
@@ -582,12 +1770,17 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         // Act
-        dot.install(temp, &Variables::default(), vec![], None)?;
+        dot.render(
+            temp,
+            &dot.copy_path(temp),
+            &Variables::default(),
+            vec![],
+            None,
+        )?;
 
         // Assert
         assert!(temp.join(".dots").exists());
@@ -605,7 +1798,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         fs::write(absolute_source_path.join("file"), "Hello __[name]__")?;
@@ -613,15 +1806,20 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
-            vars: Dot::default_vars(),
+            ..Default::default()
         };
 
         let mut vars = Variables::default();
         vars.insert("name", "Tom Bombadil");
 
         // Act
-        dot.install(temp, &Variables::default(), vec![], None)?;
+        dot.render(
+            temp,
+            &dot.copy_path(temp),
+            &Variables::default(),
+            vec![],
+            None,
+        )?;
 
         // Assert
         assert!(temp.join(".dots").exists());
@@ -639,7 +1837,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         let local_vars_path = PathBuf::from("my_vars.toml");
@@ -655,23 +1853,24 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
             vars: local_vars_path,
+            ..Default::default()
         };
 
         let mut vars = Variables::default();
         vars.insert("name", "Tom Bombadil");
 
         // Arrange
-        dot.install(
+        dot.render(
             temp,
+            &dot.copy_path(temp),
             &Variables::default(),
             vec![temp.join("source_dot/my_vars.toml")],
             None,
         )?;
 
         // Assert
-        let content = fs::read_to_string(&temp.join(".dots/source_dot/file"))?;
+        let content = fs::read_to_string(temp.join(".dots/source_dot/file"))?;
         assert_eq!(content, "Golberry is singing");
         assert!(!temp.join(".dots/source_dot/my_vars.toml").exists());
         Ok(())
@@ -686,7 +1885,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         let local_vars_path = PathBuf::from("my_vars.toml");
@@ -702,18 +1901,24 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
             vars: local_vars_path,
+            ..Default::default()
         };
 
         let mut vars = Variables::default();
         vars.insert("name", "Tom Bombadil");
 
         // Arrange
-        dot.install(temp, &Variables::default(), vec![], None)?;
+        dot.render(
+            temp,
+            &dot.copy_path(temp),
+            &Variables::default(),
+            vec![],
+            None,
+        )?;
 
         // Assert
-        let content = fs::read_to_string(&temp.join(".dots/source_dot/file"))?;
+        let content = fs::read_to_string(temp.join(".dots/source_dot/file"))?;
         assert_eq!(content, "Golberry is singing");
         Ok(())
     }
@@ -727,7 +1932,7 @@ mod tests {
         let source = &PathBuf::from("source_dot");
         let target = PathBuf::from("target_dot");
 
-        let absolute_source_path = &temp.join(&source);
+        let absolute_source_path = &temp.join(source);
 
         fs::create_dir(absolute_source_path)?;
         let local_vars_path = PathBuf::from("vars.toml");
@@ -743,19 +1948,252 @@ mod tests {
         let dot = Dot {
             source: source.clone(),
             target: target.clone(),
-            ignore: vec![],
             vars: local_vars_path,
+            ..Default::default()
         };
 
         let mut vars = Variables::default();
         vars.insert("name", "Tom Bombadil");
 
         // Arrange
-        dot.install(temp, &Variables::default(), vec![], None)?;
+        dot.render(
+            temp,
+            &dot.copy_path(temp),
+            &Variables::default(),
+            vec![],
+            None,
+        )?;
 
         // Assert
-        let content = fs::read_to_string(&temp.join(".dots/source_dot/file"))?;
+        let content = fs::read_to_string(temp.join(".dots/source_dot/file"))?;
         assert_eq!(content, "Golberry is singing");
         Ok(())
     }
+
+    #[test]
+    fn should_merge_into_existing_directory() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.join("dotfiles");
+        let copy_path = dotfiles.join(".dots").join("source_dir");
+        fs::create_dir_all(copy_path.join("sub"))?;
+        fs::write(copy_path.join("config"), "managed")?;
+        fs::write(copy_path.join("sub").join("theme"), "managed theme")?;
+
+        let target = temp.join("target_dir");
+        fs::create_dir_all(&target)?;
+        fs::write(target.join("state"), "unmanaged")?;
+
+        let dot = Dot {
+            source: PathBuf::from("source_dir"),
+            target: target.clone(),
+            merge: true,
+            ..Default::default()
+        };
+
+        // Act
+        dot.symlink(&dotfiles)?;
+
+        // Assert
+        assert!(fs::symlink_metadata(&target)?.is_dir());
+        assert_eq!(fs::read_to_string(target.join("state"))?, "unmanaged");
+        assert_eq!(fs::read_to_string(target.join("config"))?, "managed");
+        assert!(fs::symlink_metadata(target.join("sub/theme"))?
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            dot.targets(&dotfiles)?,
+            vec![target.join("config"), target.join("sub/theme")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_link_children_of_directory_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.join("dotfiles");
+        let copy_path = dotfiles.join(".dots").join("systemd");
+        fs::create_dir_all(&copy_path)?;
+        fs::write(copy_path.join("backup.timer"), "[Timer]")?;
+        let target = temp.join("systemd/user");
+        let dot: Dot = toml::from_str(&format!(
+            "source = \"systemd\"\ntarget = {:?}\nmerge = true\nlink = \"children\"",
+            target
+        ))?;
+        let directory: Dot = toml::from_str(&format!(
+            "source = \"systemd\"\ntarget = {:?}\nmerge = true\nlink = \"directory\"",
+            temp.join("linked")
+        ))?;
+
+        // Act
+        dot.symlink(&dotfiles)?;
+        fs::write(target.join("mpd.service"), "[Service]")?;
+        dot.unlink()?;
+        dot.symlink(&dotfiles)?;
+        directory.symlink(&dotfiles)?;
+
+        // Assert
+        assert!(fs::symlink_metadata(&target)?.is_dir());
+        assert!(fs::symlink_metadata(target.join("backup.timer"))?.is_symlink());
+        assert_eq!(fs::read_to_string(target.join("mpd.service"))?, "[Service]");
+        assert!(fs::symlink_metadata(temp.join("linked"))?.is_symlink());
+        Ok(())
+    }
+
+    #[test]
+    fn should_report_link_status() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.join("dotfiles");
+        fs::create_dir_all(dotfiles.join(".dots"))?;
+        fs::write(dotfiles.join(".dots/zshrc"), "managed")?;
+        fs::write(temp.join("elsewhere"), "unmanaged")?;
+
+        let dot = |target: &str| Dot {
+            source: PathBuf::from("zshrc"),
+            target: temp.join(target),
+            ..Default::default()
+        };
+
+        dot("linked").symlink(&dotfiles)?;
+        std::os::unix::fs::symlink(dotfiles.join(".dots/missing"), temp.join("broken"))?;
+        std::os::unix::fs::symlink(temp.join("elsewhere"), temp.join("unexpected"))?;
+
+        // Act & Assert
+        assert_eq!(dot("linked").link_status(&dotfiles), LinkStatus::Linked);
+        assert_eq!(dot("missing").link_status(&dotfiles), LinkStatus::Unlinked);
+        assert_eq!(dot("broken").link_status(&dotfiles), LinkStatus::Broken);
+        assert_eq!(
+            dot("unexpected").link_status(&dotfiles),
+            LinkStatus::Unexpected(Some(temp.join("elsewhere")))
+        );
+        assert_eq!(
+            dot("elsewhere").link_status(&dotfiles),
+            LinkStatus::Unexpected(None)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_inject_provenance_header() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.join("dotfiles");
+        fs::create_dir_all(dotfiles.join("scripts"))?;
+        fs::write(dotfiles.join("scripts/run.sh"), "#!/bin/sh\necho hello\n")?;
+        fs::write(dotfiles.join("scripts/style.css"), "body {}\n")?;
+        fs::write(dotfiles.join("scripts/data.json"), "{}\n")?;
+        fs::write(dotfiles.join("scripts/init.lua"), "vim.o.number = true\n")?;
+
+        let mut dot = Dot {
+            source: PathBuf::from("scripts"),
+            target: temp.join("scripts"),
+            ..Default::default()
+        };
+
+        let mut vars = Variables {
+            provenance_header: true,
+            ..Default::default()
+        };
+        vars.variables
+            .insert("bombadil.profiles".to_string(), "sway,work".to_string());
+
+        // Act
+        dot.traverse_and_copy(
+            &dotfiles.join("scripts"),
+            &dot.copy_path(&dotfiles),
+            &[],
+            &vars,
+        )?;
+
+        // Assert
+        let copy = dotfiles.join(".dots/scripts");
+        let script = fs::read_to_string(copy.join("run.sh"))?;
+        let mut lines = script.lines();
+        assert_eq!(lines.next(), Some("#!/bin/sh"));
+        let header = lines.next().unwrap();
+        assert!(header.starts_with(&format!(
+            "# Generated by bombadil from {} with profiles [sway, work] on ",
+            dotfiles.join("scripts/run.sh").display()
+        )));
+        assert!(header.contains(", do not edit sha256:"));
+        assert_eq!(lines.next(), Some("echo hello"));
+
+        let css = fs::read_to_string(copy.join("style.css"))?;
+        assert!(css.starts_with("/* Generated by bombadil from "));
+        assert!(css.ends_with(" */\nbody {}\n"));
+
+        assert_eq!(fs::read_to_string(copy.join("data.json"))?, "{}\n");
+
+        // An empty comment syntax disables the header
+        dot.comment = Some("".to_string());
+        dot.traverse_and_copy(
+            &dotfiles.join("scripts"),
+            &dot.copy_path(&dotfiles),
+            &[],
+            &vars,
+        )?;
+        assert_eq!(
+            fs::read_to_string(copy.join("init.lua"))?,
+            "vim.o.number = true\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_and_adopt_edits() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.join("dotfiles");
+        fs::create_dir_all(dotfiles.join("sway"))?;
+        fs::write(dotfiles.join("sway/config"), "bar top\n")?;
+        fs::write(dotfiles.join("sway/theme"), "color __[red]__\n")?;
+
+        let dot = Dot {
+            source: PathBuf::from("sway"),
+            target: temp.join("sway"),
+            ..Default::default()
+        };
+
+        let mut vars = Variables {
+            provenance_header: true,
+            ..Default::default()
+        };
+        vars.insert("red", "#ff0000");
+        dot.traverse_and_copy(
+            &dotfiles.join("sway"),
+            &dot.copy_path(&dotfiles),
+            &[],
+            &vars,
+        )?;
+        let untouched = dot.edited_files(&dotfiles);
+
+        // Act
+        let copy = dotfiles.join(".dots/sway");
+        for file in ["config", "theme"] {
+            let content = fs::read_to_string(copy.join(file))?;
+            fs::write(
+                copy.join(file),
+                content.replace("top", "bottom").replace("ff", "00"),
+            )?;
+        }
+        let edited = dot.edited_files(&dotfiles);
+        let adopted = dot.adopt_changes(&dotfiles)?;
+
+        // Assert
+        assert!(untouched.is_empty());
+        assert_eq!(edited, vec![copy.join("config"), copy.join("theme")]);
+        assert_eq!(adopted, vec![dotfiles.join("sway/config")]);
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("sway/config"))?,
+            "bar bottom\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("sway/theme"))?,
+            "color __[red]__\n"
+        );
+        assert_eq!(dot.edited_files(&dotfiles), vec![copy.join("theme")]);
+        Ok(())
+    }
 }
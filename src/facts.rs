@@ -0,0 +1,460 @@
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Information about the machine bombadil is running on, used to
+/// automatically enable the profiles matching it.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct Facts {
+    pub hostname: Option<String>,
+    /// Operating system, as reported by [`std::env::consts::OS`]
+    pub os: Option<String>,
+    /// Linux distribution `ID` found in `os-release`
+    pub distro: Option<String>,
+    /// Desktop session, lowercase, ex: "sway", "hyprland", "gnome" or "kde"
+    pub session: Option<String>,
+    /// Display server of the session, "wayland" or "x11"
+    pub session_type: Option<String>,
+    /// Locale of the session without its encoding, ex: "fr_FR" for "fr_FR.UTF-8"
+    pub locale: Option<String>,
+    /// Keyboard layouts, ex: ["us", "fr"], the first one is the default layout
+    pub keyboard_layouts: Vec<String>,
+}
+
+impl Facts {
+    /// Collect facts about the current machine
+    pub(crate) fn gather() -> Self {
+        Facts {
+            hostname: hostname(),
+            os: Some(std::env::consts::OS.to_string()),
+            distro: distro(),
+            session: session(|var| env::var(var).ok()),
+            session_type: session_type(|var| env::var(var).ok()),
+            locale: locale(|var| env::var(var).ok()),
+            keyboard_layouts: keyboard_layouts(|var| env::var(var).ok()),
+        }
+    }
+
+    /// The desktop session when known, the display server otherwise
+    pub(crate) fn session_name(&self) -> Option<&str> {
+        self.session.as_deref().or(self.session_type.as_deref())
+    }
+}
+
+/// The Termux installation prefix (`$PREFIX`) when running inside Termux on Android
+pub(crate) fn termux_prefix() -> Option<PathBuf> {
+    env::var("PREFIX")
+        .ok()
+        .filter(|prefix| prefix.contains("com.termux"))
+        .or_else(|| {
+            env::var("TERMUX_VERSION")
+                .ok()
+                .map(|_| "/data/data/com.termux/files/usr".to_string())
+        })
+        .map(PathBuf::from)
+}
+
+/// Termux does not have the standard `/etc` and `/usr` hierarchies, they live under `$PREFIX`
+pub(crate) fn termux_path(prefix: &Path, path: &Path) -> PathBuf {
+    if path.starts_with(prefix) {
+        path.to_path_buf()
+    } else if let Ok(relative) = path.strip_prefix("/usr") {
+        prefix.join(relative)
+    } else if let Ok(relative) = path.strip_prefix("/etc") {
+        prefix.join("etc").join(relative)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Whether bombadil runs under the Windows Subsystem for Linux
+pub(crate) fn is_wsl() -> bool {
+    env::var("WSL_DISTRO_NAME").is_ok()
+        || fs::read_to_string("/proc/sys/kernel/osrelease")
+            .map(|release| release.to_lowercase().contains("microsoft"))
+            .unwrap_or(false)
+}
+
+/// The Windows user profile directory seen from WSL, `/mnt/c/Users/<user>` for instance.
+/// Starting cmd.exe takes a while, it is only asked once, by the first dot or template using it
+pub(crate) fn wsl_windows_home() -> Option<PathBuf> {
+    static WSL_WINDOWS_HOME: OnceLock<Option<PathBuf>> = OnceLock::new();
+    WSL_WINDOWS_HOME
+        .get_or_init(|| {
+            if !is_wsl() {
+                return None;
+            }
+
+            // cmd.exe complains when started from a directory outside of the Windows filesystem
+            Command::new("cmd.exe")
+                .args(["/c", "echo %USERPROFILE%"])
+                .current_dir("/mnt/c")
+                .output()
+                .ok()
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+                .and_then(|profile| windows_to_wsl_path(profile.trim()))
+        })
+        .clone()
+}
+
+/// Whether `path` lives on a Windows drive mounted by WSL (`/mnt/c/...`)
+pub(crate) fn on_windows_drive(path: &Path) -> bool {
+    let mut components = path.components();
+    matches!(
+        (components.next(), components.next(), components.next()),
+        (Some(Component::RootDir), Some(Component::Normal(mnt)), Some(Component::Normal(drive)))
+            if mnt == "mnt" && drive.len() == 1 && drive.to_string_lossy().chars().all(|c| c.is_ascii_alphabetic())
+    )
+}
+
+/// Convert a Windows path (`C:\Users\me`) to its WSL mount point (`/mnt/c/Users/me`)
+fn windows_to_wsl_path(path: &str) -> Option<PathBuf> {
+    let drive = path.chars().next().filter(char::is_ascii_alphabetic)?;
+    let rest = path[1..].strip_prefix(':')?;
+
+    Some(PathBuf::from(format!(
+        "/mnt/{}{}",
+        drive.to_ascii_lowercase(),
+        rest.replace('\\', "/")
+    )))
+}
+
+/// Replace the `%NAME%` Windows environment variables of a dot target, ex: `%APPDATA%/alacritty`,
+/// unknown variables are left untouched
+pub(crate) fn expand_windows_vars(path: &Path, var: impl Fn(&str) -> Option<String>) -> PathBuf {
+    let path_str = match path.to_str() {
+        Some(path_str) if path_str.contains('%') => path_str,
+        _ => return path.to_path_buf(),
+    };
+
+    let mut expanded = String::new();
+    let mut rest = path_str;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let end = match after.find('%') {
+            Some(end) => end,
+            None => break,
+        };
+
+        match var(&after[..end]).filter(|_| end > 0) {
+            Some(value) => {
+                expanded.push_str(&rest[..start]);
+                expanded.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push_str(&rest[..=start]);
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+
+    PathBuf::from(expanded)
+}
+
+fn distro() -> Option<String> {
+    if termux_prefix().is_some() {
+        return Some("termux".to_string());
+    }
+
+    let os_release = fs::read_to_string("/etc/os-release")
+        .or_else(|_| fs::read_to_string("/usr/lib/os-release"))
+        .ok()?;
+
+    os_release_id(&os_release)
+}
+
+fn os_release_id(os_release: &str) -> Option<String> {
+    os_release
+        .lines()
+        .filter_map(|line| line.strip_prefix("ID="))
+        .map(|id| id.trim().trim_matches('"').trim_matches('\'').to_string())
+        .find(|id| !id.is_empty())
+}
+
+/// Compositors exporting their own socket variable are trusted first, then the
+/// `XDG_CURRENT_DESKTOP` list set by display managers, ex: "ubuntu:GNOME" gives "gnome"
+fn session(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    if var("SWAYSOCK").is_some() {
+        return Some("sway".to_string());
+    }
+
+    if var("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        return Some("hyprland".to_string());
+    }
+
+    [
+        "XDG_CURRENT_DESKTOP",
+        "XDG_SESSION_DESKTOP",
+        "DESKTOP_SESSION",
+    ]
+    .iter()
+    .filter_map(|name| var(name))
+    .filter_map(|desktop| desktop.rsplit(':').next().map(str::to_lowercase))
+    .map(|desktop| desktop.trim().to_string())
+    .find(|desktop| !desktop.is_empty())
+}
+
+fn session_type(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    match var("XDG_SESSION_TYPE").as_deref() {
+        Some(session_type @ ("wayland" | "x11")) => Some(session_type.to_string()),
+        _ if var("WAYLAND_DISPLAY").is_some() => Some("wayland".to_string()),
+        _ if var("DISPLAY").is_some() => Some("x11".to_string()),
+        _ => None,
+    }
+}
+
+/// The locale used for the character classification, like the C library picks it
+fn locale(var: impl Fn(&str) -> Option<String>) -> Option<String> {
+    ["LC_ALL", "LC_CTYPE", "LANG"]
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|locale| !locale.is_empty())
+        .and_then(|locale| locale.split(['.', '@']).next().map(str::to_string))
+        .filter(|locale| !locale.is_empty())
+}
+
+/// Layouts set for the compositor with `$XKB_DEFAULT_LAYOUT`, then those of the X11 session,
+/// then the system keyboard configuration written by `localectl` or Debian's `keyboard-configuration`
+fn keyboard_layouts(var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let setxkbmap = || {
+        Command::new("setxkbmap")
+            .arg("-query")
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+    };
+
+    var("XKB_DEFAULT_LAYOUT")
+        .or_else(|| {
+            var("DISPLAY")
+                .and_then(|_| setxkbmap())
+                .and_then(|query| layouts_in(&query))
+        })
+        .or_else(|| {
+            [
+                "/etc/default/keyboard",
+                "/etc/X11/xorg.conf.d/00-keyboard.conf",
+            ]
+            .iter()
+            .filter_map(|path| fs::read_to_string(path).ok())
+            .find_map(|config| layouts_in(&config))
+        })
+        .map(|layouts| {
+            layouts
+                .split(',')
+                .map(|layout| layout.trim().to_string())
+                .filter(|layout| !layout.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The layouts of `setxkbmap -query` (`layout: us,fr`), `/etc/default/keyboard`
+/// (`XKBLAYOUT="us,fr"`) or an xorg keyboard section (`Option "XkbLayout" "us,fr"`)
+fn layouts_in(config: &str) -> Option<String> {
+    config.lines().map(str::trim).find_map(|line| {
+        let value = if let Some(value) = line.strip_prefix("layout:") {
+            value
+        } else if let Some(value) = line.strip_prefix("XKBLAYOUT=") {
+            value
+        } else {
+            line.strip_prefix("Option")?
+                .trim_start()
+                .strip_prefix("\"XkbLayout\"")?
+        };
+
+        Some(value.trim().trim_matches('"').to_string()).filter(|value| !value.is_empty())
+    })
+}
+
+fn hostname() -> Option<String> {
+    let command = || {
+        Command::new("hostname")
+            .output()
+            .ok()
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+    };
+
+    first_hostname(&[
+        &|| fs::read_to_string("/proc/sys/kernel/hostname").ok(),
+        &|| fs::read_to_string("/etc/hostname").ok(),
+        &command,
+    ])
+}
+
+/// The first hostname found by `sources`, tried in order until one is not blank
+fn first_hostname(sources: &[&dyn Fn() -> Option<String>]) -> Option<String> {
+    sources.iter().find_map(|source| {
+        source()
+            .map(|hostname| hostname.trim().to_string())
+            .filter(|hostname| !hostname.is_empty())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::facts::{
+        expand_windows_vars, first_hostname, keyboard_layouts, layouts_in, locale,
+        on_windows_drive, os_release_id, session, session_type, termux_path, windows_to_wsl_path,
+        Facts,
+    };
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn should_fall_back_to_the_next_hostname_source() {
+        let found = first_hostname(&[
+            &|| None,
+            &|| Some(" \n".to_string()),
+            &|| Some("laptop\n".to_string()),
+            &|| panic!("sources after the hostname are not tried"),
+        ]);
+        let missing = first_hostname(&[&|| None, &|| Some(String::new())]);
+
+        assert_eq!(found, Some("laptop".to_string()));
+        assert_eq!(missing, None);
+    }
+
+    #[test]
+    fn should_get_os() {
+        let facts = Facts::gather();
+
+        assert_eq!(facts.os, Some(std::env::consts::OS.to_string()));
+    }
+
+    #[test]
+    fn should_parse_os_release_id() {
+        let os_release = "NAME=\"Arch Linux\"\nID_LIKE=\"archlinux\"\nID=\"arch\"\n";
+
+        assert_eq!(os_release_id(os_release), Some("arch".to_string()));
+    }
+
+    #[test]
+    fn should_resolve_termux_paths() {
+        let prefix = Path::new("/data/data/com.termux/files/usr");
+
+        assert_eq!(
+            termux_path(prefix, Path::new("/etc/profile")),
+            PathBuf::from("/data/data/com.termux/files/usr/etc/profile")
+        );
+        assert_eq!(
+            termux_path(prefix, Path::new("/usr/share/fonts")),
+            PathBuf::from("/data/data/com.termux/files/usr/share/fonts")
+        );
+        assert_eq!(
+            termux_path(prefix, Path::new("/sdcard/config")),
+            PathBuf::from("/sdcard/config")
+        );
+    }
+
+    #[test]
+    fn should_convert_windows_paths() {
+        assert_eq!(
+            windows_to_wsl_path("C:\\Users\\tom"),
+            Some(PathBuf::from("/mnt/c/Users/tom"))
+        );
+        assert_eq!(windows_to_wsl_path("%USERPROFILE%"), None);
+    }
+
+    #[test]
+    fn should_detect_windows_drive() {
+        assert!(on_windows_drive(Path::new("/mnt/c/Users/tom/AppData")));
+        assert!(!on_windows_drive(Path::new("/mnt/wsl/shared")));
+        assert!(!on_windows_drive(Path::new("/home/tom/.config")));
+    }
+
+    #[test]
+    fn should_expand_windows_vars() {
+        // Arrange
+        let var = |name: &str| match name {
+            "APPDATA" => Some(r"C:\Users\tom\AppData\Roaming".to_string()),
+            _ => None,
+        };
+
+        // Act
+        let alacritty = expand_windows_vars(Path::new("%APPDATA%/alacritty"), var);
+        let unknown = expand_windows_vars(Path::new("50%/%UNKNOWN%/%APPDATA%"), var);
+
+        // Assert
+        assert_eq!(
+            alacritty,
+            PathBuf::from(r"C:\Users\tom\AppData\Roaming/alacritty")
+        );
+        assert_eq!(
+            unknown,
+            PathBuf::from(r"50%/%UNKNOWN%/C:\Users\tom\AppData\Roaming")
+        );
+        assert_eq!(
+            expand_windows_vars(Path::new(".config/sway"), var),
+            PathBuf::from(".config/sway")
+        );
+    }
+
+    #[test]
+    fn should_detect_desktop_session() {
+        // Arrange
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        // Act & Assert
+        assert_eq!(
+            session(env(&[("SWAYSOCK", "/run/user/1000/sway-ipc.sock")])),
+            Some("sway".to_string())
+        );
+        assert_eq!(
+            session(env(&[("XDG_CURRENT_DESKTOP", "ubuntu:GNOME")])),
+            Some("gnome".to_string())
+        );
+        assert_eq!(session(env(&[])), None);
+        assert_eq!(
+            session_type(env(&[("XDG_SESSION_TYPE", "tty"), ("DISPLAY", ":0")])),
+            Some("x11".to_string())
+        );
+        assert_eq!(
+            session_type(env(&[("XDG_SESSION_TYPE", "wayland")])),
+            Some("wayland".to_string())
+        );
+    }
+
+    #[test]
+    fn should_detect_locale_and_keyboard_layouts() {
+        // Arrange
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let setxkbmap = "rules:      evdev\nmodel:      pc105\nlayout:     us,fr\n";
+        let debian = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"de\"\nXKBVARIANT=\"\"\n";
+        let xorg = "Section \"InputClass\"\n        Option \"XkbLayout\" \"fr\"\nEndSection\n";
+
+        // Act & Assert
+        assert_eq!(
+            locale(env(&[("LC_ALL", ""), ("LANG", "fr_FR.UTF-8")])),
+            Some("fr_FR".to_string())
+        );
+        assert_eq!(
+            locale(env(&[("LC_CTYPE", "de_DE@euro")])),
+            Some("de_DE".to_string())
+        );
+        assert_eq!(locale(env(&[])), None);
+        assert_eq!(
+            keyboard_layouts(env(&[("XKB_DEFAULT_LAYOUT", "us, de")])),
+            vec!["us", "de"]
+        );
+        assert_eq!(layouts_in(setxkbmap), Some("us,fr".to_string()));
+        assert_eq!(layouts_in(debian), Some("de".to_string()));
+        assert_eq!(layouts_in(xorg), Some("fr".to_string()));
+        assert_eq!(layouts_in("XKBLAYOUT=\"\""), None);
+    }
+}
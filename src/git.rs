@@ -1,6 +1,10 @@
 use git2::build::{CheckoutBuilder, RepoBuilder};
-use git2::{FetchOptions, Progress, RemoteCallbacks};
+use git2::{
+    BranchType, FetchOptions, IndexAddOption, Oid, Progress, PushOptions, RemoteCallbacks,
+    Repository, Status, StatusOptions, SubmoduleIgnore, SubmoduleStatus, SubmoduleUpdateOptions,
+};
 use std::cell::RefCell;
+use std::fmt;
 use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -17,11 +21,7 @@ fn print_clone_progress(state: &mut State) {
     let stats = state.progress.as_ref().unwrap();
     let network_pct = (100 * stats.received_objects()) / stats.total_objects();
     let index_pct = (100 * stats.indexed_objects()) / stats.total_objects();
-    let co_pct = if state.total > 0 {
-        (100 * state.current) / state.total
-    } else {
-        0
-    };
+    let co_pct = (100 * state.current).checked_div(state.total).unwrap_or(0);
     let kbytes = stats.received_bytes() / 1024;
     if stats.received_objects() == stats.total_objects() {
         if !state.newline {
@@ -70,7 +70,7 @@ pub(crate) fn clone(remote: &str, path: &Path) -> Result<(), git2::Error> {
     cb.transfer_progress(|stats| {
         let mut state = state.borrow_mut();
         state.progress = Some(stats.to_owned());
-        print_clone_progress(&mut *state);
+        print_clone_progress(&mut state);
         true
     });
 
@@ -80,7 +80,7 @@ pub(crate) fn clone(remote: &str, path: &Path) -> Result<(), git2::Error> {
         state.path = path.map(|p| p.to_path_buf());
         state.current = cur;
         state.total = total;
-        print_clone_progress(&mut *state);
+        print_clone_progress(&mut state);
     });
 
     let mut fo = FetchOptions::new();
@@ -109,3 +109,634 @@ fn git_credentials_callback(
 
     Err(git2::Error::from_str("no credential option available"))
 }
+
+/// Commit the changes of `paths`, or of the whole work tree if `paths` is empty, to the
+/// repository containing `repo_path`. Returns `None` if there was nothing to commit.
+pub(crate) fn commit(
+    repo_path: &Path,
+    paths: &[PathBuf],
+    message: &str,
+) -> Result<Option<Oid>, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| git2::Error::from_str("bare repositories are not supported"))?
+        .to_path_buf();
+
+    let pathspecs: Vec<String> = if paths.is_empty() {
+        vec!["*".to_string()]
+    } else {
+        paths
+            .iter()
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()))
+            .map(|path| {
+                path.strip_prefix(&workdir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path)
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect()
+    };
+
+    let mut index = repo.index()?;
+    index.add_all(pathspecs.iter(), IndexAddOption::DEFAULT, None)?;
+    index.update_all(pathspecs.iter(), None)?;
+    index.write()?;
+
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let parent = match repo.head() {
+        Ok(head) => Some(head.peel_to_commit()?),
+        Err(_) => None,
+    };
+
+    if parent.as_ref().map(|parent| parent.tree_id()) == Some(tree.id()) {
+        return Ok(None);
+    }
+
+    let signature = repo.signature()?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+    repo.commit(
+        Some("HEAD"),
+        &signature,
+        &signature,
+        message,
+        &tree,
+        &parents,
+    )
+    .map(Some)
+}
+
+/// Push the current branch to `origin`
+pub(crate) fn push(repo_path: &Path) -> Result<(), git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let head = repo.head()?;
+    let branch = head
+        .name()
+        .ok_or_else(|| git2::Error::from_str("HEAD is not a branch"))?;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(git_credentials_callback);
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+
+    let mut remote = repo.find_remote("origin")?;
+    remote.push(&[format!("{}:{}", branch, branch)], Some(&mut options))
+}
+
+/// Fetch the upstream of the current branch and rebase the local commits on top of it,
+/// like `git pull --rebase`. Returns the files changed by the update.
+pub(crate) fn pull_rebase(repo_path: &Path) -> Result<Vec<PathBuf>, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = workdir(&repo)?;
+    let head_name = fetch(&repo)?;
+    let previous = repo.head()?.peel_to_commit()?;
+
+    let upstream = upstream_reference(&repo)?;
+    let upstream = repo.reference_to_annotated_commit(&upstream)?;
+    let (analysis, _) = repo.merge_analysis(&[&upstream])?;
+
+    if analysis.is_up_to_date() {
+        return Ok(vec![]);
+    }
+
+    if analysis.is_fast_forward() {
+        let target = repo.find_commit(upstream.id())?;
+        repo.checkout_tree(target.as_object(), Some(CheckoutBuilder::new().safe()))?;
+        repo.find_reference(&head_name)?
+            .set_target(target.id(), "bombadil: fast-forward")?;
+    } else {
+        let signature = repo.signature()?;
+        let mut rebase = repo.rebase(None, Some(&upstream), None, None)?;
+        while let Some(operation) = rebase.next() {
+            operation?;
+            if repo.index()?.has_conflicts() {
+                rebase.abort()?;
+                return Err(git2::Error::from_str(
+                    "conflicts while rebasing local commits, resolve them with `git pull --rebase`",
+                ));
+            }
+            rebase.commit(None, &signature, None)?;
+        }
+        rebase.finish(Some(&signature))?;
+    }
+
+    let current = repo.head()?.peel_to_commit()?;
+    changed_files(&repo, &workdir, &previous, &current)
+}
+
+/// Commits of the upstream branch missing from the current branch, see [`fetch_upstream`]
+#[derive(Debug, PartialEq)]
+pub(crate) struct UpstreamChanges {
+    /// Id of the last upstream commit
+    pub head: String,
+    /// Summary of the new commits, newest first
+    pub commits: Vec<String>,
+    /// Files changed by the new commits
+    pub files: Vec<PathBuf>,
+}
+
+/// Fetch the upstream of the current branch without changing the work tree, and list the
+/// commits the current branch does not have yet
+pub(crate) fn fetch_upstream(repo_path: &Path) -> Result<UpstreamChanges, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = workdir(&repo)?;
+    fetch(&repo)?;
+
+    let head = repo.head()?.peel_to_commit()?;
+    let upstream = upstream_reference(&repo)?.peel_to_commit()?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(upstream.id())?;
+    revwalk.hide(head.id())?;
+    let commits = revwalk
+        .filter_map(Result::ok)
+        .filter_map(|id| repo.find_commit(id).ok())
+        .map(|commit| commit.summary().unwrap_or_default().to_string())
+        .collect::<Vec<_>>();
+
+    let files = match commits.is_empty() {
+        true => vec![],
+        false => {
+            let base = repo.find_commit(repo.merge_base(head.id(), upstream.id())?)?;
+            changed_files(&repo, &workdir, &base, &upstream)?
+        }
+    };
+
+    Ok(UpstreamChanges {
+        head: upstream.id().to_string(),
+        commits,
+        files,
+    })
+}
+
+fn workdir(repo: &Repository) -> Result<PathBuf, git2::Error> {
+    repo.workdir()
+        .map(Path::to_path_buf)
+        .ok_or_else(|| git2::Error::from_str("bare repositories are not supported"))
+}
+
+/// Fetch the upstream of the current branch, returns the name of the branch reference
+fn fetch(repo: &Repository) -> Result<String, git2::Error> {
+    let head = repo.head()?;
+    let head_name = head
+        .name()
+        .ok_or_else(|| git2::Error::from_str("HEAD is not a branch"))?
+        .to_string();
+    let branch_name = head.shorthand().unwrap_or_default().to_string();
+
+    let remote_name = repo.branch_upstream_remote(&head_name)?;
+    let remote_name = remote_name
+        .as_str()
+        .ok_or_else(|| git2::Error::from_str("invalid remote name"))?;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(git_credentials_callback);
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    repo.find_remote(remote_name)?
+        .fetch(&[&branch_name], Some(&mut options), None)?;
+
+    Ok(head_name)
+}
+
+fn upstream_reference(repo: &Repository) -> Result<git2::Reference<'_>, git2::Error> {
+    let head = repo.head()?;
+    let branch_name = head.shorthand().unwrap_or_default();
+    Ok(repo
+        .find_branch(branch_name, BranchType::Local)?
+        .upstream()?
+        .into_reference())
+}
+
+/// Files of the work tree changed between the `from` and `to` commits, sorted
+fn changed_files(
+    repo: &Repository,
+    workdir: &Path,
+    from: &git2::Commit,
+    to: &git2::Commit,
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let diff = repo.diff_tree_to_tree(Some(&from.tree()?), Some(&to.tree()?), None)?;
+    let mut files: Vec<PathBuf> = diff
+        .deltas()
+        .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+        .flatten()
+        .map(|path| workdir.join(path))
+        .collect();
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// State of a submodule work tree compared to the commit recorded in the dotfiles repository
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SubmoduleState {
+    /// Not cloned yet
+    Uninitialized,
+    UpToDate,
+    /// Checked out at another commit than the recorded one
+    Outdated,
+    /// Files changed inside the submodule work tree
+    Modified,
+}
+
+impl fmt::Display for SubmoduleState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            SubmoduleState::Uninitialized => "not initialized, run `bombadil update`",
+            SubmoduleState::UpToDate => "up to date",
+            SubmoduleState::Outdated => "not at the recorded commit, run `bombadil update`",
+            SubmoduleState::Modified => "local changes",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+/// Submodules of the repository containing `repo_path`, with their work tree path
+pub(crate) fn submodule_states(
+    repo_path: &Path,
+) -> Result<Vec<(PathBuf, SubmoduleState)>, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => return Ok(vec![]),
+    };
+
+    let mut states = vec![];
+    for submodule in repo.submodules()? {
+        let name = submodule
+            .name()
+            .ok_or_else(|| git2::Error::from_str("invalid submodule name"))?;
+        let status = repo.submodule_status(name, SubmoduleIgnore::None)?;
+
+        let state = if status.is_wd_uninitialized() || submodule.workdir_id().is_none() {
+            SubmoduleState::Uninitialized
+        } else if status.is_wd_modified() {
+            SubmoduleState::Outdated
+        } else if status.intersects(
+            SubmoduleStatus::WD_INDEX_MODIFIED
+                | SubmoduleStatus::WD_WD_MODIFIED
+                | SubmoduleStatus::WD_UNTRACKED,
+        ) {
+            SubmoduleState::Modified
+        } else {
+            SubmoduleState::UpToDate
+        };
+
+        states.push((workdir.join(submodule.path()), state));
+    }
+
+    Ok(states)
+}
+
+/// Initialize the submodules and check them out at their recorded commit, like
+/// `git submodule update --init`. Returns the files changed inside the submodules.
+pub(crate) fn update_submodules(repo_path: &Path) -> Result<Vec<PathBuf>, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => return Ok(vec![]),
+    };
+
+    let mut files = vec![];
+    for mut submodule in repo.submodules()? {
+        let previous = submodule.workdir_id();
+        if previous.is_some() && previous == submodule.index_id() {
+            continue;
+        }
+
+        let mut callbacks = RemoteCallbacks::new();
+        callbacks.credentials(git_credentials_callback);
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(callbacks);
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options);
+        submodule.update(true, Some(&mut options))?;
+
+        let submodule_repo = submodule.open()?;
+        let current = submodule_repo.head()?.peel_to_commit()?;
+        let previous = match previous {
+            Some(previous) => submodule_repo
+                .find_commit(previous)
+                .and_then(|commit| commit.tree())
+                .ok(),
+            None => None,
+        };
+
+        // A submodule cloned for the first time reports every file as changed
+        let diff =
+            submodule_repo.diff_tree_to_tree(previous.as_ref(), Some(&current.tree()?), None)?;
+        let submodule_dir = workdir.join(submodule.path());
+        files.extend(
+            diff.deltas()
+                .flat_map(|delta| [delta.old_file().path(), delta.new_file().path()])
+                .flatten()
+                .map(|path| submodule_dir.join(path)),
+        );
+    }
+
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// State of a git repository, as displayed by `bombadil doctor`
+#[derive(Debug, PartialEq)]
+pub(crate) struct RepoSummary {
+    pub branch: Option<String>,
+    pub changed: usize,
+    /// `None` without upstream branch
+    pub ahead_behind: Option<(usize, usize)>,
+}
+
+/// Current branch, number of changed files and commits ahead and behind the upstream
+/// of the git repository containing `repo_path`
+pub(crate) fn summary(repo_path: &Path) -> Result<RepoSummary, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let mut options = StatusOptions::new();
+    options.include_untracked(true).include_ignored(false);
+    let changed = repo.statuses(Some(&mut options))?.len();
+
+    let head = match repo.head() {
+        Ok(head) if head.is_branch() => head,
+        _ => {
+            return Ok(RepoSummary {
+                branch: None,
+                changed,
+                ahead_behind: None,
+            })
+        }
+    };
+
+    let branch = head.shorthand().map(str::to_string);
+    let ahead_behind = match (
+        head.target(),
+        branch
+            .as_ref()
+            .and_then(|branch| repo.find_branch(branch, BranchType::Local).ok())
+            .and_then(|branch| branch.upstream().ok())
+            .and_then(|upstream| upstream.get().target()),
+    ) {
+        (Some(local), Some(upstream)) => Some(repo.graph_ahead_behind(local, upstream)?),
+        _ => None,
+    };
+
+    Ok(RepoSummary {
+        branch,
+        changed,
+        ahead_behind,
+    })
+}
+
+/// Files under `paths` that are untracked or staged but never committed
+/// in the git repository containing `repo_path`
+pub(crate) fn uncommitted_files(
+    repo_path: &Path,
+    paths: &[PathBuf],
+) -> Result<Vec<PathBuf>, git2::Error> {
+    let repo = Repository::discover(repo_path)?;
+    let workdir = match repo.workdir() {
+        Some(workdir) => workdir.to_path_buf(),
+        None => return Ok(vec![]),
+    };
+
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(true)
+        .include_ignored(false);
+
+    let paths: Vec<PathBuf> = paths
+        .iter()
+        .filter_map(|path| path.canonicalize().ok())
+        .collect();
+
+    let mut files: Vec<PathBuf> = repo
+        .statuses(Some(&mut options))?
+        .iter()
+        .filter(|entry| {
+            entry
+                .status()
+                .intersects(Status::WT_NEW | Status::INDEX_NEW)
+        })
+        .filter_map(|entry| entry.path().map(|path| workdir.join(path)))
+        .filter(|file| paths.iter().any(|path| file.starts_with(path)))
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::git::{
+        commit, fetch_upstream, pull_rebase, submodule_states, summary, uncommitted_files,
+        update_submodules, SubmoduleState,
+    };
+    use anyhow::Result;
+    use git2::{Repository, Signature};
+    use std::fs;
+    use std::path::Path;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_find_uncommitted_files() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let repo_path = temp.canonicalize()?;
+        let repo = Repository::init(&repo_path)?;
+        fs::create_dir_all(repo_path.join("sway"))?;
+        fs::write(repo_path.join("sway/config"), "committed")?;
+        fs::write(repo_path.join("sway/local"), "untracked")?;
+        fs::write(repo_path.join("other"), "untracked outside of dots")?;
+
+        let mut index = repo.index()?;
+        index.add_path(Path::new("sway/config"))?;
+        index.write()?;
+        let tree = repo.find_tree(index.write_tree()?)?;
+        let signature = Signature::now("Tom Bombadil", "tom@bombadil.org")?;
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])?;
+
+        // Act
+        let files = uncommitted_files(&repo_path, &[repo_path.join("sway")])?;
+
+        // Assert
+        assert_eq!(files, vec![repo_path.join("sway/local")]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_commit_only_given_paths() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let repo_path = temp.canonicalize()?;
+        let repo = Repository::init(&repo_path)?;
+        let mut config = repo.config()?;
+        config.set_str("user.name", "Tom Bombadil")?;
+        config.set_str("user.email", "tom@bombadil.org")?;
+        fs::write(repo_path.join("vars.toml"), "pass = \"gpg:...\"")?;
+        fs::write(repo_path.join("scratch"), "not committed")?;
+
+        // Act
+        let first = commit(&repo_path, &[repo_path.join("vars.toml")], "add secret")?;
+        let second = commit(&repo_path, &[repo_path.join("vars.toml")], "nothing")?;
+
+        // Assert
+        assert!(first.is_some());
+        assert!(second.is_none());
+        let head = repo.head()?.peel_to_commit()?;
+        assert_eq!(head.message(), Some("add secret"));
+        assert!(head.tree()?.get_name("scratch").is_none());
+        Ok(())
+    }
+
+    fn configure_identity(repo: &Repository) -> Result<()> {
+        let mut config = repo.config()?;
+        config.set_str("user.name", "Tom Bombadil")?;
+        config.set_str("user.email", "tom@bombadil.org")?;
+        Ok(())
+    }
+
+    #[test]
+    fn should_pull_and_rebase_local_commits() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let upstream_path = temp.join("upstream");
+        let upstream = Repository::init(&upstream_path)?;
+        configure_identity(&upstream)?;
+        fs::write(upstream_path.join("zshrc"), "v1")?;
+        commit(&upstream_path, &[], "init")?;
+
+        let local_path = temp.join("local");
+        let local = Repository::clone(upstream_path.to_str().unwrap(), &local_path)?;
+        configure_identity(&local)?;
+        fs::write(local_path.join("local_only"), "local")?;
+        commit(&local_path, &[], "local change")?;
+
+        fs::write(upstream_path.join("zshrc"), "v2")?;
+        commit(&upstream_path, &[], "update zshrc")?;
+
+        // Act
+        let changed = pull_rebase(&local_path)?;
+
+        // Assert
+        assert_eq!(changed, vec![local_path.join("zshrc")]);
+        assert_eq!(fs::read_to_string(local_path.join("zshrc"))?, "v2");
+        let head = local.head()?.peel_to_commit()?;
+        assert_eq!(head.message(), Some("local change"));
+        assert_eq!(head.parent(0)?.message(), Some("update zshrc"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_fetch_upstream_commits_without_changing_the_work_tree() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let upstream_path = temp.join("upstream");
+        let upstream = Repository::init(&upstream_path)?;
+        configure_identity(&upstream)?;
+        fs::write(upstream_path.join("zshrc"), "v1")?;
+        commit(&upstream_path, &[], "init")?;
+
+        let local_path = temp.join("local");
+        Repository::clone(upstream_path.to_str().unwrap(), &local_path)?;
+        fs::create_dir_all(upstream_path.join("sway"))?;
+        fs::write(upstream_path.join("sway/config"), "bar")?;
+        commit(&upstream_path, &[], "add sway")?;
+        fs::write(upstream_path.join("zshrc"), "v2")?;
+        commit(&upstream_path, &[], "update zshrc")?;
+
+        // Act
+        let changes = fetch_upstream(&local_path)?;
+        let zshrc = fs::read_to_string(local_path.join("zshrc"))?;
+        pull_rebase(&local_path)?;
+        let up_to_date = fetch_upstream(&local_path)?;
+
+        // Assert
+        assert_eq!(changes.commits, vec!["update zshrc", "add sway"]);
+        assert_eq!(
+            changes.files,
+            vec![local_path.join("sway/config"), local_path.join("zshrc")]
+        );
+        assert_eq!(
+            changes.head,
+            upstream.head()?.peel_to_commit()?.id().to_string()
+        );
+        assert_eq!(zshrc, "v1");
+        assert!(up_to_date.commits.is_empty());
+        assert!(up_to_date.files.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_update_and_report_submodules() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let theme_path = temp.join("theme");
+        let theme = Repository::init(&theme_path)?;
+        configure_identity(&theme)?;
+        fs::write(theme_path.join("colors"), "dark")?;
+        commit(&theme_path, &[], "init theme")?;
+
+        let upstream_path = temp.join("upstream");
+        let upstream = Repository::init(&upstream_path)?;
+        configure_identity(&upstream)?;
+        let mut submodule =
+            upstream.submodule(theme_path.to_str().unwrap(), Path::new("theme"), true)?;
+        submodule.clone(None)?;
+        submodule.add_finalize()?;
+        commit(&upstream_path, &[], "add theme")?;
+
+        let local_path = temp.join("local");
+        Repository::clone(upstream_path.to_str().unwrap(), &local_path)?;
+        let before = submodule_states(&local_path)?;
+
+        // Act
+        let changed = update_submodules(&local_path)?;
+
+        // Assert
+        assert_eq!(
+            before,
+            vec![(local_path.join("theme"), SubmoduleState::Uninitialized)]
+        );
+        assert_eq!(changed, vec![local_path.join("theme/colors")]);
+        assert_eq!(fs::read_to_string(local_path.join("theme/colors"))?, "dark");
+        assert_eq!(
+            submodule_states(&local_path)?,
+            vec![(local_path.join("theme"), SubmoduleState::UpToDate)]
+        );
+        assert!(update_submodules(&local_path)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_summarize_repository_state() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let upstream_path = temp.join("upstream");
+        let upstream = Repository::init(&upstream_path)?;
+        configure_identity(&upstream)?;
+        fs::write(upstream_path.join("zshrc"), "v1")?;
+        commit(&upstream_path, &[], "init")?;
+
+        let local_path = temp.join("local");
+        let local = Repository::clone(upstream_path.to_str().unwrap(), &local_path)?;
+        configure_identity(&local)?;
+        fs::write(local_path.join("local_only"), "local")?;
+        commit(&local_path, &[], "local change")?;
+        fs::write(local_path.join("zshrc"), "dirty")?;
+
+        // Act
+        let summary = summary(&local_path)?;
+
+        // Assert
+        assert_eq!(summary.changed, 1);
+        assert_eq!(summary.ahead_behind, Some((1, 0)));
+        assert!(summary.branch.is_some());
+        Ok(())
+    }
+}
@@ -1,30 +1,102 @@
-use crate::templating::Variables;
+use crate::facts::termux_prefix;
+use crate::templating::{self, VarFormat, Variables};
 use anyhow::Result;
+use colored::Colorize;
+use std::env;
+use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::sync::OnceLock;
 
 const PGP_HEADER: &str = "-----BEGIN PGP MESSAGE-----\n\n";
 const PGP_FOOTER: &str = "\n-----END PGP MESSAGE-----";
 pub(crate) const GPG_PREFIX: &str = "gpg:";
 
+/// Words of the pinentry programs drawing a window, they can't prompt over SSH without display
+const GUI_PINENTRIES: [&str; 7] = ["gtk", "qt", "gnome", "x11", "fltk", "mac", "w32"];
+
+#[derive(Clone)]
 pub struct Gpg {
     pub user_id: String,
+    /// Detected on the first decryption, see [`pinentry_for`]
+    prompt: OnceLock<Prompt>,
+}
+
+/// Where gpg asks for the passphrase in this session
+#[derive(Debug, Clone)]
+struct Prompt {
+    pinentry: Pinentry,
+    /// Terminal of the SSH session, passed to gpg and gpg-agent as `GPG_TTY`
+    tty: Option<String>,
+}
+
+/// How gpg asks for the passphrase of the key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Pinentry {
+    /// The pinentry program of gpg-agent
+    Agent,
+    /// gpg prompts on the terminal itself, the pinentry of gpg-agent needs a display
+    Loopback,
+    /// Fail instead of prompting, there is no terminal to prompt in
+    Never,
 }
 
 impl Gpg {
     pub(crate) fn new(user_id: &str) -> Self {
         Gpg {
             user_id: user_id.to_string(),
+            prompt: OnceLock::new(),
         }
     }
 
+    fn prompt(&self) -> &Prompt {
+        self.prompt.get_or_init(detect_prompt)
+    }
+
+    /// Fail with the commands to fix it when gpg can't decrypt `secrets`, the names of the
+    /// encrypted variables
+    pub(crate) fn check_available(&self, secrets: &[String]) -> Result<()> {
+        let problem = match status(&self.user_id) {
+            GpgStatus::Ready(_) => return Ok(()),
+            GpgStatus::NotInstalled if termux_prefix().is_some() => {
+                "gpg is not installed, install it with `pkg install gnupg`".to_string()
+            }
+            GpgStatus::NotInstalled => {
+                "gpg is not installed, install gnupg with your package manager".to_string()
+            }
+            GpgStatus::MissingKey => format!(
+                "no secret key found for `{}`, import it with `gpg --import <private key file>` \
+                 and check it with `gpg --list-secret-keys {}`",
+                self.user_id, self.user_id
+            ),
+        };
+
+        Err(anyhow!(
+            "{} `{}` : {}\n{} {}\n{}",
+            "Unable to decrypt secrets with gpg_user_id".red(),
+            self.user_id,
+            problem,
+            "Secrets needing it :".red(),
+            secrets.join(", "),
+            "Nothing was changed, run `bombadil link --skip-secrets` to link the dots that don't use them"
+                .yellow()
+        ))
+    }
+
     pub(crate) fn push_secret<S: AsRef<Path> + ?Sized>(
         &self,
         key: &str,
         value: &str,
         var_file: &S,
     ) -> Result<()> {
+        if VarFormat::of(var_file.as_ref()) != VarFormat::Toml {
+            return Err(anyhow!(
+                "Secrets can only be added to toml var files, {:?} is not a toml file",
+                var_file.as_ref()
+            ));
+        }
+
         let mut vars = Variables::from_toml(var_file.as_ref(), Some(self))?;
         let encrypted = self.encrypt(value)?;
         let encrypted = encrypted.replace(PGP_HEADER, "");
@@ -34,8 +106,8 @@ impl Gpg {
         vars.insert(key, &encrypted);
 
         let toml = toml::to_string(&vars.variables)?;
-        std::fs::write(&var_file, toml)?;
-        println!("Added {} : {}", key, value);
+        std::fs::write(var_file, toml)?;
+        info!("Added {} : {}", key, value);
 
         Ok(())
     }
@@ -54,14 +126,11 @@ impl Gpg {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
-            .expect("error calling gpg command, is gpg installed ?");
-
-        {
-            let stdin = child.stdin.as_mut().unwrap();
-            stdin.write_all(content.as_bytes())?;
-        }
+            .map_err(gpg_spawn_error)?;
 
+        let written = child.stdin.as_mut().unwrap().write_all(content.as_bytes());
         let output = child.wait_with_output();
+        written?;
 
         match output {
             Ok(output) => {
@@ -76,7 +145,19 @@ impl Gpg {
     }
 
     fn decrypt(&self, content: &str) -> Result<String> {
-        let mut child = Command::new("gpg")
+        let prompt = self.prompt();
+        let pinentry = prompt.pinentry;
+        let mut command = Command::new("gpg");
+        if let Some(tty) = &prompt.tty {
+            command.env("GPG_TTY", tty);
+        }
+        match pinentry {
+            Pinentry::Agent => &mut command,
+            Pinentry::Loopback => command.args(["--pinentry-mode", "loopback"]),
+            Pinentry::Never => command.args(["--pinentry-mode", "error"]),
+        };
+
+        let mut child = command
             .arg("--decrypt")
             .arg("--armor")
             .arg("-r")
@@ -85,19 +166,33 @@ impl Gpg {
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()
-            .expect("error calling gpg command, is gpg installed ?");
-
-        {
-            let stdin = child.stdin.as_mut().unwrap();
-            stdin.write_all(content.as_bytes())?;
-        }
+            .map_err(gpg_spawn_error)?;
 
+        let written = child.stdin.as_mut().unwrap().write_all(content.as_bytes());
         let output = child.wait_with_output();
+        written?;
 
         match output {
             Ok(output) => {
                 if output.status.success() {
                     Ok(String::from_utf8(output.stdout).expect("Error decrypting content"))
+                } else if pinentry == Pinentry::Never {
+                    Err(anyhow!(
+                        "Error decrypting secret, gpg needs the passphrase of `{}` but this SSH \
+                         session has no terminal to ask it in. Connect with `ssh -t`, unlock the key \
+                         in gpg-agent beforehand, or link with `--skip-secrets`",
+                        self.user_id
+                    ))
+                } else if pinentry == Pinentry::Loopback {
+                    Err(anyhow!(
+                        "Error decrypting secret, the pinentry of gpg-agent needs a display so gpg \
+                         asked the passphrase on the terminal. Make sure `allow-loopback-pinentry` is \
+                         not disabled in gpg-agent.conf, or set `pinentry-program` to pinentry-curses"
+                    ))
+                } else if termux_prefix().is_some() {
+                    Err(anyhow!(
+                        "Error decrypting secret, make sure gpg-agent is running : `gpg-agent --daemon`"
+                    ))
                 } else {
                     Err(anyhow!(
                         String::from_utf8(output.stdout).expect("Error getting decrypting value")
@@ -109,9 +204,170 @@ impl Gpg {
     }
 }
 
+/// Whether gpg can decrypt the secrets of a `gpg_user_id`
+#[derive(Debug, PartialEq)]
+pub(crate) enum GpgStatus {
+    NotInstalled,
+    MissingKey,
+    /// The first line of `gpg --version`
+    Ready(String),
+}
+
+pub(crate) fn status(user_id: &str) -> GpgStatus {
+    let version = Command::new("gpg")
+        .arg("--version")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .output();
+    let version = match version {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .to_string(),
+        _ => return GpgStatus::NotInstalled,
+    };
+
+    let key = Command::new("gpg")
+        .arg("--list-secret-keys")
+        .arg(user_id)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    match key {
+        Ok(status) if status.success() => GpgStatus::Ready(version),
+        _ => GpgStatus::MissingKey,
+    }
+}
+
+/// Prepare the prompts of gpg for this session : over SSH gpg-agent is told to prompt on this
+/// terminal rather than the one it was started from
+fn detect_prompt() -> Prompt {
+    let remote = env::var_os("SSH_CONNECTION").is_some() || env::var_os("SSH_TTY").is_some();
+    if !remote {
+        return Prompt {
+            pinentry: Pinentry::Agent,
+            tty: None,
+        };
+    }
+
+    let tty = env::var("GPG_TTY")
+        .ok()
+        .filter(|tty| !tty.is_empty())
+        .or_else(terminal);
+    let display = ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|name| env::var_os(name).is_some_and(|value| !value.is_empty()));
+    let program = agent_config()
+        .and_then(|config| fs::read_to_string(config).ok())
+        .and_then(|config| pinentry_program(&config));
+
+    if let Some(tty) = &tty {
+        let updated = Command::new("gpg-connect-agent")
+            .env("GPG_TTY", tty)
+            .args(["updatestartuptty", "/bye"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        if let Err(err) = updated {
+            debug!("Unable to update the terminal of gpg-agent : {}", err);
+        }
+    }
+
+    let pinentry = pinentry_for(tty.is_some(), display, program.as_deref());
+    debug!(
+        "SSH session, terminal {:?}, display {}, pinentry program {:?} : {:?}",
+        tty, display, program, pinentry
+    );
+    if pinentry == Pinentry::Loopback {
+        info!("The pinentry of gpg-agent needs a display, gpg asks the passphrase on the terminal");
+    }
+    Prompt { pinentry, tty }
+}
+
+/// How gpg should prompt in an SSH session, given whether it has a terminal, a display and the
+/// `pinentry-program` of gpg-agent
+pub(crate) fn pinentry_for(tty: bool, display: bool, program: Option<&str>) -> Pinentry {
+    let needs_display = program
+        .and_then(|program| Path::new(program).file_name())
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .is_some_and(|name| GUI_PINENTRIES.iter().any(|gui| name.contains(gui)));
+
+    match (tty, display) {
+        (false, _) => Pinentry::Never,
+        (true, false) if needs_display => Pinentry::Loopback,
+        (true, _) => Pinentry::Agent,
+    }
+}
+
+/// The `pinentry-program` of a gpg-agent.conf
+fn pinentry_program(config: &str) -> Option<String> {
+    config
+        .lines()
+        .map(str::trim)
+        .find_map(|line| line.strip_prefix("pinentry-program"))
+        .map(|program| program.trim().to_string())
+        .filter(|program| !program.is_empty())
+}
+
+fn agent_config() -> Option<PathBuf> {
+    env::var_os("GNUPGHOME")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".gnupg")))
+        .map(|gnupg_home| gnupg_home.join("gpg-agent.conf"))
+}
+
+/// The terminal of stdin, if any
+#[cfg(unix)]
+fn terminal() -> Option<String> {
+    if unsafe { libc::isatty(0) } != 1 {
+        return None;
+    }
+
+    let name = unsafe { libc::ttyname(0) };
+    if name.is_null() {
+        return None;
+    }
+
+    unsafe { std::ffi::CStr::from_ptr(name) }
+        .to_str()
+        .ok()
+        .map(String::from)
+}
+
+#[cfg(not(unix))]
+fn terminal() -> Option<String> {
+    None
+}
+
+/// Names of the encrypted variables of a var file, missing or invalid files have none
+pub(crate) fn encrypted_vars(var_file: &Path) -> Vec<String> {
+    fs::read_to_string(var_file)
+        .ok()
+        .and_then(|content| templating::parse_vars(var_file, &content).ok())
+        .map(|variables| {
+            variables
+                .into_iter()
+                .filter(|(_, value)| value.starts_with(GPG_PREFIX))
+                .map(|(key, _)| key)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn gpg_spawn_error(err: std::io::Error) -> anyhow::Error {
+    let hint = if termux_prefix().is_some() {
+        "install it with `pkg install gnupg`"
+    } else {
+        "is gpg installed ?"
+    };
+
+    anyhow!("Error calling gpg command, {} : {}", hint, err)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::gpg::Gpg;
+    use crate::gpg::{pinentry_for, pinentry_program, Gpg, Pinentry};
     use anyhow::Result;
     use temp_testdir::TempDir;
     use toml::Value;
@@ -154,6 +410,29 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn should_pick_pinentry_for_ssh_sessions() {
+        // Arrange
+        let config = "default-cache-ttl 600\npinentry-program /usr/bin/pinentry-gnome3\n";
+        let gnome = pinentry_program(config);
+
+        // Act
+        let without_display = pinentry_for(true, false, gnome.as_deref());
+        let forwarded_display = pinentry_for(true, true, gnome.as_deref());
+        let curses = pinentry_for(true, false, Some("/usr/bin/pinentry-curses"));
+        let default = pinentry_for(true, false, None);
+        let without_tty = pinentry_for(false, false, None);
+
+        // Assert
+        assert_eq!(gnome.as_deref(), Some("/usr/bin/pinentry-gnome3"));
+        assert_eq!(without_display, Pinentry::Loopback);
+        assert_eq!(forwarded_display, Pinentry::Agent);
+        assert_eq!(curses, Pinentry::Agent);
+        assert_eq!(default, Pinentry::Agent);
+        assert_eq!(without_tty, Pinentry::Never);
+        assert_eq!(pinentry_program("# pinentry-program gtk\n"), None);
+    }
+
     #[test]
     fn should_push_to_var() -> Result<()> {
         let gpg = Gpg::new(GPG_ID);
@@ -1,29 +1,207 @@
+use crate::builtin;
+use crate::builtin::BUILTIN_PREFIX;
 use anyhow::Result;
 use colored::*;
-use std::io::{BufRead, BufReader};
+use std::fmt;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// File name of an inline script in the directory written by [`Hook::write_script`]
+const INLINE_SCRIPT: &str = "script";
+
+/// A hook as declared in bombadil.toml, either a command line, a built-in action
+/// like `builtin:reload-sway`, or an inline script run with the given interpreter :
+/// `{ interpreter = "python3", script = """...""" }`, or a script of the dotfiles directory :
+/// `{ script = "hooks/reload-sway.sh" }`. Commands run from another directory than `hook_cwd`
+/// are written `{ command = "make", cwd = "fonts" }`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum HookEntry {
+    Command(String),
+    Script {
+        interpreter: String,
+        script: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<PathBuf>,
+    },
+    ScriptFile {
+        script: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<PathBuf>,
+    },
+    Detailed {
+        command: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<PathBuf>,
+    },
+}
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
 pub struct Hook {
+    /// The command line, or the interpreter of an inline script
     pub command: String,
+    /// Inline script passed to `command` as a temporary file
+    #[serde(default)]
+    pub script: Option<String>,
+    /// Working directory of the hook, the directory bombadil runs from when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    /// Script file run instead of `command`, relative to the dotfiles directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script_file: Option<PathBuf>,
+}
+
+impl From<&HookEntry> for Hook {
+    fn from(entry: &HookEntry) -> Self {
+        match entry {
+            HookEntry::Command(command) => Hook::new(command),
+            HookEntry::Script {
+                interpreter,
+                script,
+                cwd,
+            } => Hook {
+                command: interpreter.to_owned(),
+                script: Some(script.to_owned()),
+                cwd: cwd.clone(),
+                script_file: None,
+            },
+            HookEntry::ScriptFile { script, cwd } => Hook {
+                cwd: cwd.clone(),
+                script_file: Some(script.clone()),
+                ..Hook::new(&script.display().to_string())
+            },
+            HookEntry::Detailed { command, cwd } => Hook {
+                cwd: cwd.clone(),
+                ..Hook::new(command)
+            },
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.script {
+            Some(_) => write!(f, "{} <inline script>", self.command),
+            None => write!(f, "{}", self.command),
+        }
+    }
 }
 
 impl Hook {
+    #[cfg(test)]
     pub(crate) fn run(&self) -> Result<()> {
-        let command_display = format!("`{}`", &self.command.green());
-        println!("Running install hook : {}", command_display);
+        self.run_with_env(&[])
+    }
+
+    /// Run the hook with the environment variables `env` set, built-in actions ignore them
+    pub(crate) fn run_with_env(&self, env: &[(&str, String)]) -> Result<()> {
+        let command_display = format!("`{}`", self.to_string().green());
+        info!("Running install hook : {}", command_display);
+
+        if let Some(script_file) = &self.script_file {
+            let args = script_file_args(script_file)?;
+            return self.spawn(
+                Hook::build_command(args.iter().map(String::as_str).collect()),
+                env,
+            );
+        }
+
+        match &self.script {
+            Some(script) => {
+                let script_dir = Hook::write_script(script)?;
+                let script_path = script_dir.join(INLINE_SCRIPT);
+                let mut args: Vec<&str> = self.command.split_whitespace().collect();
+                if args.is_empty() {
+                    return Err(anyhow!("Missing interpreter for inline script hook"));
+                }
+
+                let script_arg = script_path.to_string_lossy().to_string();
+                args.push(&script_arg);
+                let result = self.spawn(Hook::build_command(args), env);
+                let _ = fs::remove_dir_all(&script_dir);
+                result
+            }
+            None => {
+                if let Some(name) = self.command.trim().strip_prefix(BUILTIN_PREFIX) {
+                    return builtin::run(name);
+                }
+
+                let args = self.split_args()?;
+                self.spawn(Hook::build_command(args), env)
+            }
+        }
+    }
+
+    /// Write an inline script to a new directory only the user can access, in
+    /// `$XDG_RUNTIME_DIR` or the temp dir, and return the directory. Neither the directory nor
+    /// the script may exist already, so a link planted at their path is never followed.
+    fn write_script(script: &str) -> Result<PathBuf> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or_default();
+
+        let dir = dirs::runtime_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(format!("bombadil-hook-{}-{}", std::process::id(), nanos));
+
+        let mut dir_builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut dir_builder, 0o700);
+        dir_builder
+            .create(&dir)
+            .map_err(|err| anyhow!("Unable to create inline script dir {:?} : {}", dir, err))?;
+
+        let path = dir.join(INLINE_SCRIPT);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o700);
+        options
+            .open(&path)
+            .and_then(|mut file| file.write_all(script.as_bytes()))
+            .map_err(|err| {
+                let _ = fs::remove_dir_all(&dir);
+                anyhow!("Unable to write inline script {:?} : {}", path, err)
+            })?;
+        Ok(dir)
+    }
+
+    /// Run the hook from `dotfiles_dir` joined with its `cwd`, or with `default` when it has
+    /// none. Both are relative to the dotfiles directory or absolute, like its script file.
+    pub(crate) fn in_dir(mut self, dotfiles_dir: &Path, default: Option<&Path>) -> Self {
+        let cwd = self.cwd.as_deref().or(default).unwrap_or(Path::new(""));
+        self.cwd = Some(dotfiles_dir.join(cwd));
+        self.script_file = self
+            .script_file
+            .map(|script_file| dotfiles_dir.join(script_file));
+        self
+    }
 
-        let args = self.split_args()?;
-        let mut command = Hook::build_command(args);
+    fn spawn(&self, mut command: Command, env: &[(&str, String)]) -> Result<()> {
+        if let Some(cwd) = &self.cwd {
+            if !cwd.is_dir() {
+                return Err(anyhow!(
+                    "Unable to run hook `{}`, its working directory {:?} does not exist",
+                    self,
+                    cwd
+                ));
+            }
+            command.current_dir(cwd);
+        }
 
         let mut child = command
+            .envs(env.iter().map(|(name, value)| (name, value)))
             .stderr(Stdio::piped())
             .stdout(Stdio::piped())
             .spawn()?;
 
         BufReader::new(child.stdout.take().unwrap())
             .lines()
-            .for_each(|line| println!("{}", line.unwrap_or_else(|_| "".into())));
+            .for_each(|line| info!("{}", line.unwrap_or_else(|_| "".into())));
 
         BufReader::new(child.stderr.take().unwrap())
             .lines()
@@ -56,7 +234,7 @@ impl Hook {
             return Ok(self.command.split(' ').collect());
         }
 
-        if indices.len() % 2 != 0 {
+        if !indices.len().is_multiple_of(2) {
             return Err(anyhow!("Missing matching \\\""));
         }
 
@@ -79,20 +257,75 @@ impl Hook {
     }
     pub fn new(command: &str) -> Self {
         let command = command.to_owned();
-        Hook { command }
+        Hook {
+            command,
+            script: None,
+            cwd: None,
+            script_file: None,
+        }
+    }
+}
+
+/// The command line running `script_file` : the script itself when it is executable, otherwise
+/// the interpreter of its shebang, or `sh` without one. Repositories don't always keep the
+/// executable bit, on Windows for instance.
+fn script_file_args(script_file: &Path) -> Result<Vec<String>> {
+    let script = script_file.display().to_string();
+    if !script_file.is_file() {
+        return Err(anyhow!("Hook script {:?} not found", script_file));
+    }
+
+    if is_executable(script_file) {
+        return Ok(vec![script]);
+    }
+
+    let content = fs::read(script_file)
+        .map_err(|err| anyhow!("Unable to read hook script {:?} : {}", script_file, err))?;
+    let first_line =
+        String::from_utf8_lossy(content.split(|b| *b == b'\n').next().unwrap_or_default())
+            .to_string();
+    let mut args: Vec<String> = match first_line.strip_prefix("#!") {
+        Some(interpreter) => interpreter.split_whitespace().map(String::from).collect(),
+        None => vec![],
+    };
+    if args.is_empty() {
+        args.push("sh".to_string());
     }
+
+    debug!(
+        "Hook script {:?} is not executable, running it with `{}`",
+        script_file,
+        args.join(" ")
+    );
+    args.push(script);
+    Ok(args)
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::hook::Hook;
+    use crate::hook::{Hook, HookEntry, INLINE_SCRIPT};
+    use std::collections::HashMap;
+    use std::fs::{self, Permissions};
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use temp_testdir::TempDir;
 
     #[test]
     fn should_run_command() {
         // Arrange
-        let hook = Hook {
-            command: "echo hello world".to_string(),
-        };
+        let hook = Hook::new("echo hello world");
 
         // Act
         let result = hook.run();
@@ -104,9 +337,7 @@ mod tests {
     #[test]
     fn should_fail_to_run_invalid_command() {
         // Arrange
-        let hook = Hook {
-            command: "azmroih".to_string(),
-        };
+        let hook = Hook::new("azmroih");
 
         // Act
         let result = hook.run();
@@ -118,9 +349,7 @@ mod tests {
     #[test]
     fn should_split_args_with_quotes() {
         // Arrange
-        let hook = Hook {
-            command: "echo \"hello Tom\"".to_string(),
-        };
+        let hook = Hook::new("echo \"hello Tom\"");
 
         // Act
         let result = hook.split_args();
@@ -132,9 +361,7 @@ mod tests {
     #[test]
     fn should_split_args_with_multiple_quotes() {
         // Arrange
-        let hook = Hook {
-            command: "echo \"hello Tom\" || grep \"Toml\"".to_string(),
-        };
+        let hook = Hook::new("echo \"hello Tom\" || grep \"Toml\"");
 
         // Act
         let result = hook.split_args();
@@ -149,9 +376,7 @@ mod tests {
     #[test]
     fn should_split_args() {
         // Arrange
-        let hook = Hook {
-            command: "git commit -m init".to_string(),
-        };
+        let hook = Hook::new("git commit -m init");
 
         // Act
         let result = hook.split_args();
@@ -159,4 +384,100 @@ mod tests {
         // Assert
         assert_eq!(result.unwrap(), vec!["git", "commit", "-m", "init"]);
     }
+
+    #[test]
+    fn should_run_inline_script() {
+        // Arrange
+        let temp = TempDir::default();
+        let target = temp.join("from_script");
+        let entry = HookEntry::Script {
+            interpreter: "sh -e".to_string(),
+            script: format!("name=script\necho $name > {}\n", target.display()),
+            cwd: None,
+        };
+
+        // Act
+        let result = Hook::from(&entry).run();
+
+        // Assert
+        assert!(result.is_ok());
+        assert_eq!(fs::read_to_string(target).unwrap(), "script\n");
+    }
+
+    #[test]
+    fn should_write_inline_script_only_the_user_can_read() {
+        // Act
+        let dir = Hook::write_script("echo private").unwrap();
+
+        // Assert
+        let script = dir.join(INLINE_SCRIPT);
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode(&dir), 0o700);
+        assert_eq!(mode(&script), 0o700);
+        assert_eq!(fs::read_to_string(&script).unwrap(), "echo private");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn should_run_script_files_of_the_dotfiles_directory() {
+        // Arrange
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("hooks")).unwrap();
+        fs::write(
+            temp.join("hooks/shebang.sh"),
+            "#!/bin/sh -e\ntouch from_shebang\n",
+        )
+        .unwrap();
+        fs::write(temp.join("hooks/plain.sh"), "touch from_plain\n").unwrap();
+        fs::write(temp.join("hooks/exec.sh"), "#!/bin/sh\ntouch from_exec\n").unwrap();
+        fs::set_permissions(temp.join("hooks/exec.sh"), Permissions::from_mode(0o755)).unwrap();
+        let entries: HashMap<String, HookEntry> = toml::from_str(
+            "shebang = { script = \"hooks/shebang.sh\" }\n\
+             plain = { script = \"hooks/plain.sh\", cwd = \"hooks\" }\n\
+             exec = { script = \"hooks/exec.sh\" }\n\
+             missing = { script = \"hooks/missing.sh\" }",
+        )
+        .unwrap();
+        let hook = |name: &str| Hook::from(&entries[name]).in_dir(&temp, None);
+
+        // Act
+        let shebang = hook("shebang").run();
+        let plain = hook("plain").run();
+        let exec = hook("exec").run();
+        let missing = hook("missing").run();
+
+        // Assert
+        assert!(shebang.is_ok() && plain.is_ok() && exec.is_ok());
+        assert!(temp.join("from_shebang").exists());
+        assert!(temp.join("from_exec").exists());
+        assert!(temp.join("hooks/from_plain").exists());
+        assert!(missing.is_err());
+        assert_eq!(hook("shebang").to_string(), "hooks/shebang.sh");
+    }
+
+    #[test]
+    fn should_run_hooks_in_their_working_directory() {
+        // Arrange
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("fonts")).unwrap();
+        fs::create_dir_all(temp.join("scripts")).unwrap();
+        let entries: HashMap<String, HookEntry> =
+            toml::from_str("fonts = { command = \"touch made\", cwd = \"fonts\" }").unwrap();
+        let default = Some(Path::new("scripts"));
+
+        // Act
+        let detailed = Hook::from(&entries["fonts"]).in_dir(&temp, default);
+        let fonts = detailed.run();
+        let scripts = Hook::new("touch default").in_dir(&temp, default).run();
+        let missing = Hook::new("touch missing")
+            .in_dir(&temp, Some(Path::new("missing")))
+            .run();
+
+        // Assert
+        assert!(fonts.is_ok() && scripts.is_ok());
+        assert_eq!(detailed.cwd, Some(temp.join("fonts")));
+        assert!(temp.join("fonts/made").exists());
+        assert!(temp.join("scripts/default").exists());
+        assert!(missing.is_err());
+    }
 }
@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Catalogs bundled with bombadil by language, written in the Fluent syntax. English is the
+/// reference catalog, messages missing from another catalog fall back to it.
+const CATALOGS: [(&str, &str); 2] = [
+    ("en", include_str!("i18n/en.ftl")),
+    ("fr", include_str!("i18n/fr.ftl")),
+];
+
+const FALLBACK_LANGUAGE: &str = "en";
+
+/// Variables selecting the language of the messages, by precedence
+const LOCALE_ENV: [&str; 3] = ["LC_ALL", "LC_MESSAGES", "LANG"];
+
+/// Format the localized message `id`, ex: `t!("config-not-found", path = path.display())`.
+/// The arguments replace the `{ $name }` placeables of the message.
+macro_rules! t {
+    ($id:expr) => {
+        $crate::i18n::message($id, &[])
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::message($id, &[$((stringify!($name), $value.to_string())),+])
+    };
+}
+
+pub(crate) use t;
+
+type Catalog = HashMap<String, String>;
+
+struct Localizer {
+    /// Language of `catalog`, it selects the plural variants
+    language: String,
+    catalog: Catalog,
+    fallback: Catalog,
+}
+
+impl Localizer {
+    fn new(language: &str) -> Self {
+        let catalog = |language: &str| {
+            CATALOGS
+                .iter()
+                .find(|(catalog_language, _)| *catalog_language == language)
+                .map(|(_, source)| parse(source))
+                .unwrap_or_default()
+        };
+
+        Localizer {
+            language: language.to_string(),
+            catalog: catalog(language),
+            fallback: catalog(FALLBACK_LANGUAGE),
+        }
+    }
+
+    /// Unknown messages are displayed as their id, like Fluent does
+    fn message(&self, id: &str, args: &[(&str, String)]) -> String {
+        match (self.catalog.get(id), self.fallback.get(id)) {
+            (Some(pattern), _) => format(pattern, args, &self.language),
+            (None, Some(pattern)) => format(pattern, args, FALLBACK_LANGUAGE),
+            (None, None) => id.to_string(),
+        }
+    }
+}
+
+/// See [`t`]
+pub(crate) fn message(id: &str, args: &[(&str, String)]) -> String {
+    static LOCALIZER: OnceLock<Localizer> = OnceLock::new();
+    LOCALIZER
+        .get_or_init(|| {
+            // Tests assert the English messages
+            let language = if cfg!(test) {
+                FALLBACK_LANGUAGE.to_string()
+            } else {
+                language(|name| std::env::var(name).ok())
+            };
+            debug!("Using the `{}` message catalog", language);
+            Localizer::new(&language)
+        })
+        .message(id, args)
+}
+
+/// The language of the first set locale variable, `var` looks up an environment variable.
+/// `fr_FR.UTF-8` gives `fr`, the `C` and `POSIX` locales give English.
+fn language(var: impl Fn(&str) -> Option<String>) -> String {
+    LOCALE_ENV
+        .iter()
+        .filter_map(|name| var(name))
+        .find(|locale| !locale.is_empty())
+        .and_then(|locale| {
+            locale
+                .split(['_', '-', '.', '@'])
+                .next()
+                .map(str::to_lowercase)
+        })
+        .filter(|language| !language.is_empty() && language != "c" && language != "posix")
+        .unwrap_or_else(|| FALLBACK_LANGUAGE.to_string())
+}
+
+/// Messages of a catalog by id. Rather than depending on `fluent-bundle`, only the Fluent
+/// syntax used by the bundled catalogs is supported :
+/// - `#`, `##` and `###` comments
+/// - `id = pattern` messages, with multiline patterns on indented continuation lines
+/// - `{ $name }` variable placeables and `{ "literal" }` string literals, see [`format`]
+/// - select expressions on a variable, one variant per line, matching its value or its plural
+///   category in the language of the catalog, see [`plural_category`]
+///
+/// Terms (`-brand = ...`), attributes (`.label = ...`), message references, nested select
+/// expressions and functions such as `NUMBER()` are not, the tests check that the bundled
+/// catalogs don't use them.
+fn parse(source: &str) -> Catalog {
+    let mut catalog = Catalog::new();
+    let mut current: Option<String> = None;
+
+    for line in source.lines() {
+        if line.starts_with(' ') && !line.trim().is_empty() {
+            if let Some(pattern) = current.as_ref().and_then(|id| catalog.get_mut(id)) {
+                if !pattern.is_empty() {
+                    pattern.push('\n');
+                }
+                pattern.push_str(line.trim());
+            }
+            continue;
+        }
+
+        current = None;
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+
+        match line.split_once('=') {
+            Some((id, pattern)) => {
+                let id = id.trim().to_string();
+                catalog.insert(id.clone(), pattern.trim().to_string());
+                current = Some(id);
+            }
+            None => warn!("Invalid message catalog entry `{}`", line),
+        }
+    }
+
+    catalog
+}
+
+/// Replace the `{ $name }` placeables of `pattern` with their argument, the
+/// `{ "literal" }` ones with their text and the select expressions with their selected
+/// variant. Plural categories are the ones of `language`.
+fn format(pattern: &str, args: &[(&str, String)], language: &str) -> String {
+    let mut message = String::new();
+    let mut rest = pattern;
+
+    while let Some(start) = rest.find('{') {
+        let end = match closing_brace(&rest[start..]) {
+            Some(end) => start + end,
+            None => break,
+        };
+
+        message.push_str(&rest[..start]);
+        let placeable = rest[start + 1..end].trim();
+        if let Some((selector, variants)) = placeable.split_once("->") {
+            let variant = select(selector.trim(), variants, args, language);
+            message.push_str(&format(&variant, args, language));
+        } else if let Some(name) = placeable.strip_prefix('$') {
+            match args.iter().find(|(arg, _)| *arg == name) {
+                Some((_, value)) => message.push_str(value),
+                None => message.push_str(&format!("{{${}}}", name)),
+            }
+        } else {
+            message.push_str(placeable.trim_matches('"'));
+        }
+        rest = &rest[end + 1..];
+    }
+
+    message.push_str(rest);
+    message
+}
+
+/// Offset of the `}` closing the placeable opening `pattern`, the braces of the nested
+/// placeables and of the string literals are skipped
+fn closing_brace(pattern: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut literal = false;
+    let mut previous = ' ';
+    for (offset, c) in pattern.char_indices() {
+        match c {
+            '"' if literal => literal = false,
+            // Quotes only start a literal right after a brace, not in the text of a variant
+            '"' if previous == '{' => literal = true,
+            '{' if !literal => depth += 1,
+            '}' if !literal => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(offset);
+                }
+            }
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            previous = c;
+        }
+    }
+
+    None
+}
+
+/// The pattern of the variant of `{ $name -> variants }` whose key is the value of the
+/// argument `name` or its plural category in `language`, the `*[key]` variant otherwise
+fn select(selector: &str, variants: &str, args: &[(&str, String)], language: &str) -> String {
+    let value = selector
+        .strip_prefix('$')
+        .and_then(|name| args.iter().find(|(arg, _)| *arg == name))
+        .map(|(_, value)| value.as_str());
+    let category = value
+        .and_then(|value| value.parse::<f64>().ok())
+        .map(|number| plural_category(language, number));
+
+    // Default marker, key and pattern of each variant
+    let mut parsed: Vec<(bool, &str, String)> = vec![];
+    for line in variants.lines().map(str::trim) {
+        let (default, variant) = match line.strip_prefix('*') {
+            Some(variant) => (true, variant),
+            None => (false, line),
+        };
+        match variant
+            .strip_prefix('[')
+            .and_then(|variant| variant.split_once(']'))
+        {
+            Some((key, pattern)) => parsed.push((default, key.trim(), pattern.trim().to_string())),
+            // Continuation of a multiline variant
+            None => {
+                if let Some((_, _, pattern)) = parsed.last_mut() {
+                    if !pattern.is_empty() && !line.is_empty() {
+                        pattern.push('\n');
+                    }
+                    pattern.push_str(line);
+                }
+            }
+        }
+    }
+
+    parsed
+        .iter()
+        .find(|(_, key, _)| Some(*key) == value || Some(*key) == category)
+        .or_else(|| parsed.iter().find(|(default, _, _)| *default))
+        .map(|(_, _, pattern)| pattern.clone())
+        .unwrap_or_default()
+}
+
+/// CLDR plural category of `number` in `language`, only `one` and `other` : the bundled
+/// catalogs don't need the others
+fn plural_category(language: &str, number: f64) -> &'static str {
+    let one = match language {
+        // 0 and 1 are singular in French
+        "fr" => (0.0..2.0).contains(&number),
+        _ => number == 1.0,
+    };
+
+    match one {
+        true => "one",
+        false => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::i18n::{format, language, parse, Localizer, CATALOGS};
+
+    #[test]
+    fn should_format_messages_with_english_fallback() {
+        // Arrange
+        let localizer = Localizer {
+            language: "fr".to_string(),
+            catalog: parse("# Linking\nconfig-not-found = Fichier { $path } introuvable\n"),
+            fallback: parse(
+                "config-not-found = Unable to find { $path }\n\
+                 run-report =\n    Linked { $completed } dot(s),\n    { $failed } failed\n",
+            ),
+        };
+
+        // Act
+        let translated = localizer.message("config-not-found", &[("path", "a.toml".into())]);
+        let fallback = localizer.message(
+            "run-report",
+            &[("completed", "2".into()), ("failed", "1".into())],
+        );
+        let unknown = localizer.message("unknown-id", &[]);
+
+        // Assert
+        assert_eq!(translated, "Fichier a.toml introuvable");
+        assert_eq!(fallback, "Linked 2 dot(s),\n1 failed");
+        assert_eq!(unknown, "unknown-id");
+        assert_eq!(format("{ \"{\" } { $missing }", &[], "en"), "{ {$missing}");
+    }
+
+    #[test]
+    fn should_select_plural_variants() {
+        // Arrange
+        let catalog = parse(
+            "run-report =\n    Linked { $completed ->\n        [0] no dot\n        [one] { $completed } dot\n       *[other] { $completed } dots\n    }, { $failed } failed\n",
+        );
+        let pattern = &catalog["run-report"];
+        let report = |completed: &str, language: &str| {
+            format(
+                pattern,
+                &[("completed", completed.into()), ("failed", "1".into())],
+                language,
+            )
+        };
+
+        // Act
+        let none = report("0", "en");
+        let one = report("1", "en");
+        let many = report("2", "en");
+        let zero = |language: &str| {
+            format(
+                "{ $n ->\n[one] singular\n*[other] plural\n}",
+                &[("n", "0".into())],
+                language,
+            )
+        };
+        let unknown = format("{ $missing ->\n[one] one\n*[other] other\n}", &[], "en");
+
+        // Assert
+        assert_eq!(none, "Linked no dot, 1 failed");
+        assert_eq!(one, "Linked 1 dot, 1 failed");
+        assert_eq!(many, "Linked 2 dots, 1 failed");
+        // 0 is singular in French
+        assert_eq!(zero("fr"), "singular");
+        assert_eq!(zero("en"), "plural");
+        assert_eq!(unknown, "other");
+    }
+
+    #[test]
+    fn should_select_language_from_locale() {
+        // Arrange
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        // Act
+        let messages = language(env(&[("LC_ALL", ""), ("LC_MESSAGES", "fr_FR.UTF-8")]));
+        let lang = language(env(&[("LANG", "de_DE@euro")]));
+        let posix = language(env(&[("LC_ALL", "C"), ("LANG", "fr_FR")]));
+        let unset = language(env(&[]));
+
+        // Assert
+        assert_eq!(messages, "fr");
+        assert_eq!(lang, "de");
+        assert_eq!(posix, "en");
+        assert_eq!(unset, "en");
+    }
+
+    #[test]
+    fn should_only_use_supported_fluent_syntax() {
+        // Arrange
+        let placeables = |pattern: &str| {
+            pattern
+                .split('{')
+                .skip(1)
+                .filter_map(|placeable| placeable.split_once('}'))
+                .map(|(placeable, _)| placeable.trim().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        // Act
+        let unsupported: Vec<String> = CATALOGS
+            .iter()
+            .flat_map(|(language, source)| {
+                parse(source)
+                    .into_iter()
+                    .filter(|(id, pattern)| {
+                        id.starts_with('-')
+                            || pattern.lines().any(|line| line.starts_with('.'))
+                            || placeables(pattern).iter().any(|placeable| {
+                                !(placeable.starts_with('$') || placeable.starts_with('"'))
+                            })
+                    })
+                    .map(|(id, _)| format!("{} : {}", language, id))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Assert
+        assert!(
+            unsupported.is_empty(),
+            "Unsupported Fluent syntax : {:?}",
+            unsupported
+        );
+    }
+
+    #[test]
+    fn should_translate_every_english_message() {
+        // Arrange
+        let english = parse(CATALOGS[0].1);
+
+        // Act
+        let missing: Vec<String> = CATALOGS[1..]
+            .iter()
+            .flat_map(|(language, source)| {
+                let catalog = parse(source);
+                english
+                    .keys()
+                    .filter(|id| !catalog.contains_key(*id))
+                    .map(|id| format!("{} : {}", language, id))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Assert
+        assert!(english.len() > 1);
+        assert!(missing.is_empty(), "Missing translations : {:?}", missing);
+    }
+}
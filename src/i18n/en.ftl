@@ -0,0 +1,64 @@
+# English messages, the reference catalog. Messages missing from another catalog are
+# displayed in English.
+
+## Settings loading
+
+config-not-found = Unable to find bombadil config file { $path }
+config-error = Config error : { $error }
+config-format-error = Config format error
+local-config-format-error = Local config format error
+config-migrate-hint = Configurations written for an older bombadil can be upgraded with `bombadil migrate`
+config-newer-version = Configuration { $path } was written for a newer bombadil (version { $version }), some settings may be ignored
+config-load-error = Error loading settings from : { $path } { $error }
+home-not-found = $HOME directory not found
+dotfiles-dir-not-found = Dotfiles directory { $path } does not exist
+import-not-found = Unable to find bombadil import file
+import-remote-error = Unable to load remote import
+import-too-deep = Skipping import { $path }, nested deeper than `max_import_depth` ({ $max_depth })
+import-already-merged = Skipping import { $path }, already merged
+source-config-not-found = Unable to find bombadil config of source
+invalid-profile-matcher = Invalid profile matcher `{ $pattern }` : { $error }
+overlay-read-error = Unable to read overlay { $path } : { $error }
+overlay-format-error = Overlay format error
+
+## Linking
+
+dotfiles-path-not-found = Dotfiles base path : { $path }, not found
+dotfiles-path-not-dir = Provided dotfiles directory { $path } is not a directory
+local-config-in-use = Using machine local config { $path }
+previous-config-cleaned = Previous configuration cleaned up
+no-previous-config = No previous configuration found, skipping clean up
+vars-changed = Variables changed since the last link :
+degraded-link = Linked in degraded mode, { $count } files render their secrets as `{ $sentinel }`, run `bombadil link` once gpg works
+dot-no-longer-exists = Dot entry no longer exists in config
+posthooks-skipped = No dot changed since the last link, post install hooks were not run
+gc-done =
+    Discarded { $generations ->
+        [one] { $generations } previous link
+       *[other] { $generations } previous links
+    } and { $backups ->
+        [one] { $backups } replaced file
+       *[other] { $backups } replaced files
+    }, { $bytes } bytes freed
+preflight-failed = Preflight checks failed, nothing was changed :
+preflight-no-space = Not enough space in { $path } : { $required } bytes needed, { $available } bytes available
+preflight-inside-dotfiles = { $dot } : target { $target } overlaps the dotfiles directory { $path }, use `--allow-inside-dotfiles` to link it anyway
+preflight-not-writable = { $dot } : cannot create { $target }, { $path } is not writable
+preflight-no-parent = { $dot } : no existing parent directory for { $target }
+preflight-chown-not-allowed = { $dot } : cannot give the files to { $owner }, run bombadil as root to change their owner
+atomic-render-failed = Some dots failed to render, nothing was changed :
+render-only-failed = Some dots failed to render :
+render-only-done =
+    Rendered { $count ->
+        [one] { $count } dot
+       *[other] { $count } dots
+    }, run `bombadil link --only-link` to link them
+dot-not-rendered = The dot is not rendered, run `bombadil link --only-render` first
+sources-changed = The dotfiles sources changed during the link :
+run-report =
+    Linked { $completed ->
+        [one] { $completed } dot
+       *[other] { $completed } dots
+    }, { $failed } failed :
+run-resume-hint = Fix the errors above and run `bombadil resume` to link the remaining dots
+run-lock-waiting = Waiting for the bombadil run { $pid } to finish
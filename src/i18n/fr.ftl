@@ -0,0 +1,63 @@
+# Messages en français
+
+## Chargement de la configuration
+
+config-not-found = Fichier de configuration bombadil { $path } introuvable
+config-error = Erreur de configuration : { $error }
+config-format-error = Erreur de format de la configuration
+local-config-format-error = Erreur de format de la configuration locale
+config-migrate-hint = Les configurations écrites pour une version antérieure de bombadil peuvent être mises à jour avec `bombadil migrate`
+config-newer-version = La configuration { $path } a été écrite pour une version plus récente de bombadil (version { $version }), certains paramètres peuvent être ignorés
+config-load-error = Erreur au chargement de la configuration : { $path } { $error }
+home-not-found = Répertoire $HOME introuvable
+dotfiles-dir-not-found = Le répertoire de dotfiles { $path } n'existe pas
+import-not-found = Fichier d'import bombadil introuvable
+import-remote-error = Impossible de charger l'import distant
+import-too-deep = Import { $path } ignoré, imbriqué au-delà de `max_import_depth` ({ $max_depth })
+import-already-merged = Import { $path } ignoré, déjà fusionné
+source-config-not-found = Configuration bombadil de la source introuvable
+invalid-profile-matcher = Critère de profil `{ $pattern }` invalide : { $error }
+overlay-read-error = Impossible de lire la surcouche { $path } : { $error }
+overlay-format-error = Erreur de format de la surcouche
+
+## Liaison
+
+dotfiles-path-not-found = Répertoire de dotfiles : { $path }, introuvable
+dotfiles-path-not-dir = Le répertoire de dotfiles { $path } n'est pas un répertoire
+local-config-in-use = Utilisation de la configuration locale { $path }
+previous-config-cleaned = Configuration précédente nettoyée
+no-previous-config = Aucune configuration précédente, nettoyage ignoré
+vars-changed = Variables modifiées depuis la dernière liaison :
+degraded-link = Liaison en mode dégradé, { $count } fichiers affichent leurs secrets comme `{ $sentinel }`, lancez `bombadil link` une fois gpg fonctionnel
+dot-no-longer-exists = Ce dot n'existe plus dans la configuration
+posthooks-skipped = Aucun dot n'a changé depuis la dernière liaison, les hooks post-installation n'ont pas été lancés
+gc-done =
+    { $generations ->
+        [one] { $generations } liaison précédente
+       *[other] { $generations } liaisons précédentes
+    } et { $backups ->
+        [one] { $backups } fichier remplacé
+       *[other] { $backups } fichiers remplacés
+    } supprimés, { $bytes } octets libérés
+preflight-failed = Échec des vérifications préalables, rien n'a été modifié :
+preflight-no-space = Espace insuffisant dans { $path } : { $required } octets nécessaires, { $available } octets disponibles
+preflight-inside-dotfiles = { $dot } : la cible { $target } chevauche le répertoire de dotfiles { $path }, utilisez `--allow-inside-dotfiles` pour la lier malgré tout
+preflight-not-writable = { $dot } : impossible de créer { $target }, { $path } n'est pas accessible en écriture
+preflight-no-parent = { $dot } : aucun répertoire parent existant pour { $target }
+preflight-chown-not-allowed = { $dot } : impossible de donner les fichiers à { $owner }, lancez bombadil en root pour changer leur propriétaire
+atomic-render-failed = Le rendu de certains dots a échoué, rien n'a été modifié :
+render-only-failed = Le rendu de certains dots a échoué :
+render-only-done =
+    { $count ->
+        [one] { $count } dot rendu
+       *[other] { $count } dots rendus
+    }, lancez `bombadil link --only-link` pour les lier
+dot-not-rendered = Le dot n'est pas rendu, lancez d'abord `bombadil link --only-render`
+sources-changed = Les sources des dotfiles ont été modifiées pendant la liaison :
+run-report =
+    { $completed ->
+        [one] { $completed } dot lié
+       *[other] { $completed } dots liés
+    }, { $failed } en échec :
+run-resume-hint = Corrigez les erreurs ci-dessus et lancez `bombadil resume` pour lier les dots restants
+run-lock-waiting = En attente de la fin de l'exécution de bombadil { $pid }
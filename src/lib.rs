@@ -4,46 +4,190 @@ extern crate serde_derive;
 extern crate anyhow;
 #[macro_use]
 extern crate pest_derive;
-
-use crate::dots::{Dot, DotVar};
+#[macro_use]
+extern crate log;
+
+use crate::audit::SecretAudit;
+use crate::autostart::Autostart;
+use crate::dots::{Dot, DotVar, LinkStatus, Rendering};
+use crate::facts::expand_windows_vars;
+use crate::facts::Facts;
+use crate::git::SubmoduleState;
 use crate::gpg::Gpg;
-use crate::hook::Hook;
-use crate::settings::{Profile, Settings};
-use crate::state::BombadilState;
-use crate::templating::Variables;
+use crate::hook::{Hook, HookEntry};
+use crate::i18n::t;
+use crate::lock::{RunLock, LOCK_ENV};
+use crate::logger::Outcome;
+use crate::mimeapps::Defaults;
+use crate::picker::PickerItem;
+use crate::protect::SourceSnapshot;
+use crate::settings::{
+    GitSettings, LocalProvenance, Overlay, Profile, Provenance, RetentionSettings, Settings, Theme,
+    OVERLAY_PROFILE,
+};
+use crate::shell::InitShell;
+use crate::showcase::{Showcase, ShowcaseEntry};
+use crate::state::{
+    BombadilState, DotHashes, InputHashes, LinkJournal, ProfileState, ResolvedVars, RunManifest,
+    StateManifest,
+};
+use crate::templating::{DegradedSecrets, MockSecrets, Variables};
+use crate::timings::RenderProfiler;
+use crate::watch::{Snapshot, Watcher};
 use anyhow::Result;
 use colored::*;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::os::unix;
+use std::iter;
 use std::path::{Path, PathBuf};
-
+use std::time::{SystemTime, UNIX_EPOCH};
+
+mod audit;
+mod autostart;
+mod builtin;
+mod check;
+mod clean;
+mod diff;
+mod doctor;
 mod dots;
+mod facts;
 mod git;
 mod gpg;
 mod hook;
+mod i18n;
+mod lock;
+pub mod logger;
+mod migrate;
+mod mimeapps;
+mod owner;
+mod parallel;
+mod picker;
+mod preflight;
+mod protect;
+mod remote;
 pub mod settings;
+pub mod shell;
+mod showcase;
 mod state;
+pub mod systemd;
 mod templating;
+mod timings;
+mod watch;
 
 pub(crate) const BOMBADIL_CONFIG: &str = "bombadil.toml";
+/// Configuration generated by `bombadil import dotbot`
+const DOTBOT_IMPORT: &str = "dotbot.toml";
+/// Template variable and hook environment variable holding the enabled profiles, comma separated
+pub(crate) const PROFILES_VAR: &str = "bombadil.profiles";
+const PROFILES_ENV: &str = "BOMBADIL_PROFILES";
+/// Template variable holding the theme chosen with `bombadil theme set`, empty without theme
+const THEME_VAR: &str = "bombadil.theme";
+/// Template variable holding the desktop session, or the display server when the desktop is unknown
+pub(crate) const SESSION_VAR: &str = "bombadil.session";
+/// Template variable holding the locale of the session, ex: `fr_FR`
+const LOCALE_VAR: &str = "bombadil.locale";
+/// Template variable holding the keyboard layouts, comma separated
+const KEYBOARD_VAR: &str = "bombadil.keyboard";
+/// Template variable holding the Windows home directory when running under WSL
+pub(crate) const WSL_WINDOWS_HOME_VAR: &str = "wsl.windows_home";
+/// Rendered in place of the secrets with `--degraded` when no `secret_sentinel` is configured
+const DEFAULT_SECRET_SENTINEL: &str = "BOMBADIL_SECRET_UNAVAILABLE";
 
 /// The main crate struct, it contains all needed medata about a
 /// dotfile directory and how to install it.
+#[derive(Default, Clone)]
 pub struct Bombadil {
     path: PathBuf,
+    /// Previous links kept in `.dots`, see [`Bombadil::gc`]
+    retention: RetentionSettings,
     dots: HashMap<String, Dot>,
+    /// Source and target of the dots using `%var%` placeholders, as declared
+    declared_paths: HashMap<String, (PathBuf, PathBuf)>,
+    /// Absolute directory of the relative dot targets, `None` for $HOME
+    target_base: Option<PathBuf>,
+    /// XDG autostart entries, disabled ones included
+    autostart: HashMap<String, Autostart>,
+    /// Default applications of `mimeapps.list`
+    defaults: Defaults,
     vars: Variables,
+    /// Absolute paths of the global and profile var files
+    var_paths: Vec<PathBuf>,
     prehooks: Vec<Hook>,
     posthooks: Vec<Hook>,
+    /// Working directory of the hooks without `cwd`, see [`Bombadil::hook`]
+    hook_cwd: Option<PathBuf>,
+    /// Path of the run lock, [`lock::lock_path`] when unset
+    run_lock: Option<PathBuf>,
+    /// Configuration given to [`Bombadil::from_settings`], passed to the hooks as
+    /// `BOMBADIL_CONFIG`
+    config: Option<PathBuf>,
     profiles: HashMap<String, Profile>,
+    /// Names of the profiles enabled with [`Bombadil::enable_profiles`]
+    active_profiles: Vec<String>,
+    themes: HashMap<String, Theme>,
+    /// Theme chosen with [`Bombadil::set_theme`], its vars apply on top of the profile vars
+    theme: Option<String>,
     gpg: Option<Gpg>,
+    /// Configured `gpg_user_id`, even when linking without gpg
+    gpg_user_id: Option<String>,
+    /// Warn about dot sources that are not committed to the dotfiles repository
+    warn_untracked: bool,
+    /// Entries merged from the machine local configuration
+    local: Option<LocalProvenance>,
+    /// Configuration file declaring each dot, profile, hook and var file
+    provenance: Provenance,
+    /// Configuration files merged into the settings, imports included
+    config_files: Vec<PathBuf>,
+    git: GitSettings,
+    /// Overwrite rendered files edited since the last link
+    force: bool,
+    /// Render every dot before changing anything, see [`Bombadil::atomic`]
+    atomic: bool,
+    /// Fail links changing the sources, see [`Bombadil::write_protect`]
+    write_protect: bool,
+    /// Render every dot, even those whose inputs did not change, see [`Bombadil::rerender`]
+    rerender: bool,
+    /// Only render the dots to `.dots`, see [`Bombadil::only_render`]
+    only_render: bool,
+    /// Only link the dots already rendered to `.dots`, see [`Bombadil::only_link`]
+    only_link: bool,
+    /// Link targets resolving inside a dotfiles directory
+    allow_inside_dotfiles: bool,
+    /// Manifest recording the created files, they are not recorded when `None`
+    state_manifest: Option<PathBuf>,
+    /// Variables of the `--overlay` profile, set once the profiles are enabled
+    overlay_variables: HashMap<String, String>,
+    diff_tool: Option<String>,
+    /// Tells about upstream changes, see [`Bombadil::notify_upstream`]
+    sync_notify: Option<Hook>,
+}
+
+/// New upstream commits of the dotfiles repository affecting the active profiles, see
+/// [`Bombadil::fetch_upstream`]
+#[derive(Debug, PartialEq)]
+pub struct UpstreamUpdate {
+    /// Id of the last upstream commit
+    pub head: String,
+    /// Summary of the new commits, newest first
+    pub commits: Vec<String>,
+    /// Active dots changed by the new commits
+    pub dots: Vec<String>,
+    /// The configuration changed, any dot may be affected
+    pub config_changed: bool,
 }
 
 /// Enable or disable GPG encryption when linking dotfiles
 pub enum Mode {
     Gpg,
     NoGpg,
+    /// Don't decrypt secrets, dots using them fail to render and are not linked
+    SkipSecrets,
+    /// Don't decrypt secrets, they are rendered with the `secret_sentinel`
+    Degraded,
+    /// Don't decrypt secrets, they are rendered with the values of an unencrypted fixture.
+    /// Only [`Bombadil::check_render`] is allowed, linking refuses to run.
+    MockSecrets(PathBuf),
 }
 
 impl Bombadil {
@@ -56,9 +200,16 @@ impl Bombadil {
         profiles: Option<Vec<&str>>,
     ) -> Result<()> {
         git::clone(remote, path.as_path())?;
-        Bombadil::link_self_config(Some(path.join(BOMBADIL_CONFIG)))?;
+        Bombadil::link_self_config(Some(path.clone()))?;
 
-        let mut bombadil = Bombadil::from_settings(Mode::Gpg)?;
+        let mut bombadil = Bombadil::from_settings(Mode::Gpg, None).map_err(|err| {
+            anyhow!(
+                "{}\nMake sure `dotfiles_dir` in {} points to {}",
+                err,
+                settings::config_file(&path).display(),
+                path.display()
+            )
+        })?;
 
         if let Some(profiles) = profiles {
             bombadil.enable_profiles(profiles)?;
@@ -70,29 +221,30 @@ impl Bombadil {
     }
 
     /// Symlink `bombadil.toml` to `$XDG_CONFIG/bombadil.toml` so we can later read it from there.
+    /// `bombadil.yaml`, `bombadil.yml` and `bombadil.json` are linked with their own extension.
     pub fn link_self_config(dotfiles_path: Option<PathBuf>) -> Result<()> {
-        let xdg_config_dir = dirs::config_dir();
-        if xdg_config_dir.is_none() {
-            return Err(anyhow!("$XDG_CONFIG does not exist"));
-        }
-
-        let xdg_config = Settings::bombadil_config_xdg_path()?;
+        let xdg_config_dir =
+            dirs::config_dir().ok_or_else(|| anyhow!("$XDG_CONFIG does not exist"))?;
 
-        if fs::symlink_metadata(&xdg_config).is_ok() {
-            fs::remove_file(&xdg_config)?;
+        // Remove the links of every format so only one configuration remains
+        for xdg_config in settings::config_files(&xdg_config_dir) {
+            if fs::symlink_metadata(&xdg_config).is_ok() {
+                fs::remove_file(&xdg_config)?;
+            }
         }
 
         let dotfiles_path = &dotfiles_path
-            .unwrap_or_else(|| PathBuf::from(BOMBADIL_CONFIG))
+            .unwrap_or_else(|| PathBuf::from("."))
             .canonicalize()?;
 
-        let dotfiles_path = if dotfiles_path.is_dir() {
-            dotfiles_path.join(BOMBADIL_CONFIG)
-        } else {
-            return Err(anyhow!("Config not found"));
+        let dotfiles_path = match settings::config_file(dotfiles_path) {
+            config if dotfiles_path.is_dir() && config.exists() => config,
+            _ => return Err(anyhow!("Config not found")),
         };
+        // Unwrap is safe, `config_file` always returns a file name
+        let xdg_config = xdg_config_dir.join(dotfiles_path.file_name().unwrap());
 
-        unix::fs::symlink(&dotfiles_path, &xdg_config)
+        symlink(&dotfiles_path, &xdg_config)
             .map_err(|err| {
                 anyhow!(
                     "Unable to symlink {:?} to {:?} : {}",
@@ -101,652 +253,5509 @@ impl Bombadil {
                     err
                 )
             })
-            .map(|_result| {
-                let source = format!("{:?}", &dotfiles_path).blue();
-                let dest = format!("{:?}", &xdg_config).green();
-                println!("{} => {}", source, dest)
-            })
+            .map(|_result| info!("{}", logger::linked(&dotfiles_path, &xdg_config)))
+    }
+
+    /// Validate the configuration `config`, or the one in use, its imports and sources without
+    /// linking anything. Every problem found is reported at once.
+    pub fn check(config: Option<&Path>) -> Result<()> {
+        let settings = Settings::load(config)?;
+        let problems = check::check(&settings);
+
+        if problems.is_empty() {
+            info!("{}", "Configuration is valid".green());
+            return Ok(());
+        }
+
+        let problems: Vec<String> = problems.iter().map(ToString::to_string).collect();
+        Err(anyhow!(
+            "{}\n\t{}",
+            format!("Found {} configuration problems :", problems.len()).red(),
+            problems.join("\n\t")
+        ))
+    }
+
+    /// Upgrade the configuration `config`, or the one linked to `$XDG_CONFIG/bombadil.toml`, or
+    /// `BOMBADIL_CONFIG`, to the current schema, the original is kept next to it as
+    /// `bombadil.toml.v<version>.bak`
+    pub fn migrate(config: Option<&Path>) -> Result<()> {
+        let xdg_config = Settings::config_path_from(config)?;
+        let config_path = xdg_config
+            .canonicalize()
+            .map_err(|err| anyhow!("Unable to read {:?} : {}", xdg_config, err))?;
+        if config_path.extension() != Some(std::ffi::OsStr::new("toml")) {
+            return Err(anyhow!(
+                "Only toml configurations can be migrated, {:?} is not",
+                config_path
+            ));
+        }
+
+        let content = fs::read_to_string(&config_path)?;
+        let mut config: toml::Value = toml::from_str(&content)
+            .map_err(|err| anyhow!("Unable to parse {:?} : {}", config_path, err))?;
+        let version = config
+            .get("version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(1);
+        let changes = migrate::migrate_config(&mut config)?;
+
+        if changes.is_empty() {
+            info!(
+                "{}",
+                format!("{:?} is already up to date", config_path).green()
+            );
+            return Ok(());
+        }
+
+        let backup = config_path.with_extension(format!("toml.v{}.bak", version));
+        fs::copy(&config_path, &backup)?;
+        fs::write(&config_path, toml::to_string(&config)?)?;
+
+        info!("{}", format!("Migrated {:?} :", config_path).green());
+        changes
+            .iter()
+            .for_each(|change| info!("{}", format!("\t{}", change).green()));
+        info!("Original configuration saved to {:?}", backup);
+
+        Ok(())
+    }
+
+    /// Check the runtime environment : gpg, target permissions, broken symlinks in `.dots`,
+    /// the `$XDG_CONFIG/bombadil.toml` symlink and the git state of the dotfiles directory
+    pub fn doctor(&self) -> Result<()> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let has_secrets = self
+            .vars
+            .variables
+            .values()
+            .any(|value| value.starts_with(gpg::GPG_PREFIX));
+
+        let mut targets: Vec<(String, PathBuf)> = self
+            .dots
+            .iter()
+            .filter_map(|(key, dot)| dot.target_path().ok().map(|target| (key.clone(), target)))
+            .collect();
+        targets.sort();
+
+        let copy_dirs = self.copy_dirs(&dotfiles_dir);
+
+        let mut sections = vec![
+            (
+                "gpg",
+                doctor::check_gpg(self.gpg_user_id.as_deref(), has_secrets),
+            ),
+            ("targets", doctor::check_targets(&targets)),
+            (".dots", doctor::check_copy_dirs(&copy_dirs)),
+            ("git", doctor::check_git(&dotfiles_dir)),
+        ];
+        let config = match (&self.config, std::env::var_os(settings::CONFIG_ENV)) {
+            (Some(config), _) => Some(doctor::Finding::ok(format!(
+                "Using {} from --config",
+                config.display()
+            ))),
+            (None, Some(config)) => Some(doctor::Finding::ok(format!(
+                "Using {} from {}",
+                PathBuf::from(config).display(),
+                settings::CONFIG_ENV
+            ))),
+            (None, None) => Settings::bombadil_config_xdg_path()
+                .ok()
+                .map(|xdg_config| doctor::check_self_config(&xdg_config, &dotfiles_dir)),
+        };
+        if let Some(config) = config {
+            sections.insert(3, ("config", vec![config]));
+        }
+
+        let mut errors = 0;
+        for (name, findings) in sections.iter().filter(|(_, findings)| !findings.is_empty()) {
+            println!("{}", name.bold());
+            for finding in findings {
+                println!("{}", finding);
+                if finding.level == doctor::Level::Error {
+                    errors += 1;
+                }
+            }
+        }
+
+        if errors > 0 {
+            return Err(anyhow!("{}", format!("Found {} problems", errors).red()));
+        }
+
+        Ok(())
+    }
+
+    /// Remove the `$XDG_CONFIG/bombadil.toml` symlink, a regular file is left untouched
+    pub fn unlink_self_config() -> Result<()> {
+        let xdg_config = Settings::bombadil_config_xdg_path()?;
+        match fs::symlink_metadata(&xdg_config) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                fs::remove_file(&xdg_config)?;
+                info!("{}", format!("Removed {:?}", xdg_config).green());
+            }
+            _ => {}
+        }
+
+        Ok(())
     }
 
     /// The installation process is composed of the following steps :
     /// 1. Run pre install hooks
-    /// 2. If any previous state is found in `.dot/previous_state.toml`, remove the existing symlinks
-    /// 3. Clean existing rendered dotfiles templates in `.dot`
-    /// 4. Copy and symlink dotfiles according to the current `$XDG_CONFIG/bombadil.toml` configuration
-    /// 5. Run post install hooks
-    /// 6. Write current state to `.dot/previous_state.toml`
+    /// 2. Run `on_profile_deactivate` hooks of the profiles enabled in the previous state but not anymore
+    /// 3. If any previous state is found in `.dot/previous_state.toml`, remove the existing symlinks
+    /// 4. Keep the rendered dotfiles templates of the last link in `.dots/.previous`
+    /// 5. Copy and symlink dotfiles according to the current `$XDG_CONFIG/bombadil.toml` configuration
+    /// 6. Run post install hooks
+    /// 7. Write current state to `.dot/previous_state.toml`
+    /// 8. Run `on_profile_activate` hooks of the profiles that were not enabled in the previous state
+    ///
+    /// Before any of these steps, free space in `.dots` and the target directories permissions are
+    /// checked, every problem found is reported at once. A [`Bombadil::atomic`] link also renders
+    /// every dot then, before the pre install hooks.
+    ///
+    /// Progress is tracked in `.dots/run_manifest.toml`, if some dots fail to link the manifest
+    /// is kept so the run can be continued later with [`Bombadil::resume`].
     pub fn install(&self) -> Result<()> {
-        self.check_dotfile_dir()?;
-        self.prehooks.iter().map(Hook::run).for_each(|result| {
-            if let Err(err) = result {
-                eprintln!("{}", err);
-            }
-        });
-        let dot_copy_dir = &self.path.join(".dots");
+        self.protecting_sources(|| self.install_dots())
+    }
 
+    fn install_dots(&self) -> Result<()> {
+        self.check_dotfile_dir()?;
+        if self.only_render {
+            return self.render_only();
+        }
+        self.preflight()?;
+        self.check_edits()?;
         let absolute_path_to_dot = &self.dotfiles_absolute_path()?;
+        // Nothing ran yet when a dot fails to render, prehooks included
+        if self.atomic {
+            self.stage_dots(absolute_path_to_dot)?;
+        }
+        if self.warn_untracked {
+            self.warn_uncommitted_sources();
+        }
+        if let Some(local) = &self.local {
+            let notice = t!("local-config-in-use", path = local.path.display()).yellow();
+            info!("{}", notice);
+        }
+        self.run_hooks(&self.prehooks);
+        let dot_copy_dir = &self.path.join(".dots");
 
         // Get previous state if any and remove symlinks
         let previous_state = BombadilState::read(absolute_path_to_dot.to_owned());
 
-        match previous_state {
+        let previous_profiles = match previous_state {
             Ok(state) => {
-                state.remove_targets();
-                println!("{}", "Previous configuration cleaned up".green())
+                self.run_profile_hooks(&state.profiles, &self.active_profiles, |profile| {
+                    &profile.on_profile_deactivate
+                });
+                self.remove_previous_targets(&state, absolute_path_to_dot)
+                    .iter()
+                    .filter_map(|result| result.as_ref().err())
+                    .for_each(|err| warn!("{}", err));
+                info!("{}", t!("previous-config-cleaned").green());
+                state.profiles
             }
-            Err(err) => println!(
-                "{} : {}",
-                "No previous configuration found, skipping clean up".yellow(),
-                err
-            ),
-        }
+            Err(err) => {
+                warn!("{} : {}", t!("no-previous-config").yellow(), err);
+                vec![]
+            }
+        };
 
-        if dot_copy_dir.exists() {
-            fs::remove_dir_all(&dot_copy_dir)?;
+        // Report variables whose value changed since the last link
+        let resolved_vars = ResolvedVars::new(&self.vars.variables);
+        if let Ok(previous_vars) = ResolvedVars::read(absolute_path_to_dot) {
+            let changes = resolved_vars.diff(&previous_vars);
+            if !changes.is_empty() {
+                info!("{}", t!("vars-changed").yellow());
+                changes.iter().for_each(|change| info!("\t{}", change));
+            }
         }
 
-        // Render current config and create symlinks
-        fs::create_dir(dot_copy_dir)?;
-        for (key, dot) in self.dots.iter() {
-            if let Err(err) = dot.install(
-                absolute_path_to_dot,
-                &self.vars,
-                self.get_auto_ignored_files(key),
-                self.gpg.as_ref(),
-            ) {
-                eprintln!("{}", err);
-                continue;
+        // The dots rendered by the last link are kept for `bombadil rollback`, within the
+        // `[retention]` policy
+        if !self.only_link {
+            for copy_dir in self.copy_dirs(absolute_path_to_dot) {
+                state::rotate_generation(&copy_dir, &self.retention)?;
             }
-
-            dot.unlink()?;
-            dot.symlink(absolute_path_to_dot)?;
         }
 
-        // Run post install hooks
-        self.posthooks.iter().map(Hook::run).for_each(|result| {
-            if let Err(err) = result {
-                eprintln!("{}", err);
+        // Render current config and create symlinks
+        fs::create_dir_all(dot_copy_dir)?;
+        if self.atomic {
+            for copy_dir in self.copy_dirs(absolute_path_to_dot) {
+                state::swap_in_staging(&copy_dir)?;
             }
+        }
+        let mut journal = LinkJournal::read(&absolute_path_to_dot.join(".dots"))?;
+        journal.last_profiles = Some(
+            ProfileState::read()
+                .ok()
+                .filter(|state| state.dotfiles_dir == self.path)
+                .map(|state| state.profiles)
+                .unwrap_or_default(),
+        );
+        journal.linked_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs());
+        journal.write()?;
+        resolved_vars.write(absolute_path_to_dot)?;
+        let mut dot_keys: Vec<String> = self.dots.keys().cloned().collect();
+        dot_keys.sort();
+
+        let mut manifest =
+            RunManifest::new(absolute_path_to_dot, self.active_profiles.clone(), dot_keys);
+        manifest.write()?;
+
+        self.link_dots(&mut manifest)?;
+
+        self.run_profile_hooks(&self.active_profiles, &previous_profiles, |profile| {
+            &profile.on_profile_activate
         });
 
-        // Dump current config
-        BombadilState::from(self).write()?;
+        if let Some(degraded) = &self.vars.degraded {
+            let files = degraded.files();
+            if !files.is_empty() {
+                info!(
+                    "{}",
+                    t!(
+                        "degraded-link",
+                        count = files.len(),
+                        sentinel = degraded.sentinel
+                    )
+                    .yellow()
+                );
+            }
+        }
 
         Ok(())
     }
 
-    /// Unlink dotfiles according to previous state
-    pub fn uninstall(&self) -> Result<()> {
-        let mut success_paths: Vec<&PathBuf> = Vec::new();
-        let mut error_paths: Vec<&anyhow::Error> = Vec::new();
-
-        // Remove symlink from previous state
-        let path = self.dotfiles_absolute_path()?;
-        let previous_state = BombadilState::read(path)?;
-        let remove_result = previous_state.remove_targets();
+    /// Render every dot to its `.dots` after keeping the last generation, see
+    /// [`Bombadil::only_render`]
+    fn render_only(&self) -> Result<()> {
+        self.check_edits()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        for copy_dir in self.copy_dirs(&dotfiles_dir) {
+            state::rotate_generation(&copy_dir, &self.retention)?;
+            fs::create_dir_all(&copy_dir)?;
+        }
 
-        remove_result
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        let rendered = self.render_dots(&keys, &dotfiles_dir, RenderTarget::Rotated);
+        ResolvedVars::new(&self.vars.variables).write(&dotfiles_dir)?;
+        let errors: Vec<String> = keys
             .iter()
-            .for_each(|remove_result| match remove_result {
-                Ok(path) => success_paths.push(path),
-                Err(e) => error_paths.push(e),
-            });
-
-        if !success_paths.is_empty() {
-            println!("{}", "Removed symlinks:".green());
-            success_paths.iter().for_each(|path| {
-                let path_string = format!("\t{:?}", path).green();
-                println!("{}", path_string);
-            });
-        }
+            .filter_map(|key| match rendered.get(*key) {
+                Some(Err(err)) => Some(format!("{} : {}", key, err)),
+                _ => None,
+            })
+            .collect();
 
-        if !error_paths.is_empty() {
-            println!("{}", "Error removing symlinks:".red());
-            error_paths.iter().for_each(|path| {
-                let path_string = format!("\t{:?}", path).red();
-                println!("{}", path_string);
-            });
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}",
+                t!("render-only-failed").red(),
+                errors.join("\n\t")
+            ));
         }
 
+        info!("{}", t!("render-only-done", count = keys.len()).green());
         Ok(())
     }
 
-    /// Add a gpg secret encrypted variable to the target variable file
-    pub fn add_secret<S: AsRef<Path> + ?Sized>(
-        &self,
-        key: &str,
-        value: &str,
-        var_file: &S,
-    ) -> Result<()> {
-        if let Some(gpg) = &self.gpg {
-            gpg.push_secret(key, value, var_file)
-        } else {
-            Err(anyhow!("No gpg_user_id in bombadil config"))
+    /// Render every dot to the staging directory of its `.dots`, they are all removed if a dot
+    /// fails to render
+    fn stage_dots(&self, dotfiles_dir: &Path) -> Result<()> {
+        let copy_dirs = self.copy_dirs(dotfiles_dir);
+        for copy_dir in &copy_dirs {
+            unlink(&state::staging_dir(copy_dir))?;
         }
-    }
-
-    /// Pretty print current bombadil variables
-    pub fn display_vars(&self) {
-        self.vars
-            .variables
-            .iter()
-            .for_each(|(key, value)| println!("{} = {}", key.red(), value))
-    }
-
-    /// Enable a dotfile profile by merging its config with the default profile
-    pub fn enable_profiles(&mut self, profile_keys: Vec<&str>) -> Result<()> {
-        let mut profiles: Vec<Profile> = profile_keys
-            .iter()
-            // unwrap here is safe cause allowed profile keys are checked by clap
-            .map(|profile_key| self.profiles.get(&profile_key.to_string()).unwrap())
-            .cloned()
-            .collect();
 
-        let sub_profiles: Vec<Profile> = profiles
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        let rendered = self.render_dots(&keys, dotfiles_dir, RenderTarget::Staging);
+        let errors: Vec<String> = keys
             .iter()
-            .flat_map(|profile| {
-                profile
-                    .extra_profiles
-                    .iter()
-                    .flat_map(|sub_profile| self.profiles.get(sub_profile))
-                    .collect::<Vec<&Profile>>()
+            .filter_map(|key| match rendered.get(*key) {
+                Some(Err(err)) => Some(format!("{} : {}", key, err)),
+                _ => None,
             })
-            .cloned()
             .collect();
 
-        profiles.extend(sub_profiles);
-
-        // Merge profile dots
-        for profile in profiles.iter() {
-            profile.dots.iter().for_each(|(key, dot_override)| {
-                // Dot exist let's override
-                if let Some(dot) = self.dots.get_mut(key) {
-                    if let Some(source) = &dot_override.source {
-                        dot.source = source.clone()
-                    }
+        if !errors.is_empty() {
+            for copy_dir in &copy_dirs {
+                unlink(&state::staging_dir(copy_dir))?;
+            }
 
-                    if let Some(target) = &dot_override.target {
-                        dot.target = target.clone()
-                    }
+            return Err(anyhow!(
+                "{}\n\t{}",
+                t!("atomic-render-failed").red(),
+                errors.join("\n\t")
+            ));
+        }
 
-                    if let Some(vars) = &dot_override.vars {
-                        dot.vars = vars.clone();
-                    }
+        Ok(())
+    }
 
-                    if let (None, None, None) = (
-                        &dot_override.source,
-                        &dot_override.target,
-                        &dot_override.vars,
-                    ) {
-                        let warning = format!(
-                            "Skipping {}, no `source`, `target` or `vars` to override",
-                            key
-                        )
-                        .yellow();
-                        eprintln!("{}", warning);
-                    }
-                // Nothing to override, let's create a new dot entry
-                } else if let (Some(source), Some(target)) =
-                    (&dot_override.source, &dot_override.target)
-                {
-                    let source = source.clone();
-                    let target = target.clone();
-                    let ignore = dot_override.ignore.clone();
+    /// Check that `.dots` has enough free space for the rendered dots, that every target
+    /// can be created and stays out of the dotfiles directories, so the link fails before
+    /// changing anything instead of midway
+    fn preflight(&self) -> Result<()> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut problems = vec![];
+
+        let mut dotfiles_dirs = vec![dotfiles_dir.clone()];
+        for repository in self.dots.values().filter_map(|dot| dot.repository.as_ref()) {
+            if !dotfiles_dirs.contains(repository) {
+                dotfiles_dirs.push(repository.clone());
+            }
+        }
 
-                    self.dots.insert(
-                        key.to_string(),
-                        Dot {
-                            source,
-                            target,
-                            ignore,
-                            vars: Dot::default_vars(),
-                        },
-                    );
-                } else {
-                    if dot_override.source.is_none() {
-                        let warning = format!("`source` field missing for {}", key).yellow();
-                        eprintln!("{}", warning);
-                    }
+        let sources: Vec<PathBuf> = self
+            .dots
+            .values()
+            .map(|dot| dot.dotfiles_dir(&dotfiles_dir).join(&dot.source))
+            .collect();
+        let required = preflight::required_space(&sources);
+        if let Some(available) = preflight::available_space(&dotfiles_dir) {
+            if required > available {
+                problems.push(t!(
+                    "preflight-no-space",
+                    path = dotfiles_dir.display(),
+                    required = required,
+                    available = available
+                ));
+            }
+        }
 
-                    if dot_override.target.is_none() {
-                        let warning = format!("`target` field missing for {}", key).yellow();
-                        eprintln!("{}", warning);
-                    }
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        for key in keys {
+            let target = match self.dots[key].target_path() {
+                Ok(target) => target,
+                Err(err) => {
+                    problems.push(format!("{} : {}", key, err));
+                    continue;
                 }
-            });
+            };
 
-            // Add profile vars
-            let variables = Variables::from_paths(&self.path, &profile.vars, self.gpg.as_ref())?;
-            self.vars.extend(variables);
-            // Add Profile pre hooks
-            let prehooks = profile
-                .prehooks
+            let overlapped = dotfiles_dirs
                 .iter()
-                .map(|command| command.as_ref())
-                .map(Hook::new)
-                .collect::<Vec<Hook>>();
-            self.prehooks.extend(prehooks);
+                .find(|dir| preflight::overlaps_dotfiles(&target, dir));
+            if let Some(dir) = overlapped.filter(|_| !self.allow_inside_dotfiles) {
+                problems.push(t!(
+                    "preflight-inside-dotfiles",
+                    dot = key,
+                    target = target.display(),
+                    path = dir.display()
+                ));
+                continue;
+            }
 
-            // Add profile post hooks
-            let posthooks = profile
-                .posthooks
-                .iter()
-                .map(|command| command.as_ref())
-                .map(Hook::new)
-                .collect::<Vec<Hook>>();
-            self.posthooks.extend(posthooks);
+            match preflight::existing_ancestor(&target) {
+                Some(ancestor) if !preflight::is_writable(ancestor) => problems.push(t!(
+                    "preflight-not-writable",
+                    dot = key,
+                    target = target.display(),
+                    path = ancestor.display()
+                )),
+                Some(_) => {}
+                None => problems.push(t!(
+                    "preflight-no-parent",
+                    dot = key,
+                    target = target.display()
+                )),
+            }
+
+            match self.dots[key].owner() {
+                Ok(Some(owner)) if !owner.is_allowed() => problems.push(t!(
+                    "preflight-chown-not-allowed",
+                    dot = key,
+                    owner = self.dots[key].chown.as_deref().unwrap_or_default()
+                )),
+                Ok(_) => {}
+                Err(err) => problems.push(format!("{} : {}", key, err)),
+            }
         }
 
-        Ok(())
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{}\n\t{}",
+                t!("preflight-failed").red(),
+                problems.join("\n\t")
+            ))
+        }
     }
 
-    fn check_dotfile_dir(&self) -> Result<()> {
-        if !self.path.exists() {
-            return Err(anyhow!(
-                "Dotfiles base path : {}, not found",
-                self.path.display(),
-            ));
+    /// Render every dot to a temporary directory with the mock secrets of
+    /// [`Mode::MockSecrets`], failing if a dot does not render or uses a secret missing from
+    /// the fixture. Nothing is linked.
+    pub fn check_render(&self) -> Result<()> {
+        let mock = self
+            .vars
+            .mock_secrets
+            .as_ref()
+            .ok_or_else(|| anyhow!("Rendering checks need mock secrets"))?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let check_dir = std::env::temp_dir().join(format!("bombadil-check-{}", std::process::id()));
+
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        let mut problems = vec![];
+        for key in keys {
+            let dot = &self.dots[key];
+            if let Err(err) = dot.render(
+                &dot.dotfiles_dir(&dotfiles_dir),
+                &check_dir.join(key),
+                &self.vars,
+                self.get_auto_ignored_files(key),
+                None,
+            ) {
+                problems.push(format!("{} : {}", key, err));
+            }
         }
-
-        if !self.path.is_dir() {
-            let err = format!(
-                "{} {:?} {}",
-                "Provided dotfiles directory".red(),
-                &self.path,
-                "is not a directory".red()
-            );
-            return Err(anyhow!(err));
+        let _ = fs::remove_dir_all(&check_dir);
+
+        problems.extend(mock.missing().iter().map(|(file, key)| {
+            format!(
+                "{:?} uses the secret `{}`, missing from {:?}",
+                file, key, mock.path
+            )
+        }));
+
+        if problems.is_empty() {
+            let rendered = format!("Rendered {} dots with mock secrets", self.dots.len()).green();
+            info!("{}", logger::outcome(Outcome::Ok, rendered));
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{}\n\t{}",
+                format!("Found {} rendering problems :", problems.len()).red(),
+                problems.join("\n\t")
+            ))
         }
+    }
 
-        Ok(())
+    /// Mock secrets are only meant for checks, rendered dots must never be linked with them
+    fn refuse_mock_secrets(&self) -> Result<()> {
+        match &self.vars.mock_secrets {
+            Some(mock) => Err(anyhow!(
+                "Refusing to link with the mock secrets {:?}, they are only allowed with `bombadil check`",
+                mock.path
+            )),
+            None => Ok(()),
+        }
     }
 
-    /// Load Bombadil config from a `bombadil.toml`
-    pub fn from_settings(mode: Mode) -> Result<Bombadil> {
-        let config = Settings::get()?;
-        let path = config.get_dotfiles_path()?;
+    /// Show what linking would change in the rendered dots, with the configured `diff_tool`
+    /// or a built-in unified diff
+    pub fn diff(&self) -> Result<()> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let preview_dir =
+            std::env::temp_dir().join(format!("bombadil-diff-{}", std::process::id()));
+        let tool = self
+            .diff_tool
+            .as_deref()
+            .map(diff::DiffTool::new)
+            .transpose()?;
+
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        let mut changes = vec![];
+        for key in keys {
+            let dot = &self.dots[key];
+            let dot_dir = dot.dotfiles_dir(&dotfiles_dir);
+            let current = dot.copy_path(&dot_dir);
+            let preview = preview_dir.join(key);
+            if let Err(err) = dot.render(
+                &dot_dir,
+                &preview,
+                &self.vars,
+                self.get_auto_ignored_files(key),
+                self.gpg.as_ref(),
+            ) {
+                error!("{} : {}", key, err);
+                continue;
+            }
 
-        let gpg = match mode {
-            Mode::Gpg => config.gpg_user_id.map(|user_id| Gpg::new(&user_id)),
-            Mode::NoGpg => None,
+            for file in diff::changed_files(&current, &preview) {
+                changes.push((current.join(&file), preview.join(&file)));
+            }
+        }
+
+        let result = match tool {
+            _ if changes.is_empty() => {
+                let up_to_date = "Nothing to link, the rendered dots are up to date".green();
+                info!("{}", logger::outcome(Outcome::Ok, up_to_date));
+                Ok(())
+            }
+            Some(tool) if tool.compares_files() => changes
+                .iter()
+                .try_for_each(|(current, preview)| tool.compare(current, preview)),
+            Some(tool) => {
+                let unified: String = changes
+                    .iter()
+                    .map(|(current, preview)| diff::unified(current, preview))
+                    .collect();
+                tool.page(&unified)
+            }
+            None => {
+                changes.iter().for_each(|(current, preview)| {
+                    if logger::is_plain() {
+                        println!("{}", logger::outcome(Outcome::Changed, current.display()));
+                    }
+                    let unified = diff::unified(current, preview);
+                    match diff::is_terminal() {
+                        true => print!("{}", diff::colorize(&unified)),
+                        false => print!("{}", unified),
+                    }
+                });
+                Ok(())
+            }
         };
 
-        // Resolve variables from path
-        let mut vars = Variables::from_paths(&path, &config.settings.vars, gpg.as_ref())?;
+        let _ = fs::remove_dir_all(&preview_dir);
+        result
+    }
 
-        // Replace % reference with their ref value
-        vars.resolve_ref();
+    /// Refuse to overwrite rendered files edited by hand since the last link
+    fn check_edits(&self) -> Result<()> {
+        if self.force {
+            return Ok(());
+        }
 
-        // Resolve hooks from config
-        let posthooks = config
-            .settings
-            .posthooks
-            .iter()
-            .map(|cmd| Hook::new(cmd))
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        let edited: Vec<String> = keys
+            .into_iter()
+            .flat_map(|key| self.edited_files(key))
+            .map(|path| path.display().to_string())
             .collect();
 
-        let prehooks = config
-            .settings
-            .prehooks
-            .iter()
-            .map(|cmd| Hook::new(cmd))
-            .collect();
-        let dots = config.settings.dots;
-        let profiles = config.profiles;
+        if edited.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "{}\n\t{}\n{}",
+                "The following files were edited since the last link :".red(),
+                edited.join("\n\t"),
+                "Use `bombadil link --force` to overwrite them or `bombadil adopt-changes` to copy the edits to their source"
+            ))
+        }
+    }
 
-        Ok(Self {
-            path,
-            dots,
-            vars,
-            prehooks,
-            posthooks,
-            profiles,
-            gpg,
-        })
+    fn edited_files(&self, key: &str) -> Vec<PathBuf> {
+        let dotfiles_dir = match self.dotfiles_absolute_path() {
+            Ok(path) => path,
+            Err(_) => return vec![],
+        };
+
+        let dot = &self.dots[key];
+        dot.edited_files(&dot.dotfiles_dir(&dotfiles_dir))
     }
 
-    /// Pretty print metadata, possible values are Dots, PreHooks, PostHook, Path, Profiles, Vars, Secrets
-    pub fn print_metadata(&self, metadata_type: MetadataType) {
-        let rows = match metadata_type {
-            MetadataType::Dots => self
-                .dots
+    /// Record the render timings of every template during the next link
+    pub fn profile_render(&mut self) {
+        self.vars.render_profiler = Some(RenderProfiler::default());
+    }
+
+    /// Print the render timings recorded with [`Bombadil::profile_render`], slowest first,
+    /// and write them as folded stacks to `folded` for flamegraph tools
+    pub fn print_render_timings(&self, folded: Option<&Path>) -> Result<()> {
+        let profiler = match &self.vars.render_profiler {
+            Some(profiler) => profiler,
+            None => return Ok(()),
+        };
+
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        println!("{}", "Render timings, slowest first :".green());
+        print!("{}", profiler.report(&dotfiles_dir));
+
+        if let Some(folded) = folded {
+            fs::write(folded, profiler.folded(&dotfiles_dir)).map_err(|err| {
+                anyhow!("Unable to write render timings to {:?} : {}", folded, err)
+            })?;
+            println!(
+                "{} {}",
+                "Folded stacks written to".green(),
+                folded.display()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Overwrite rendered files even if they were edited since the last link
+    pub fn force(&mut self) {
+        self.force = true;
+    }
+
+    pub fn allow_inside_dotfiles(&mut self) {
+        self.allow_inside_dotfiles = true;
+    }
+
+    /// Render every dot to a staging directory first, the link fails without running any hook
+    /// or changing anything when a dot does not render
+    pub fn atomic(&mut self) {
+        self.atomic = true;
+    }
+
+    /// Check that linking leaves the sources untouched : bombadil only reads them, but hooks
+    /// and render paths could write to them. The link fails when the content or permissions
+    /// of a file of the dotfiles directories, outside of `.dots` and `.git`, changed, or when
+    /// one was created or removed. The sources stay writable, changes are reported afterward.
+    pub fn write_protect(&mut self) {
+        self.write_protect = true;
+    }
+
+    /// Render every dot again. By default a dot whose variables and source files did not change
+    /// since the last link reuses its rendered files, and post install hooks are not run when
+    /// no dot changed.
+    pub fn rerender(&mut self) {
+        self.rerender = true;
+    }
+
+    /// Render every dot to `.dots` without touching their targets nor running the hooks, ex: to
+    /// render on a build machine and copy `.dots` to the machines linking them
+    pub fn only_render(&mut self) {
+        self.only_render = true;
+    }
+
+    /// Link the dots rendered to `.dots` as is, without rendering them. The link fails for the dots
+    /// that were not rendered, and `.dots` is not kept for `bombadil rollback`.
+    pub fn only_link(&mut self) {
+        self.only_link = true;
+    }
+
+    /// Copy the edits made to rendered files back to their dot source
+    pub fn adopt_changes(&self) -> Result<()> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+
+        let mut adopted = vec![];
+        for key in keys {
+            let dot = &self.dots[key];
+            adopted.extend(dot.adopt_changes(&dot.dotfiles_dir(&dotfiles_dir))?);
+        }
+
+        if adopted.is_empty() {
+            info!("{}", "No edits to adopt".yellow());
+        } else {
+            info!("{}", "Adopted the edits of :".green());
+            adopted
                 .iter()
-                .map(|(k, v)| {
-                    format!(
-                        "{}: {} => {}",
-                        k,
-                        self.path.join(&v.source).display(),
-                        v.target_path()
-                            .unwrap_or_else(|_| v.target.clone())
-                            .display()
-                    )
-                })
-                .collect(),
-            MetadataType::PreHooks => self.prehooks.iter().map(|h| h.command.clone()).collect(),
-            MetadataType::PostHooks => self.posthooks.iter().map(|h| h.command.clone()).collect(),
-            MetadataType::Path => vec![self.path.display().to_string()],
-            MetadataType::Profiles => {
-                let mut profiles = vec!["default".to_string()];
-                profiles.extend(self.profiles.iter().map(|(k, _)| k.clone()));
-                profiles
+                .for_each(|source| info!("\t{}", source.display()));
+        }
+
+        Ok(())
+    }
+
+    /// Print the dot sources that only exist on this machine because they were never committed
+    fn warn_uncommitted_sources(&self) {
+        let dotfiles_dir = match self.dotfiles_absolute_path() {
+            Ok(path) => path,
+            Err(_) => return,
+        };
+
+        let sources: Vec<PathBuf> = self
+            .dots
+            .values()
+            .map(|dot| dot.dotfiles_dir(&dotfiles_dir).join(&dot.source))
+            .collect();
+
+        match git::uncommitted_files(&dotfiles_dir, &sources) {
+            Ok(files) if !files.is_empty() => {
+                let warning =
+                    "WARNING: the following files are not committed to your dotfiles repository :"
+                        .yellow();
+                warn!("{}", warning);
+                files.iter().for_each(|file| warn!("\t{}", file.display()));
             }
-            MetadataType::Vars => self
-                .vars
-                .variables
-                .iter()
-                .map(|(k, v)| format!("{}: {}", k, v))
-                .collect(),
-            MetadataType::Secrets => self
+            Ok(_) => {}
+            Err(err) => {
+                let warning = format!("Unable to check for uncommitted files : {}", err).yellow();
+                warn!("{}", warning);
+            }
+        }
+    }
+
+    /// Replace the variables with the values recorded by the last link,
+    /// to reproduce a previous render
+    pub fn freeze_vars(&mut self) -> Result<()> {
+        let recorded = ResolvedVars::read(&self.dotfiles_absolute_path()?)?;
+        self.vars.variables.extend(recorded.variables);
+        info!(
+            "{}",
+            "Using the variables recorded by the last link".yellow()
+        );
+        Ok(())
+    }
+
+    /// Profiles explicitly enabled by the last `bombadil link -p ...` of this dotfiles directory,
+    /// profiles that no longer exist in the config are skipped.
+    pub fn last_profiles(&self) -> Vec<String> {
+        let state = match ProfileState::read() {
+            Ok(state) if state.dotfiles_dir == self.path => state,
+            _ => return vec![],
+        };
+
+        state
+            .profiles
+            .into_iter()
+            .filter(|profile| {
+                let exists = self.profiles.contains_key(profile);
+                if !exists {
+                    let warning =
+                        format!("Profile `{}` no longer exists, not enabling it", profile).yellow();
+                    warn!("{}", warning);
+                }
+                exists
+            })
+            .collect()
+    }
+
+    /// Remember the given profiles so they are enabled again by the next bare `bombadil link`
+    pub fn save_profiles(&self, profiles: &[&str]) -> Result<()> {
+        ProfileState {
+            dotfiles_dir: self.path.clone(),
+            profiles: profiles.iter().map(|profile| profile.to_string()).collect(),
+            theme: self.theme.clone(),
+        }
+        .write()
+    }
+
+    /// Themes by name, see [`Bombadil::set_theme`]
+    pub fn theme_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.themes.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The theme chosen with [`Bombadil::set_theme`], if it still exists
+    pub fn active_theme(&self) -> Option<&str> {
+        self.theme.as_deref()
+    }
+
+    /// Make `name` the active theme of this dotfiles directory, the next links use its vars.
+    /// Relink with [`Bombadil::relink_theme`] once reloaded.
+    pub fn set_theme(&self, name: &str) -> Result<()> {
+        if !self.themes.contains_key(name) {
+            return Err(anyhow!(
+                "Theme `{}` not found, available themes : {}",
+                name,
+                self.theme_names().join(", ")
+            ));
+        }
+
+        let mut state = ProfileState::read()
+            .ok()
+            .filter(|state| state.dotfiles_dir == self.path)
+            .unwrap_or_else(|| ProfileState {
+                dotfiles_dir: self.path.clone(),
+                ..Default::default()
+            });
+        state.theme = Some(name.to_string());
+        state.write()
+    }
+
+    /// Render the dots again with the vars of the active theme, only the dots using the changed
+    /// vars are rendered and post install hooks run when some did
+    pub fn relink_theme(&self) -> Result<()> {
+        self.check_dotfile_dir()?;
+        self.refuse_mock_secrets()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut keys: Vec<&String> = self.dots.keys().collect();
+        keys.sort();
+        self.relink(keys, &dotfiles_dir)
+    }
+
+    /// Print the themes with their description and var files, the active one first
+    pub fn print_themes(&self) {
+        for name in self.theme_names() {
+            let theme = &self.themes[&name];
+            if self.active_theme() == Some(name.as_str()) {
+                println!("{} {}", name.green().bold(), "(active)".green());
+            } else {
+                println!("{}", name.bold());
+            }
+
+            if let Some(description) = &theme.description {
+                println!("\t{}", description);
+            }
+
+            let vars: Vec<String> = theme
                 .vars
-                .secrets
                 .iter()
-                .map(|(k, v)| format!("{}: {}", k, v))
-                .collect(),
+                .map(|var_path| var_path.display().to_string())
+                .collect();
+            if !vars.is_empty() {
+                println!("\t{} : {}", "vars".blue(), vars.join(", "));
+            }
+        }
+    }
+
+    /// Describe the profiles and themes for a dotfiles README : their description, the number
+    /// of dots they link and the colors of their vars. Written to stdout, or between the
+    /// showcase markers of `output` when it exists.
+    pub fn showcase(&self, html: bool, output: Option<&Path>) -> Result<()> {
+        let mut profiles = vec![ShowcaseEntry {
+            name: "default".to_string(),
+            description: None,
+            dots: Some(self.dots.len()),
+            colors: showcase_colors(&self.vars, None),
+        }];
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+        for name in names {
+            let mut enabled = self.clone();
+            enabled.enable_profiles(vec![name])?;
+            profiles.push(ShowcaseEntry {
+                name: name.clone(),
+                description: self.profiles[name].description.clone(),
+                dots: Some(enabled.dots.len()),
+                colors: showcase_colors(&enabled.vars, None),
+            });
+        }
+
+        let mut themes = vec![];
+        for name in self.theme_names() {
+            let theme = &self.themes[&name];
+            let mut themed = self.clone();
+            themed.theme = Some(name.clone());
+            themed.merge_theme_vars()?;
+            let theme_vars = Variables::from_paths(&self.path, &theme.vars, None)?;
+            themes.push(ShowcaseEntry {
+                name,
+                description: theme.description.clone(),
+                dots: None,
+                colors: showcase_colors(&themed.vars, Some(&theme_vars)),
+            });
+        }
+
+        let showcase = Showcase { profiles, themes };
+        let rendered = match html {
+            true => showcase.html(),
+            false => showcase.markdown(),
         };
 
-        if !rows.is_empty() {
-            println!("{}", rows.join("\n"));
+        match output {
+            Some(output) if output.exists() => {
+                let document = fs::read_to_string(output)
+                    .map_err(|err| anyhow!("Unable to read {:?} : {}", output, err))?;
+                fs::write(output, showcase::embed(&document, &rendered)?)?;
+                info!("Updated the showcase of {:?}", output);
+            }
+            Some(output) => {
+                fs::write(output, rendered)?;
+                info!("Wrote the showcase to {:?}", output);
+            }
+            None => print!("{}", rendered),
         }
+
+        Ok(())
     }
 
-    fn dotfiles_absolute_path(&self) -> Result<PathBuf> {
-        dirs::home_dir()
-            .ok_or_else(|| anyhow!("$HOME dir not found"))
-            .map(|path| path.join(&self.path))
+    /// Merge the var files of the active theme on top of the vars merged so far
+    fn merge_theme_vars(&mut self) -> Result<()> {
+        let themes = &self.themes;
+        let theme = match self.theme.as_ref().and_then(|name| themes.get(name)) {
+            Some(theme) => theme,
+            None => return Ok(()),
+        };
+
+        for var_path in theme.vars.iter() {
+            let var_path = self.path.join(var_path);
+            if !self.var_paths.contains(&var_path) {
+                self.var_paths.push(var_path);
+            }
+        }
+        let variables = Variables::from_paths(&self.path, &theme.vars, self.gpg.as_ref())?;
+        self.vars.extend(variables);
+        Ok(())
     }
 
-    fn get_auto_ignored_files(&self, dot_key: &str) -> Vec<PathBuf> {
-        let dot_origin = self.dots.get(dot_key);
-        let origin_source = dot_origin.map(|dot| &dot.source);
+    /// The hook of `entry`, run from its `cwd` or from `hook_cwd`, relative to the dotfiles
+    /// directory
+    fn hook(&self, entry: &HookEntry) -> Hook {
+        Hook::from(entry).in_dir(&self.path, self.hook_cwd.as_deref())
+    }
 
-        let mut ignored: Vec<PathBuf> = self
-            .profiles
+    /// Run `hooks` one after the other, a failing hook is reported and the next ones still run.
+    /// Hooks only run while the run lock is held, bombadil started by them does not wait for it
+    /// and uses the same configuration. The enabled profiles are in `$BOMBADIL_PROFILES`.
+    fn run_hooks(&self, hooks: &[Hook]) {
+        let mut env = vec![
+            (LOCK_ENV, self.lock_path().display().to_string()),
+            (PROFILES_ENV, self.active_profiles.join(",")),
+        ];
+        if let Some(config) = &self.config {
+            env.push((settings::CONFIG_ENV, config.display().to_string()));
+        }
+        for hook in hooks {
+            if let Err(err) = hook.run_with_env(&env) {
+                error!("{}", err);
+            }
+        }
+    }
+
+    /// The lock taken by the commands changing `.dots` and the dot targets
+    fn lock(&self) -> Result<RunLock> {
+        RunLock::acquire(&self.lock_path())
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        self.run_lock.clone().unwrap_or_else(lock::lock_path)
+    }
+
+    /// Run the hooks selected by `hooks` for every profile in `profiles` that is not in `others`
+    fn run_profile_hooks<F>(&self, profiles: &[String], others: &[String], hooks: F)
+    where
+        F: Fn(&Profile) -> &Vec<HookEntry>,
+    {
+        profiles
             .iter()
-            .filter_map(|(_, profile)| profile.dots.get(dot_key))
-            .filter(|dot| dot.vars.is_some())
-            .filter_map(|dot| dot.resolve_var_path(&self.path, origin_source))
+            .filter(|profile| !others.contains(profile))
+            .for_each(|name| match self.profiles.get(name) {
+                Some(profile) => {
+                    let profile_hooks: Vec<Hook> = hooks(profile)
+                        .iter()
+                        .map(|entry| self.hook(entry))
+                        .collect();
+                    self.run_hooks(&profile_hooks);
+                }
+                None => {
+                    let warning =
+                        format!("Profile `{}` no longer exists, skipping its hooks", name).yellow();
+                    warn!("{}", warning);
+                }
+            });
+    }
+
+    /// Continue a link operation interrupted by errors : only the failed and pending dots
+    /// recorded in `.dots/run_manifest.toml` are linked, and post install hooks are run
+    /// if they were not already.
+    pub fn resume(&self) -> Result<()> {
+        self.check_dotfile_dir()?;
+        let absolute_path_to_dot = &self.dotfiles_absolute_path()?;
+        let mut manifest = RunManifest::read(absolute_path_to_dot)?;
+        manifest.pending = manifest.remaining();
+        manifest.failed.clear();
+
+        self.protecting_sources(|| self.link_dots(&mut manifest))
+    }
+
+    /// Run `link` and fail if it modified, created or removed a file of the dotfiles
+    /// directories while they are write protected
+    fn protecting_sources(&self, link: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.refuse_mock_secrets()?;
+        let _lock = self.lock()?;
+        if !self.write_protect {
+            return link();
+        }
+
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let dotfiles_dirs: Vec<PathBuf> = self
+            .copy_dirs(&dotfiles_dir)
+            .iter()
+            .filter_map(|copy_dir| copy_dir.parent().map(Path::to_path_buf))
             .collect();
+        let snapshot = SourceSnapshot::take(&dotfiles_dirs);
+        let result = link();
 
-        let _ = dot_origin.map(|dot| {
-            dot.resolve_var_path(&self.path)
-                .map(|path| ignored.push(path))
-        });
+        let changes = snapshot.changes(&dotfiles_dirs);
+        if changes.is_empty() {
+            return result;
+        }
 
-        ignored
+        if let Err(err) = result {
+            error!("{}", err);
+        }
+        Err(anyhow!(
+            "{}\n\t{}",
+            t!("sources-changed").red(),
+            changes.join("\n\t")
+        ))
     }
-}
 
-pub(crate) fn unlink(path: &Path) -> Result<()> {
-    if fs::symlink_metadata(path).is_ok() {
-        if path.is_dir() {
-            fs::remove_dir_all(path)?;
+    /// Profiles recorded in the run manifest of an interrupted link operation
+    pub fn interrupted_run_profiles(&self) -> Result<Vec<String>> {
+        let absolute_path_to_dot = &self.dotfiles_absolute_path()?;
+        RunManifest::read(absolute_path_to_dot).map(|manifest| manifest.profiles)
+    }
+
+    fn link_dots(&self, manifest: &mut RunManifest) -> Result<()> {
+        let absolute_path_to_dot = &self.dotfiles_absolute_path()?;
+        let mut journal = LinkJournal::read(&absolute_path_to_dot.join(".dots"))?;
+        let mut created = self.read_state_manifest();
+
+        // Dots are rendered in parallel then linked one at a time, atomic links render
+        // them beforehand
+        let pending = manifest.pending.clone();
+        let mut rendered = if self.atomic || self.only_link {
+            HashMap::new()
         } else {
-            fs::remove_file(path)?;
+            let keys: Vec<&String> = pending.iter().collect();
+            self.render_dots(&keys, absolute_path_to_dot, RenderTarget::Rotated)
+        };
+
+        for key in pending {
+            let result = match (self.dots.get(&key), rendered.remove(&key)) {
+                (Some(_), Some(Err(err))) => Err(err),
+                (Some(dot), _)
+                    if self.only_link
+                        && !dot
+                            .copy_path(&dot.dotfiles_dir(absolute_path_to_dot))
+                            .exists() =>
+                {
+                    Err(anyhow!(t!("dot-not-rendered")))
+                }
+                (Some(dot), _) => {
+                    self.link_dot(&key, dot, absolute_path_to_dot, Some(&mut journal))
+                }
+                (None, _) => Err(anyhow!(t!("dot-no-longer-exists"))),
+            };
+
+            match (&result, self.dots.get(&key)) {
+                (Err(err), _) => error!("{}", logger::outcome(Outcome::Failed, err)),
+                (Ok(()), Some(dot)) => {
+                    let links = dot
+                        .links(&dot.dotfiles_dir(absolute_path_to_dot))
+                        .unwrap_or_default();
+                    journal.record(&links);
+                    record_created(&mut created, absolute_path_to_dot, links);
+                }
+                (Ok(()), None) => {}
+            }
+
+            manifest.record(&key, &result);
+            manifest.write()?;
+            journal.write()?;
         }
+
+        let mut generated_targets = self.autostart_targets();
+        generated_targets.extend(self.mimeapps_target());
+        journal.backup(&generated_targets, &self.copy_dirs(absolute_path_to_dot))?;
+        let mut generated = self.link_autostart(absolute_path_to_dot);
+        generated.extend(self.link_mimeapps(absolute_path_to_dot));
+        journal.record(&generated);
+        journal.write()?;
+        record_created(&mut created, absolute_path_to_dot, generated);
+        if let Some(created) = created {
+            created.write()?;
+        }
+
+        if !manifest.posthooks_done && self.is_unchanged(absolute_path_to_dot) {
+            info!("{}", t!("posthooks-skipped"));
+            manifest.posthooks_done = true;
+            manifest.write()?;
+        }
+
+        if !manifest.posthooks_done {
+            // Run post install hooks
+            self.run_hooks(&self.posthooks);
+
+            manifest.posthooks_done = true;
+            manifest.write()?;
+        }
+
+        // Dump current config
+        BombadilState::from(self).write()?;
+
+        if manifest.is_complete() {
+            manifest.remove()?;
+        } else {
+            manifest.print_report();
+        }
+
+        Ok(())
     }
 
-    Ok(())
-}
+    /// Render the enabled autostart entries to `.dots/.autostart` and link them to
+    /// `$XDG_CONFIG/autostart`, entries that fail are reported and skipped.
+    /// Returns the rendered entries and their target.
+    fn link_autostart(&self, dotfiles_dir: &Path) -> Vec<(PathBuf, PathBuf)> {
+        let mut keys: Vec<&String> = self.autostart.keys().collect();
+        keys.sort();
+        let mut linked_entries = vec![];
+
+        for key in keys {
+            let entry = &self.autostart[key];
+            if !entry.is_enabled() {
+                debug!("Autostart entry `{}` is disabled", key);
+                continue;
+            }
 
-pub enum MetadataType {
-    Dots,
-    PreHooks,
-    PostHooks,
-    Path,
-    Profiles,
-    Vars,
-    Secrets,
-}
+            let copy_path = autostart::copy_path(dotfiles_dir, key);
+            let linked = autostart::target_path(key).and_then(|target| {
+                let content = entry.desktop_entry(key)?;
+                let content = self.vars.render_content(&copy_path, &content)?;
+                for dir in [copy_path.parent(), target.parent()].iter().flatten() {
+                    fs::create_dir_all(dir)?;
+                }
+                fs::write(&copy_path, content)?;
+                unlink(&target)?;
+                symlink_or_copy(&copy_path, &target)?;
+                Ok(target)
+            });
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::dots::{DotOverride, DotVar};
-    use crate::Mode::NoGpg;
-    use std::collections::HashMap;
-    use std::fs;
-    use std::fs::read_link;
-    use std::os::unix;
-    use temp_testdir::TempDir;
+            match linked {
+                Ok(target) => {
+                    info!("{}", logger::linked(&copy_path, &target));
+                    linked_entries.push((copy_path, target));
+                }
+                Err(err) => {
+                    let entry = format!("autostart `{}`", key).red();
+                    error!(
+                        "{}",
+                        logger::outcome(Outcome::Failed, format!("{} : {}", entry, err))
+                    )
+                }
+            }
+        }
+
+        linked_entries
+    }
+
+    /// Write the default applications to `.dots/.mimeapps.list` and link it to
+    /// `$XDG_CONFIG/mimeapps.list`, applications that are not installed are only reported
+    fn link_mimeapps(&self, dotfiles_dir: &Path) -> Option<(PathBuf, PathBuf)> {
+        if self.defaults.is_empty() {
+            return None;
+        }
+
+        let application_dirs = mimeapps::application_dirs();
+        for desktop_file in self.defaults.desktop_files() {
+            if !mimeapps::is_installed(desktop_file, &application_dirs) {
+                let warning = format!("Default application {} is not installed", desktop_file);
+                warn!("{}", warning.yellow());
+            }
+        }
+
+        let copy_path = mimeapps::copy_path(dotfiles_dir);
+        let linked = mimeapps::target_path().and_then(|target| {
+            for dir in [copy_path.parent(), target.parent()].iter().flatten() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&copy_path, self.defaults.mimeapps_list())?;
+            unlink(&target)?;
+            symlink_or_copy(&copy_path, &target)?;
+            Ok(target)
+        });
+
+        match linked {
+            Ok(target) => {
+                info!("{}", logger::linked(&copy_path, &target));
+                Some((copy_path, target))
+            }
+            Err(err) => {
+                let line = format!("{} : {}", "mimeapps.list".red(), err);
+                error!("{}", logger::outcome(Outcome::Failed, line));
+                None
+            }
+        }
+    }
+
+    /// Remove the targets of the previous link. Files recorded in the state manifest are only
+    /// removed when unchanged since they were linked, even if their dot left the config.
+    fn remove_previous_targets(
+        &self,
+        state: &BombadilState,
+        dotfiles_dir: &Path,
+    ) -> Vec<Result<PathBuf>> {
+        let mut created = match self.read_state_manifest() {
+            Some(created) => created,
+            None => return state.remove_targets(&[]),
+        };
+
+        let recorded = created.targets(dotfiles_dir);
+        let mut results = state.remove_targets(&recorded);
+        results.extend(created.remove_targets(dotfiles_dir));
+        if let Err(err) = created.write() {
+            warn!("Unable to write the state manifest : {}", err);
+        }
+
+        results
+    }
+
+    /// The manifest of the created files, `None` when they are not recorded or it is unreadable
+    fn read_state_manifest(&self) -> Option<StateManifest> {
+        let path = self.state_manifest.as_ref()?;
+        StateManifest::read(path)
+            .map_err(|err| warn!("{}", err))
+            .ok()
+    }
+
+    /// Targets of the enabled autostart entries
+    fn autostart_targets(&self) -> Vec<PathBuf> {
+        self.autostart
+            .iter()
+            .filter(|(_, entry)| entry.is_enabled() && entry.exec.is_some())
+            .filter_map(|(key, _)| autostart::target_path(key).ok())
+            .collect()
+    }
+
+    /// Target of the generated `mimeapps.list`, when default applications are set
+    fn mimeapps_target(&self) -> Option<PathBuf> {
+        if self.defaults.is_empty() {
+            return None;
+        }
+
+        mimeapps::target_path().ok()
+    }
+
+    /// Render the given dots to `target`, the files of every dot are rendered in parallel.
+    /// A dot whose inputs hash the same as when it was rendered to the previous generation
+    /// reuses its rendered files instead, unless they were edited. Returns the result of each
+    /// dot, `Ok(false)` when it was not rendered.
+    fn render_dots(
+        &self,
+        keys: &[&String],
+        dotfiles_dir: &Path,
+        target: RenderTarget,
+    ) -> HashMap<String, Result<bool>> {
+        let mut results = HashMap::new();
+        let mut pending = vec![];
+        let mut renderings = vec![];
+        let mut hashes: BTreeMap<PathBuf, InputHashes> = BTreeMap::new();
+        let mut previous_hashes: BTreeMap<PathBuf, InputHashes> = BTreeMap::new();
+        for key in keys.iter().filter(|key| self.dots.contains_key(**key)) {
+            let dot = &self.dots[*key];
+            let dot_dir = dot.dotfiles_dir(dotfiles_dir);
+            let dots_dir = dot_dir.join(".dots");
+            let copy_dir = target.copy_dir(&dots_dir);
+            let rendering = dot.prepare_render(
+                &dot_dir,
+                &copy_dir.join(&dot.source),
+                &self.vars,
+                self.get_auto_ignored_files(key),
+                self.gpg.as_ref(),
+            );
+
+            let rendering = match rendering {
+                Ok(rendering) => rendering,
+                Err(err) => {
+                    results.insert((*key).clone(), Err(err));
+                    continue;
+                }
+            };
+
+            let inputs = Some(&rendering)
+                .filter(|rendering| rendering.is_reproducible())
+                .and_then(|rendering| rendering.input_hash(&copy_dir).ok());
+            let reused = match (&inputs, target.previous_dir(&dots_dir)) {
+                (Some(inputs), Some(previous_dir)) if !self.rerender => {
+                    let previous = previous_hashes
+                        .entry(previous_dir.clone())
+                        .or_insert_with(|| InputHashes::read(&previous_dir));
+                    reuse_render(key, &rendering, inputs, previous, &copy_dir, &previous_dir)
+                }
+                _ => None,
+            };
+
+            let copy_hashes = hashes
+                .entry(copy_dir.clone())
+                .or_insert_with(|| InputHashes::read(&copy_dir));
+            copy_hashes.dots.remove(*key);
+            match reused {
+                Some(dot_hashes) => {
+                    copy_hashes.dots.insert((*key).clone(), dot_hashes);
+                    results.insert((*key).clone(), Ok(false));
+                }
+                None => {
+                    pending.push(((*key).clone(), copy_dir, inputs));
+                    renderings.push(rendering);
+                }
+            }
+        }
+
+        let rendered = dots::render_all(&renderings);
+        for (((key, copy_dir, inputs), rendering), result) in
+            pending.iter().zip(&renderings).zip(rendered)
+        {
+            let outputs = inputs
+                .as_ref()
+                .filter(|_| result.is_ok())
+                .and_then(|_| rendering.output_hash(copy_dir, copy_dir).ok());
+            if let (Some(inputs), Some(outputs)) = (inputs, outputs) {
+                let dot_hashes = DotHashes {
+                    inputs: inputs.clone(),
+                    outputs,
+                };
+                if let Some(copy_hashes) = hashes.get_mut(copy_dir) {
+                    copy_hashes.dots.insert(key.clone(), dot_hashes);
+                }
+            }
+            results.insert(key.clone(), result.map(|_| true));
+        }
+
+        for copy_hashes in hashes.values() {
+            if let Err(err) = copy_hashes.write() {
+                warn!("{:?} : {}", copy_hashes.path, err);
+            }
+        }
+        results
+    }
+
+    /// Every dot was reused at the last link and the previous generation had the same dots,
+    /// post install hooks have nothing new to act on
+    fn is_unchanged(&self, dotfiles_dir: &Path) -> bool {
+        if self.rerender || self.dots.is_empty() {
+            return false;
+        }
+
+        self.copy_dirs(dotfiles_dir).iter().all(|copy_dir| {
+            let current = InputHashes::read(copy_dir);
+            let previous = state::previous_generation(copy_dir)
+                .map(|previous_dir| InputHashes::read(&previous_dir));
+            let hashed = self
+                .dots
+                .iter()
+                .filter(|(_, dot)| dot.dotfiles_dir(dotfiles_dir).join(".dots") == *copy_dir)
+                .all(|(key, _)| current.dots.contains_key(key));
+
+            hashed && previous.is_some_and(|previous| previous.dots == current.dots)
+        })
+    }
+
+    /// Render and link a dot, the targets it replaces are saved in the `journal` if any
+    fn link_dot(
+        &self,
+        key: &str,
+        dot: &Dot,
+        absolute_path_to_dot: &Path,
+        journal: Option<&mut LinkJournal>,
+    ) -> Result<()> {
+        let dotfiles_dir = dot.dotfiles_dir(absolute_path_to_dot);
+        debug!("Linking dot `{}`", key);
+        if let Some(journal) = journal {
+            journal.backup(
+                &dot.targets(&dotfiles_dir)?,
+                &self.copy_dirs(absolute_path_to_dot),
+            )?;
+        }
+
+        dot.unlink()?;
+        dot.symlink(&dotfiles_dir)
+    }
+
+    /// Discard the previous links and the replaced files outside of the `[retention]` policy,
+    /// every link already does it for the generations it keeps
+    pub fn gc(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut pruned = state::Pruned::default();
+        for copy_dir in self.copy_dirs(&dotfiles_dir) {
+            pruned.add(state::prune_generations(&copy_dir, &self.retention)?);
+        }
+        info!(
+            "{}",
+            t!(
+                "gc-done",
+                generations = pruned.generations,
+                backups = pruned.backups,
+                bytes = pruned.bytes
+            )
+            .green()
+        );
+        Ok(())
+    }
+
+    /// Watch the dotfiles directory and relink the dots whose source or vars changed, then run
+    /// post install hooks. Returns when the bombadil configuration changes so it can be reloaded.
+    pub fn watch(&self) -> Result<()> {
+        self.check_dotfile_dir()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+
+        let config_files: Vec<PathBuf> = [
+            Settings::config_path_from(self.config.as_deref()),
+            Settings::local_config_xdg_path(),
+        ]
+        .iter()
+        .filter_map(|path| path.as_ref().ok())
+        .map(|path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf()))
+        .collect();
+
+        let mut roots = vec![dotfiles_dir.clone()];
+        roots.extend(self.dots.values().filter_map(|dot| dot.repository.clone()));
+        roots.extend(self.var_paths.iter().cloned());
+        roots.extend(config_files.iter().cloned());
+        roots.sort();
+        roots.dedup();
+
+        let mut directories: Vec<PathBuf> = roots
+            .iter()
+            .map(|root| match root.is_dir() {
+                true => root.clone(),
+                false => root.parent().map(Path::to_path_buf).unwrap_or_default(),
+            })
+            .collect();
+        directories.sort();
+        directories.dedup();
+
+        info!(
+            "{} {}",
+            "Watching for changes in".green(),
+            dotfiles_dir.display()
+        );
+
+        let mut watcher = Watcher::new(&directories)?;
+        let mut snapshot = Snapshot::take(&roots);
+        loop {
+            watcher.wait()?;
+            // Watch again before looking at the files, the changes made meanwhile wake up the next wait
+            watcher = Watcher::new(&directories)?;
+            let current = Snapshot::take(&roots);
+
+            if current.changed(&snapshot, &config_files) {
+                info!("{}", "Configuration changed, reloading".yellow());
+                return Ok(());
+            }
+
+            let vars_changed = current.changed(&snapshot, &self.var_paths);
+            let changed = self.affected_dots(&dotfiles_dir, |inputs| {
+                vars_changed || current.changed(&snapshot, inputs)
+            });
+            snapshot = current;
+
+            self.relink(changed, &dotfiles_dir)?;
+        }
+    }
+
+    /// Pull the dotfiles repository, rebasing local commits on top of the upstream changes,
+    /// and print the updated files
+    pub fn pull(&self) -> Result<Vec<PathBuf>> {
+        self.check_dotfile_dir()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?.canonicalize()?;
+        let mut changed = git::pull_rebase(&dotfiles_dir)?;
+        // Submodules are checked out at their new commit after the pull, or cloned if missing
+        changed.extend(git::update_submodules(&dotfiles_dir)?);
+        changed.sort();
+        changed.dedup();
+
+        if changed.is_empty() {
+            info!("{}", "Already up to date".green());
+        } else {
+            info!("{}", "Updated files:".green());
+            changed.iter().for_each(|path| {
+                let path = path.strip_prefix(&dotfiles_dir).unwrap_or(path);
+                info!("\t{}", path.display());
+            });
+        }
+
+        Ok(changed)
+    }
+
+    /// Fetch the dotfiles repository without changing it, returns the new upstream commits when
+    /// they change the active dots or the configuration
+    pub fn fetch_upstream(&self) -> Result<Option<UpstreamUpdate>> {
+        self.check_dotfile_dir()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let upstream = git::fetch_upstream(&dotfiles_dir.canonicalize()?)?;
+        if upstream.commits.is_empty() {
+            debug!("Upstream has no new commits");
+            return Ok(None);
+        }
+
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let is_changed = |path: &PathBuf| {
+            let path = canonical(path);
+            upstream.files.iter().any(|file| file.starts_with(&path))
+        };
+        let config_changed = self.config_changed(&dotfiles_dir, &upstream.files);
+        let vars_changed = self.var_paths.iter().any(is_changed);
+        let dots: Vec<String> = self
+            .affected_dots(&dotfiles_dir, |inputs| {
+                vars_changed || inputs.iter().any(is_changed)
+            })
+            .into_iter()
+            .cloned()
+            .collect();
+
+        if dots.is_empty() && !config_changed {
+            debug!(
+                "{} upstream commit(s) not affecting the active profiles",
+                upstream.commits.len()
+            );
+            return Ok(None);
+        }
+
+        Ok(Some(UpstreamUpdate {
+            head: upstream.head,
+            commits: upstream.commits,
+            dots,
+            config_changed,
+        }))
+    }
+
+    /// Print the upstream `update` and run the `sync_notify` command, or send a desktop
+    /// notification with `notify-send` when none is set. The command gets the number of new
+    /// commits in `BOMBADIL_COMMITS`, the affected dots in `BOMBADIL_DOTS` and a summary in
+    /// `BOMBADIL_SUMMARY`.
+    pub fn notify_upstream(&self, update: &UpstreamUpdate) {
+        let mut affected = update.dots.clone();
+        if update.config_changed {
+            affected.insert(0, "configuration".to_string());
+        }
+        let summary = format!(
+            "{} new commit(s) changing {}",
+            update.commits.len(),
+            affected.join(", ")
+        );
+
+        info!("{}", summary.yellow());
+        update
+            .commits
+            .iter()
+            .for_each(|commit| info!("\t{}", commit));
+
+        let env = [
+            ("BOMBADIL_COMMITS", update.commits.len().to_string()),
+            ("BOMBADIL_DOTS", update.dots.join(",")),
+            ("BOMBADIL_SUMMARY", summary.clone()),
+        ];
+        let result = match &self.sync_notify {
+            Some(hook) => hook.run_with_env(&env),
+            None => std::process::Command::new("notify-send")
+                .args(["Dotfiles update", &summary])
+                .status()
+                .map(|_| ())
+                .map_err(|err| anyhow!("Unable to run notify-send : {}", err)),
+        };
+
+        if let Err(err) = result {
+            debug!("{}", err);
+        }
+    }
+
+    /// Print the state of the dotfiles repository submodules that need attention
+    pub fn print_submodules(&self) -> Result<()> {
+        for submodule in self.submodule_entries()? {
+            let row = format!(
+                "submodule {}: ({})",
+                submodule.path.display(),
+                submodule.status.to_string().yellow()
+            );
+            println!("{}", logger::outcome(Outcome::Changed, row));
+        }
+
+        Ok(())
+    }
+
+    /// Submodules that are not up to date, relative to the dotfiles directory
+    fn submodule_entries(&self) -> Result<Vec<SubmoduleEntry>> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?.canonicalize()?;
+        let states = match git::submodule_states(&dotfiles_dir) {
+            Ok(states) => states,
+            // Not a git repository, or no submodules
+            Err(_) => return Ok(vec![]),
+        };
+
+        Ok(states
+            .into_iter()
+            .filter(|(_, state)| *state != SubmoduleState::UpToDate)
+            .map(|(path, status)| SubmoduleEntry {
+                path: path
+                    .strip_prefix(&dotfiles_dir)
+                    .map(Path::to_path_buf)
+                    .unwrap_or(path),
+                status,
+            })
+            .collect())
+    }
+
+    /// Relink the dots affected by the `changed` files and run post install hooks.
+    /// Everything is relinked if the bombadil configuration changed.
+    pub fn relink_changed(&self, changed: &[PathBuf]) -> Result<()> {
+        self.refuse_mock_secrets()?;
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        // Changed files are reported relative to the canonical git work tree
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        if self.config_changed(&dotfiles_dir, changed) {
+            info!("{}", "Configuration changed, relinking everything".yellow());
+            return self.install();
+        }
+
+        let is_changed = |path: &PathBuf| {
+            let path = canonical(path);
+            changed.iter().any(|file| file.starts_with(&path))
+        };
+        let vars_changed = self.var_paths.iter().any(is_changed);
+        let affected = self.affected_dots(&dotfiles_dir, |inputs| {
+            vars_changed || inputs.iter().any(is_changed)
+        });
+
+        self.relink(affected, &dotfiles_dir)
+    }
+
+    /// Whether the main configuration or one of the files merged into it is among the
+    /// canonical paths `changed`
+    fn config_changed(&self, dotfiles_dir: &Path, changed: &[PathBuf]) -> bool {
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        iter::once(settings::config_file(dotfiles_dir))
+            .chain(self.config_files.iter().cloned())
+            .any(|file| changed.contains(&canonical(&file)))
+    }
+
+    /// Open the source of the dot `key` in `$EDITOR`, then relink it and run the post install
+    /// hooks when it changed
+    pub fn edit(&self, key: &str) -> Result<()> {
+        let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+        self.edit_with(key, &editor)
+    }
+
+    /// Open the source of the dot `key` with the command `editor`, see [`Bombadil::edit`]
+    fn edit_with(&self, key: &str, editor: &str) -> Result<()> {
+        self.check_dotfile_dir()?;
+        let (key, dot) = self
+            .dots
+            .get_key_value(key)
+            .ok_or_else(|| anyhow!("Dot `{}` not found", key))?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let source = dot.dotfiles_dir(&dotfiles_dir).join(&dot.source);
+
+        // The editor can take arguments, ex: `code --wait`
+        let mut words = editor.split_whitespace();
+        let program = words.next().unwrap_or("vi");
+        debug!("Editing {:?} with `{}`", source, editor);
+        let status = std::process::Command::new(program)
+            .args(words)
+            .arg(&source)
+            .status()
+            .map_err(|err| anyhow!("Unable to run `{}` : {}", editor, err))?;
+        if !status.success() {
+            return Err(anyhow!(
+                "`{}` failed with {}, {} was not relinked",
+                editor,
+                status,
+                key
+            ));
+        }
+
+        self.relink(vec![key], &dotfiles_dir)
+    }
+
+    /// Move an existing file or directory to the dotfiles directory, append its dot to
+    /// `bombadil.toml` and link it back. The source is named after the file without its leading
+    /// dot unless `source` is given, ex: `~/.zshrc` is moved to `zshrc`.
+    pub fn add(&mut self, path: &Path, source: Option<&Path>) -> Result<()> {
+        self.check_dotfile_dir()?;
+        let lock = self.lock()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let target = std::env::current_dir()?.join(path);
+        // Resolve `..` and the symlinked directories leading to the target, not the target itself
+        let target = match (target.parent(), target.file_name()) {
+            (Some(parent), Some(name)) => parent.canonicalize().map(|parent| parent.join(name)),
+            _ => target.canonicalize(),
+        }
+        .map_err(|err| anyhow!("Unable to add {:?} : {}", target, err))?;
+        let metadata = fs::symlink_metadata(&target)
+            .map_err(|err| anyhow!("Unable to add {:?} : {}", target, err))?;
+        if metadata.is_symlink() {
+            return Err(anyhow!(
+                "{:?} is a symlink, only files and directories can be added",
+                target
+            ));
+        }
+
+        if target.starts_with(dotfiles_dir.canonicalize()?) {
+            return Err(anyhow!(
+                "{:?} is already in the dotfiles directory {:?}",
+                target,
+                dotfiles_dir
+            ));
+        }
+
+        let managed = self.dots.iter().find(|(_, dot)| {
+            dot.target_path()
+                .is_ok_and(|dot_target| dot_target == target)
+        });
+        if let Some((key, _)) = managed {
+            return Err(anyhow!(
+                "{:?} is already the target of dot `{}`",
+                target,
+                key
+            ));
+        }
+
+        let source = match source {
+            Some(source) if source.is_absolute() => {
+                return Err(anyhow!(
+                    "The source {:?} must be relative to the dotfiles directory",
+                    source
+                ))
+            }
+            Some(source) => source.to_path_buf(),
+            None => target
+                .file_name()
+                .map(|name| name.to_string_lossy().trim_start_matches('.').to_string())
+                .filter(|name| !name.is_empty())
+                .map(PathBuf::from)
+                .ok_or_else(|| anyhow!("Unable to name the source of {:?}", target))?,
+        };
+
+        let source_path = dotfiles_dir.join(&source);
+        if fs::symlink_metadata(&source_path).is_ok() {
+            return Err(anyhow!(
+                "{:?} already exists, choose another source with `--source`",
+                source_path
+            ));
+        }
+
+        // The configuration is checked before moving anything
+        let config_path = self.toml_config()?;
+        let mut taken = self.dots.keys().cloned().collect();
+        let dot = migrate::ImportedDot {
+            name: source
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            source: source.clone(),
+            target: target.clone(),
+            notes: vec![],
+        };
+        let base = self.target_base.clone().or_else(dirs::home_dir);
+        let added = migrate::to_toml(&[dot], Path::new(""), base.as_deref(), &mut taken);
+        let key = taken
+            .into_iter()
+            .find(|key| !self.dots.contains_key(key))
+            .unwrap_or_default();
+        let config = format!("{}{}", fs::read_to_string(&config_path)?, added);
+        toml::from_str::<toml::Value>(&config).map_err(|err| {
+            anyhow!(
+                "Unable to add the dot to {:?}, is there an inline `dots` table ? {}",
+                config_path,
+                err
+            )
+        })?;
+
+        if let Some(parent) = source_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::rename(&target, &source_path).is_err() {
+            // The dotfiles directory is on another file system
+            copy_recursively(&target, &source_path)?;
+            unlink(&target)?;
+        }
+        fs::write(&config_path, config)?;
+        info!(
+            "{} {:?} to {:?} as dot `{}`",
+            "Moved".green(),
+            target,
+            source_path,
+            key
+        );
+
+        self.dots.insert(
+            key.clone(),
+            Dot {
+                source,
+                target,
+                ..Default::default()
+            },
+        );
+        self.relink_locked(vec![&key], &dotfiles_dir, &lock)?;
+
+        if self.git.auto_commit {
+            let message = format!("bombadil: add dot `{}`", key);
+            self.commit(&[source_path, config_path], &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop managing the dot `key` : remove it from the configuration file declaring it and
+    /// replace its links with a copy of the rendered files, so the programs using them keep
+    /// working. The dot source stays in the dotfiles directory.
+    pub fn remove(&self, key: &str) -> Result<()> {
+        self.check_dotfile_dir()?;
+        let _lock = self.lock()?;
+        let (key, dot) = self
+            .dots
+            .get_key_value(key)
+            .ok_or_else(|| anyhow!("Dot `{}` not found", key))?;
+        let origin = self.provenance.dots.get(key).ok_or_else(|| {
+            anyhow!(
+                "Dot `{}` is declared by a profile, remove it from the profile by hand",
+                key
+            )
+        })?;
+
+        let line = origin.line.ok_or_else(|| {
+            anyhow!(
+                "Unable to find dot `{}` in {:?}, remove it by hand",
+                key,
+                origin.file
+            )
+        })?;
+        if origin.file.extension() != Some(std::ffi::OsStr::new("toml")) {
+            return Err(anyhow!(
+                "Dots can only be removed from a toml configuration, {:?} is not",
+                origin.file
+            ));
+        }
+        let config = settings::without_dot(&fs::read_to_string(&origin.file)?, key, line)?;
+
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let dot_dir = dot.dotfiles_dir(&dotfiles_dir);
+        let links = dot.links(&dot_dir)?;
+        let mut errors = vec![];
+        for (rendered, target) in &links {
+            match fs::read_link(target) {
+                // Copied dots and targets that are gone or not ours are left untouched
+                Ok(destination) if destination == *rendered => {}
+                _ => continue,
+            }
+
+            if let Err(err) = replace_with_copy(rendered, target) {
+                errors.push(format!("{:?} : {}", target, err));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}\n{}",
+                format!(
+                    "Unable to replace some links of `{}`, it is still managed :",
+                    key
+                )
+                .red(),
+                errors.join("\n\t"),
+                format!("Fix these targets and run `bombadil remove {}` again", key).yellow()
+            ));
+        }
+
+        fs::write(&origin.file, config)?;
+        unlink(&dot.copy_path(&dot_dir))?;
+
+        // The restored files now belong to the user, the next link must not remove them
+        let targets: Vec<PathBuf> = links.into_iter().map(|(_, target)| target).collect();
+        if let Ok(mut state) = BombadilState::read(dotfiles_dir.clone()) {
+            state.symlinks.retain(|target| !targets.contains(target));
+            state.write()?;
+        }
+        if let Some(mut created) = self.read_state_manifest() {
+            created.forget_targets(&targets);
+            created.write()?;
+        }
+
+        info!(
+            "{} `{}` from {:?}, its source {:?} was kept",
+            "Removed".green(),
+            key,
+            origin.file,
+            dot_dir.join(&dot.source)
+        );
+
+        if self.git.auto_commit {
+            let message = format!("bombadil: remove dot `{}`", key);
+            self.commit(std::slice::from_ref(&origin.file), &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Keys of the dots for which `changed` returns true given their source and var paths
+    fn affected_dots<F>(&self, dotfiles_dir: &Path, changed: F) -> Vec<&String>
+    where
+        F: Fn(&[PathBuf]) -> bool,
+    {
+        let mut affected: Vec<&String> = self
+            .dots
+            .iter()
+            .filter(|(_, dot)| {
+                let dotfiles_dir = dot.dotfiles_dir(dotfiles_dir);
+                let mut inputs = vec![dotfiles_dir.join(&dot.source)];
+                inputs.extend(dot.resolve_var_path(&dotfiles_dir));
+                changed(&inputs)
+            })
+            .map(|(key, _)| key)
+            .collect();
+
+        affected.sort();
+        affected
+    }
+
+    /// Link the given dots, then run post install hooks and save the state
+    fn relink(&self, keys: Vec<&String>, dotfiles_dir: &Path) -> Result<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        let lock = self.lock()?;
+        self.relink_locked(keys, dotfiles_dir, &lock)
+    }
+
+    /// Same as [`Bombadil::relink`] for a command already holding the run lock `_lock`
+    fn relink_locked(
+        &self,
+        keys: Vec<&String>,
+        dotfiles_dir: &Path,
+        _lock: &RunLock,
+    ) -> Result<()> {
+        let keys: Vec<&String> = keys
+            .into_iter()
+            .filter(|key| {
+                let edited = !self.force && !self.edited_files(key).is_empty();
+                if edited {
+                    let warning = format!(
+                        "Skipping {}, its rendered files were edited since the last link",
+                        key
+                    )
+                    .yellow();
+                    warn!("{}", warning);
+                }
+                !edited
+            })
+            .collect();
+
+        let mut rendered = self.render_dots(&keys, dotfiles_dir, RenderTarget::InPlace);
+        let mut changed = false;
+        for key in keys {
+            let result = rendered.remove(key).unwrap_or(Ok(true)).and_then(|render| {
+                changed |= render;
+                self.link_dot(key, &self.dots[key], dotfiles_dir, None)
+            });
+            if let Err(err) = result {
+                error!("{}", logger::outcome(Outcome::Failed, err));
+            }
+        }
+
+        if changed {
+            self.run_hooks(&self.posthooks);
+        } else {
+            info!("{}", t!("posthooks-skipped"));
+        }
+
+        BombadilState::from(self).write()
+    }
+
+    /// Unlink dotfiles according to previous state
+    pub fn uninstall(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        let mut success_paths: Vec<&PathBuf> = Vec::new();
+        let mut error_paths: Vec<&anyhow::Error> = Vec::new();
+
+        // Remove symlink from previous state
+        let path = self.dotfiles_absolute_path()?;
+        let previous_state = BombadilState::read(path.clone())?;
+        let remove_result = self.remove_previous_targets(&previous_state, &path);
+
+        remove_result
+            .iter()
+            .for_each(|remove_result| match remove_result {
+                Ok(path) => success_paths.push(path),
+                Err(e) => error_paths.push(e),
+            });
+
+        if !success_paths.is_empty() {
+            info!("{}", "Removed symlinks:".green());
+            success_paths.iter().for_each(|path| {
+                let path_string = format!("\t{:?}", path).green();
+                info!("{}", path_string);
+            });
+        }
+
+        if !error_paths.is_empty() {
+            error!("{}", "Error removing symlinks:".red());
+            error_paths.iter().for_each(|path| {
+                let path_string = format!("\t{:?}", path).red();
+                error!("{}", path_string);
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Undo the last link : remove its links, put back the targets it replaced, then restore the
+    /// dots rendered by the link before it and link them again. Each rollback undoes one more
+    /// link, as long as `keep_generations` kept it.
+    pub fn rollback(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let dot_copy_dir = dotfiles_dir.join(".dots");
+        let previous_dir = state::previous_generation(&dot_copy_dir)
+            .ok_or_else(|| anyhow!("No previous link to roll back to"))?;
+        let journal = LinkJournal::read(&dot_copy_dir)?;
+        let previous_journal = LinkJournal::read(&previous_dir)?;
+        if previous_journal.links.is_empty() {
+            return Err(anyhow!(
+                "The previous link did not record its links, unable to roll back"
+            ));
+        }
+
+        let mut errors = vec![];
+        // Targets replaced since the last link are not ours anymore, they are left untouched
+        let mut kept = vec![];
+        for link in &journal.links {
+            match fs::read_link(&link.target) {
+                Ok(destination) if destination == link.source => {
+                    if let Err(err) = unlink(&link.target) {
+                        errors.push(format!("{:?} : {}", link.target, err));
+                    }
+                }
+                _ if fs::symlink_metadata(&link.target).is_err() => {}
+                _ => {
+                    warn!(
+                        "{}",
+                        format!(
+                            "Kept {:?}, it no longer links to {:?} since the last link",
+                            link.target, link.source
+                        )
+                        .yellow()
+                    );
+                    kept.push(&link.target);
+                }
+            }
+        }
+        for backup in &journal.backups {
+            if fs::symlink_metadata(&backup.target).is_err() {
+                match backup.restore(&dot_copy_dir) {
+                    Ok(()) => info!("{}", format!("Restored {:?}", backup.target).green()),
+                    Err(err) => errors.push(format!("{:?} : {}", backup.target, err)),
+                }
+            }
+        }
+
+        for copy_dir in self.copy_dirs(&dotfiles_dir) {
+            state::restore_generation(&copy_dir)?;
+        }
+
+        let mut relinked = vec![];
+        for link in &previous_journal.links {
+            if kept.contains(&&link.target) {
+                continue;
+            }
+            let linked = link
+                .target
+                .parent()
+                .map_or(Ok(()), fs::create_dir_all)
+                .map_err(anyhow::Error::from)
+                .and_then(|_| unlink(&link.target))
+                .and_then(|_| symlink_or_copy(&link.source, &link.target));
+            match linked {
+                Ok(_) => {
+                    info!("{}", logger::linked(&link.source, &link.target));
+                    relinked.push((link.source.clone(), link.target.clone()));
+                }
+                Err(err) => errors.push(format!("{:?} : {}", link.target, err)),
+            }
+        }
+
+        let mut created = self.read_state_manifest();
+        if let Some(created) = created.as_mut() {
+            let targets: Vec<PathBuf> = journal
+                .links
+                .iter()
+                .map(|link| link.target.clone())
+                .collect();
+            created.forget_targets(&targets);
+        }
+        record_created(&mut created, &dotfiles_dir, relinked);
+        if let Some(created) = created {
+            created.write()?;
+        }
+
+        // A bare `bombadil link` enables the same profiles as before the undone link
+        if let Some(profiles) = journal.last_profiles {
+            ProfileState {
+                dotfiles_dir: self.path.clone(),
+                profiles,
+                theme: self.theme.clone(),
+            }
+            .write()?;
+        }
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}",
+                "Rolled back with errors :".red(),
+                errors.join("\n\t")
+            ));
+        }
+
+        info!("{}", "Rolled back to the previous link".green());
+        Ok(())
+    }
+
+    /// Replace every symlink of the previous link with a plain copy of the rendered dot, then
+    /// remove the `.dots` directories. The machine stays configured without bombadil state.
+    pub fn eject(&self) -> Result<()> {
+        let _lock = self.lock()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let previous_state = BombadilState::read(dotfiles_dir.clone())?;
+        let copy_dirs = self.copy_dirs(&dotfiles_dir);
+
+        let mut ejected = vec![];
+        let mut errors = vec![];
+        for target in &previous_state.symlinks {
+            let rendered = match fs::read_link(target) {
+                Ok(rendered) if copy_dirs.iter().any(|dir| rendered.starts_with(dir)) => rendered,
+                // Copied dots and targets that are gone or not ours are left untouched
+                _ => continue,
+            };
+
+            match replace_with_copy(&rendered, target) {
+                Ok(()) => ejected.push(target),
+                Err(err) => errors.push(format!("{:?} : {}", target, err)),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}\n{}",
+                "Unable to replace some symlinks, `.dots` was kept :".red(),
+                errors.join("\n\t"),
+                "Fix these targets and run `bombadil eject` again".yellow()
+            ));
+        }
+
+        for copy_dir in copy_dirs.iter().filter(|dir| dir.exists()) {
+            fs::remove_dir_all(copy_dir)?;
+        }
+
+        // The copies now belong to the user
+        if let Some(mut created) = self.read_state_manifest() {
+            created.forget(&dotfiles_dir);
+            created.write()?;
+        }
+
+        info!("{}", "Replaced symlinks with their content :".green());
+        ejected
+            .iter()
+            .for_each(|target| info!("{}", format!("\t{:?}", target).green()));
+
+        Ok(())
+    }
+
+    /// Find the links of the previous link whose dot left the configuration, or that now
+    /// have another target, and the rendered files of `.dots` no dot uses anymore
+    pub fn clean_plan(&self) -> Result<CleanPlan> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let copy_dirs = self.copy_dirs(&dotfiles_dir);
+
+        let mut candidates = BombadilState::read(dotfiles_dir.clone())
+            .map(|state| state.symlinks)
+            .unwrap_or_default();
+        if let Some(created) = self.read_state_manifest() {
+            candidates.extend(created.targets(&dotfiles_dir));
+        }
+        let current = BombadilState::from(self).symlinks;
+
+        let mut kept = state::state_files(&dotfiles_dir);
+        kept.extend(
+            copy_dirs
+                .iter()
+                .flat_map(|copy_dir| state::generation_files(copy_dir)),
+        );
+        kept.extend(
+            self.dots
+                .values()
+                .map(|dot| dot.copy_path(&dot.dotfiles_dir(&dotfiles_dir))),
+        );
+        kept.extend(
+            self.autostart
+                .iter()
+                .filter(|(_, entry)| entry.is_enabled() && entry.exec.is_some())
+                .map(|(key, _)| autostart::copy_path(&dotfiles_dir, key)),
+        );
+        if !self.defaults.is_empty() {
+            kept.push(mimeapps::copy_path(&dotfiles_dir));
+        }
+
+        Ok(CleanPlan {
+            orphaned_links: clean::orphaned_links(&candidates, &copy_dirs, &current),
+            stale_renders: copy_dirs
+                .iter()
+                .flat_map(|copy_dir| clean::stale_renders(copy_dir, &kept))
+                .collect(),
+        })
+    }
+
+    /// Remove what `plan` found, and forget the removed links
+    pub fn clean(&self, plan: &CleanPlan) -> Result<()> {
+        let _lock = self.lock()?;
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut errors = vec![];
+        for path in plan.orphaned_links.iter().chain(&plan.stale_renders) {
+            match unlink(path) {
+                Ok(()) => info!("{}", format!("Removed {:?}", path).green()),
+                Err(err) => errors.push(format!("{:?} : {}", path, err)),
+            }
+        }
+
+        if let Ok(mut state) = BombadilState::read(dotfiles_dir.clone()) {
+            state
+                .symlinks
+                .retain(|target| !plan.orphaned_links.contains(target));
+            state.write()?;
+        }
+        if let Some(mut created) = self.read_state_manifest() {
+            created.forget_targets(&plan.orphaned_links);
+            created.write()?;
+        }
+
+        if !errors.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}",
+                "Unable to remove :".red(),
+                errors.join("\n\t")
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Add a gpg secret encrypted variable to the target variable file
+    pub fn add_secret<S: AsRef<Path> + ?Sized>(
+        &self,
+        key: &str,
+        value: &str,
+        var_file: &S,
+    ) -> Result<()> {
+        if let Some(gpg) = &self.gpg {
+            gpg.push_secret(key, value, var_file)?;
+        } else {
+            return Err(anyhow!("No gpg_user_id in bombadil config"));
+        }
+
+        if self.git.auto_commit {
+            let var_file = var_file.as_ref();
+            let message = format!("bombadil: add secret `{}` to {}", key, var_file.display());
+            self.commit(&[var_file.to_path_buf()], &message)?;
+        }
+
+        Ok(())
+    }
+
+    /// Commit every change in the dotfiles repository, pushing it if `auto_push` is enabled
+    pub fn save(&self, message: &str) -> Result<()> {
+        self.commit(&[], message)
+    }
+
+    /// Commit `paths`, or every change if empty, and push if `auto_push` is enabled
+    fn commit(&self, paths: &[PathBuf], message: &str) -> Result<()> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+
+        match git::commit(&dotfiles_dir, paths, message)? {
+            Some(oid) => info!("{} {} : {}", "Committed".green(), oid, message),
+            None => {
+                info!("{}", "Nothing to commit".yellow());
+                return Ok(());
+            }
+        }
+
+        if self.git.auto_push {
+            git::push(&dotfiles_dir)?;
+            info!("{}", "Pushed to origin".green());
+        }
+
+        Ok(())
+    }
+
+    /// Append a dot to `bombadil.toml` for every file stow would link from `stow_dir`,
+    /// see [`migrate::stow_dots`]. The stow directory must be inside the dotfiles directory.
+    pub fn import_stow(
+        &self,
+        stow_dir: &Path,
+        target_dir: &Path,
+        dotfiles: bool,
+        dry_run: bool,
+    ) -> Result<()> {
+        let (stow_dir, prefix) = self.imported_dir(stow_dir)?;
+        let dots = migrate::stow_dots(&stow_dir, target_dir, dotfiles)?;
+        self.append_dots(dots, &prefix, dry_run)
+    }
+
+    /// Append a dot to `bombadil.toml` for every target of the chezmoi source directory
+    /// `source_dir`, see [`migrate::chezmoi_dots`]. Dots needing manual conversion are
+    /// annotated with a `TODO` comment, scripts and chezmoi special files are reported.
+    pub fn import_chezmoi(
+        &self,
+        source_dir: &Path,
+        target_dir: &Path,
+        dry_run: bool,
+    ) -> Result<()> {
+        let (source_dir, prefix) = self.imported_dir(source_dir)?;
+        let (dots, warnings) = migrate::chezmoi_dots(&source_dir, target_dir)?;
+        let to_convert = dots.iter().filter(|dot| !dot.notes.is_empty()).count();
+        self.append_dots(dots, &prefix, dry_run)?;
+
+        if to_convert > 0 {
+            let warning = format!(
+                "{} dots need a manual conversion, look for `TODO` comments in bombadil.toml",
+                to_convert
+            );
+            warn!("{}", warning.yellow());
+        }
+
+        for warning in warnings {
+            warn!("{} {}", "Not imported".yellow(), warning);
+        }
+
+        Ok(())
+    }
+
+    /// The canonical path of an imported directory and its path relative to the dotfiles directory
+    fn imported_dir(&self, dir: &Path) -> Result<(PathBuf, PathBuf)> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?.canonicalize()?;
+        let dir = dir
+            .canonicalize()
+            .map_err(|err| anyhow!("Unable to find {:?} : {}", dir, err))?;
+        let prefix = dir
+            .strip_prefix(&dotfiles_dir)
+            .map_err(|_| {
+                anyhow!(
+                    "{:?} is not inside your dotfiles directory {:?}, move it there first",
+                    dir,
+                    dotfiles_dir
+                )
+            })?
+            .to_path_buf();
+
+        Ok((dir, prefix))
+    }
+
+    /// Convert a dotbot configuration to a `dotbot.toml` file imported by `bombadil.toml`,
+    /// see [`migrate::dotbot`]. The dotbot directory must be inside the dotfiles directory.
+    pub fn import_dotbot(&self, config: &Path, dry_run: bool) -> Result<()> {
+        let config = config
+            .canonicalize()
+            .map_err(|err| anyhow!("Unable to find {:?} : {}", config, err))?;
+        let base_dir = config.parent().unwrap_or_else(|| Path::new("/"));
+        let (base_dir, prefix) = self.imported_dir(base_dir)?;
+        let home = dirs::home_dir().ok_or_else(|| anyhow!("$HOME dir not found"))?;
+
+        let import = migrate::dotbot(&config, &home)?;
+        let dots = self.unmanaged(import.dots, &prefix);
+        let to_convert = dots.iter().filter(|dot| !dot.notes.is_empty()).count();
+
+        // Hooks run from the dotbot directory, like dotbot does
+        let directory = match base_dir.strip_prefix(&home) {
+            Ok(relative) => format!("\"$HOME/{}\"", relative.display()),
+            Err(_) => format!("\"{}\"", base_dir.display()),
+        };
+
+        let mut taken = self.dots.keys().cloned().collect();
+        let imported = format!(
+            "# Converted from {} with `bombadil import dotbot`\n[settings]\n{}{}{}",
+            prefix
+                .join(config.file_name().unwrap_or_default())
+                .display(),
+            migrate::to_hooks_toml("prehooks", &import.prehooks, &directory),
+            migrate::to_hooks_toml("posthooks", &import.posthooks, &directory),
+            migrate::to_toml(&dots, &prefix, Some(&home), &mut taken)
+        );
+
+        if dry_run {
+            print!("{}", imported);
+        } else {
+            let dotfiles_dir = self.dotfiles_absolute_path()?;
+            let import_path = dotfiles_dir.join(DOTBOT_IMPORT);
+            if import_path.exists() {
+                return Err(anyhow!(
+                    "{:?} already exists, remove it to import dotbot again",
+                    import_path
+                ));
+            }
+
+            let config_path = self.toml_config()?;
+            let config = format!(
+                "{}\n[[import]]\npath = \"{}\"\n",
+                fs::read_to_string(&config_path)?,
+                DOTBOT_IMPORT
+            );
+            toml::from_str::<toml::Value>(&config).map_err(|err| {
+                anyhow!(
+                    "Unable to import {} in {:?}, is there an inline `import` array ? {}",
+                    DOTBOT_IMPORT,
+                    config_path,
+                    err
+                )
+            })?;
+
+            fs::write(&import_path, imported)?;
+            fs::write(&config_path, config)?;
+            info!(
+                "{} {} dots and {} hooks to {:?}",
+                "Imported".green(),
+                dots.len(),
+                import.prehooks.len() + import.posthooks.len(),
+                import_path
+            );
+        }
+
+        if to_convert > 0 {
+            let warning = format!(
+                "{} dots need a manual conversion, look for `TODO` comments in {}",
+                to_convert, DOTBOT_IMPORT
+            );
+            warn!("{}", warning.yellow());
+        }
+
+        for warning in import.warnings {
+            warn!("{} {}", "Not imported".yellow(), warning);
+        }
+
+        Ok(())
+    }
+
+    /// The imported dots whose source is not managed yet
+    fn unmanaged(
+        &self,
+        dots: Vec<migrate::ImportedDot>,
+        prefix: &Path,
+    ) -> Vec<migrate::ImportedDot> {
+        let managed: Vec<PathBuf> = self.dots.values().map(|dot| dot.source.clone()).collect();
+        dots.into_iter()
+            .filter(|dot| !managed.contains(&prefix.join(&dot.source)))
+            .collect()
+    }
+
+    /// The configuration imported dots are appended to, only toml configurations are supported
+    fn toml_config(&self) -> Result<PathBuf> {
+        let config_path = settings::config_file(&self.dotfiles_absolute_path()?);
+        if config_path.extension() == Some(std::ffi::OsStr::new("toml")) {
+            Ok(config_path)
+        } else {
+            Err(anyhow!(
+                "Imported dots can only be appended to a toml configuration, {:?} is not",
+                config_path
+            ))
+        }
+    }
+
+    /// Append the imported dots to `bombadil.toml`, skipping the ones whose source is already managed
+    fn append_dots(
+        &self,
+        dots: Vec<migrate::ImportedDot>,
+        prefix: &Path,
+        dry_run: bool,
+    ) -> Result<()> {
+        let dots = self.unmanaged(dots, prefix);
+        if dots.is_empty() {
+            info!("{}", "Nothing to import".yellow());
+            return Ok(());
+        }
+
+        let config_path = self.toml_config()?;
+        let config = fs::read_to_string(&config_path)?;
+        let mut taken = self.dots.keys().cloned().collect();
+        let imported = migrate::to_toml(&dots, prefix, dirs::home_dir().as_deref(), &mut taken);
+
+        if dry_run {
+            print!("{}", imported);
+            return Ok(());
+        }
+
+        let config = format!("{}{}", config, imported);
+        toml::from_str::<toml::Value>(&config).map_err(|err| {
+            anyhow!(
+                "Unable to add the imported dots to {:?}, is there an inline `dots` table ? {}",
+                config_path,
+                err
+            )
+        })?;
+
+        fs::write(&config_path, config)?;
+        info!(
+            "{} {} dots to {:?}",
+            "Imported".green(),
+            dots.len(),
+            config_path
+        );
+        Ok(())
+    }
+
+    /// Pretty print current bombadil variables
+    pub fn display_vars(&self) {
+        self.vars
+            .variables
+            .iter()
+            .for_each(|(key, value)| println!("{} = {}", key.red(), value))
+    }
+
+    /// Get the names of the profiles whose machine matchers (hostname, os, distro, session, locale,
+    /// keyboard) match the current machine
+    pub fn matching_profiles(&self) -> Vec<String> {
+        let facts = Facts::gather();
+        let mut matching: Vec<String> = self
+            .profiles
+            .iter()
+            .filter(|(_, profile)| profile.matches(&facts))
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        matching.sort();
+        matching
+    }
+
+    /// Enable a dotfile profile by merging its config with the default profile
+    /// `extra_profiles` are resolved transitively, see [`Bombadil::resolve_profiles`]
+    pub fn enable_profiles(&mut self, profile_keys: Vec<&str>) -> Result<()> {
+        let mut resolved = self.resolve_profiles(&profile_keys)?;
+        // The overlay applies on top of every other profile
+        resolved.sort_by_key(|resolved| resolved.name == OVERLAY_PROFILE);
+
+        let conflicts = self.conflicting_overrides(&resolved);
+        if !conflicts.is_empty() {
+            return Err(anyhow!(
+                "{}\n\t{}\n{}",
+                "Enabled profiles override the same dots differently :".red(),
+                conflicts.join("\n\t"),
+                "Enable only one of them, or remove the override from one of them"
+            ));
+        }
+
+        let profiles: Vec<Profile> = resolved
+            .iter()
+            .filter_map(|resolved| self.profiles.get(&resolved.name))
+            .cloned()
+            .collect();
+
+        for resolved in resolved {
+            // The overlay only lasts for this run, it is not recorded as enabled
+            if resolved.name != OVERLAY_PROFILE && !self.active_profiles.contains(&resolved.name) {
+                self.active_profiles.push(resolved.name);
+            }
+        }
+        self.vars
+            .variables
+            .insert(PROFILES_VAR.to_string(), self.active_profiles.join(","));
+
+        // Placeholders are interpolated again once the profile vars are merged
+        for (key, (source, target)) in self.declared_paths.drain() {
+            if let Some(dot) = self.dots.get_mut(&key) {
+                dot.source = source;
+                dot.target = target;
+            }
+        }
+
+        // Profiles with `inherit_hooks = false` replace the default hooks
+        if profiles
+            .iter()
+            .any(|profile| profile.inherit_hooks == Some(false))
+        {
+            debug!("Default hooks replaced by a profile with `inherit_hooks = false`");
+            self.prehooks.clear();
+            self.posthooks.clear();
+        }
+
+        // Merge profile dots
+        for profile in profiles.iter() {
+            profile.dots.iter().for_each(|(key, dot_override)| {
+                // Dot exist let's override
+                if let Some(dot) = self.dots.get_mut(key) {
+                    if let Some(source) = &dot_override.source {
+                        dot.source = source.clone();
+                        dot.repository = dot_override.repository.clone();
+                    }
+
+                    if let Some(target) = dot_override.target() {
+                        dot.target = target.clone()
+                    }
+
+                    if let Some(vars) = &dot_override.vars {
+                        dot.vars = vars.clone();
+                    }
+
+                    if let Some(merge) = dot_override.merge {
+                        dot.merge = merge;
+                    }
+
+                    if let Some(comment) = &dot_override.comment {
+                        dot.comment = Some(comment.clone());
+                    }
+
+                    if let Some(chmod) = dot_override.chmod {
+                        dot.chmod = Some(chmod);
+                    }
+
+                    if let Some(dir_mode) = dot_override.dir_mode {
+                        dot.dir_mode = Some(dir_mode);
+                    }
+
+                    if let Some(chown) = &dot_override.chown {
+                        dot.chown = Some(chown.clone());
+                    }
+
+                    if let Some(template) = dot_override.template {
+                        dot.template = template;
+                    }
+
+                    if let Some(link) = dot_override.link {
+                        dot.link = Some(link);
+                    }
+
+                    if let (None, None, None, None, None, None, None, None, None, None) = (
+                        &dot_override.source,
+                        &dot_override.target,
+                        &dot_override.vars,
+                        &dot_override.merge,
+                        &dot_override.comment,
+                        &dot_override.chmod,
+                        &dot_override.dir_mode,
+                        &dot_override.chown,
+                        &dot_override.template,
+                        &dot_override.link,
+                    ) {
+                        let warning = format!(
+                            "Skipping {}, no `source`, `target`, `vars`, `merge`, `comment`, `chmod`, `dir_mode`, `chown`, `template` or `link` to override",
+                            key
+                        )
+                        .yellow();
+                        warn!("{}", warning);
+                    }
+                // Nothing to override, let's create a new dot entry
+                } else if dot_override.target.is_some() && dot_override.target().is_none() {
+                    debug!("Dot `{}` has no target for {}", key, std::env::consts::OS);
+                } else if let (Some(source), Some(target)) =
+                    (&dot_override.source, &dot_override.target)
+                {
+                    let source = source.clone();
+                    let target = target.clone();
+                    let ignore = dot_override.ignore.clone();
+
+                    self.dots.insert(
+                        key.to_string(),
+                        Dot {
+                            source,
+                            target,
+                            ignore,
+                            vars: Dot::default_vars(),
+                            merge: dot_override.merge.unwrap_or_default(),
+                            repository: dot_override.repository.clone(),
+                            comment: dot_override.comment.clone(),
+                            chmod: dot_override.chmod,
+                            dir_mode: dot_override.dir_mode,
+                            chown: dot_override.chown.clone(),
+                            template: dot_override.template.unwrap_or(true),
+                            link: dot_override.link,
+                        },
+                    );
+                } else {
+                    if dot_override.source.is_none() {
+                        let warning = format!("`source` field missing for {}", key).yellow();
+                        warn!("{}", warning);
+                    }
+
+                    if dot_override.target.is_none() {
+                        let warning = format!("`target` field missing for {}", key).yellow();
+                        warn!("{}", warning);
+                    }
+                }
+            });
+
+            for (key, entry) in profile.autostart.iter() {
+                self.autostart.entry(key.clone()).or_default().merge(entry);
+            }
+            self.defaults.merge(&profile.defaults);
+
+            // Remove dots excluded by the profile
+            for key in profile.dots_exclude.iter() {
+                debug!("Dot `{}` excluded by `dots_exclude`", key);
+                if self.dots.remove(key).is_none() {
+                    let warning = format!("Cannot exclude {}, no such dot", key).yellow();
+                    warn!("{}", warning);
+                }
+            }
+
+            // Add profile vars
+            let dotfiles_dir = &self.path;
+            self.var_paths.extend(
+                profile
+                    .vars
+                    .iter()
+                    .map(|var_path| dotfiles_dir.join(var_path)),
+            );
+            let variables = Variables::from_paths(&self.path, &profile.vars, self.gpg.as_ref())?;
+            self.vars.extend(variables);
+            // Add profile pre and post hooks, a hook shared by combined profiles runs once
+            let prehooks = profile.prehooks.iter().map(|entry| self.hook(entry));
+            let prehooks: Vec<Hook> = prehooks.collect();
+            add_hooks(&mut self.prehooks, prehooks);
+            let posthooks = profile.posthooks.iter().map(|entry| self.hook(entry));
+            let posthooks: Vec<Hook> = posthooks.collect();
+            add_hooks(&mut self.posthooks, posthooks);
+        }
+
+        self.merge_theme_vars()?;
+        for (key, value) in self.overlay_variables.drain() {
+            self.vars.insert(&key, &value);
+        }
+        self.interpolate_dot_paths();
+
+        // Deploy every dot under the profile target prefix
+        let mut prefixes: Vec<&PathBuf> = profiles
+            .iter()
+            .filter_map(|profile| profile.target_prefix.as_ref())
+            .collect();
+        prefixes.sort();
+        prefixes.dedup();
+
+        match prefixes.as_slice() {
+            [] => {}
+            [prefix] => self
+                .dots
+                .values_mut()
+                .for_each(|dot| dot.prefix_target(prefix)),
+            _ => {
+                return Err(anyhow!(
+                    "Enabled profiles declare conflicting `target_prefix` : {:?}",
+                    prefixes
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Dots whose `source` or `target` are overridden with different values by several of the
+    /// `resolved` profiles. The overlay applies on top of them and never conflicts, nor does a
+    /// profile with the profiles it pulls in with `extra_profiles` : the last one applied wins.
+    fn conflicting_overrides(&self, resolved: &[ResolvedProfile]) -> Vec<String> {
+        let profiles: Vec<(&String, &Profile)> = resolved
+            .iter()
+            .filter(|resolved| resolved.name != OVERLAY_PROFILE)
+            .filter_map(|resolved| self.profiles.get_key_value(&resolved.name))
+            .collect();
+
+        let mut conflicts = vec![];
+        for (index, (name, profile)) in profiles.iter().enumerate() {
+            for (other_name, other) in &profiles[index + 1..] {
+                if self.extends(name, other_name) || self.extends(other_name, name) {
+                    continue;
+                }
+
+                for (key, dot) in &profile.dots {
+                    let other_dot = match other.dots.get(key) {
+                        Some(other_dot) => other_dot,
+                        None => continue,
+                    };
+
+                    if let (Some(source), Some(other_source)) = (&dot.source, &other_dot.source) {
+                        if (source, &dot.repository) != (other_source, &other_dot.repository) {
+                            conflicts.push(format!(
+                                "{} : `{}` sources it from {:?} and `{}` from {:?}",
+                                key, name, source, other_name, other_source
+                            ));
+                        }
+                    }
+
+                    if let (Some(target), Some(other_target)) = (dot.target(), other_dot.target()) {
+                        if target != other_target {
+                            conflicts.push(format!(
+                                "{} : `{}` links it to {:?} and `{}` to {:?}",
+                                key, name, target, other_name, other_target
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        conflicts.sort();
+        conflicts
+    }
+
+    /// Whether the profile `name` pulls in `other` with its `extra_profiles`, transitively
+    fn extends(&self, name: &str, other: &str) -> bool {
+        let mut visited = vec![];
+        let mut pending = vec![name];
+        while let Some(name) = pending.pop() {
+            if visited.contains(&name) {
+                continue;
+            }
+            visited.push(name);
+
+            let extra_profiles = self
+                .profiles
+                .get(name)
+                .map(|profile| profile.extra_profiles.as_slice())
+                .unwrap_or_default();
+            if extra_profiles
+                .iter()
+                .any(|extra_profile| extra_profile == other)
+            {
+                return true;
+            }
+            pending.extend(extra_profiles.iter().map(String::as_str));
+        }
+
+        false
+    }
+
+    /// Add the overlay as the `@overlay` profile, enable it with the other profiles
+    pub fn add_overlay(&mut self, overlay: Overlay) {
+        self.profiles
+            .insert(OVERLAY_PROFILE.to_string(), overlay.profile);
+        self.overlay_variables = overlay.variables;
+    }
+
+    /// Resolve the given profiles and their `extra_profiles` transitively. Each profile appears once,
+    /// profiles given explicitly come first followed by the profiles they pull in, level by level.
+    /// Returns an error if a profile does not exist or if `extra_profiles` contain a cycle.
+    pub fn resolve_profiles(&self, profile_keys: &[&str]) -> Result<Vec<ResolvedProfile>> {
+        let mut resolved = vec![];
+
+        for key in profile_keys {
+            if !self.profiles.contains_key(*key) {
+                return Err(anyhow!("Profile `{}` does not exist", key));
+            }
+
+            self.visit_profile(key, &mut vec![], &mut resolved)?;
+        }
+
+        // Stable sort, keeps the order in which profiles were found at each level
+        resolved.sort_by_key(|profile| profile.via.len());
+        Ok(resolved)
+    }
+
+    fn visit_profile(
+        &self,
+        name: &str,
+        stack: &mut Vec<String>,
+        resolved: &mut Vec<ResolvedProfile>,
+    ) -> Result<()> {
+        if let Some(position) = stack.iter().position(|parent| parent == name) {
+            let mut cycle = stack[position..].to_vec();
+            cycle.push(name.to_string());
+            return Err(anyhow!(
+                "Cycle detected in extra_profiles : {}",
+                cycle.join(" -> ")
+            ));
+        }
+
+        if resolved.iter().any(|profile| profile.name == name) {
+            return Ok(());
+        }
+
+        let profile = match self.profiles.get(name) {
+            Some(profile) => profile,
+            None => {
+                let warning = format!(
+                    "Profile `{}` referenced in extra_profiles of `{}` does not exist",
+                    name,
+                    stack.last().map(String::as_str).unwrap_or_default()
+                )
+                .yellow();
+                warn!("{}", warning);
+                return Ok(());
+            }
+        };
+
+        resolved.push(ResolvedProfile {
+            name: name.to_string(),
+            via: stack.clone(),
+        });
+
+        stack.push(name.to_string());
+        for extra_profile in profile.extra_profiles.iter() {
+            self.visit_profile(extra_profile, stack, resolved)?;
+        }
+        stack.pop();
+
+        Ok(())
+    }
+
+    /// Print the profiles enabled by the given profile keys and which profile pulled them in
+    pub fn explain_profiles(&self, profile_keys: &[&str]) -> Result<()> {
+        println!("{}", "Resolved profiles :".green());
+        for profile in self.resolve_profiles(profile_keys)? {
+            if profile.via.is_empty() {
+                println!("\t{}", profile.name);
+            } else {
+                println!("\t{} (via {})", profile.name, profile.via.join(" -> "));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_dotfile_dir(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Err(anyhow!(t!(
+                "dotfiles-path-not-found",
+                path = self.path.display()
+            )));
+        }
+
+        if !self.path.is_dir() {
+            let err = t!("dotfiles-path-not-dir", path = format!("{:?}", self.path));
+            return Err(anyhow!(err.red()));
+        }
+
+        Ok(())
+    }
+
+    /// Load Bombadil config from a `bombadil.toml`, `config` replaces the configuration in use,
+    /// see [`Settings::get_from`]
+    pub fn from_settings(mode: Mode, config_path: Option<&Path>) -> Result<Bombadil> {
+        let config = Settings::get_from(config_path)?;
+        let path = config.get_dotfiles_path()?;
+
+        let gpg_user_id = config.gpg_user_id.clone();
+        let gpg = match mode {
+            Mode::Gpg => config.gpg_user_id.map(|user_id| Gpg::new(&user_id)),
+            Mode::NoGpg | Mode::SkipSecrets | Mode::Degraded | Mode::MockSecrets(_) => None,
+        };
+
+        // Resolve variables from path, secrets are decrypted once gpg is known to work
+        let mut vars = Variables::from_paths(&path, &config.settings.vars, None)?;
+        vars.skip_secrets = matches!(mode, Mode::SkipSecrets | Mode::Degraded);
+        if let Mode::Degraded = mode {
+            let sentinel = config.settings.secret_sentinel.as_deref();
+            vars.degraded = Some(DegradedSecrets::new(
+                sentinel.unwrap_or(DEFAULT_SECRET_SENTINEL),
+            ));
+        }
+        if let Mode::MockSecrets(fixture) = &mode {
+            vars.mock_secrets = Some(MockSecrets::read(fixture)?);
+        }
+
+        if config.settings.audit_secrets {
+            vars.secret_audit = Some(SecretAudit::new()?);
+        }
+        vars.allow_commands = config.settings.allow_cmd_templates;
+        vars.provenance_header = config.settings.provenance_header;
+        vars.provenance_exclude = config.settings.provenance_header_exclude.clone();
+
+        // Replace % reference with their ref value
+        vars.resolve_ref();
+        vars.variables
+            .entry(PROFILES_VAR.to_string())
+            .or_insert_with(String::new);
+        let facts = Facts::gather();
+        if let Some(session) = facts.session_name() {
+            vars.variables
+                .insert(SESSION_VAR.to_string(), session.to_string());
+        }
+        if let Some(locale) = &facts.locale {
+            vars.variables
+                .insert(LOCALE_VAR.to_string(), locale.to_string());
+        }
+        if !facts.keyboard_layouts.is_empty() {
+            vars.variables
+                .insert(KEYBOARD_VAR.to_string(), facts.keyboard_layouts.join(","));
+        }
+
+        // Resolve hooks from config, they run from the dotfiles directory by default
+        let hook_cwd = config.settings.hook_cwd.clone();
+        let hook = |entry: &HookEntry| Hook::from(entry).in_dir(&path, hook_cwd.as_deref());
+        let posthooks = config.settings.posthooks.iter().map(hook).collect();
+
+        let prehooks = config.settings.prehooks.iter().map(hook).collect();
+        let sync_notify = config.settings.sync_notify.as_ref().map(hook);
+        let var_paths = config
+            .settings
+            .vars
+            .iter()
+            .map(|var_path| path.join(var_path))
+            .collect();
+        let dots = config
+            .settings
+            .dots
+            .into_iter()
+            .filter(|(key, dot)| {
+                let has_target = !dot.target.as_os_str().is_empty();
+                if !has_target {
+                    debug!("Dot `{}` has no target for {}", key, std::env::consts::OS);
+                }
+                has_target
+            })
+            .collect();
+        let profiles = config.profiles;
+        let themes = config.themes;
+        let theme = ProfileState::read()
+            .ok()
+            .filter(|state| state.dotfiles_dir == path)
+            .and_then(|state| state.theme)
+            .filter(|theme| {
+                let exists = themes.contains_key(theme);
+                if !exists {
+                    let warning =
+                        format!("Theme `{}` no longer exists, not enabling it", theme).yellow();
+                    warn!("{}", warning);
+                }
+                exists
+            });
+        vars.variables
+            .insert(THEME_VAR.to_string(), theme.clone().unwrap_or_default());
+        let target_base = match config.settings.target_base {
+            Some(base) if base.is_relative() => dirs::home_dir()
+                .map(|home| Some(home.join(base)))
+                .ok_or_else(|| anyhow!(t!("home-not-found")))?,
+            base => base,
+        };
+
+        let mut bombadil = Self {
+            path,
+            retention: config.retention,
+            dots,
+            declared_paths: HashMap::new(),
+            target_base,
+            autostart: config.settings.autostart,
+            defaults: config.settings.defaults,
+            vars,
+            var_paths,
+            prehooks,
+            posthooks,
+            hook_cwd,
+            run_lock: None,
+            config: config_path.map(Path::to_path_buf),
+            profiles,
+            active_profiles: vec![],
+            themes,
+            theme,
+            gpg,
+            gpg_user_id,
+            warn_untracked: config.settings.warn_untracked,
+            local: config.local,
+            provenance: config.provenance,
+            config_files: config.files,
+            force: false,
+            atomic: false,
+            write_protect: config.settings.write_protect,
+            rerender: false,
+            only_render: false,
+            only_link: false,
+            allow_inside_dotfiles: false,
+            state_manifest: StateManifest::default_path().ok(),
+            overlay_variables: HashMap::new(),
+            diff_tool: config.settings.diff_tool.clone(),
+            sync_notify,
+            git: config.git,
+        };
+        bombadil.merge_theme_vars()?;
+        bombadil.interpolate_dot_paths();
+
+        if let Some(gpg) = &bombadil.gpg {
+            let secrets = bombadil.encrypted_vars();
+            if !secrets.is_empty() {
+                gpg.check_available(&secrets)?;
+                bombadil.vars.decrypt_secrets(gpg)?;
+            }
+        }
+
+        Ok(bombadil)
+    }
+
+    /// Replace the `%var%` placeholders of the dot sources and targets with the value of the
+    /// variable, then root the relative targets in `target_base`. Placeholders of unknown
+    /// variables are left as is, [`Dot::target_path`] expands them as environment variables.
+    fn interpolate_dot_paths(&mut self) {
+        let mut vars = self.vars.clone();
+        vars.resolve_ref();
+        let value = |name: &str| vars.variables.get(name).cloned();
+
+        for (key, dot) in self.dots.iter_mut() {
+            let declared = (dot.source.clone(), dot.target.clone());
+            dot.source = expand_windows_vars(&dot.source, value);
+            dot.target = expand_windows_vars(&dot.target, value);
+            if let Some(base) = &self.target_base {
+                dot.root_target(base);
+            }
+
+            if (&dot.source, &dot.target) != (&declared.0, &declared.1) {
+                debug!(
+                    "Dot `{}` : interpolated {:?} => {:?}",
+                    key, dot.source, dot.target
+                );
+                self.declared_paths.insert(key.clone(), declared);
+            }
+        }
+    }
+
+    /// Names of the encrypted variables in the global, profile and dot var files
+    fn encrypted_vars(&self) -> Vec<String> {
+        let mut var_files: Vec<PathBuf> = self.var_paths.clone();
+        var_files.extend(
+            self.profiles
+                .values()
+                .flat_map(|profile| profile.vars.iter())
+                .map(|var_path| self.path.join(var_path)),
+        );
+        if let Ok(dotfiles_dir) = self.dotfiles_absolute_path() {
+            var_files.extend(self.dots.values().filter_map(|dot| {
+                dot.find_from_source(&dot.dotfiles_dir(&dotfiles_dir), &dot.source, &dot.vars)
+            }));
+        }
+
+        let mut secrets: Vec<String> = var_files
+            .iter()
+            .flat_map(|var_file| gpg::encrypted_vars(var_file))
+            .collect();
+        secrets.sort();
+        secrets.dedup();
+        secrets
+    }
+
+    /// Pretty print metadata, possible values are Dots, PreHooks, PostHook, Path, Profiles, Vars, Secrets
+    pub fn print_metadata(&self, metadata_type: MetadataType) {
+        let rows = match metadata_type {
+            MetadataType::Dots => self
+                .dot_entries(None)
+                .iter()
+                .map(|dot| {
+                    format!(
+                        "{}: {} => {}{}",
+                        dot.name,
+                        dot.source.display(),
+                        dot.target.display(),
+                        self.local_marker(|local| local.dots.contains(&dot.name))
+                    )
+                })
+                .collect(),
+            MetadataType::UnhealthyDots(filter) => self
+                .dot_entries(Some(filter))
+                .iter()
+                .map(|dot| {
+                    let status = match (&dot.status, &dot.points_to) {
+                        (Some(DotHealth::PointsElsewhere), Some(destination)) => {
+                            format!("points to {}", destination.display())
+                        }
+                        (Some(status), _) => status.to_string(),
+                        (None, _) => String::new(),
+                    };
+                    let outcome = match dot.status {
+                        Some(DotHealth::Edited) | Some(DotHealth::Degraded) => Outcome::Changed,
+                        _ => Outcome::Failed,
+                    };
+                    let row = format!(
+                        "{}: {} => {} ({})",
+                        dot.name,
+                        dot.source.display(),
+                        dot.target.display(),
+                        status.yellow()
+                    );
+                    logger::outcome(outcome, row)
+                })
+                .collect(),
+            MetadataType::PreHooks => self
+                .prehooks
+                .iter()
+                .map(|h| {
+                    let marker = self.local_marker(|local| {
+                        local.prehooks.iter().any(|entry| &self.hook(entry) == h)
+                    });
+                    format!("{}{}", h, marker)
+                })
+                .collect(),
+            MetadataType::PostHooks => self
+                .posthooks
+                .iter()
+                .map(|h| {
+                    let marker = self.local_marker(|local| {
+                        local.posthooks.iter().any(|entry| &self.hook(entry) == h)
+                    });
+                    format!("{}{}", h, marker)
+                })
+                .collect(),
+            MetadataType::Path => vec![self.path.display().to_string()],
+            MetadataType::Profiles => {
+                let mut profiles = vec!["default".to_string()];
+                profiles.extend(self.profiles.keys().map(|name| {
+                    let marker = self.local_marker(|local| local.profiles.contains(name));
+                    format!("{}{}", name, marker)
+                }));
+                profiles
+            }
+            MetadataType::Vars => self
+                .vars
+                .variables
+                .iter()
+                .map(|(k, v)| format!("{}: {}", k, v))
+                .collect(),
+            MetadataType::Secrets => self
+                .vars
+                .secrets
+                .iter()
+                .map(|(k, v)| {
+                    self.audit_printed(k);
+                    format!("{}: {}", k, v)
+                })
+                .collect(),
+        };
+
+        if !rows.is_empty() {
+            println!("{}", rows.join("\n"));
+        }
+    }
+
+    /// Record the printed secret `key` in the secret audit log, if enabled
+    fn audit_printed(&self, key: &str) {
+        if let Some(audit) = &self.vars.secret_audit {
+            audit.printed(key);
+        }
+    }
+
+    /// The same metadata as [`Bombadil::print_metadata`], as pretty printed json for scripts
+    pub fn metadata_json(&self, metadata_type: MetadataType) -> Result<String> {
+        let json = match metadata_type {
+            MetadataType::Dots => serde_json::to_string_pretty(&self.dot_entries(None))?,
+            MetadataType::UnhealthyDots(filter) => {
+                serde_json::to_string_pretty(&self.dot_entries(Some(filter)))?
+            }
+            MetadataType::PreHooks => serde_json::to_string_pretty(
+                &self
+                    .prehooks
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            )?,
+            MetadataType::PostHooks => serde_json::to_string_pretty(
+                &self
+                    .posthooks
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+            )?,
+            MetadataType::Path => serde_json::to_string_pretty(&self.path)?,
+            MetadataType::Profiles => {
+                let mut names: Vec<&String> = self.profiles.keys().collect();
+                names.sort();
+                let mut profiles = vec![ProfileEntry {
+                    name: "default".to_string(),
+                    active: true,
+                    local: false,
+                }];
+                profiles.extend(names.into_iter().map(|name| {
+                    ProfileEntry {
+                        name: name.clone(),
+                        active: self.active_profiles.contains(name),
+                        local: self
+                            .local
+                            .as_ref()
+                            .is_some_and(|local| local.profiles.contains(name)),
+                    }
+                }));
+                serde_json::to_string_pretty(&profiles)?
+            }
+            MetadataType::Vars => serde_json::to_string_pretty(
+                &self.vars.variables.iter().collect::<BTreeMap<_, _>>(),
+            )?,
+            MetadataType::Secrets => {
+                self.vars
+                    .secrets
+                    .keys()
+                    .for_each(|key| self.audit_printed(key));
+                serde_json::to_string_pretty(&self.vars.secrets.iter().collect::<BTreeMap<_, _>>())?
+            }
+        };
+
+        Ok(json)
+    }
+
+    /// A resolved setting for `bombadil config get` : `dotfiles_dir`, `render_dir`, `gpg_user_id`,
+    /// `profiles`, `dots`, `dots.<dot>.source`, `dots.<dot>.target` or `vars.<var>`
+    pub fn config_value(&self, key: &str) -> Result<serde_json::Value> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let unknown = || {
+            anyhow!(
+                "Unknown setting `{}`, expected one of dotfiles_dir, render_dir, gpg_user_id, \
+                profiles, dots, provenance, dots.<dot>.source, dots.<dot>.target, \
+                dots.<dot>.origin or vars.<var>",
+                key
+            )
+        };
+
+        let value = match key {
+            "dotfiles_dir" => serde_json::to_value(&dotfiles_dir)?,
+            "render_dir" => serde_json::to_value(dotfiles_dir.join(".dots"))?,
+            "gpg_user_id" => serde_json::to_value(&self.gpg_user_id)?,
+            "profiles" => serde_json::to_value(&self.active_profiles)?,
+            "dots" => {
+                let mut names: Vec<&String> = self.dots.keys().collect();
+                names.sort();
+                serde_json::to_value(names)?
+            }
+            "provenance" => serde_json::to_value(&self.provenance)?,
+            _ => {
+                if let Some(var) = key.strip_prefix("vars.") {
+                    let value = self
+                        .vars
+                        .variables
+                        .get(var)
+                        .ok_or_else(|| anyhow!("Variable `{}` not found", var))?;
+                    return Ok(serde_json::to_value(value)?);
+                }
+
+                let (name, field) = key
+                    .strip_prefix("dots.")
+                    .and_then(|dot| dot.rsplit_once('.'))
+                    .ok_or_else(unknown)?;
+                let dot = self
+                    .dots
+                    .get(name)
+                    .ok_or_else(|| anyhow!("Dot `{}` not found", name))?;
+                match field {
+                    "source" => {
+                        serde_json::to_value(dot.dotfiles_dir(&dotfiles_dir).join(&dot.source))?
+                    }
+                    "target" => serde_json::to_value(dot.target_path()?)?,
+                    "origin" => serde_json::to_value(self.dot_origin(name))?,
+                    _ => return Err(unknown()),
+                }
+            }
+        };
+
+        Ok(value)
+    }
+
+    /// The configuration file declaring the dot `name`, dots of the enabled profiles come from
+    /// the last profile declaring them
+    fn dot_origin(&self, name: &str) -> Option<&settings::Origin> {
+        self.active_profiles
+            .iter()
+            .rev()
+            .filter(|profile| {
+                self.profiles
+                    .get(*profile)
+                    .is_some_and(|profile| profile.dots.contains_key(name))
+            })
+            .find_map(|profile| self.provenance.profiles.get(profile))
+            .or_else(|| self.provenance.dots.get(name))
+    }
+
+    /// `bombadil status` as json : the unhealthy dots and the submodules that are not up to date
+    pub fn status_json(&self) -> Result<String> {
+        let status = StatusReport {
+            dots: self.dot_entries(Some(DotFilter {
+                unlinked: true,
+                broken: true,
+                edited: true,
+                degraded: true,
+            })),
+            submodules: self.submodule_entries()?,
+        };
+
+        Ok(serde_json::to_string_pretty(&status)?)
+    }
+
+    /// Dots sorted by name, only those matching `filter` with their status when filtering
+    fn dot_entries(&self, filter: Option<DotFilter>) -> Vec<DotEntry> {
+        let dotfiles_dir = self
+            .dotfiles_absolute_path()
+            .unwrap_or_else(|_| self.path.clone());
+        let degraded = match filter {
+            Some(filter) if filter.degraded => BombadilState::read(dotfiles_dir.clone())
+                .map(|state| state.degraded)
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+
+        let mut entries: Vec<DotEntry> = self
+            .dots
+            .iter()
+            .filter_map(|(k, v)| {
+                let mut points_to = None;
+                let status = match filter {
+                    None => None,
+                    Some(filter) => Some(match v.link_status(&v.dotfiles_dir(&dotfiles_dir)) {
+                        LinkStatus::Unlinked if filter.unlinked => DotHealth::NotLinked,
+                        LinkStatus::Broken if filter.broken => DotHealth::BrokenLink,
+                        LinkStatus::Unexpected(Some(destination)) if filter.broken => {
+                            points_to = Some(destination);
+                            DotHealth::PointsElsewhere
+                        }
+                        LinkStatus::Unexpected(None) if filter.broken => DotHealth::NotManaged,
+                        LinkStatus::Linked if filter.edited && !self.edited_files(k).is_empty() => {
+                            DotHealth::Edited
+                        }
+                        LinkStatus::Linked
+                            if filter.degraded
+                                && degraded.iter().any(|file| {
+                                    file.starts_with(v.dotfiles_dir(&dotfiles_dir).join(&v.source))
+                                }) =>
+                        {
+                            DotHealth::Degraded
+                        }
+                        _ => return None,
+                    }),
+                };
+
+                Some(DotEntry {
+                    name: k.clone(),
+                    source: v.dotfiles_dir(&self.path).join(&v.source),
+                    target: v.target_path().unwrap_or_else(|_| v.target.clone()),
+                    local: self
+                        .local
+                        .as_ref()
+                        .is_some_and(|local| local.dots.contains(k)),
+                    status,
+                    points_to,
+                })
+            })
+            .collect();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        entries
+    }
+
+    /// Shell functions switching profiles with `theme` and editing dots with `dotedit`
+    pub fn shell_init(&self, shell: InitShell) -> Result<String> {
+        let dotfiles_dir = self.dotfiles_absolute_path()?;
+        let mut profiles: Vec<String> = self.profiles.keys().cloned().collect();
+        profiles.sort();
+
+        // Dots added by profiles can be edited even when their profile is not enabled
+        let mut dots: HashMap<String, PathBuf> = self
+            .profiles
+            .values()
+            .flat_map(|profile| profile.dots.iter())
+            .filter_map(|(name, dot)| {
+                let source = dot.source.as_ref()?;
+                let dotfiles_dir = dot.repository.as_ref().unwrap_or(&dotfiles_dir);
+                Some((name.clone(), dotfiles_dir.join(source)))
+            })
+            .collect();
+        dots.extend(self.dots.iter().map(|(name, dot)| {
+            let source = dot.dotfiles_dir(&dotfiles_dir).join(&dot.source);
+            (name.clone(), source)
+        }));
+
+        let mut dots: Vec<(String, PathBuf)> = dots.into_iter().collect();
+        dots.sort();
+        Ok(shell::init_script(shell, &profiles, &dots))
+    }
+
+    /// Print every profile with its description, the dots it overrides or adds
+    /// and whether it was enabled during the last link
+    pub fn print_profiles(&self) {
+        let active_profiles = self
+            .dotfiles_absolute_path()
+            .and_then(BombadilState::read)
+            .map(|state| state.profiles)
+            .unwrap_or_default();
+
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        for name in names {
+            let profile = &self.profiles[name];
+            if active_profiles.contains(name) {
+                println!("{} {}", name.green().bold(), "(active)".green());
+            } else {
+                println!("{}", name.bold());
+            }
+
+            if let Some(description) = &profile.description {
+                println!("\t{}", description);
+            }
+
+            for (label, values) in self.profile_details(profile) {
+                println!("\t{} : {}", label.blue(), values.join(", "));
+            }
+        }
+    }
+
+    /// Open a terminal picker to choose the profiles to enable, the profiles enabled by the last
+    /// link are preselected. Returns `None` if the selection was aborted.
+    pub fn pick_profiles(&self) -> Result<Option<Vec<String>>> {
+        let mut names: Vec<&String> = self.profiles.keys().collect();
+        names.sort();
+
+        let items = names
+            .into_iter()
+            .map(|name| {
+                let profile = &self.profiles[name];
+                PickerItem {
+                    name: name.clone(),
+                    description: profile.description.clone(),
+                    preview: self
+                        .profile_details(profile)
+                        .into_iter()
+                        .map(|(label, values)| format!("{} : {}", label, values.join(", ")))
+                        .collect(),
+                }
+            })
+            .collect();
+
+        picker::pick(items, &self.last_profiles())
+    }
+
+    /// Non empty lists of dots a profile overrides, adds or excludes and its extra profiles
+    fn profile_details<'a>(&self, profile: &'a Profile) -> Vec<(&'static str, Vec<&'a str>)> {
+        let mut dot_keys: Vec<&String> = profile.dots.keys().collect();
+        dot_keys.sort();
+        let (overrides, added): (Vec<&String>, Vec<&String>) = dot_keys
+            .into_iter()
+            .partition(|key| self.dots.contains_key(*key));
+
+        vec![
+            (
+                "overrides",
+                overrides.iter().map(|key| key.as_str()).collect(),
+            ),
+            ("adds", added.iter().map(|key| key.as_str()).collect()),
+            (
+                "excludes",
+                profile.dots_exclude.iter().map(String::as_str).collect(),
+            ),
+            (
+                "extra profiles",
+                profile.extra_profiles.iter().map(String::as_str).collect(),
+            ),
+            (
+                "hooks",
+                match profile.inherit_hooks {
+                    Some(false) => vec!["replace the default hooks"],
+                    _ => vec![],
+                },
+            ),
+        ]
+        .into_iter()
+        .filter(|(_, values): &(&str, Vec<&str>)| !values.is_empty())
+        .collect()
+    }
+
+    /// ` (local: <path>)` if the entry comes from the machine local configuration
+    fn local_marker<F>(&self, is_local: F) -> String
+    where
+        F: Fn(&LocalProvenance) -> bool,
+    {
+        match &self.local {
+            Some(local) if is_local(local) => {
+                format!(" {}", format!("(local: {})", local.path.display()).yellow())
+            }
+            _ => String::new(),
+        }
+    }
+
+    fn dotfiles_absolute_path(&self) -> Result<PathBuf> {
+        dirs::home_dir()
+            .ok_or_else(|| anyhow!("$HOME dir not found"))
+            .map(|path| path.join(&self.path))
+    }
+
+    /// The `.dots` directories the dots are rendered in, the dotfiles one and those of the
+    /// `[[source]]` repositories
+    fn copy_dirs(&self, dotfiles_dir: &Path) -> Vec<PathBuf> {
+        let mut copy_dirs = vec![dotfiles_dir.join(".dots")];
+        copy_dirs.extend(
+            self.dots
+                .values()
+                .filter_map(|dot| dot.repository.as_ref())
+                .map(|repository| repository.join(".dots")),
+        );
+        copy_dirs.sort();
+        copy_dirs.dedup();
+        copy_dirs
+    }
+
+    fn get_auto_ignored_files(&self, dot_key: &str) -> Vec<PathBuf> {
+        let dot_origin = self.dots.get(dot_key);
+        let origin_source = dot_origin.map(|dot| &dot.source);
+        let origin_dir = dot_origin
+            .map(|dot| dot.dotfiles_dir(&self.path))
+            .unwrap_or_else(|| self.path.clone());
+
+        let mut ignored: Vec<PathBuf> = self
+            .profiles
+            .values()
+            .filter_map(|profile| profile.dots.get(dot_key))
+            .filter(|dot| dot.vars.is_some())
+            .filter_map(|dot| {
+                // Overrides without a source are relative to the overridden dot
+                let dotfiles_dir = match (&dot.source, &dot.repository) {
+                    (Some(_), Some(repository)) => repository.clone(),
+                    (Some(_), None) => self.path.clone(),
+                    (None, _) => origin_dir.clone(),
+                };
+                dot.resolve_var_path(&dotfiles_dir, origin_source)
+            })
+            .collect();
+
+        let _ = dot_origin.map(|dot| {
+            dot.resolve_var_path(&origin_dir)
+                .map(|path| ignored.push(path))
+        });
+
+        ignored
+    }
+}
+
+/// Where [`Bombadil::render_dots`] renders the dots of a `.dots` directory, and where their
+/// previous generation is
+#[derive(Clone, Copy)]
+enum RenderTarget {
+    /// `.dots`, after the last generation was moved to `.dots/.previous`
+    Rotated,
+    /// `.dots`, over the last generation
+    InPlace,
+    /// The staging directory of `.dots`, see [`Bombadil::atomic`]
+    Staging,
+}
+
+impl RenderTarget {
+    fn copy_dir(self, dots_dir: &Path) -> PathBuf {
+        match self {
+            RenderTarget::Staging => state::staging_dir(dots_dir),
+            RenderTarget::Rotated | RenderTarget::InPlace => dots_dir.to_path_buf(),
+        }
+    }
+
+    fn previous_dir(self, dots_dir: &Path) -> Option<PathBuf> {
+        match self {
+            RenderTarget::Rotated => state::previous_generation(dots_dir),
+            RenderTarget::InPlace | RenderTarget::Staging => Some(dots_dir.to_path_buf()),
+        }
+    }
+}
+
+/// Append the `added` hooks missing from `hooks`
+fn add_hooks(hooks: &mut Vec<Hook>, added: Vec<Hook>) {
+    for hook in added {
+        if hooks.contains(&hook) {
+            debug!("Hook `{}` already runs, skipping its copy", hook);
+        } else {
+            hooks.push(hook);
+        }
+    }
+}
+
+/// The color variables of `vars` sorted by name, only those declared in `only` when given.
+/// Bombadil's own variables are left out.
+fn showcase_colors(vars: &Variables, only: Option<&Variables>) -> Vec<(String, String)> {
+    let mut vars = vars.clone();
+    vars.resolve_ref();
+    let mut colors: Vec<(String, String)> = vars
+        .variables
+        .into_iter()
+        .filter(|(name, _)| !name.starts_with("bombadil."))
+        .filter(|(name, _)| only.is_none_or(|only| only.variables.contains_key(name)))
+        .filter(|(_, value)| showcase::is_color(value))
+        .collect();
+    colors.sort();
+    colors
+}
+
+/// Reuse the files of dot `key` rendered to `previous_dir` when its `inputs` and the rendered
+/// files hash the same as `previous` recorded, returns their hashes
+fn reuse_render(
+    key: &str,
+    rendering: &Rendering,
+    inputs: &str,
+    previous: &InputHashes,
+    copy_dir: &Path,
+    previous_dir: &Path,
+) -> Option<DotHashes> {
+    let hashes = previous
+        .dots
+        .get(key)
+        .filter(|hashes| hashes.inputs == inputs)?;
+    match rendering.output_hash(copy_dir, previous_dir) {
+        Ok(outputs) if outputs == hashes.outputs => {}
+        _ => {
+            debug!("`{}` : the rendered files changed, rendering it again", key);
+            return None;
+        }
+    }
+
+    match rendering.reuse(copy_dir, previous_dir) {
+        Ok(()) => {
+            debug!(
+                "`{}` did not change since the last link, not rendering it",
+                key
+            );
+            Some(hashes.clone())
+        }
+        Err(err) => {
+            debug!("`{}` : unable to reuse the rendered files, {}", key, err);
+            None
+        }
+    }
+}
+
+/// Record the linked `(source, target)` pairs in the state manifest, if any
+fn record_created(
+    created: &mut Option<StateManifest>,
+    dotfiles_dir: &Path,
+    links: Vec<(PathBuf, PathBuf)>,
+) {
+    if let Some(created) = created {
+        for (source, target) in links {
+            if let Err(err) = created.record(dotfiles_dir, &source, &target) {
+                warn!(
+                    "Unable to record {:?} in the state manifest : {}",
+                    target, err
+                );
+            }
+        }
+    }
+}
+
+/// Symlink `source` to `target`, when the target filesystem does not support symlinks
+/// (Android shared storage for instance) `source` is copied instead.
+/// On Windows, directories fall back to a junction before being copied.
+/// Returns true if the source was copied.
+/// On WSL, targets on a Windows drive are always copied since Windows programs can't follow
+/// symlinks created from the Linux side.
+pub(crate) fn symlink_or_copy(source: &Path, target: &Path) -> Result<bool> {
+    if facts::on_windows_drive(target) && facts::is_wsl() {
+        copy_recursively(source, target)?;
+        return Ok(true);
+    }
+
+    match symlink(source, target) {
+        Ok(()) => Ok(false),
+        Err(err) if symlink_unsupported(&err) => {
+            #[cfg(windows)]
+            if source.is_dir() && junction(source, target).is_ok() {
+                return Ok(false);
+            }
+
+            copy_recursively(source, target)?;
+            Ok(true)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(source, target)
+}
+
+/// Windows distinguishes file and directory symlinks, creating either requires
+/// Developer Mode or an elevated shell
+#[cfg(windows)]
+pub(crate) fn symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    if source.is_dir() {
+        std::os::windows::fs::symlink_dir(source, target)
+    } else {
+        std::os::windows::fs::symlink_file(source, target)
+    }
+}
+
+/// Directory junctions need no privilege, they are used when symlinks are not allowed
+#[cfg(windows)]
+fn junction(source: &Path, target: &Path) -> Result<()> {
+    let status = std::process::Command::new("cmd")
+        .arg("/c")
+        .arg("mklink")
+        .arg("/J")
+        .arg(target)
+        .arg(source)
+        .stdout(std::process::Stdio::null())
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("mklink /J exited with {}", status))
+    }
+}
+
+fn symlink_unsupported(err: &std::io::Error) -> bool {
+    #[cfg(unix)]
+    const UNSUPPORTED: [i32; 2] = [libc::EPERM, libc::EOPNOTSUPP];
+    // ERROR_INVALID_FUNCTION, returned by filesystems without symlinks, and ERROR_PRIVILEGE_NOT_HELD
+    #[cfg(windows)]
+    const UNSUPPORTED: [i32; 2] = [1, 1314];
+
+    err.kind() == std::io::ErrorKind::Unsupported
+        || err
+            .raw_os_error()
+            .is_some_and(|code| UNSUPPORTED.contains(&code))
+}
+
+pub(crate) fn copy_recursively(source: &Path, target: &Path) -> Result<()> {
+    // Internal symlinks of a directory dot, see `Dot::collect_files`
+    if fs::symlink_metadata(source).is_ok_and(|metadata| metadata.is_symlink()) {
+        if let Ok(()) = fs::read_link(source).and_then(|destination| symlink(&destination, target))
+        {
+            return Ok(());
+        }
+        // Following a link to a directory could loop
+        if source.is_dir() {
+            debug!("{:?} : directory link not copied", source);
+            return Ok(());
+        }
+    }
+
+    if source.is_dir() {
+        fs::create_dir_all(target)?;
+        for entry in source.read_dir()? {
+            let entry = entry?;
+            copy_recursively(&entry.path(), &target.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(source, target)?;
+    }
+
+    Ok(())
+}
+
+/// Replace the link `target` with a copy of `source`. The copy is made next to the link and
+/// renamed over it, so the link stays in place when the copy fails.
+fn replace_with_copy(source: &Path, target: &Path) -> Result<()> {
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| anyhow!("{:?} has no file name", target))?;
+    let mut copy_name = std::ffi::OsString::from(".");
+    copy_name.push(file_name);
+    copy_name.push(".bombadil-copy");
+    let copy = target.with_file_name(copy_name);
+
+    unlink(&copy)?;
+    let replaced = copy_recursively(source, &copy).and_then(|_| {
+        // A directory can't be renamed over a link, the copy is complete when it is removed
+        if fs::symlink_metadata(&copy).is_ok_and(|metadata| metadata.is_dir()) {
+            fs::remove_file(target)?;
+        }
+        fs::rename(&copy, target).map_err(anyhow::Error::from)
+    });
+
+    if replaced.is_err() {
+        let _ = unlink(&copy);
+    }
+    replaced
+}
+
+pub(crate) fn unlink(path: &Path) -> Result<()> {
+    if fs::symlink_metadata(path).is_ok() {
+        if path.is_dir() {
+            fs::remove_dir_all(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// A profile enabled either explicitly or through other profiles `extra_profiles`
+#[derive(Debug, Clone)]
+pub struct ResolvedProfile {
+    pub name: String,
+    /// Chain of profiles that pulled this profile in, empty if it was enabled explicitly
+    pub via: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum MetadataType {
+    Dots,
+    /// Dots whose target is not linked to the rendered dot
+    UnhealthyDots(DotFilter),
+    PreHooks,
+    PostHooks,
+    Path,
+    Profiles,
+    Vars,
+    Secrets,
+}
+
+/// Select dots by the state of their target
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DotFilter {
+    /// Targets that do not exist
+    pub unlinked: bool,
+    /// Broken symlinks, and targets pointing somewhere else
+    pub broken: bool,
+    /// Rendered files edited since the last link
+    pub edited: bool,
+    /// Dots linked with `--degraded` whose templates use secrets
+    pub degraded: bool,
+}
+
+/// A dot, as listed by `bombadil get dots --json`
+#[derive(Debug, Serialize)]
+struct DotEntry {
+    name: String,
+    source: PathBuf,
+    target: PathBuf,
+    /// Added by the machine local configuration
+    local: bool,
+    /// Only set when listing unhealthy dots
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<DotHealth>,
+    /// Where the target points to instead of the rendered dot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    points_to: Option<PathBuf>,
+}
+
+/// Why a dot is listed by `bombadil status`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DotHealth {
+    NotLinked,
+    BrokenLink,
+    PointsElsewhere,
+    NotManaged,
+    Edited,
+    Degraded,
+}
+
+impl fmt::Display for DotHealth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self {
+            DotHealth::NotLinked => "not linked",
+            DotHealth::BrokenLink => "broken link",
+            DotHealth::PointsElsewhere => "points elsewhere",
+            DotHealth::NotManaged => "not managed by bombadil",
+            DotHealth::Edited => "edited since the last link",
+            DotHealth::Degraded => "degraded, secrets rendered with a sentinel",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ProfileEntry {
+    name: String,
+    active: bool,
+    local: bool,
+}
+
+/// What `bombadil clean` removes
+#[derive(Debug, Default)]
+pub struct CleanPlan {
+    /// Symlinks to a rendered dot that is no longer a target of the configuration
+    pub orphaned_links: Vec<PathBuf>,
+    /// Files and directories of `.dots` rendered for dots that are no longer in the configuration
+    pub stale_renders: Vec<PathBuf>,
+}
+
+impl CleanPlan {
+    pub fn is_empty(&self) -> bool {
+        self.orphaned_links.is_empty() && self.stale_renders.is_empty()
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct SubmoduleEntry {
+    path: PathBuf,
+    status: SubmoduleState,
+}
+
+#[derive(Debug, Serialize)]
+struct StatusReport {
+    dots: Vec<DotEntry>,
+    submodules: Vec<SubmoduleEntry>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dots::DotOverride;
+    use crate::Mode::NoGpg;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::fs::read_link;
+    use std::os::unix;
+    use std::os::unix::fs::MetadataExt;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn self_link_works() {
+        // Arrange
+        let config_path = PathBuf::from("tests/dotfiles_simple/");
+
+        // Act
+        Bombadil::link_self_config(Some(config_path)).unwrap();
+
+        // Assert
+        let link = dirs::config_dir().unwrap().join(BOMBADIL_CONFIG);
+        assert!(link.exists());
+    }
+
+    #[test]
+    fn install_single_file_works() {
+        // Arrange
+        let target = TempDir::new("/tmp/dot_target", false).to_path_buf();
+
+        let mut map = HashMap::new();
+        map.insert("red".to_string(), "red_value".to_string());
+
+        let mut dots = HashMap::new();
+        dots.insert(
+            "dot".to_string(),
+            Dot {
+                source: PathBuf::from("template"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+
+        let config = Bombadil {
+            path: PathBuf::from("tests/dotfiles_simple")
+                .canonicalize()
+                .unwrap(),
+            dots,
+            vars: Variables {
+                variables: map,
+                secrets: Default::default(),
+                allow_commands: false,
+                provenance_header: false,
+                provenance_exclude: vec![],
+                render_profiler: None,
+                skip_secrets: false,
+                degraded: None,
+                mock_secrets: None,
+                secret_audit: None,
+            },
+            prehooks: vec![],
+            posthooks: vec![],
+            profiles: Default::default(),
+            ..Default::default()
+        };
+
+        // Act
+        config.install().unwrap();
+
+        // Assert
+        assert!(target.exists());
+        assert_eq!(
+            fs::read_to_string(&target).unwrap(),
+            "color: red_value".to_string()
+        );
+    }
+
+    #[test]
+    fn install_should_fail_and_continue() {
+        // Arrange
+        let target = TempDir::new("/tmp/dot_target", false).to_path_buf();
+
+        let mut dots = HashMap::new();
+
+        dots.insert(
+            "dot".to_string(),
+            Dot {
+                source: PathBuf::from("template"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+        dots.insert(
+            "dot".to_string(),
+            Dot {
+                source: PathBuf::from("invalid_path"),
+                target: PathBuf::from("somewhere"),
+                ..Default::default()
+            },
+        );
+
+        let config = Bombadil {
+            path: PathBuf::from("tests/dotfiles_invalid_dot")
+                .canonicalize()
+                .unwrap(),
+            dots,
+            vars: Variables {
+                variables: HashMap::new(),
+                secrets: Default::default(),
+                allow_commands: false,
+                provenance_header: false,
+                provenance_exclude: vec![],
+                render_profiler: None,
+                skip_secrets: false,
+                degraded: None,
+                mock_secrets: None,
+                secret_audit: None,
+            },
+            prehooks: vec![],
+            posthooks: vec![],
+            profiles: Default::default(),
+            ..Default::default()
+        };
+
+        // Act
+        config.install().unwrap();
+
+        // Assert
+        assert!(target.exists());
+    }
+
+    #[test]
+    fn install_with_subdir() {
+        // Arrange
+        let target = TempDir::new("/tmp/sub_dir_target", false).to_path_buf();
+
+        let mut map = HashMap::new();
+        map.insert("red".to_string(), "red_value".to_string());
+        map.insert("blue".to_string(), "blue_value".to_string());
+
+        let mut dots = HashMap::new();
+
+        dots.insert(
+            "dot".to_string(),
+            Dot {
+                source: PathBuf::from("sub_dir"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+
+        let config = Bombadil {
+            path: PathBuf::from("tests/dotfiles_nested")
+                .canonicalize()
+                .unwrap(),
+            dots,
+            vars: Variables {
+                variables: map,
+                secrets: Default::default(),
+                allow_commands: false,
+                provenance_header: false,
+                provenance_exclude: vec![],
+                render_profiler: None,
+                skip_secrets: false,
+                degraded: None,
+                mock_secrets: None,
+                secret_audit: None,
+            },
+            prehooks: vec![],
+            posthooks: vec![],
+            profiles: Default::default(),
+            ..Default::default()
+        };
+
+        // Act
+        config.install().unwrap();
+
+        // Assert
+        assert!(target.exists());
+        let path = &target.read_link().unwrap();
+        let red_dot = fs::read_to_string(path.join("template_1")).unwrap();
+        let blue_dot = fs::read_to_string(path.join("template_2")).unwrap();
+        assert_eq!(red_dot, "color: red_value".to_string());
+        assert_eq!(blue_dot, "color: blue_value".to_string());
+    }
+
+    #[test]
+    fn install_with_nested_subdirs() {
+        // Arrange
+        let target = TempDir::new("/tmp/sub_dir_2_target", false).to_path_buf();
+
+        let mut map = HashMap::new();
+        map.insert("red".to_string(), "red_value".to_string());
+        map.insert("blue".to_string(), "blue_value".to_string());
+
+        let mut dots = HashMap::new();
+        dots.insert(
+            "dot".to_string(),
+            Dot {
+                source: PathBuf::from("sub_dir_1"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+        let config = Bombadil {
+            path: PathBuf::from("tests/dotfiles_nested_2")
+                .canonicalize()
+                .unwrap(),
+            dots,
+            vars: Variables {
+                variables: map,
+                secrets: Default::default(),
+                allow_commands: false,
+                provenance_header: false,
+                provenance_exclude: vec![],
+                render_profiler: None,
+                skip_secrets: false,
+                degraded: None,
+                mock_secrets: None,
+                secret_audit: None,
+            },
+            prehooks: vec![],
+            posthooks: vec![],
+            profiles: Default::default(),
+            ..Default::default()
+        };
+
+        // Act
+        config.install().unwrap();
+
+        // Assert
+        assert!(target.exists());
+        let path = &target.read_link().unwrap();
+        let red_dot = fs::read_to_string(path.join("template_1")).unwrap();
+        let blue_dot = fs::read_to_string(path.join("subdir_2").join("template_2")).unwrap();
+        assert_eq!(red_dot, "color: red_value".to_string());
+        assert_eq!(blue_dot, "color: blue_value".to_string());
+    }
+
+    #[test]
+    fn should_resume_failed_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.to_path_buf();
+        let target = dotfiles.join("target");
+        fs::write(dotfiles.join("linked"), "linked")?;
+
+        let mut dots = HashMap::new();
+        dots.insert(
+            "linked".to_string(),
+            Dot {
+                source: PathBuf::from("linked"),
+                target: dotfiles.join("linked_target"),
+                ..Default::default()
+            },
+        );
+        dots.insert(
+            "missing".to_string(),
+            Dot {
+                source: PathBuf::from("missing"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            dots,
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
+            active_profiles: vec!["profile".to_string()],
+            ..Default::default()
+        };
+
+        config.install()?;
+        let manifest = RunManifest::read(&dotfiles)?;
+        assert_eq!(manifest.completed, vec!["linked".to_string()]);
+        assert_eq!(manifest.failed[0].dot, "missing");
+        assert_eq!(config.interrupted_run_profiles()?, vec!["profile"]);
+
+        // Act
+        fs::write(dotfiles.join("missing"), "fixed")?;
+        config.resume()?;
+
+        // Assert
+        assert_eq!(fs::read_to_string(&target)?, "fixed");
+        assert!(RunManifest::read(&dotfiles).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_relink_changed_dots_only() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let mut dots = HashMap::new();
+        for key in ["zshrc", "sway"] {
+            fs::write(dotfiles.join(key), "v1")?;
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: dotfiles.join(format!("{}_target", key)),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let import = dotfiles.join("sway.toml");
+        fs::write(&import, "")?;
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            dots,
+            config_files: vec![import.clone()],
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
+            ..Default::default()
+        };
+        config.install()?;
+        fs::write(dotfiles.join("zshrc"), "v2")?;
+        fs::write(dotfiles.join("sway"), "v2")?;
+
+        // Act
+        config.relink_changed(&[dotfiles.join("zshrc")])?;
+        let sway_before_import = fs::read_to_string(dotfiles.join("sway_target"))?;
+        config.relink_changed(&[import])?;
+
+        // Assert
+        assert_eq!(fs::read_to_string(dotfiles.join("zshrc_target"))?, "v2");
+        assert_eq!(sway_before_import, "v1");
+        // An imported configuration file changed, everything is relinked
+        assert_eq!(fs::read_to_string(dotfiles.join("sway_target"))?, "v2");
+        Ok(())
+    }
+
+    #[test]
+    fn should_leave_previous_link_when_atomic_link_fails() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("zshrc"), "v1")?;
+        let dot = |source: &str| Dot {
+            source: PathBuf::from(source),
+            target: dotfiles.join(format!("{}_target", source)),
+            ..Default::default()
+        };
+
+        let mut config = Bombadil {
+            path: dotfiles.clone(),
+            dots: vec![("zshrc".to_string(), dot("zshrc"))]
+                .into_iter()
+                .collect(),
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
+            ..Default::default()
+        };
+        config.install()?;
+        fs::write(dotfiles.join("zshrc"), "v2")?;
+        config.dots.insert("missing".to_string(), dot("missing"));
+        let marker = dotfiles.join("prehook_ran");
+        config.prehooks = vec![Hook::new(&format!("touch {}", marker.display()))];
+        config.atomic();
+
+        // Act
+        let result = config.install();
+
+        // Assert
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(dotfiles.join("zshrc_target"))?, "v1");
+        assert!(!dotfiles.join(".dots.staging").exists());
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_reuse_dots_whose_inputs_did_not_change() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let marker = dotfiles.join("posthook_ran");
+        fs::write(dotfiles.join("zshrc"), "zsh")?;
+        fs::write(dotfiles.join("vimrc"), "v1")?;
+        let dot = |source: &str| Dot {
+            source: PathBuf::from(source),
+            target: dotfiles.join(format!("{}_target", source)),
+            ..Default::default()
+        };
+
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            dots: vec![
+                ("zshrc".to_string(), dot("zshrc")),
+                ("vimrc".to_string(), dot("vimrc")),
+            ]
+            .into_iter()
+            .collect(),
+            posthooks: vec![Hook::new(&format!("touch {}", marker.display()))],
+            allow_inside_dotfiles: true,
+            ..Default::default()
+        };
+        config.install()?;
+        fs::remove_file(&marker)?;
+        fs::write(dotfiles.join("vimrc"), "v2")?;
+        let inode = |path: PathBuf| fs::metadata(path).map(|metadata| metadata.ino());
+
+        // Act
+        config.install()?;
+        let changed_run_hooks = marker.exists();
+        fs::remove_file(&marker)?;
+        config.install()?;
+
+        // Assert
+        let dots = dotfiles.join(".dots");
+        assert_eq!(
+            inode(dots.join("zshrc"))?,
+            inode(dots.join(".previous/zshrc"))?
+        );
+        assert_eq!(fs::read_to_string(dotfiles.join("vimrc_target"))?, "v2");
+        assert_eq!(fs::read_to_string(dotfiles.join("zshrc_target"))?, "zsh");
+        assert!(changed_run_hooks);
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_run_profile_transition_hooks() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.to_path_buf();
+        let activated = dotfiles.join("activated");
+        let deactivated = dotfiles.join("deactivated");
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                on_profile_activate: vec![HookEntry::Command(format!(
+                    "touch {}",
+                    activated.display()
+                ))],
+                on_profile_deactivate: vec![HookEntry::Command(format!(
+                    "touch {}",
+                    deactivated.display()
+                ))],
+                ..Default::default()
+            },
+        );
+
+        let mut config = Bombadil {
+            path: dotfiles.clone(),
+            profiles,
+            ..Default::default()
+        };
+
+        // Act
+        config.enable_profiles(vec!["work"])?;
+        config.install()?;
+        let activated_on_enable = activated.exists();
+        fs::remove_file(&activated)?;
+
+        config.install()?;
+        let activated_twice = activated.exists();
+
+        config.active_profiles.clear();
+        config.install()?;
+
+        // Assert
+        assert!(activated_on_enable);
+        assert!(!activated_twice);
+        assert!(deactivated.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_exclude_profile_dots() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        for key in ["discord", "nvidia", "alacritty"] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: PathBuf::from(key),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                dots_exclude: vec!["discord".to_string(), "nvidia".to_string()],
+                ..Default::default()
+            },
+        );
+
+        let mut config = Bombadil {
+            dots,
+            profiles,
+            ..Default::default()
+        };
+
+        // Act
+        config.enable_profiles(vec!["work"])?;
+
+        // Assert
+        assert_eq!(config.dots.len(), 1);
+        assert!(config.dots.contains_key("alacritty"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_interpolate_dot_paths_with_profile_vars() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        fs::write(temp.join("work.toml"), "terminal = \"kitty\"\n")?;
+        let mut dots = HashMap::new();
+        dots.insert(
+            "terminal".to_string(),
+            Dot {
+                source: PathBuf::from("%terminal%"),
+                target: PathBuf::from(".config/%terminal%/%APPDATA%"),
+                ..Default::default()
+            },
+        );
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                vars: vec![PathBuf::from("work.toml")],
+                ..Default::default()
+            },
+        );
+
+        let mut config = Bombadil {
+            path: temp.to_path_buf(),
+            dots,
+            profiles,
+            ..Default::default()
+        };
+        config.vars.insert("terminal", "alacritty");
+        config.interpolate_dot_paths();
+        let default_target = config.dots["terminal"].target.clone();
+
+        // Act
+        config.enable_profiles(vec!["work"])?;
+
+        // Assert
+        let dot = &config.dots["terminal"];
+        assert_eq!(default_target, PathBuf::from(".config/alacritty/%APPDATA%"));
+        assert_eq!(dot.source, PathBuf::from("kitty"));
+        assert_eq!(dot.target, PathBuf::from(".config/kitty/%APPDATA%"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_root_relative_targets_in_target_base() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        for (key, target) in [
+            ("alacritty", "alacritty"),
+            ("hosts", "/etc/hosts"),
+            ("appdata", "%APPDATA%/alacritty"),
+        ] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: PathBuf::from(target),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                target_prefix: Some(PathBuf::from("work-env")),
+                ..Default::default()
+            },
+        );
+
+        let mut config = Bombadil {
+            dots,
+            profiles,
+            target_base: Some(PathBuf::from("/mnt/home/.config")),
+            ..Default::default()
+        };
+
+        // Act
+        config.interpolate_dot_paths();
+        let targets: Vec<PathBuf> = ["alacritty", "hosts", "appdata"]
+            .iter()
+            .map(|key| config.dots[*key].target.clone())
+            .collect();
+        config.enable_profiles(vec!["work"])?;
+
+        // Assert
+        assert_eq!(
+            targets,
+            vec![
+                PathBuf::from("/mnt/home/.config/alacritty"),
+                PathBuf::from("/etc/hosts"),
+                PathBuf::from("%APPDATA%/alacritty"),
+            ]
+        );
+        assert_eq!(
+            config.dots["alacritty"].target,
+            PathBuf::from("work-env/mnt/home/.config/alacritty")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_overlay_on_top_of_profiles() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        dots.insert(
+            "zsh".to_string(),
+            Dot {
+                source: PathBuf::from("zsh"),
+                target: PathBuf::from("zsh"),
+                ..Default::default()
+            },
+        );
+
+        let work: Profile = toml::from_str("[dots]\nzsh = { target = \"work_zsh\" }")?;
+        let mut profiles = HashMap::new();
+        profiles.insert("work".to_string(), work);
+
+        let overlay: Overlay = toml::from_str(
+            "extra_profiles = [\"work\"]\n\
+             [variables]\n\
+             color = \"blue\"\n\
+             [dots]\n\
+             zsh = { target = \"overlay_zsh\" }",
+        )?;
+        let mut config = Bombadil {
+            dots,
+            profiles,
+            ..Default::default()
+        };
+
+        // Act
+        config.add_overlay(overlay);
+        config.enable_profiles(vec![OVERLAY_PROFILE])?;
+
+        // Assert
+        assert_eq!(config.dots["zsh"].target, PathBuf::from("overlay_zsh"));
+        assert_eq!(config.vars.variables["color"], "blue");
+        assert_eq!(config.active_profiles, vec!["work".to_string()]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_profiles_overriding_a_dot_differently() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        dots.insert(
+            "zsh".to_string(),
+            Dot {
+                source: PathBuf::from("zsh"),
+                target: PathBuf::from("zsh"),
+                ..Default::default()
+            },
+        );
+
+        let mut profiles = HashMap::new();
+        for (name, profile) in [
+            (
+                "work",
+                "[dots]\nzsh = { source = \"zsh-work\", target = \"zsh\" }",
+            ),
+            (
+                "laptop",
+                "[dots]\nzsh = { source = \"zsh-laptop\", target = \"zsh\" }",
+            ),
+            (
+                "dark",
+                "[dots]\nzsh = { source = \"zsh-work\", vars = \"dark.toml\" }",
+            ),
+            (
+                "workstation",
+                "extra_profiles = [\"laptop\"]\n[dots]\nzsh = { source = \"zsh-workstation\" }",
+            ),
+        ] {
+            profiles.insert(name.to_string(), toml::from_str::<Profile>(profile)?);
+        }
+        let config = || Bombadil {
+            dots: dots.clone(),
+            profiles: profiles.clone(),
+            ..Default::default()
+        };
+
+        // Act
+        let conflicting = config().enable_profiles(vec!["work", "laptop"]);
+        let mut compatible = config();
+        compatible.enable_profiles(vec!["work", "dark"])?;
+        let mut extended = config();
+        extended.enable_profiles(vec!["workstation"])?;
+
+        // Assert
+        let err = conflicting.unwrap_err().to_string();
+        assert!(err.contains(
+            "zsh : `work` sources it from \"zsh-work\" and `laptop` from \"zsh-laptop\""
+        ));
+        assert!(!err.contains("links it"));
+        assert_eq!(compatible.dots["zsh"].source, PathBuf::from("zsh-work"));
+        assert_eq!(compatible.dots["zsh"].vars, PathBuf::from("dark.toml"));
+        assert_eq!(extended.dots["zsh"].source, PathBuf::from("zsh-laptop"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_replace_default_hooks_and_run_shared_hooks_once() -> Result<()> {
+        // Arrange
+        let dotfiles = PathBuf::from("/dotfiles");
+        let command = |command: &str| HookEntry::Command(command.to_string());
+        let hook = |command: &str| Hook::new(command).in_dir(&dotfiles, Some(Path::new("scripts")));
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "desktop".to_string(),
+            Profile {
+                posthooks: vec![command("fc-cache -f"), command("swaymsg reload")],
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "fonts".to_string(),
+            Profile {
+                posthooks: vec![command("fc-cache -f")],
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "server".to_string(),
+            Profile {
+                prehooks: vec![command("systemctl --user stop sway")],
+                inherit_hooks: Some(false),
+                ..Default::default()
+            },
+        );
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            hook_cwd: Some(PathBuf::from("scripts")),
+            prehooks: vec![hook("git pull")],
+            posthooks: vec![hook("notify-send linked"), hook("fc-cache -f")],
+            profiles,
+            ..Default::default()
+        };
+
+        // Act
+        let mut combined = config.clone();
+        combined.enable_profiles(vec!["desktop", "fonts"])?;
+        let mut replaced = config.clone();
+        replaced.enable_profiles(vec!["server", "fonts"])?;
+
+        // Assert
+        assert_eq!(combined.prehooks, vec![hook("git pull")]);
+        assert_eq!(
+            combined.posthooks,
+            vec![
+                hook("notify-send linked"),
+                hook("fc-cache -f"),
+                hook("swaymsg reload"),
+            ]
+        );
+        assert_eq!(replaced.prehooks, vec![hook("systemctl --user stop sway")]);
+        assert_eq!(replaced.posthooks, vec![hook("fc-cache -f")]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_showcase_profiles_and_theme_palettes() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("minimal.toml"), "background = \"#ffffff\"\n")?;
+        fs::write(dotfiles.join("dracula.toml"), "foreground = \"#f8f8f2\"\n")?;
+        fs::write(
+            dotfiles.join("README.md"),
+            "# Dotfiles\n<!-- bombadil showcase start -->\n<!-- bombadil showcase end -->\n",
+        )?;
+        let mut dots = HashMap::new();
+        for key in ["kitty", "vim"] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: dotfiles.join(format!("{}_target", key)),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "minimal".to_string(),
+            Profile {
+                description: Some("Servers".to_string()),
+                vars: vec![PathBuf::from("minimal.toml")],
+                dots_exclude: vec!["vim".to_string()],
+                ..Default::default()
+            },
+        );
+        let mut themes = HashMap::new();
+        themes.insert(
+            "dracula".to_string(),
+            Theme {
+                vars: vec![PathBuf::from("dracula.toml")],
+                ..Default::default()
+            },
+        );
+
+        let mut variables = HashMap::new();
+        variables.insert("background".to_string(), "#000000".to_string());
+        variables.insert("font".to_string(), "mono".to_string());
+        variables.insert(THEME_VAR.to_string(), "#abc".to_string());
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            dots,
+            profiles,
+            themes,
+            vars: Variables {
+                variables,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Act
+        config.showcase(false, Some(&dotfiles.join("README.md")))?;
+        config.showcase(false, Some(&dotfiles.join("README.md")))?;
+
+        // Assert
+        let readme = fs::read_to_string(dotfiles.join("README.md"))?;
+        assert!(readme.starts_with("# Dotfiles\n<!-- bombadil showcase start -->\n"));
+        assert!(readme.contains(
+            "### default\n\n2 dots\n\n| Color | Variable | Value |\n| --- | --- | --- |\n\
+             | $\\color{#000000}\\blacksquare$ | background | `#000000` |\n\n"
+        ));
+        assert!(readme.contains("### minimal\n\nServers\n\n1 dot\n"));
+        assert!(readme.contains("| background | `#ffffff` |"));
+        assert!(readme.contains(
+            "### dracula\n\n| Color | Variable | Value |\n| --- | --- | --- |\n\
+             | $\\color{#f8f8f2}\\blacksquare$ | foreground | `#f8f8f2` |\n\n"
+        ));
+        assert_eq!(
+            readme.matches("<!-- bombadil showcase start -->").count(),
+            1
+        );
+        assert!(readme.ends_with("<!-- bombadil showcase end -->\n"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_apply_theme_vars_on_top_of_profiles() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(
+            dotfiles.join("work.toml"),
+            "color = \"white\"\nfont = \"mono\"\n",
+        )?;
+        fs::write(dotfiles.join("dracula.toml"), "color = \"purple\"\n")?;
+        fs::write(
+            dotfiles.join("kitty"),
+            "__[color]__ __[font]__ __[bombadil.theme]__",
+        )?;
+        let mut dots = HashMap::new();
+        dots.insert(
+            "kitty".to_string(),
+            Dot {
+                source: PathBuf::from("kitty"),
+                target: dotfiles.join("kitty_target"),
+                ..Default::default()
+            },
+        );
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                vars: vec![PathBuf::from("work.toml")],
+                ..Default::default()
+            },
+        );
+        let mut themes = HashMap::new();
+        themes.insert(
+            "dracula".to_string(),
+            Theme {
+                vars: vec![PathBuf::from("dracula.toml")],
+                ..Default::default()
+            },
+        );
+
+        let mut variables = HashMap::new();
+        variables.insert(THEME_VAR.to_string(), "dracula".to_string());
+        let mut config = Bombadil {
+            path: dotfiles.clone(),
+            dots,
+            profiles,
+            themes,
+            theme: Some("dracula".to_string()),
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
+            vars: Variables {
+                variables,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Act
+        config.enable_profiles(vec!["work"])?;
+        config.relink_theme()?;
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("kitty_target"))?,
+            "purple mono dracula"
+        );
+        assert_eq!(config.theme_names(), vec!["dracula".to_string()]);
+        assert!(config.set_theme("solarized").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_prefix_profile_targets() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        for (key, target) in [("sway", ".config/sway"), ("hosts", "/etc/hosts")] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: PathBuf::from(target),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut profiles = HashMap::new();
+        profiles.insert(
+            "work".to_string(),
+            Profile {
+                target_prefix: Some(PathBuf::from("work-env")),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "test".to_string(),
+            Profile {
+                target_prefix: Some(PathBuf::from("/tmp/test-root")),
+                ..Default::default()
+            },
+        );
+        profiles.insert(
+            "laptop".to_string(),
+            Profile {
+                target_prefix: Some(PathBuf::from("work-env")),
+                ..Default::default()
+            },
+        );
+
+        let mut config = Bombadil {
+            dots,
+            profiles,
+            ..Default::default()
+        };
+        let mut conflicting = Bombadil {
+            dots: config.dots.clone(),
+            profiles: config.profiles.clone(),
+            ..Default::default()
+        };
+
+        // Act
+        config.enable_profiles(vec!["work", "laptop"])?;
+        let conflict = conflicting.enable_profiles(vec!["work", "test", "laptop"]);
+
+        // Assert
+        assert_eq!(
+            config.dots["sway"].target,
+            PathBuf::from("work-env/.config/sway")
+        );
+        assert_eq!(
+            config.dots["hosts"].target,
+            PathBuf::from("work-env/etc/hosts")
+        );
+        assert!(conflict.is_err());
+        Ok(())
+    }
+
+    fn profiles_with_extras(extras: &[(&str, &[&str])]) -> HashMap<String, Profile> {
+        extras
+            .iter()
+            .map(|(name, extra_profiles)| {
+                (
+                    name.to_string(),
+                    Profile {
+                        extra_profiles: extra_profiles.iter().map(|p| p.to_string()).collect(),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_resolve_extra_profiles_transitively() -> Result<()> {
+        // Arrange
+        let config = Bombadil {
+            profiles: profiles_with_extras(&[
+                ("workstation", &["i3", "corporate"]),
+                ("corporate", &["vpn"]),
+                ("i3", &["vpn"]),
+                ("vpn", &[]),
+            ]),
+            ..Default::default()
+        };
+
+        // Act
+        let resolved = config.resolve_profiles(&["workstation"])?;
+
+        // Assert
+        let names: Vec<&str> = resolved.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["workstation", "i3", "corporate", "vpn"]);
+        assert_eq!(resolved[3].via, vec!["workstation", "i3"]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_extra_profiles_cycle() {
+        // Arrange
+        let config = Bombadil {
+            profiles: profiles_with_extras(&[("a", &["b"]), ("b", &["c"]), ("c", &["a"])]),
+            ..Default::default()
+        };
+
+        // Act
+        let resolved = config.resolve_profiles(&["a"]);
+
+        // Assert
+        let err = resolved.unwrap_err().to_string();
+        assert!(err.contains("a -> b -> c -> a"));
+    }
+
+    #[test]
+    fn uninstall_works() {
+        // Arrange
+        let target = TempDir::new("/tmp/dot_unlink_target", false).to_path_buf();
+
+        let mut dots = HashMap::new();
+        dots.insert(
+            "dot_1".to_string(),
+            Dot {
+                source: PathBuf::from("dot_1"),
+                target: target.clone(),
+                ..Default::default()
+            },
+        );
+
+        let config = Bombadil {
+            path: PathBuf::from("tests/dotfiles_unlink")
+                .canonicalize()
+                .unwrap(),
+            dots,
+            vars: Variables {
+                variables: HashMap::new(),
+                secrets: Default::default(),
+                allow_commands: false,
+                provenance_header: false,
+                provenance_exclude: vec![],
+                render_profiler: None,
+                skip_secrets: false,
+                degraded: None,
+                mock_secrets: None,
+                secret_audit: None,
+            },
+            prehooks: vec![],
+            posthooks: vec![],
+            profiles: Default::default(),
+            ..Default::default()
+        };
+
+        config.install().unwrap();
+
+        // Act
+        config.uninstall().unwrap();
+
+        // Assert
+        assert!(!target.exists());
+    }
+
+    #[test]
+    fn should_relink_dot_after_editing_it() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("zshrc"), "export EDITOR=__[editor]__\n")?;
+        let editor = dotfiles.join("editor.sh");
+        fs::write(&editor, "#!/bin/sh\necho 'export PAGER=less' >> \"$1\"\n")?;
+        fs::set_permissions(&editor, std::os::unix::fs::PermissionsExt::from_mode(0o755))?;
+        let mut dots = HashMap::new();
+        dots.insert(
+            "zshrc".to_string(),
+            Dot {
+                source: PathBuf::from("zshrc"),
+                target: dotfiles.join("zshrc_target"),
+                ..Default::default()
+            },
+        );
+
+        let mut variables = HashMap::new();
+        variables.insert("editor".to_string(), "vim".to_string());
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            dots,
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
+            vars: Variables {
+                variables,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        config.install()?;
+
+        // Act
+        config.edit_with("zshrc", &editor.display().to_string())?;
+        let unknown = config.edit_with("vimrc", &editor.display().to_string());
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("zshrc_target"))?,
+            "export EDITOR=vim\nexport PAGER=less\n"
+        );
+        assert!(unknown.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_add_an_existing_file() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let dotfiles = temp.join("dotfiles");
+        fs::create_dir_all(&dotfiles)?;
+        fs::create_dir_all(temp.join("home"))?;
+        fs::write(
+            dotfiles.join("bombadil.toml"),
+            "# My dotfiles\n[settings]\n",
+        )?;
+        let file = temp.join("home").join(".zshrc");
+        fs::write(&file, "export EDITOR=vim")?;
+        let mut config = Bombadil {
+            path: dotfiles.clone(),
+            ..Default::default()
+        };
+
+        // Act
+        config.add(&file, None)?;
+        let added_again = config.add(&file, None);
+        fs::create_dir_all(dotfiles.join("local"))?;
+        fs::write(dotfiles.join("local").join("notes"), "")?;
+        let inside_dotfiles = config.add(&temp.join("home/../dotfiles/local/notes"), None);
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("zshrc"))?,
+            "export EDITOR=vim"
+        );
+        assert_eq!(fs::read_link(&file)?, dotfiles.join(".dots").join("zshrc"));
+        assert_eq!(fs::read_to_string(&file)?, "export EDITOR=vim");
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("bombadil.toml"))?,
+            format!(
+                "# My dotfiles\n[settings]\n\n[settings.dots.zshrc]\nsource = \"zshrc\"\ntarget = \"{}\"\n",
+                file.display()
+            )
+        );
+        assert!(added_again.is_err());
+        assert!(inside_dotfiles.is_err());
+        Ok(())
+    }
 
     #[test]
-    fn self_link_works() {
+    fn should_commit_added_dot() -> Result<()> {
         // Arrange
-        let config_path = PathBuf::from("tests/dotfiles_simple/");
+        let temp = TempDir::default();
+        let temp = temp.canonicalize()?;
+        let dotfiles = temp.join("dotfiles");
+        fs::create_dir_all(&dotfiles)?;
+        fs::create_dir_all(temp.join("home"))?;
+        let repo = git2::Repository::init(&dotfiles)?;
+        let mut git_config = repo.config()?;
+        git_config.set_str("user.name", "Tom Bombadil")?;
+        git_config.set_str("user.email", "tom@bombadil.org")?;
+        fs::write(dotfiles.join("bombadil.toml"), "[settings]\n")?;
+        let file = temp.join("home").join(".zshrc");
+        fs::write(&file, "export EDITOR=vim")?;
+        let mut config = Bombadil {
+            path: dotfiles.clone(),
+            git: GitSettings {
+                auto_commit: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
 
         // Act
-        Bombadil::link_self_config(Some(config_path)).unwrap();
+        config.add(&file, None)?;
 
         // Assert
-        let link = dirs::config_dir().unwrap().join(BOMBADIL_CONFIG);
-        assert!(link.exists());
+        let head = repo.head()?.peel_to_commit()?;
+        assert_eq!(head.message(), Some("bombadil: add dot `zshrc`"));
+        let tree = head.tree()?;
+        assert!(tree.get_name("zshrc").is_some());
+        assert!(tree.get_name("bombadil.toml").is_some());
+        assert!(tree.get_name(".dots").is_none());
+        Ok(())
     }
 
     #[test]
-    fn install_single_file_works() {
+    fn should_remove_dot_and_restore_its_content() -> Result<()> {
         // Arrange
-        let target = TempDir::new("/tmp/dot_target", false).to_path_buf();
-
-        let mut map = HashMap::new();
-        map.insert("red".to_string(), "red_value".to_string());
-
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let config_path = dotfiles.join("bombadil.toml");
+        fs::write(
+            &config_path,
+            "[settings.dots]\n\
+             zshrc = { source = \"zshrc\", target = \"zshrc_target\" }\n\
+             vimrc = { source = \"vimrc\", target = \"vimrc_target\" }\n",
+        )?;
+        fs::write(dotfiles.join("zshrc"), "export EDITOR=__[editor]__")?;
+        fs::write(dotfiles.join("vimrc"), "set number")?;
         let mut dots = HashMap::new();
-        dots.insert(
-            "dot".to_string(),
-            Dot {
-                source: PathBuf::from("template"),
-                target: target.clone(),
-                ignore: vec![],
-                vars: Dot::default_vars(),
-            },
-        );
+        let mut provenance = settings::Provenance::default();
+        for (line, key) in [(2, "zshrc"), (3, "vimrc")] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: dotfiles.join(format!("{}_target", key)),
+                    ..Default::default()
+                },
+            );
+            provenance.dots.insert(
+                key.to_string(),
+                settings::Origin {
+                    file: config_path.clone(),
+                    line: Some(line),
+                },
+            );
+        }
 
+        let mut variables = HashMap::new();
+        variables.insert("editor".to_string(), "vim".to_string());
         let config = Bombadil {
-            path: PathBuf::from("tests/dotfiles_simple")
-                .canonicalize()
-                .unwrap(),
+            path: dotfiles.clone(),
             dots,
+            provenance,
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
             vars: Variables {
-                variables: map,
-                secrets: Default::default(),
+                variables,
+                ..Default::default()
             },
-            prehooks: vec![],
-            posthooks: vec![],
-            profiles: Default::default(),
-            gpg: None,
+            ..Default::default()
         };
+        config.install()?;
 
         // Act
-        config.install().unwrap();
+        config.remove("zshrc")?;
 
         // Assert
-        assert!(target.exists());
+        let target = dotfiles.join("zshrc_target");
+        assert!(!fs::symlink_metadata(&target)?.is_symlink());
+        assert_eq!(fs::read_to_string(&target)?, "export EDITOR=vim");
+        assert!(!dotfiles.join(".dots").join("zshrc").exists());
+        assert!(dotfiles.join("zshrc").exists());
         assert_eq!(
-            fs::read_to_string(&target).unwrap(),
-            "color: red_value".to_string()
+            fs::read_to_string(&config_path)?,
+            "[settings.dots]\nvimrc = { source = \"vimrc\", target = \"vimrc_target\" }\n"
         );
+        let state = BombadilState::read(dotfiles.clone())?;
+        assert!(!state.symlinks.contains(&target));
+        assert!(state.symlinks.contains(&dotfiles.join("vimrc_target")));
+        Ok(())
     }
 
     #[test]
-    fn install_should_fail_and_continue() {
+    fn should_commit_removed_dot() -> Result<()> {
         // Arrange
-        let target = TempDir::new("/tmp/dot_target", false).to_path_buf();
-
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let repo = git2::Repository::init(&dotfiles)?;
+        let mut git_config = repo.config()?;
+        git_config.set_str("user.name", "Tom Bombadil")?;
+        git_config.set_str("user.email", "tom@bombadil.org")?;
+        let config_path = dotfiles.join("bombadil.toml");
+        fs::write(
+            &config_path,
+            "[settings.dots]\nzshrc = { source = \"zshrc\", target = \"zshrc_target\" }\n",
+        )?;
+        fs::write(dotfiles.join("zshrc"), "export EDITOR=vim")?;
         let mut dots = HashMap::new();
-
         dots.insert(
-            "dot".to_string(),
+            "zshrc".to_string(),
             Dot {
-                source: PathBuf::from("template"),
-                target: target.clone(),
-                ignore: vec![],
-                vars: Dot::default_vars(),
+                source: PathBuf::from("zshrc"),
+                target: dotfiles.join("zshrc_target"),
+                ..Default::default()
             },
         );
-        dots.insert(
-            "dot".to_string(),
-            Dot {
-                source: PathBuf::from("invalid_path"),
-                target: PathBuf::from("somewhere"),
-                ignore: vec![],
-                vars: Dot::default_vars(),
+        let mut provenance = settings::Provenance::default();
+        provenance.dots.insert(
+            "zshrc".to_string(),
+            settings::Origin {
+                file: config_path.clone(),
+                line: Some(2),
             },
         );
-
         let config = Bombadil {
-            path: PathBuf::from("tests/dotfiles_invalid_dot")
-                .canonicalize()
-                .unwrap(),
+            path: dotfiles.clone(),
             dots,
-            vars: Variables {
-                variables: HashMap::new(),
-                secrets: Default::default(),
+            provenance,
+            allow_inside_dotfiles: true,
+            git: GitSettings {
+                auto_commit: true,
+                ..Default::default()
             },
-            prehooks: vec![],
-            posthooks: vec![],
-            profiles: Default::default(),
-            gpg: None,
+            ..Default::default()
         };
+        config.install()?;
 
         // Act
-        config.install().unwrap();
+        config.remove("zshrc")?;
 
         // Assert
-        assert!(target.exists());
+        let head = repo.head()?.peel_to_commit()?;
+        assert_eq!(head.message(), Some("bombadil: remove dot `zshrc`"));
+        let tree = head.tree()?;
+        assert!(tree.get_name("bombadil.toml").is_some());
+        assert!(tree.get_name("zshrc").is_none());
+        Ok(())
     }
 
     #[test]
-    fn install_with_subdir() {
+    fn should_render_and_link_in_separate_phases() -> Result<()> {
         // Arrange
-        let target = TempDir::new("/tmp/sub_dir_target", false).to_path_buf();
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("zshrc"), "export EDITOR=__[editor]__")?;
+        fs::write(dotfiles.join("vimrc"), "set number")?;
+        let config = |keys: &[&str], editor: &str| {
+            let mut dots = HashMap::new();
+            for key in keys {
+                dots.insert(
+                    key.to_string(),
+                    Dot {
+                        source: PathBuf::from(key),
+                        target: dotfiles.join(format!("{}_target", key)),
+                        ..Default::default()
+                    },
+                );
+            }
 
-        let mut map = HashMap::new();
-        map.insert("red".to_string(), "red_value".to_string());
-        map.insert("blue".to_string(), "blue_value".to_string());
+            let mut variables = HashMap::new();
+            variables.insert("editor".to_string(), editor.to_string());
+            Bombadil {
+                path: dotfiles.clone(),
+                dots,
+                // Targets live in the temp dotfiles directory
+                allow_inside_dotfiles: true,
+                vars: Variables {
+                    variables,
+                    ..Default::default()
+                },
+                ..Default::default()
+            }
+        };
+        let mut renderer = config(&["zshrc"], "vim");
+        renderer.only_render();
+        let mut linker = config(&["zshrc", "vimrc"], "nano");
+        linker.only_link();
 
-        let mut dots = HashMap::new();
+        // Act
+        renderer.install()?;
+        let linked_after_render = dotfiles.join("zshrc_target").exists();
+        linker.install()?;
 
-        dots.insert(
-            "dot".to_string(),
-            Dot {
-                source: PathBuf::from("sub_dir"),
-                target: target.clone(),
-                ignore: vec![],
-                vars: Dot::default_vars(),
-            },
+        // Assert
+        assert!(!linked_after_render);
+        assert_eq!(
+            fs::read_to_string(dotfiles.join("zshrc_target"))?,
+            "export EDITOR=vim"
         );
+        assert!(!dotfiles.join("vimrc_target").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn should_eject_rendered_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("zshrc"), "export EDITOR=__[editor]__")?;
+        fs::create_dir_all(dotfiles.join("sway"))?;
+        fs::write(dotfiles.join("sway").join("config"), "bar")?;
+        let mut dots = HashMap::new();
+        for key in ["zshrc", "sway"] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: dotfiles.join(format!("{}_target", key)),
+                    ..Default::default()
+                },
+            );
+        }
 
+        let mut variables = HashMap::new();
+        variables.insert("editor".to_string(), "vim".to_string());
         let config = Bombadil {
-            path: PathBuf::from("tests/dotfiles_nested")
-                .canonicalize()
-                .unwrap(),
+            path: dotfiles.clone(),
             dots,
+            // Targets live in the temp dotfiles directory
+            allow_inside_dotfiles: true,
             vars: Variables {
-                variables: map,
-                secrets: Default::default(),
+                variables,
+                ..Default::default()
             },
-            prehooks: vec![],
-            posthooks: vec![],
-            profiles: Default::default(),
-            gpg: None,
+            ..Default::default()
         };
+        config.install()?;
 
         // Act
-        config.install().unwrap();
+        config.eject()?;
 
         // Assert
-        assert!(target.exists());
-        let path = &target.read_link().unwrap();
-        let red_dot = fs::read_to_string(path.join("template_1")).unwrap();
-        let blue_dot = fs::read_to_string(path.join("template_2")).unwrap();
-        assert_eq!(red_dot, "color: red_value".to_string());
-        assert_eq!(blue_dot, "color: blue_value".to_string());
+        let zshrc = dotfiles.join("zshrc_target");
+        let sway = dotfiles.join("sway_target");
+        assert!(!fs::symlink_metadata(&zshrc)?.file_type().is_symlink());
+        assert!(!fs::symlink_metadata(&sway)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(zshrc)?, "export EDITOR=vim");
+        assert_eq!(fs::read_to_string(sway.join("config"))?, "bar");
+        assert!(!dotfiles.join(".dots").exists());
+        Ok(())
     }
 
     #[test]
-    fn install_with_nested_subdirs() {
+    fn should_roll_back_only_the_links_left_in_place() -> Result<()> {
         // Arrange
-        let target = TempDir::new("/tmp/sub_dir_2_target", false).to_path_buf();
-
-        let mut map = HashMap::new();
-        map.insert("red".to_string(), "red_value".to_string());
-        map.insert("blue".to_string(), "blue_value".to_string());
-
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        fs::write(dotfiles.join("zshrc"), "first")?;
+        fs::write(dotfiles.join("vimrc"), "first")?;
         let mut dots = HashMap::new();
-        dots.insert(
-            "dot".to_string(),
-            Dot {
-                source: PathBuf::from("sub_dir_1"),
-                target: target.clone(),
-                ignore: vec![],
-                vars: Dot::default_vars(),
-            },
-        );
+        for key in ["zshrc", "vimrc"] {
+            dots.insert(
+                key.to_string(),
+                Dot {
+                    source: PathBuf::from(key),
+                    target: dotfiles.join(format!("{}_target", key)),
+                    ..Default::default()
+                },
+            );
+        }
         let config = Bombadil {
-            path: PathBuf::from("tests/dotfiles_nested_2")
-                .canonicalize()
-                .unwrap(),
+            path: dotfiles.clone(),
             dots,
-            vars: Variables {
-                variables: map,
-                secrets: Default::default(),
-            },
-            prehooks: vec![],
-            posthooks: vec![],
-            profiles: Default::default(),
-            gpg: None,
+            allow_inside_dotfiles: true,
+            ..Default::default()
         };
+        config.install()?;
+        fs::write(dotfiles.join("zshrc"), "second")?;
+        fs::write(dotfiles.join("vimrc"), "second")?;
+        config.install()?;
+        let vimrc = dotfiles.join("vimrc_target");
+        fs::remove_file(&vimrc)?;
+        fs::write(&vimrc, "mine")?;
 
         // Act
-        config.install().unwrap();
+        config.rollback()?;
 
         // Assert
-        assert!(target.exists());
-        let path = &target.read_link().unwrap();
-        let red_dot = fs::read_to_string(path.join("template_1")).unwrap();
-        let blue_dot = fs::read_to_string(path.join("subdir_2").join("template_2")).unwrap();
-        assert_eq!(red_dot, "color: red_value".to_string());
-        assert_eq!(blue_dot, "color: blue_value".to_string());
+        let zshrc = dotfiles.join("zshrc_target");
+        assert_eq!(fs::read_link(&zshrc)?, dotfiles.join(".dots").join("zshrc"));
+        assert_eq!(fs::read_to_string(zshrc)?, "first");
+        assert!(!fs::symlink_metadata(&vimrc)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(vimrc)?, "mine");
+        Ok(())
     }
 
     #[test]
-    fn uninstall_works() {
+    fn should_keep_link_when_its_copy_fails() -> Result<()> {
         // Arrange
-        let target = TempDir::new("/tmp/dot_unlink_target", false).to_path_buf();
-
-        let mut dots = HashMap::new();
-        dots.insert(
-            "dot_1".to_string(),
-            Dot {
-                source: PathBuf::from("dot_1"),
-                target: target.clone(),
-                ignore: vec![],
-                vars: Dot::default_vars(),
-            },
-        );
-
-        let config = Bombadil {
-            path: PathBuf::from("tests/dotfiles_unlink")
-                .canonicalize()
-                .unwrap(),
-            dots,
-            vars: Variables {
-                variables: HashMap::new(),
-                secrets: Default::default(),
-            },
-            prehooks: vec![],
-            posthooks: vec![],
-            profiles: Default::default(),
-            gpg: None,
-        };
-
-        config.install().unwrap();
+        let temp = TempDir::default();
+        let rendered = temp.join("rendered");
+        let link = temp.join("zshrc");
+        unix::fs::symlink(&rendered, &link)?;
 
         // Act
-        config.uninstall().unwrap();
+        let result = replace_with_copy(&rendered, &link);
 
         // Assert
-        assert!(!target.exists());
+        assert!(result.is_err());
+        assert_eq!(fs::read_link(&link)?, rendered);
+        assert!(fs::symlink_metadata(temp.join(".zshrc.bombadil-copy")).is_err());
+        Ok(())
     }
 
     #[test]
@@ -759,11 +5768,9 @@ mod tests {
             dots: HashMap::new(),
             vars: Variables::default(),
             prehooks: vec![],
-            posthooks: vec![Hook {
-                command: format!("touch {}/dummy", target_str_path),
-            }],
+            posthooks: vec![Hook::new(&format!("touch {}/dummy", target_str_path))],
             profiles: Default::default(),
-            gpg: None,
+            ..Default::default()
         };
 
         // Act
@@ -781,12 +5788,10 @@ mod tests {
             path: PathBuf::from("tests/hook").canonicalize().unwrap(),
             dots: HashMap::new(),
             vars: Variables::default(),
-            prehooks: vec![Hook {
-                command: format!("touch {}/dummy", target_str_path),
-            }],
+            prehooks: vec![Hook::new(&format!("touch {}/dummy", target_str_path))],
             posthooks: vec![],
             profiles: Default::default(),
-            gpg: None,
+            ..Default::default()
         };
 
         // Act
@@ -796,23 +5801,60 @@ mod tests {
         assert!(target.join("dummy").exists());
     }
 
+    #[test]
+    fn should_pass_the_run_lock_profiles_and_config_to_hooks() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let lock = dotfiles.join("run.lock");
+        let output = dotfiles.join("hook_env");
+        let config = Bombadil {
+            path: dotfiles.clone(),
+            posthooks: vec![Hook::from(&HookEntry::Script {
+                interpreter: "sh".to_string(),
+                script: format!(
+                    "echo \"$BOMBADIL_RUN_LOCK $BOMBADIL_PROFILES $BOMBADIL_CONFIG\" > {}",
+                    output.display()
+                ),
+                cwd: None,
+            })],
+            run_lock: Some(lock.clone()),
+            config: Some(dotfiles.join("bombadil.toml")),
+            active_profiles: vec!["sway".to_string(), "work".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        config.install()?;
+
+        // Assert
+        assert_eq!(
+            fs::read_to_string(&output)?,
+            format!(
+                "{} sway,work {}\n",
+                lock.display(),
+                dotfiles.join("bombadil.toml").display()
+            )
+        );
+        assert!(lock.exists());
+        assert!(std::env::var_os(LOCK_ENV).is_none());
+        assert!(std::env::var_os(PROFILES_ENV).is_none());
+        Ok(())
+    }
+
     #[test]
     fn meta_var_works() {
         // Arrange
         let dotfiles = TempDir::new("/tmp/bombadil_tests", false).to_path_buf();
         // We need an absolute path to the test can pass anywhere
-        fs::copy(
-            "tests/vars/meta_vars.toml",
-            &dotfiles.join("meta_vars.toml"),
-        )
-        .unwrap();
-        fs::copy("tests/vars/vars.toml", &dotfiles.join("vars.toml")).unwrap();
-        fs::copy("tests/vars/bombadil.toml", &dotfiles.join(BOMBADIL_CONFIG)).unwrap();
+        fs::copy("tests/vars/meta_vars.toml", dotfiles.join("meta_vars.toml")).unwrap();
+        fs::copy("tests/vars/vars.toml", dotfiles.join("vars.toml")).unwrap();
+        fs::copy("tests/vars/bombadil.toml", dotfiles.join(BOMBADIL_CONFIG)).unwrap();
 
         Bombadil::link_self_config(Some(dotfiles.clone())).unwrap();
 
         // Act
-        let bombadil = Bombadil::from_settings(NoGpg).unwrap();
+        let bombadil = Bombadil::from_settings(NoGpg, None).unwrap();
 
         // Assert
         assert_eq!(
@@ -831,21 +5873,105 @@ mod tests {
         let _ = fs::remove_dir_all(dotfiles);
     }
 
+    #[test]
+    fn should_serialize_metadata_as_json() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        dots.insert(
+            "zsh".to_string(),
+            Dot {
+                source: PathBuf::from("zshrc"),
+                target: PathBuf::from("/tmp/bombadil_json_zshrc"),
+                ..Default::default()
+            },
+        );
+        let mut variables = HashMap::new();
+        variables.insert("red".to_string(), "#FF0000".to_string());
+        let bombadil = Bombadil {
+            path: PathBuf::from("/tmp/dotfiles"),
+            dots,
+            vars: Variables {
+                variables,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // Act
+        let dots = bombadil.metadata_json(MetadataType::Dots)?;
+        let vars = bombadil.metadata_json(MetadataType::Vars)?;
+        let profiles = bombadil.metadata_json(MetadataType::Profiles)?;
+
+        // Assert
+        let dots: serde_json::Value = serde_json::from_str(&dots)?;
+        assert_eq!(
+            dots,
+            serde_json::json!([{
+                "name": "zsh",
+                "source": "/tmp/dotfiles/zshrc",
+                "target": "/tmp/bombadil_json_zshrc",
+                "local": false,
+            }])
+        );
+        assert_eq!(vars, "{\n  \"red\": \"#FF0000\"\n}");
+        assert!(profiles.contains("\"name\": \"default\""));
+        Ok(())
+    }
+
+    #[test]
+    fn should_get_resolved_config_values() -> Result<()> {
+        // Arrange
+        let mut dots = HashMap::new();
+        dots.insert(
+            "sway.conf".to_string(),
+            Dot {
+                source: PathBuf::from("sway"),
+                target: PathBuf::from("/tmp/bombadil_config_sway"),
+                ..Default::default()
+            },
+        );
+        let mut variables = HashMap::new();
+        variables.insert("red".to_string(), "#FF0000".to_string());
+        let bombadil = Bombadil {
+            path: PathBuf::from("/tmp/dotfiles"),
+            dots,
+            vars: Variables {
+                variables,
+                ..Default::default()
+            },
+            active_profiles: vec!["work".to_string()],
+            ..Default::default()
+        };
+
+        // Act
+        let render_dir = bombadil.config_value("render_dir")?;
+        let source = bombadil.config_value("dots.sway.conf.source")?;
+        let target = bombadil.config_value("dots.sway.conf.target")?;
+        let red = bombadil.config_value("vars.red")?;
+        let profiles = bombadil.config_value("profiles")?;
+
+        // Assert
+        assert_eq!(render_dir, serde_json::json!("/tmp/dotfiles/.dots"));
+        assert_eq!(source, serde_json::json!("/tmp/dotfiles/sway"));
+        assert_eq!(target, serde_json::json!("/tmp/bombadil_config_sway"));
+        assert_eq!(red, serde_json::json!("#FF0000"));
+        assert_eq!(profiles, serde_json::json!(["work"]));
+        assert!(bombadil.config_value("dots.sway.conf.mode").is_err());
+        assert!(bombadil.config_value("vars.blue").is_err());
+        Ok(())
+    }
+
     #[test]
     fn should_print_metadata() {
         // Arrange
         let dotfiles = TempDir::new("/tmp/bombadil_tests", false).to_path_buf();
         // We need an absolute path to the test can pass anywhere
-        fs::copy(
-            "tests/vars/meta_vars.toml",
-            &dotfiles.join("meta_vars.toml"),
-        )
-        .unwrap();
-        fs::copy("tests/vars/vars.toml", &dotfiles.join("vars.toml")).unwrap();
-        fs::copy("tests/vars/bombadil.toml", &dotfiles.join(BOMBADIL_CONFIG)).unwrap();
+        fs::copy("tests/vars/meta_vars.toml", dotfiles.join("meta_vars.toml")).unwrap();
+        fs::copy("tests/vars/vars.toml", dotfiles.join("vars.toml")).unwrap();
+        fs::copy("tests/vars/bombadil.toml", dotfiles.join(BOMBADIL_CONFIG)).unwrap();
 
         Bombadil::link_self_config(Some(dotfiles.clone())).unwrap();
-        let bombadil = Bombadil::from_settings(NoGpg).unwrap();
+        let bombadil = Bombadil::from_settings(NoGpg, None).unwrap();
 
         // Act
         bombadil.print_metadata(MetadataType::Dots);
@@ -881,8 +6007,8 @@ mod tests {
             Dot {
                 source: source.clone(),
                 target: Default::default(),
-                ignore: vec![],
                 vars: PathBuf::from("vars_default.toml"),
+                ..Default::default()
             },
         );
 
@@ -894,6 +6020,14 @@ mod tests {
                 target: Default::default(),
                 ignore: vec![],
                 vars: Some(PathBuf::from("vars_p1.toml")),
+                merge: None,
+                repository: None,
+                comment: None,
+                chmod: None,
+                dir_mode: None,
+                chown: None,
+                template: None,
+                link: None,
             },
         );
 
@@ -905,6 +6039,14 @@ mod tests {
                 target: Default::default(),
                 ignore: vec![],
                 vars: Some(PathBuf::from("vars_p2.toml")),
+                merge: None,
+                repository: None,
+                comment: None,
+                chmod: None,
+                dir_mode: None,
+                chown: None,
+                template: None,
+                link: None,
             },
         );
 
@@ -913,10 +6055,7 @@ mod tests {
             "profile_one".to_string(),
             Profile {
                 dots: dots_profile_one,
-                extra_profiles: vec![],
-                prehooks: vec![],
-                posthooks: vec![],
-                vars: vec![],
+                ..Default::default()
             },
         );
 
@@ -924,10 +6063,7 @@ mod tests {
             "profile_two".to_string(),
             Profile {
                 dots: dots_profile_two,
-                prehooks: vec![],
-                posthooks: vec![],
-                vars: vec![],
-                extra_profiles: vec![],
+                ..Default::default()
             },
         );
 
@@ -938,7 +6074,7 @@ mod tests {
             prehooks: vec![],
             posthooks: vec![],
             profiles,
-            gpg: None,
+            ..Default::default()
         };
 
         let ignored = bombadil.get_auto_ignored_files("dot");
@@ -970,6 +6106,25 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn should_copy_recursively() -> Result<()> {
+        let tmp = TempDir::default();
+        let source = tmp.join("source");
+        let target = tmp.join("target");
+        fs::create_dir_all(source.join("sub"))?;
+        fs::write(source.join("sub").join("file"), "Hello Tom")?;
+
+        copy_recursively(&source, &target)?;
+
+        assert_eq!(
+            fs::read_to_string(target.join("sub").join("file"))?,
+            "Hello Tom"
+        );
+        assert!(!fs::symlink_metadata(&target)?.file_type().is_symlink());
+
+        Ok(())
+    }
+
     #[test]
     fn should_unlink_file() -> Result<()> {
         let tmp = TempDir::default();
@@ -0,0 +1,215 @@
+use crate::i18n::t;
+use anyhow::Result;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+const LOCK_FILE: &str = "bombadil.lock";
+
+/// Set in the environment of the hooks of a run holding the lock, bombadil started by them
+/// does not wait for it
+pub(crate) const LOCK_ENV: &str = "BOMBADIL_RUN_LOCK";
+
+/// Advisory lock held by the commands changing `.dots` and the dot targets, so a `watch`
+/// and a manual `link` can't interleave. Released when dropped.
+#[derive(Debug)]
+pub(crate) struct RunLock {
+    /// `None` when the lock is held by the run that started this one, closing the file
+    /// releases the lock
+    _file: Option<File>,
+}
+
+impl RunLock {
+    /// Take the lock `path`, see [`lock_path`], waiting for the other runs. The lock is not
+    /// reentrant, a run takes it once.
+    pub(crate) fn acquire(path: &Path) -> Result<Self> {
+        RunLock::acquire_with(path, env::var_os(LOCK_ENV))
+    }
+
+    /// Same as [`RunLock::acquire`], `held` is the lock passed by the run starting this one.
+    /// It is only reused when it is `path` and its holder is an ancestor of this process, a
+    /// stale or inherited `BOMBADIL_RUN_LOCK` does not bypass the lock.
+    fn acquire_with(path: &Path, held: Option<OsString>) -> Result<Self> {
+        if held.is_some_and(|held| Path::new(&held) == path) && held_by_ancestor(path) {
+            debug!("The run lock is held by the bombadil run starting this one");
+            return Ok(RunLock { _file: None });
+        }
+
+        RunLock::at(path)
+    }
+
+    /// Take the lock `path`, waiting for the run holding it
+    fn at(path: &Path) -> Result<Self> {
+        if let Some(lock) = RunLock::try_at(path)? {
+            return Ok(lock);
+        }
+
+        let mut holder = String::new();
+        File::open(path)?.read_to_string(&mut holder)?;
+        info!("{}", t!("run-lock-waiting", pid = holder.trim()));
+        let file = open(path)?;
+        lock(&file, true)?;
+        RunLock::held(file)
+    }
+
+    /// Take the lock `path`, `None` when another run holds it
+    fn try_at(path: &Path) -> Result<Option<Self>> {
+        let file = open(path)?;
+        match lock(&file, false) {
+            Ok(()) => RunLock::held(file).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+            Err(err) => Err(anyhow!("Unable to lock {:?} : {}", path, err)),
+        }
+    }
+
+    /// Record the process holding the lock, it is shown to the runs waiting for it
+    fn held(mut file: File) -> Result<Self> {
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())?;
+        Ok(RunLock { _file: Some(file) })
+    }
+}
+
+/// Whether the process recorded in the lock `path` is an ancestor of this one
+fn held_by_ancestor(path: &Path) -> bool {
+    let holder = match fs::read_to_string(path).map(|pid| pid.trim().parse::<u32>()) {
+        Ok(Ok(holder)) => holder,
+        _ => return false,
+    };
+
+    let mut pid = std::process::id();
+    while let Some(parent) = parent_pid(pid) {
+        if parent == holder {
+            return true;
+        }
+        if parent <= 1 {
+            break;
+        }
+        pid = parent;
+    }
+
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The parent follows the state, after the command name which may contain spaces
+    stat.rsplit_once(')')?
+        .1
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn parent_pid(pid: u32) -> Option<u32> {
+    let output = std::process::Command::new("ps")
+        .args(["-o", "ppid=", "-p", &pid.to_string()])
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// `$XDG_RUNTIME_DIR/bombadil.lock`, or in the state directory without runtime directory
+pub(crate) fn lock_path() -> PathBuf {
+    match dirs::runtime_dir() {
+        Some(runtime_dir) => runtime_dir.join(LOCK_FILE),
+        None => dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .unwrap_or_else(env::temp_dir)
+            .join("bombadil")
+            .join(LOCK_FILE),
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|err| anyhow!("Unable to open the run lock {:?} : {}", path, err))
+}
+
+#[cfg(unix)]
+fn lock(file: &File, wait: bool) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let operation = match wait {
+        true => libc::LOCK_EX,
+        false => libc::LOCK_EX | libc::LOCK_NB,
+    };
+
+    match unsafe { libc::flock(file.as_raw_fd(), operation) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(unix))]
+fn lock(_file: &File, _wait: bool) -> std::io::Result<()> {
+    warn!("Runs are not locked on this platform, a `watch` and a `link` may interleave");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock::RunLock;
+    use anyhow::Result;
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_lock_out_concurrent_runs() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let path = temp.join("runtime/bombadil.lock");
+
+        // Act
+        let lock = RunLock::acquire(&path)?;
+        let concurrent = RunLock::try_at(&path)?;
+        let holder = fs::read_to_string(&path)?;
+        drop(lock);
+        let released = RunLock::try_at(&path)?;
+
+        // Assert
+        assert!(concurrent.is_none());
+        assert_eq!(holder, std::process::id().to_string());
+        assert!(released.is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_reuse_the_lock_of_an_ancestor_run() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let path = temp.join("bombadil.lock");
+        let held = Some(path.clone().into_os_string());
+        let parent = unsafe { libc::getppid() };
+        fs::write(&path, parent.to_string())?;
+
+        // Act
+        let started_by_holder = RunLock::acquire_with(&path, held.clone())?;
+        let other_lock = RunLock::acquire_with(&path, Some("other.lock".into()))?;
+        let other_holder = fs::read_to_string(&path)?;
+        drop(other_lock);
+        let stale = RunLock::acquire_with(&path, held)?;
+
+        // Assert
+        assert!(started_by_holder._file.is_none());
+        assert_eq!(other_holder, std::process::id().to_string());
+        // The holder recorded by the other lock is this process, not an ancestor
+        assert!(stale._file.is_some());
+        Ok(())
+    }
+}
@@ -0,0 +1,132 @@
+use colored::*;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Only the records of bombadil itself are shown, dependencies log their own internals
+const CRATE_TARGET: &str = env!("CARGO_CRATE_NAME");
+
+/// Messages are colored where they are logged, only debug and trace records get a prefix.
+/// Info records go to stdout along with the command results, the others to stderr.
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+/// See [`plain`]
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// How a line of output ended, told by a word instead of a color in plain mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Outcome {
+    Ok,
+    Changed,
+    Failed,
+}
+
+impl Outcome {
+    fn word(&self) -> &'static str {
+        match self {
+            Outcome::Ok => "OK",
+            Outcome::Changed => "CHANGED",
+            Outcome::Failed => "FAILED",
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level() && metadata.target().starts_with(CRATE_TARGET)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        match record.level() {
+            Level::Info => println!("{}", record.args()),
+            Level::Error | Level::Warn => eprintln!("{}", record.args()),
+            Level::Debug => eprintln!("{} {}", "debug:".blue(), record.args()),
+            Level::Trace => eprintln!("{} {}", "trace:".dimmed(), record.args()),
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// The level shown for a verbosity : errors only below zero (`-q`), info by default,
+/// debug with `-v` and trace with `-vv`
+pub fn level(verbosity: i8) -> LevelFilter {
+    match verbosity {
+        i8::MIN..=-1 => LevelFilter::Error,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Install the logger, without it the library does not output anything but command results
+pub fn init(verbosity: i8) {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level(verbosity));
+    }
+}
+
+/// Output for screen readers and terminals without colors : no colors nor glyphs, the
+/// outcome of status, diff and link lines is told by a leading `OK`, `CHANGED` or `FAILED`
+pub fn plain() {
+    PLAIN.store(true, Ordering::Relaxed);
+    colored::control::set_override(false);
+}
+
+pub(crate) fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// `line` prefixed with the word of its outcome in plain mode
+pub(crate) fn outcome(outcome: Outcome, line: impl Display) -> String {
+    with_outcome(is_plain(), outcome, line)
+}
+
+fn with_outcome(plain: bool, outcome: Outcome, line: impl Display) -> String {
+    if plain {
+        format!("{} {}", outcome.word(), line)
+    } else {
+        line.to_string()
+    }
+}
+
+/// The line reporting that `source` was linked to `target`
+pub(crate) fn linked(source: &Path, target: &Path) -> String {
+    let source = format!("{:?}", source).blue();
+    let target = format!("{:?}", target).green();
+    outcome(Outcome::Ok, format!("{} => {}", source, target))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::logger::{level, with_outcome, Outcome};
+    use log::LevelFilter;
+
+    #[test]
+    fn should_map_verbosity_to_level() {
+        // Assert
+        assert_eq!(level(-1), LevelFilter::Error);
+        assert_eq!(level(0), LevelFilter::Info);
+        assert_eq!(level(1), LevelFilter::Debug);
+        assert_eq!(level(2), LevelFilter::Trace);
+        assert_eq!(level(5), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn should_tell_outcome_with_a_word_in_plain_mode() {
+        // Act
+        let plain = with_outcome(true, Outcome::Changed, "zsh: ~/.zshrc (edited)");
+        let colored = with_outcome(false, Outcome::Failed, "zsh");
+
+        // Assert
+        assert_eq!(plain, "CHANGED zsh: ~/.zshrc (edited)");
+        assert_eq!(colored, "zsh");
+    }
+}
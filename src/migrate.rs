@@ -0,0 +1,975 @@
+use crate::settings::CONFIG_VERSION;
+use anyhow::Result;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use yaml_rust::{Yaml, YamlLoader};
+
+/// Files stow never links, see `stow --help`
+const STOW_IGNORED: [&str; 4] = [".git", ".gitignore", ".gitmodules", ".stow-local-ignore"];
+/// Files stow never links at the root of a package
+const STOW_IGNORED_ROOT: [&str; 3] = ["README", "LICENSE", "COPYING"];
+
+/// A dot generated from the layout of another dotfiles manager
+#[derive(Debug, PartialEq)]
+pub(crate) struct ImportedDot {
+    pub(crate) name: String,
+    /// Relative to the imported directory
+    pub(crate) source: PathBuf,
+    pub(crate) target: PathBuf,
+    /// What needs to be converted by hand, rendered as comments
+    pub(crate) notes: Vec<String>,
+}
+
+/// Map every package of a stow directory to dots targeting `target_dir`.
+/// Like stow, directories that already exist in `target_dir` or are shared by
+/// several packages are not linked themselves, their content is.
+/// With `dotfiles` the `dot-` prefixes are replaced with `.` like `stow --dotfiles`.
+pub(crate) fn stow_dots(
+    stow_dir: &Path,
+    target_dir: &Path,
+    dotfiles: bool,
+) -> Result<Vec<ImportedDot>> {
+    let packages: Vec<PathBuf> = sorted_entries(stow_dir)?
+        .into_iter()
+        .filter(|path| path.is_dir() && !is_hidden(path))
+        .collect();
+
+    let mut dots = vec![];
+    for package in packages.iter() {
+        let package_name = file_name(package);
+        let mut leaves = vec![];
+        collect_stow_leaves(
+            package,
+            Path::new(""),
+            &packages,
+            target_dir,
+            dotfiles,
+            &mut leaves,
+        )?;
+
+        let single = leaves.len() == 1;
+        for leaf in leaves {
+            let target = stow_target(&leaf, dotfiles);
+            let name = if single {
+                package_name.clone()
+            } else {
+                let target_name = file_name(&target);
+                format!("{}_{}", package_name, target_name.trim_start_matches('.'))
+            };
+
+            dots.push(ImportedDot {
+                name,
+                target: target_dir.join(target),
+                source: Path::new(&package_name).join(leaf),
+                notes: vec![],
+            });
+        }
+    }
+
+    Ok(dots)
+}
+
+fn collect_stow_leaves(
+    package: &Path,
+    relative: &Path,
+    packages: &[PathBuf],
+    target_dir: &Path,
+    dotfiles: bool,
+    leaves: &mut Vec<PathBuf>,
+) -> Result<()> {
+    for entry in sorted_entries(&package.join(relative))? {
+        let name = file_name(&entry);
+        let is_root = relative.as_os_str().is_empty();
+        if STOW_IGNORED.contains(&name.as_str())
+            || (is_root && STOW_IGNORED_ROOT.iter().any(|root| name.starts_with(root)))
+        {
+            continue;
+        }
+
+        let entry_relative = relative.join(&name);
+        let shared = packages
+            .iter()
+            .filter(|other| *other != package)
+            .any(|other| other.join(&entry_relative).is_dir());
+        let existing =
+            fs::symlink_metadata(target_dir.join(stow_target(&entry_relative, dotfiles)))
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+
+        if entry.is_dir() && (shared || existing) {
+            collect_stow_leaves(
+                package,
+                &entry_relative,
+                packages,
+                target_dir,
+                dotfiles,
+                leaves,
+            )?;
+        } else {
+            leaves.push(entry_relative);
+        }
+    }
+
+    Ok(())
+}
+
+fn stow_target(relative: &Path, dotfiles: bool) -> PathBuf {
+    if !dotfiles {
+        return relative.to_path_buf();
+    }
+
+    relative
+        .iter()
+        .map(|component| {
+            let component = component.to_string_lossy();
+            match component.strip_prefix("dot-") {
+                Some(name) => format!(".{}", name),
+                None => component.to_string(),
+            }
+        })
+        .collect()
+}
+
+/// Chezmoi source state attributes, in the order chezmoi expects them
+const CHEZMOI_PREFIXES: [&str; 17] = [
+    "create_",
+    "modify_",
+    "remove_",
+    "run_",
+    "once_",
+    "onchange_",
+    "before_",
+    "after_",
+    "symlink_",
+    "external_",
+    "exact_",
+    "encrypted_",
+    "private_",
+    "readonly_",
+    "empty_",
+    "executable_",
+    "literal_",
+];
+
+/// A chezmoi source file name parsed into its target name and attributes
+#[derive(Debug, PartialEq)]
+struct ChezmoiName {
+    target: String,
+    attributes: Vec<&'static str>,
+    template: bool,
+}
+
+impl ChezmoiName {
+    fn parse(name: &str) -> Self {
+        let mut attributes = vec![];
+        let mut rest = name;
+        let mut literal = false;
+        'prefixes: loop {
+            for prefix in CHEZMOI_PREFIXES.iter() {
+                if let Some(stripped) = rest.strip_prefix(prefix) {
+                    rest = stripped;
+                    if *prefix == "literal_" {
+                        literal = true;
+                        break 'prefixes;
+                    }
+                    attributes.push(prefix.trim_end_matches('_'));
+                    continue 'prefixes;
+                }
+            }
+            break;
+        }
+
+        let mut target = match rest.strip_prefix("dot_") {
+            Some(name) if !literal => format!(".{}", name),
+            _ => rest.to_string(),
+        };
+
+        let mut template = false;
+        if let Some(literal) = target.strip_suffix(".literal") {
+            target = literal.to_string();
+        } else {
+            if let Some(stripped) = target.strip_suffix(".tmpl") {
+                target = stripped.to_string();
+                template = true;
+            }
+
+            if attributes.contains(&"encrypted") {
+                for extension in [".age", ".asc"] {
+                    if let Some(stripped) = target.strip_suffix(extension) {
+                        target = stripped.to_string();
+                    }
+                }
+            }
+        }
+
+        ChezmoiName {
+            target,
+            attributes,
+            template,
+        }
+    }
+
+    /// True if the target has the same name and no chezmoi specific behavior
+    fn is_plain(&self, name: &str) -> bool {
+        self.target == name && self.attributes.is_empty() && !self.template
+    }
+
+    /// Attributes bombadil can't reproduce on its own
+    fn notes(&self, source: &Path) -> Vec<String> {
+        let mut notes = vec![];
+        for attribute in self.attributes.iter() {
+            let note = match *attribute {
+                "create" => "chezmoi only creates this file if missing, bombadil always links it",
+                "exact" => "chezmoi removes unmanaged files from this directory, bombadil does not",
+                "encrypted" => "encrypted with chezmoi, decrypt it and store the secrets with `bombadil add-secret`",
+                "private" => "private in chezmoi, bombadil keeps the source permissions : `chmod go-rwx` the source",
+                "readonly" => "readonly in chezmoi, bombadil keeps the source permissions : `chmod a-w` the source",
+                "executable" => "executable in chezmoi, bombadil keeps the source permissions : `chmod +x` the source",
+                "symlink" => "chezmoi symlink, the file contains the link target",
+                _ => continue,
+            };
+            notes.push(note.to_string());
+        }
+
+        if self.template {
+            let expressions = fs::read_to_string(source)
+                .map(|content| content.matches("{{").count())
+                .unwrap_or(0);
+            notes.push(format!(
+                "chezmoi template with {} expressions, convert them to bombadil `__[variables]__`",
+                expressions
+            ));
+        }
+
+        notes
+    }
+
+    /// Scripts and file operations that can't be a dot
+    fn script(&self) -> Option<&'static str> {
+        if self.attributes.contains(&"run") {
+            Some("chezmoi script, convert it to a bombadil prehook or posthook")
+        } else if self.attributes.contains(&"modify") {
+            Some("chezmoi modify script, convert it to a bombadil posthook")
+        } else if self.attributes.contains(&"remove") {
+            Some("chezmoi removes this target, bombadil can't remove files")
+        } else {
+            None
+        }
+    }
+}
+
+/// Map a chezmoi source directory to dots targeting `target_dir`, the entries bombadil
+/// can't convert are returned as warnings
+pub(crate) fn chezmoi_dots(
+    source_dir: &Path,
+    target_dir: &Path,
+) -> Result<(Vec<ImportedDot>, Vec<String>)> {
+    let mut source_dir = source_dir.to_path_buf();
+    if let Ok(root) = fs::read_to_string(source_dir.join(".chezmoiroot")) {
+        source_dir = source_dir.join(root.trim());
+    }
+
+    let mut dots = vec![];
+    let mut warnings = vec![];
+    for entry in sorted_entries(&source_dir)? {
+        let name = file_name(&entry);
+        match name.as_str() {
+            ".chezmoiignore" => warnings.push(format!(
+                "{} : convert the ignored patterns to dot `ignore` lists",
+                name
+            )),
+            ".chezmoitemplates" => warnings.push(format!(
+                "{} : shared templates are not supported, inline them",
+                name
+            )),
+            ".chezmoiscripts" => warnings.push(format!(
+                "{} : convert the scripts to bombadil prehooks or posthooks",
+                name
+            )),
+            _ if name.starts_with(".chezmoidata") || name.starts_with(".chezmoi.") => warnings
+                .push(format!(
+                    "{} : move the template data to a bombadil var file",
+                    name
+                )),
+            _ if name.starts_with(".chezmoiexternal") => warnings.push(format!(
+                "{} : externals are not supported, vendor them in your dotfiles",
+                name
+            )),
+            _ if name.starts_with('.') => continue,
+            _ => collect_chezmoi_dots(
+                &source_dir,
+                &entry,
+                target_dir,
+                Path::new(""),
+                &mut dots,
+                &mut warnings,
+            )?,
+        }
+    }
+
+    Ok((dots, warnings))
+}
+
+fn collect_chezmoi_dots(
+    source_dir: &Path,
+    entry: &Path,
+    target_dir: &Path,
+    target_parent: &Path,
+    dots: &mut Vec<ImportedDot>,
+    warnings: &mut Vec<String>,
+) -> Result<()> {
+    let name = file_name(entry);
+    let parsed = ChezmoiName::parse(&name);
+    let target = target_parent.join(&parsed.target);
+    let source = entry
+        .strip_prefix(source_dir)
+        .unwrap_or(entry)
+        .to_path_buf();
+
+    if let Some(script) = parsed.script() {
+        warnings.push(format!("{} : {}", source.display(), script));
+        return Ok(());
+    }
+
+    let existing = fs::symlink_metadata(target_dir.join(&target))
+        .map(|metadata| metadata.is_dir())
+        .unwrap_or(false);
+
+    if entry.is_dir() && (existing || !is_plain_tree(entry)?) {
+        for child in sorted_entries(entry)? {
+            if !file_name(&child).starts_with('.') {
+                collect_chezmoi_dots(source_dir, &child, target_dir, &target, dots, warnings)?;
+            }
+        }
+        return Ok(());
+    }
+
+    dots.push(ImportedDot {
+        name: dot_name(&target),
+        notes: parsed.notes(entry),
+        target: target_dir.join(target),
+        source,
+    });
+
+    Ok(())
+}
+
+/// A dot name from its target relative to home, `.config/fish/config.fish` gives `fish_config.fish`
+fn dot_name(target: &Path) -> String {
+    let mut components: Vec<String> = target
+        .iter()
+        .map(|component| {
+            component
+                .to_string_lossy()
+                .trim_start_matches('.')
+                .to_string()
+        })
+        .collect();
+    if components.len() > 1 && components[0] == "config" {
+        components.remove(0);
+    }
+
+    components.join("_")
+}
+
+/// True if no entry under `dir` uses chezmoi attributes
+fn is_plain_tree(dir: &Path) -> Result<bool> {
+    for entry in sorted_entries(dir)? {
+        let name = file_name(&entry);
+        if !ChezmoiName::parse(&name).is_plain(&name) || (entry.is_dir() && !is_plain_tree(&entry)?)
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Dots and hooks converted from a dotbot configuration
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct DotbotImport {
+    pub(crate) dots: Vec<ImportedDot>,
+    /// Shell commands declared before the first `link` directive
+    pub(crate) prehooks: Vec<String>,
+    pub(crate) posthooks: Vec<String>,
+    pub(crate) warnings: Vec<String>,
+}
+
+/// Link options bombadil can't reproduce
+const DOTBOT_UNSUPPORTED_LINK_OPTIONS: [(&str, &str); 5] = [
+    ("glob", "dotbot glob link, list the matching files as dots"),
+    ("if", "dotbot conditional link, move it to a profile"),
+    (
+        "relative",
+        "dotbot relative symlink, bombadil links absolute paths",
+    ),
+    (
+        "exclude",
+        "dotbot glob exclusions, use the dot `ignore` list",
+    ),
+    ("prefix", "dotbot glob prefix, rename the sources"),
+];
+
+/// Convert the `link`, `shell` and `create` directives of a dotbot `install.conf.yaml`,
+/// or its json equivalent, sources are relative to the directory of the configuration
+pub(crate) fn dotbot(config: &Path, home: &Path) -> Result<DotbotImport> {
+    let content = fs::read_to_string(config)
+        .map_err(|err| anyhow!("Unable to read {:?} : {}", config, err))?;
+    let documents = YamlLoader::load_from_str(&content)
+        .map_err(|err| anyhow!("Unable to parse {:?} : {}", config, err))?;
+    let directives = documents
+        .first()
+        .and_then(Yaml::as_vec)
+        .ok_or_else(|| anyhow!("{:?} is not a list of dotbot directives", config))?;
+
+    let mut import = DotbotImport::default();
+    let mut link_defaults = yaml_rust::yaml::Hash::new();
+    let mut linked = false;
+
+    for (name, value) in directives
+        .iter()
+        .filter_map(Yaml::as_hash)
+        .flat_map(|directive| directive.iter())
+    {
+        let name = name.as_str().unwrap_or_default();
+        match name {
+            "defaults" => {
+                if let Some(defaults) = value["link"].as_hash() {
+                    link_defaults = defaults.clone();
+                }
+            }
+            "link" => {
+                linked = true;
+                for (target, spec) in value.as_hash().into_iter().flatten() {
+                    let target = target.as_str().unwrap_or_default();
+                    if let Some(dot) = dotbot_link(target, spec, &link_defaults, home) {
+                        import.dots.push(dot);
+                    }
+                }
+            }
+            "shell" => {
+                let commands = value
+                    .as_vec()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|command| match command {
+                        Yaml::String(command) => Some(command.clone()),
+                        Yaml::Array(command) => command.first()?.as_str().map(str::to_string),
+                        Yaml::Hash(_) => command["command"].as_str().map(str::to_string),
+                        _ => None,
+                    });
+
+                if linked {
+                    import.posthooks.extend(commands);
+                } else {
+                    import.prehooks.extend(commands);
+                }
+            }
+            "create" => {
+                let directories: Vec<&str> = match value {
+                    Yaml::Array(directories) => {
+                        directories.iter().filter_map(Yaml::as_str).collect()
+                    }
+                    Yaml::Hash(directories) => {
+                        directories.keys().filter_map(Yaml::as_str).collect()
+                    }
+                    _ => vec![],
+                };
+
+                if !directories.is_empty() {
+                    import
+                        .prehooks
+                        .push(format!("mkdir -p {}", directories.join(" ")));
+                }
+            }
+            "clean" => import.warnings.push(
+                "clean : bombadil does not remove dead links, `bombadil unlink` removes the previous links"
+                    .to_string(),
+            ),
+            _ => import
+                .warnings
+                .push(format!("{} : dotbot plugins are not supported", name)),
+        }
+    }
+
+    Ok(import)
+}
+
+fn dotbot_link(
+    target: &str,
+    spec: &Yaml,
+    defaults: &yaml_rust::yaml::Hash,
+    home: &Path,
+) -> Option<ImportedDot> {
+    let target_path = match target.strip_prefix('~') {
+        Some(relative) => home.join(relative.trim_start_matches('/')),
+        None => home.join(target),
+    };
+    let target_relative = target_path.strip_prefix(home).ok()?.to_path_buf();
+    if target_relative.as_os_str().is_empty() {
+        return None;
+    }
+
+    // A null path links the target name without its leading dot
+    let default_source = file_name(&target_relative)
+        .trim_start_matches('.')
+        .to_string();
+    let source = match spec {
+        Yaml::String(source) => source.clone(),
+        Yaml::Hash(_) => spec["path"]
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or(default_source),
+        _ => default_source,
+    };
+
+    let option = |name: &str| -> bool {
+        let key = Yaml::String(name.to_string());
+        let value = match spec {
+            Yaml::Hash(options) => options.get(&key),
+            _ => None,
+        }
+        .or_else(|| defaults.get(&key));
+
+        !matches!(value, None | Some(Yaml::Boolean(false)) | Some(Yaml::Null))
+    };
+
+    let notes = DOTBOT_UNSUPPORTED_LINK_OPTIONS
+        .iter()
+        .filter(|(name, _)| option(name))
+        .map(|(_, note)| note.to_string())
+        .collect();
+
+    Some(ImportedDot {
+        name: dot_name(&target_relative),
+        source: PathBuf::from(source.trim_end_matches('/')),
+        target: target_path,
+        notes,
+    })
+}
+
+/// Render shell commands as inline `sh` hooks running in `directory` like dotbot does
+pub(crate) fn to_hooks_toml(key: &str, commands: &[String], directory: &str) -> String {
+    if commands.is_empty() {
+        return String::new();
+    }
+
+    let mut toml = format!("{} = [\n", key);
+    for command in commands {
+        let script = format!("cd {}\n{}\n", directory, command);
+        toml.push_str(&format!(
+            "    {{ interpreter = \"sh -e\", script = {} }},\n",
+            toml::Value::String(script)
+        ));
+    }
+    toml.push_str("]\n");
+    toml
+}
+
+/// Render dots as `[settings.dots.<name>]` tables, `prefix` is the path of the
+/// imported directory relative to the dotfiles directory. Names already in `taken`
+/// get a numeric suffix.
+pub(crate) fn to_toml(
+    dots: &[ImportedDot],
+    prefix: &Path,
+    home: Option<&Path>,
+    taken: &mut HashSet<String>,
+) -> String {
+    let mut toml = String::new();
+    for dot in dots {
+        let base = toml_key(&dot.name);
+        let mut name = base.clone();
+        let mut suffix = 2;
+        while taken.contains(&name) {
+            name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+        taken.insert(name.clone());
+
+        let target = home
+            .and_then(|home| dot.target.strip_prefix(home).ok())
+            .unwrap_or(&dot.target);
+
+        toml.push('\n');
+        for note in dot.notes.iter() {
+            toml.push_str(&format!("# TODO: {}\n", note));
+        }
+
+        toml.push_str(&format!(
+            "[settings.dots.{}]\nsource = {}\ntarget = {}\n",
+            name,
+            toml_string(&prefix.join(&dot.source)),
+            toml_string(target),
+        ));
+    }
+
+    toml
+}
+
+/// A bare toml key, only ASCII letters, digits, `-` and `_` are allowed
+fn toml_key(name: &str) -> String {
+    let key: String = name
+        .trim_start_matches('.')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    if key.is_empty() {
+        "dot".to_string()
+    } else {
+        key
+    }
+}
+
+fn toml_string(path: &Path) -> String {
+    toml::Value::String(path.to_string_lossy().to_string()).to_string()
+}
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(|err| anyhow!("Unable to read {:?} : {}", dir, err))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn is_hidden(path: &Path) -> bool {
+    file_name(path).starts_with('.')
+}
+
+/// Rewrite a configuration written for an older bombadil into the current schema, in place.
+/// Each step only applies when the old keys are found, returns the changes made.
+pub(crate) fn migrate_config(config: &mut toml::Value) -> Result<Vec<String>> {
+    let table = config
+        .as_table_mut()
+        .ok_or_else(|| anyhow!("The configuration is not a toml table"))?;
+
+    let version = match table.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_integer()
+            .ok_or_else(|| anyhow!("`version` must be an integer"))?,
+    };
+    if version > CONFIG_VERSION as i64 {
+        return Err(anyhow!(
+            "The configuration version {} is newer than this bombadil supports ({}), upgrade bombadil",
+            version,
+            CONFIG_VERSION
+        ));
+    }
+
+    let mut changes = vec![];
+    if let Some(settings) = table
+        .get_mut("settings")
+        .and_then(toml::Value::as_table_mut)
+    {
+        migrate_profile(settings, "settings", &mut changes);
+    }
+    if let Some(profiles) = table
+        .get_mut("profiles")
+        .and_then(toml::Value::as_table_mut)
+    {
+        for (name, profile) in profiles.iter_mut() {
+            if let Some(profile) = profile.as_table_mut() {
+                migrate_profile(profile, &format!("profiles.{}", name), &mut changes);
+            }
+        }
+    }
+
+    if version < CONFIG_VERSION as i64 {
+        table.insert(
+            "version".to_string(),
+            toml::Value::Integer(CONFIG_VERSION as i64),
+        );
+        changes.push(format!("set `version = {}`", CONFIG_VERSION));
+    }
+
+    Ok(changes)
+}
+
+/// Pre 2.0 `secrets` files are stored alongside `vars`, 2.1 renamed `hooks` to `posthooks`
+fn migrate_profile(profile: &mut toml::value::Table, path: &str, changes: &mut Vec<String>) {
+    let renames = [("secrets", "vars"), ("hooks", "posthooks")];
+    for (old, new) in renames.iter() {
+        let old_entries = match profile.remove(*old) {
+            Some(toml::Value::Array(entries)) => entries,
+            Some(entry) => vec![entry],
+            None => continue,
+        };
+
+        let entries = profile
+            .entry(new.to_string())
+            .or_insert_with(|| toml::Value::Array(vec![]));
+        match entries {
+            toml::Value::Array(entries) => entries.extend(old_entries),
+            entry => {
+                let mut merged = vec![entry.clone()];
+                merged.extend(old_entries);
+                *entry = toml::Value::Array(merged);
+            }
+        }
+        changes.push(format!("moved `{}.{}` to `{}.{}`", path, old, path, new));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrate::{
+        chezmoi_dots, dotbot, migrate_config, stow_dots, to_hooks_toml, to_toml, ChezmoiName,
+        ImportedDot,
+    };
+    use anyhow::Result;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_map_stow_packages_to_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let stow = temp.join("stow");
+        let home = temp.join("home");
+        fs::create_dir_all(stow.join("zsh"))?;
+        fs::write(stow.join("zsh/dot-zshrc"), "")?;
+        fs::write(stow.join("zsh/dot-zshenv"), "")?;
+        fs::write(stow.join("zsh/README.md"), "")?;
+        fs::create_dir_all(stow.join("nvim/dot-config/nvim"))?;
+        fs::write(stow.join("nvim/dot-config/nvim/init.lua"), "")?;
+        fs::create_dir_all(stow.join("sway/dot-config/sway"))?;
+        fs::write(stow.join("sway/dot-config/sway/config"), "")?;
+        fs::create_dir_all(home.join(".config/sway"))?;
+
+        // Act
+        let dots = stow_dots(&stow, &home, true)?;
+
+        // Assert
+        let dot = |name: &str, source: &str, target: &str| ImportedDot {
+            name: name.to_string(),
+            source: PathBuf::from(source),
+            target: home.join(target),
+            notes: vec![],
+        };
+
+        assert_eq!(
+            dots,
+            vec![
+                dot("nvim", "nvim/dot-config/nvim", ".config/nvim"),
+                dot("sway", "sway/dot-config/sway/config", ".config/sway/config"),
+                dot("zsh_zshenv", "zsh/dot-zshenv", ".zshenv"),
+                dot("zsh_zshrc", "zsh/dot-zshrc", ".zshrc"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_render_dots_with_unique_names() {
+        // Arrange
+        let home = Path::new("/home/tom");
+        let dots = vec![ImportedDot {
+            name: ".zshrc".to_string(),
+            source: PathBuf::from("zsh/.zshrc"),
+            target: home.join(".zshrc"),
+            notes: vec!["chezmoi template".to_string()],
+        }];
+        let mut taken: HashSet<String> = vec!["zshrc".to_string()].into_iter().collect();
+
+        // Act
+        let toml = to_toml(&dots, Path::new("stow"), Some(home), &mut taken);
+
+        // Assert
+        assert_eq!(
+            toml,
+            "\n# TODO: chezmoi template\n[settings.dots.zshrc_2]\nsource = \"stow/zsh/.zshrc\"\ntarget = \".zshrc\"\n"
+        );
+        assert!(toml::from_str::<toml::Value>(&toml).is_ok());
+    }
+
+    #[test]
+    fn should_parse_chezmoi_names() {
+        // Act
+        let private_ssh = ChezmoiName::parse("private_dot_ssh");
+        let template = ChezmoiName::parse("executable_dot_profile.tmpl");
+        let literal = ChezmoiName::parse("literal_dot_file.tmpl.literal");
+
+        // Assert
+        assert_eq!(private_ssh.target, ".ssh");
+        assert_eq!(private_ssh.attributes, vec!["private"]);
+        assert!(!private_ssh.template);
+        assert_eq!(template.target, ".profile");
+        assert_eq!(template.attributes, vec!["executable"]);
+        assert!(template.template);
+        assert_eq!(literal.target, "dot_file.tmpl");
+        assert!(literal.attributes.is_empty());
+        assert!(!literal.template);
+    }
+
+    #[test]
+    fn should_map_chezmoi_source_to_dots() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let source = temp.join("chezmoi");
+        let home = temp.join("home");
+        fs::create_dir_all(source.join("dot_config/nvim/lua"))?;
+        fs::write(source.join("dot_config/nvim/init.lua"), "")?;
+        fs::write(source.join("dot_config/nvim/lua/plugins.lua"), "")?;
+        fs::create_dir_all(source.join("dot_config/fish"))?;
+        fs::write(
+            source.join("dot_config/fish/config.fish.tmpl"),
+            "set -x EDITOR {{ .editor }}\n{{ if eq .chezmoi.os \"linux\" }}linux{{ end }}\n",
+        )?;
+        fs::write(source.join("dot_zshrc"), "")?;
+        fs::write(source.join("run_once_install.sh"), "")?;
+        fs::write(source.join(".chezmoiignore"), "README.md")?;
+        fs::create_dir_all(home.join(".config"))?;
+
+        // Act
+        let (dots, warnings) = chezmoi_dots(&source, &home)?;
+
+        // Assert
+        let dot = |name: &str, source: &str, target: &str, notes: Vec<String>| ImportedDot {
+            name: name.to_string(),
+            source: PathBuf::from(source),
+            target: home.join(target),
+            notes,
+        };
+
+        assert_eq!(
+            dots,
+            vec![
+                dot(
+                    "fish_config.fish",
+                    "dot_config/fish/config.fish.tmpl",
+                    ".config/fish/config.fish",
+                    vec!["chezmoi template with 3 expressions, convert them to bombadil `__[variables]__`".to_string()]
+                ),
+                dot("nvim", "dot_config/nvim", ".config/nvim", vec![]),
+                dot("zshrc", "dot_zshrc", ".zshrc", vec![]),
+            ]
+        );
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with(".chezmoiignore"));
+        assert!(warnings[1].starts_with("run_once_install.sh"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_convert_dotbot_directives() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let home = temp.join("home");
+        let config = temp.join("install.conf.yaml");
+        fs::write(
+            &config,
+            r#"
+- shell:
+  - [git submodule update --init, Installing submodules]
+- link:
+    ~/.vimrc:
+    ~/.config/nvim:
+      path: nvim/
+      create: true
+    ~/.local/bin/tools:
+      path: bin/*
+      glob: true
+- shell:
+  - command: echo "done"
+    description: Done
+- brew: [git]
+"#,
+        )?;
+
+        // Act
+        let import = dotbot(&config, &home)?;
+
+        // Assert
+        let dot = |name: &str, source: &str, target: &str, notes: Vec<&str>| ImportedDot {
+            name: name.to_string(),
+            source: PathBuf::from(source),
+            target: home.join(target),
+            notes: notes.into_iter().map(str::to_string).collect(),
+        };
+
+        assert_eq!(
+            import.dots,
+            vec![
+                dot("vimrc", "vimrc", ".vimrc", vec![]),
+                dot("nvim", "nvim", ".config/nvim", vec![]),
+                dot(
+                    "local_bin_tools",
+                    "bin/*",
+                    ".local/bin/tools",
+                    vec!["dotbot glob link, list the matching files as dots"]
+                ),
+            ]
+        );
+        assert_eq!(import.prehooks, vec!["git submodule update --init"]);
+        assert_eq!(import.posthooks, vec!["echo \"done\""]);
+        assert_eq!(import.warnings.len(), 1);
+
+        let hooks = to_hooks_toml("posthooks", &import.posthooks, "\"$HOME/dotfiles\"");
+        let hooks: toml::Value = toml::from_str(&hooks)?;
+        assert_eq!(
+            hooks["posthooks"][0]["script"].as_str(),
+            Some("cd \"$HOME/dotfiles\"\necho \"done\"\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_migrate_old_configuration() -> Result<()> {
+        // Arrange
+        let mut config: toml::Value = toml::from_str(
+            r#"
+dotfiles_dir = "dotfiles"
+
+[settings]
+vars = ["vars.toml"]
+secrets = ["secrets.toml"]
+hooks = ["sway reload"]
+
+[profiles.dark]
+hooks = "notify-send dark"
+posthooks = ["pkill -USR1 kitty"]
+"#,
+        )?;
+
+        // Act
+        let changes = migrate_config(&mut config)?;
+
+        // Assert
+        let expected: toml::Value = toml::from_str(
+            r#"
+dotfiles_dir = "dotfiles"
+version = 2
+
+[settings]
+vars = ["vars.toml", "secrets.toml"]
+posthooks = ["sway reload"]
+
+[profiles.dark]
+posthooks = ["pkill -USR1 kitty", "notify-send dark"]
+"#,
+        )?;
+        assert_eq!(config, expected);
+        assert_eq!(changes.len(), 4);
+        assert!(migrate_config(&mut config)?.is_empty());
+        Ok(())
+    }
+}
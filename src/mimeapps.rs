@@ -0,0 +1,223 @@
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The generated list is kept in `.dots`, next to the rendered dots
+const MIMEAPPS_COPY: &str = ".mimeapps.list";
+
+const BROWSER_TYPES: [&str; 4] = [
+    "text/html",
+    "application/xhtml+xml",
+    "x-scheme-handler/http",
+    "x-scheme-handler/https",
+];
+const EDITOR_TYPES: [&str; 1] = ["text/plain"];
+const IMAGE_VIEWER_TYPES: [&str; 6] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+    "image/svg+xml",
+];
+const PDF_VIEWER_TYPES: [&str; 1] = ["application/pdf"];
+const FILE_MANAGER_TYPES: [&str; 1] = ["inode/directory"];
+const MAIL_TYPES: [&str; 1] = ["x-scheme-handler/mailto"];
+
+/// Default applications written to `$XDG_CONFIG/mimeapps.list`, by `.desktop` file name.
+/// Profiles use the same table to override some of them.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq)]
+pub struct Defaults {
+    /// Web pages, http and https links
+    #[serde(default)]
+    pub browser: Option<String>,
+
+    /// Plain text files
+    #[serde(default)]
+    pub editor: Option<String>,
+
+    /// Common image formats : png, jpeg, gif, webp, bmp and svg
+    #[serde(default)]
+    pub image_viewer: Option<String>,
+
+    #[serde(default)]
+    pub pdf_viewer: Option<String>,
+
+    /// Directories
+    #[serde(default)]
+    pub file_manager: Option<String>,
+
+    /// `mailto:` links
+    #[serde(default)]
+    pub mail: Option<String>,
+
+    /// Any other MIME type, ex: `"video/mp4" = "mpv.desktop"`, wins over the roles above
+    #[serde(default)]
+    pub mime: HashMap<String, String>,
+}
+
+impl Defaults {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.associations().is_empty()
+    }
+
+    /// Override the applications set in a profile
+    pub(crate) fn merge(&mut self, other: &Defaults) {
+        let merge = |field: &mut Option<String>, other: &Option<String>| {
+            if other.is_some() {
+                field.clone_from(other);
+            }
+        };
+
+        merge(&mut self.browser, &other.browser);
+        merge(&mut self.editor, &other.editor);
+        merge(&mut self.image_viewer, &other.image_viewer);
+        merge(&mut self.pdf_viewer, &other.pdf_viewer);
+        merge(&mut self.file_manager, &other.file_manager);
+        merge(&mut self.mail, &other.mail);
+        self.mime
+            .extend(other.mime.iter().map(|(k, v)| (k.clone(), v.clone())));
+    }
+
+    /// Every MIME type and its `.desktop` file, sorted by MIME type
+    pub(crate) fn associations(&self) -> BTreeMap<&str, &str> {
+        let roles: [(&Option<String>, &[&str]); 6] = [
+            (&self.browser, &BROWSER_TYPES),
+            (&self.editor, &EDITOR_TYPES),
+            (&self.image_viewer, &IMAGE_VIEWER_TYPES),
+            (&self.pdf_viewer, &PDF_VIEWER_TYPES),
+            (&self.file_manager, &FILE_MANAGER_TYPES),
+            (&self.mail, &MAIL_TYPES),
+        ];
+
+        let mut associations = BTreeMap::new();
+        for (desktop_file, mime_types) in roles.iter() {
+            if let Some(desktop_file) = desktop_file {
+                for mime_type in mime_types.iter() {
+                    associations.insert(*mime_type, desktop_file.as_str());
+                }
+            }
+        }
+        for (mime_type, desktop_file) in self.mime.iter() {
+            associations.insert(mime_type.as_str(), desktop_file.as_str());
+        }
+
+        associations
+    }
+
+    /// Referenced `.desktop` files, sorted and deduplicated
+    pub(crate) fn desktop_files(&self) -> Vec<&str> {
+        let mut desktop_files: Vec<&str> = self.associations().into_values().collect();
+        desktop_files.sort_unstable();
+        desktop_files.dedup();
+        desktop_files
+    }
+
+    /// The `mimeapps.list` content
+    pub(crate) fn mimeapps_list(&self) -> String {
+        let mut content = String::from("# Generated by bombadil\n[Default Applications]\n");
+        for (mime_type, desktop_file) in self.associations() {
+            content.push_str(&format!("{}={}\n", mime_type, desktop_file));
+        }
+
+        content
+    }
+}
+
+/// Where the list is written before being linked
+pub(crate) fn copy_path(dotfiles_dir: &Path) -> PathBuf {
+    dotfiles_dir.join(".dots").join(MIMEAPPS_COPY)
+}
+
+/// `$XDG_CONFIG/mimeapps.list`
+pub(crate) fn target_path() -> Result<PathBuf> {
+    dirs::config_dir()
+        .map(|config| config.join("mimeapps.list"))
+        .ok_or_else(|| anyhow!("$XDG_CONFIG does not exist"))
+}
+
+/// Directories searched for installed `.desktop` files : `$XDG_DATA_HOME/applications`
+/// then each `$XDG_DATA_DIRS/applications`
+pub(crate) fn application_dirs() -> Vec<PathBuf> {
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    dirs::data_dir()
+        .into_iter()
+        .chain(env::split_paths(&data_dirs))
+        .map(|dir| dir.join("applications"))
+        .collect()
+}
+
+/// Desktop file ids map dashes to sub directories, `kde-okular.desktop` may be
+/// installed as `kde/okular.desktop`
+pub(crate) fn is_installed(desktop_file: &str, application_dirs: &[PathBuf]) -> bool {
+    let mut candidates = vec![PathBuf::from(desktop_file)];
+    for (index, _) in desktop_file.match_indices('-') {
+        let (dir, file) = desktop_file.split_at(index);
+        candidates.push(Path::new(dir).join(&file[1..]));
+    }
+
+    application_dirs.iter().any(|dir| {
+        candidates
+            .iter()
+            .any(|candidate| dir.join(candidate).is_file())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mimeapps::{is_installed, Defaults};
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_generate_mimeapps_list_with_profile_overrides() -> anyhow::Result<()> {
+        // Arrange
+        let mut defaults = Defaults {
+            browser: Some("firefox.desktop".to_string()),
+            editor: Some("nvim.desktop".to_string()),
+            ..Default::default()
+        };
+        let work = Defaults {
+            browser: Some("chromium.desktop".to_string()),
+            mime: vec![("text/html".to_string(), "kde-falkon.desktop".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("kde"))?;
+        fs::write(temp.join("kde/falkon.desktop"), "")?;
+        fs::write(temp.join("nvim.desktop"), "")?;
+        let application_dirs = vec![temp.to_path_buf()];
+
+        // Act
+        defaults.merge(&work);
+        let list = defaults.mimeapps_list();
+
+        // Assert
+        assert_eq!(
+            list,
+            "# Generated by bombadil\n\
+             [Default Applications]\n\
+             application/xhtml+xml=chromium.desktop\n\
+             text/html=kde-falkon.desktop\n\
+             text/plain=nvim.desktop\n\
+             x-scheme-handler/http=chromium.desktop\n\
+             x-scheme-handler/https=chromium.desktop\n"
+        );
+        let missing: Vec<&str> = defaults
+            .desktop_files()
+            .into_iter()
+            .filter(|desktop_file| !is_installed(desktop_file, &application_dirs))
+            .collect();
+        assert_eq!(missing, vec!["chromium.desktop"]);
+        assert!(Defaults::default().is_empty());
+        Ok(())
+    }
+}
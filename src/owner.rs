@@ -0,0 +1,210 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+/// Owner and group given to the rendered files and the links of a dot, ex:
+/// `chown = "alice:staff"`. Only root can give files to another user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Owner {
+    uid: Option<u32>,
+    gid: Option<u32>,
+}
+
+impl Owner {
+    /// Parse `user`, `user:group` or `:group`, names are looked up in the user database and
+    /// numeric ids are used as is
+    pub(crate) fn parse(spec: &str) -> Result<Self> {
+        let (user, group) = match spec.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (spec, None),
+        };
+
+        let owner = Owner {
+            uid: Some(user)
+                .filter(|user| !user.is_empty())
+                .map(user_id)
+                .transpose()?,
+            gid: group
+                .filter(|group| !group.is_empty())
+                .map(group_id)
+                .transpose()?,
+        };
+
+        match owner {
+            Owner {
+                uid: None,
+                gid: None,
+            } => Err(anyhow!(
+                "invalid owner \"{}\", expected \"user\", \"user:group\" or \":group\"",
+                spec
+            )),
+            owner => Ok(owner),
+        }
+    }
+
+    /// The current user can give its files to this owner : it is root, or keeps the files
+    /// and gives them to one of its groups
+    #[cfg(unix)]
+    pub(crate) fn is_allowed(&self) -> bool {
+        let euid = unsafe { libc::geteuid() };
+        if euid == 0 {
+            return true;
+        }
+
+        self.uid.is_none_or(|uid| uid == euid) && self.gid.is_none_or(|gid| groups().contains(&gid))
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn is_allowed(&self) -> bool {
+        false
+    }
+
+    /// Change the owner of `path`, links are not followed
+    #[cfg(unix)]
+    pub(crate) fn apply(&self, path: &Path) -> std::io::Result<()> {
+        std::os::unix::fs::lchown(path, self.uid, self.gid)
+    }
+
+    #[cfg(not(unix))]
+    pub(crate) fn apply(&self, _path: &Path) -> std::io::Result<()> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "file owners are only supported on unix",
+        ))
+    }
+
+    /// Change the owner of `path` and everything inside of it, links are not followed
+    pub(crate) fn apply_recursively(&self, path: &Path) -> std::io::Result<()> {
+        self.apply(path)?;
+        if fs::symlink_metadata(path)?.is_dir() {
+            for entry in path.read_dir()? {
+                self.apply_recursively(&entry?.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn user_id(user: &str) -> Result<u32> {
+    if let Ok(uid) = user.parse() {
+        return Ok(uid);
+    }
+
+    let name = std::ffi::CString::new(user)?;
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16_384];
+    unsafe {
+        libc::getpwnam_r(
+            name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if result.is_null() {
+        Err(anyhow!("unknown user \"{}\"", user))
+    } else {
+        Ok(passwd.pw_uid)
+    }
+}
+
+#[cfg(unix)]
+fn group_id(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+
+    let name = std::ffi::CString::new(group)?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16_384];
+    unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+
+    if result.is_null() {
+        Err(anyhow!("unknown group \"{}\"", group))
+    } else {
+        Ok(entry.gr_gid)
+    }
+}
+
+#[cfg(not(unix))]
+fn user_id(user: &str) -> Result<u32> {
+    user.parse().map_err(|_| {
+        anyhow!(
+            "unknown user \"{}\", file owners are only supported on unix",
+            user
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn group_id(group: &str) -> Result<u32> {
+    group.parse().map_err(|_| {
+        anyhow!(
+            "unknown group \"{}\", file owners are only supported on unix",
+            group
+        )
+    })
+}
+
+/// Effective and supplementary groups of the current user
+#[cfg(unix)]
+fn groups() -> Vec<u32> {
+    let count = unsafe { libc::getgroups(0, std::ptr::null_mut()) };
+    let mut groups = vec![0; count.max(0) as usize];
+    let count = unsafe { libc::getgroups(count, groups.as_mut_ptr()) };
+    groups.truncate(count.max(0) as usize);
+    groups.push(unsafe { libc::getegid() });
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::owner::Owner;
+    use anyhow::Result;
+    use std::fs;
+    use std::os::unix::fs::MetadataExt;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_parse_and_apply_owners() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("ssh"))?;
+        fs::write(temp.join("ssh/config"), "Host *")?;
+        let metadata = fs::metadata(temp.join("ssh/config"))?;
+        let current = format!("{}:{}", metadata.uid(), metadata.gid());
+
+        // Act
+        let owner = Owner::parse(&current)?;
+        let root = Owner::parse("root")?;
+        owner.apply_recursively(&temp.join("ssh"))?;
+
+        // Assert
+        assert!(owner.is_allowed());
+        assert_eq!(
+            root,
+            Owner {
+                uid: Some(0),
+                gid: None
+            }
+        );
+        assert_eq!(Owner::parse(":0")?.gid, Some(0));
+        assert!(Owner::parse(":").is_err());
+        assert!(Owner::parse("no-such-bombadil-user").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use crate::logger;
+use anyhow::Result;
+use colored::*;
+use std::io::{self, Read, Write};
+
+/// An entry of the terminal picker
+#[derive(Debug, Clone)]
+pub(crate) struct PickerItem {
+    pub name: String,
+    pub description: Option<String>,
+    /// Lines displayed below the list when the item is highlighted
+    pub preview: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Key {
+    Up,
+    Down,
+    Toggle,
+    Confirm,
+    Abort,
+    Other,
+}
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Confirmed(Vec<String>),
+    Aborted,
+}
+
+/// Multi-select list state, kept apart from the terminal handling
+struct Picker {
+    items: Vec<PickerItem>,
+    selected: Vec<bool>,
+    cursor: usize,
+}
+
+impl Picker {
+    fn new(items: Vec<PickerItem>, preselected: &[String]) -> Self {
+        let selected = items
+            .iter()
+            .map(|item| preselected.contains(&item.name))
+            .collect();
+
+        Picker {
+            items,
+            selected,
+            cursor: 0,
+        }
+    }
+
+    fn handle(&mut self, key: Key) -> Option<Outcome> {
+        match key {
+            Key::Up => self.cursor = self.cursor.checked_sub(1).unwrap_or(self.items.len() - 1),
+            Key::Down => self.cursor = (self.cursor + 1) % self.items.len(),
+            Key::Toggle => self.selected[self.cursor] = !self.selected[self.cursor],
+            Key::Confirm => {
+                let names = self
+                    .items
+                    .iter()
+                    .zip(self.selected.iter())
+                    .filter(|(_, selected)| **selected)
+                    .map(|(item, _)| item.name.clone())
+                    .collect();
+                return Some(Outcome::Confirmed(names));
+            }
+            Key::Abort => return Some(Outcome::Aborted),
+            Key::Other => {}
+        }
+
+        None
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::from("\x1b[2J\x1b[H");
+        let arrows = if logger::is_plain() {
+            "up/down"
+        } else {
+            "↑/↓"
+        };
+        out.push_str(&format!(
+            "{}\n\n",
+            format!(
+                "Select profiles ({} or j/k to move, space to toggle, enter to link, q to abort)",
+                arrows
+            )
+            .bold()
+        ));
+
+        for (idx, item) in self.items.iter().enumerate() {
+            let pointer = if idx == self.cursor { ">" } else { " " };
+            let checkbox = if self.selected[idx] { "[x]" } else { "[ ]" };
+            let name = if idx == self.cursor {
+                item.name.green().bold()
+            } else {
+                item.name.normal()
+            };
+
+            match &item.description {
+                Some(description) => out.push_str(&format!(
+                    "{} {} {} - {}\n",
+                    pointer, checkbox, name, description
+                )),
+                None => out.push_str(&format!("{} {} {}\n", pointer, checkbox, name)),
+            }
+        }
+
+        let highlighted = &self.items[self.cursor];
+        out.push_str(&format!("\n{}\n", highlighted.name.blue()));
+        if highlighted.preview.is_empty() {
+            out.push_str("  no dot changes\n");
+        }
+        for line in highlighted.preview.iter() {
+            out.push_str(&format!("  {}\n", line));
+        }
+
+        out
+    }
+}
+
+/// Restore the terminal attributes when dropped
+#[cfg(unix)]
+struct RawMode {
+    original: libc::termios,
+}
+
+#[cfg(unix)]
+impl RawMode {
+    fn enable() -> Result<Self> {
+        // SAFETY: termios is a plain C struct filled by tcgetattr
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut original) } != 0 {
+            return Err(anyhow!(
+                "Interactive mode needs a terminal : {}",
+                io::Error::last_os_error()
+            ));
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+        // Reads give up after 100ms without input, a lone ESC is not followed by the rest of a sequence
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 1;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(anyhow!(io::Error::last_os_error()));
+        }
+
+        // Switch to the alternate screen and hide the cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(RawMode { original })
+    }
+}
+
+#[cfg(unix)]
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original) };
+    }
+}
+
+#[cfg(not(unix))]
+struct RawMode;
+
+/// The terminal raw mode is only implemented with termios
+#[cfg(not(unix))]
+impl RawMode {
+    fn enable() -> Result<Self> {
+        Err(anyhow!("Interactive mode requires a Unix terminal"))
+    }
+}
+
+/// The next byte of `stdin`, `None` when the terminal read timed out
+fn read_byte(stdin: &mut impl Read) -> Result<Option<u8>> {
+    let mut byte = [0; 1];
+    match stdin.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+fn read_key(stdin: &mut impl Read) -> Result<Key> {
+    let byte = loop {
+        if let Some(byte) = read_byte(stdin)? {
+            break byte;
+        }
+    };
+
+    let key = match byte {
+        b'k' => Key::Up,
+        b'j' => Key::Down,
+        b' ' => Key::Toggle,
+        b'\r' | b'\n' => Key::Confirm,
+        // q or Ctrl+C
+        b'q' | 3 => Key::Abort,
+        // Escape sequence, arrow keys are sent as `ESC [ A` and `ESC [ B`, ESC alone aborts
+        27 => match (read_byte(stdin)?, read_byte(stdin)?) {
+            (None, _) => Key::Abort,
+            (Some(b'['), Some(b'A')) => Key::Up,
+            (Some(b'['), Some(b'B')) => Key::Down,
+            _ => Key::Other,
+        },
+        _ => Key::Other,
+    };
+
+    Ok(key)
+}
+
+/// Display the items in a multi-select list, returns the selected names
+/// or `None` if the user aborted
+pub(crate) fn pick(items: Vec<PickerItem>, preselected: &[String]) -> Result<Option<Vec<String>>> {
+    if items.is_empty() {
+        return Err(anyhow!("No profile defined in bombadil.toml"));
+    }
+
+    let mut picker = Picker::new(items, preselected);
+    let _raw_mode = RawMode::enable()?;
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+
+    loop {
+        write!(stdout, "{}", picker.render())?;
+        stdout.flush()?;
+
+        match picker.handle(read_key(&mut stdin)?) {
+            Some(Outcome::Confirmed(names)) => return Ok(Some(names)),
+            Some(Outcome::Aborted) => return Ok(None),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::picker::{read_key, Key, Outcome, Picker, PickerItem};
+
+    fn items() -> Vec<PickerItem> {
+        ["i3", "sway", "work"]
+            .iter()
+            .map(|name| PickerItem {
+                name: name.to_string(),
+                description: None,
+                preview: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_toggle_and_confirm_selection() {
+        // Arrange
+        let mut picker = Picker::new(items(), &["work".to_string()]);
+
+        // Act
+        picker.handle(Key::Down);
+        picker.handle(Key::Toggle);
+        let outcome = picker.handle(Key::Confirm);
+
+        // Assert
+        assert_eq!(
+            outcome,
+            Some(Outcome::Confirmed(vec![
+                "sway".to_string(),
+                "work".to_string()
+            ]))
+        );
+    }
+
+    #[test]
+    fn should_wrap_cursor() {
+        // Arrange
+        let mut picker = Picker::new(items(), &[]);
+
+        // Act
+        picker.handle(Key::Up);
+
+        // Assert
+        assert_eq!(picker.cursor, 2);
+    }
+
+    #[test]
+    fn should_read_arrow_keys() {
+        // Arrange
+        let mut input: &[u8] = b"\x1b[A\x1b[Bq\x1b";
+
+        // Act & Assert
+        assert_eq!(read_key(&mut input).unwrap(), Key::Up);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Down);
+        assert_eq!(read_key(&mut input).unwrap(), Key::Abort);
+        // A lone ESC does not wait for the rest of a sequence
+        assert_eq!(read_key(&mut input).unwrap(), Key::Abort);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::state;
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// Bytes needed to render the given sources, links inside a directory dot are not followed
+/// since they may loop
+pub(crate) fn required_space(sources: &[PathBuf]) -> u64 {
+    sources
+        .iter()
+        .map(|source| state::size(&fs::canonicalize(source).unwrap_or_else(|_| source.clone())))
+        .sum()
+}
+
+/// Bytes available to unprivileged users on the filesystem containing `path`
+#[cfg(unix)]
+pub(crate) fn available_space(path: &Path) -> Option<u64> {
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: statvfs is a plain C struct filled by the call
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } == 0 {
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    } else {
+        None
+    }
+}
+
+/// Free space is not checked outside of Unix systems
+#[cfg(not(unix))]
+pub(crate) fn available_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// The directory that will hold `target` once its missing parents are created
+pub(crate) fn existing_ancestor(target: &Path) -> Option<&Path> {
+    target
+        .ancestors()
+        .skip(1)
+        .find(|ancestor| ancestor.is_dir())
+}
+
+/// `target` with the symlinks of its existing parents resolved, the target itself is
+/// usually a symlink to `.dots` and is left as is
+pub(crate) fn resolve_parents(target: &Path) -> PathBuf {
+    let ancestor = match existing_ancestor(target) {
+        Some(ancestor) => ancestor,
+        None => return target.to_path_buf(),
+    };
+
+    match (ancestor.canonicalize(), target.strip_prefix(ancestor)) {
+        (Ok(resolved), Ok(rest)) => resolved.join(rest),
+        _ => target.to_path_buf(),
+    }
+}
+
+/// Linking `target` would replace a file of the dotfiles directory `dotfiles_dir`,
+/// or the dotfiles directory itself when `target` contains it
+pub(crate) fn overlaps_dotfiles(target: &Path, dotfiles_dir: &Path) -> bool {
+    let target = resolve_parents(target);
+    let dotfiles_dir = dotfiles_dir
+        .canonicalize()
+        .unwrap_or_else(|_| dotfiles_dir.to_path_buf());
+
+    target.starts_with(&dotfiles_dir) || dotfiles_dir.starts_with(&target)
+}
+
+#[cfg(unix)]
+pub(crate) fn is_writable(path: &Path) -> bool {
+    CString::new(path.as_os_str().as_bytes())
+        .map(|path| unsafe { libc::access(path.as_ptr(), libc::W_OK) } == 0)
+        .unwrap_or(false)
+}
+
+/// Only the read-only attribute can be checked without opening the file
+#[cfg(not(unix))]
+pub(crate) fn is_writable(path: &Path) -> bool {
+    fs::metadata(path)
+        .map(|metadata| !metadata.permissions().readonly())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::preflight::{available_space, existing_ancestor, overlaps_dotfiles, required_space};
+    use anyhow::Result;
+    use std::fs;
+    use std::os::unix;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_compute_required_space() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("sway/sub"))?;
+        fs::write(temp.join("sway/config"), "12345")?;
+        fs::write(temp.join("sway/sub/theme"), "123")?;
+        fs::write(temp.join("zshrc"), "12")?;
+
+        // Act
+        let required = required_space(&[temp.join("sway"), temp.join("zshrc")]);
+
+        // Assert
+        assert_eq!(required, 10);
+        assert!(available_space(&temp).is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn should_find_existing_ancestor() {
+        // Arrange
+        let temp = TempDir::default();
+        let target = temp.join("not/created/yet/config");
+
+        // Act
+        let ancestor = existing_ancestor(&target);
+
+        // Assert
+        assert_eq!(ancestor, Some(&*temp));
+    }
+
+    #[test]
+    fn should_detect_targets_inside_dotfiles() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let home = temp.canonicalize()?;
+        let dotfiles = home.join("dotfiles");
+        fs::create_dir_all(dotfiles.join(".dots/sway"))?;
+        fs::create_dir_all(home.join(".config"))?;
+        unix::fs::symlink(&dotfiles, home.join("linked_dotfiles"))?;
+        unix::fs::symlink(dotfiles.join(".dots/sway"), home.join(".config/sway"))?;
+
+        // Assert
+        assert!(overlaps_dotfiles(&dotfiles.join(".dots/zshrc"), &dotfiles));
+        assert!(overlaps_dotfiles(
+            &home.join("linked_dotfiles/.config/nvim"),
+            &dotfiles
+        ));
+        assert!(overlaps_dotfiles(&home, &dotfiles));
+        assert!(!overlaps_dotfiles(&home.join(".config/sway"), &dotfiles));
+        assert!(!overlaps_dotfiles(
+            &home.join(".config/not/created"),
+            &dotfiles
+        ));
+        Ok(())
+    }
+}
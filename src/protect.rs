@@ -0,0 +1,136 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directories of a dotfiles directory written by bombadil or git, they are not sources
+const UNPROTECTED_DIRS: [&str; 3] = [".dots", ".dots.staging", ".git"];
+
+/// Permissions and content hash of every source file in some dotfiles directories, links are
+/// not followed. Contents are hashed since an edit keeping the size within the modification
+/// time granularity leaves the metadata unchanged.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct SourceSnapshot {
+    files: BTreeMap<PathBuf, (fs::Permissions, Vec<u8>)>,
+}
+
+impl SourceSnapshot {
+    pub(crate) fn take(dotfiles_dirs: &[PathBuf]) -> Self {
+        let mut snapshot = SourceSnapshot::default();
+        for dir in dotfiles_dirs {
+            snapshot.collect(dir);
+        }
+
+        snapshot
+    }
+
+    /// Sources modified, created or removed since the snapshot was taken, sorted by path
+    pub(crate) fn changes(&self, dotfiles_dirs: &[PathBuf]) -> Vec<String> {
+        let current = SourceSnapshot::take(dotfiles_dirs);
+        let mut changes: Vec<(&PathBuf, &str)> = current
+            .files
+            .iter()
+            .filter_map(|(path, stamp)| match self.files.get(path) {
+                None => Some((path, "created")),
+                Some(previous) if previous != stamp => Some((path, "modified")),
+                Some(_) => None,
+            })
+            .collect();
+
+        changes.extend(
+            self.files
+                .keys()
+                .filter(|path| !current.files.contains_key(*path))
+                .map(|path| (path, "removed")),
+        );
+        changes.sort();
+
+        changes
+            .into_iter()
+            .map(|(path, change)| format!("{} : {}", path.display(), change))
+            .collect()
+    }
+
+    fn collect(&mut self, dir: &Path) {
+        let entries = match dir.read_dir() {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let metadata = match fs::symlink_metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+
+            if metadata.is_dir() {
+                let unprotected = UNPROTECTED_DIRS
+                    .iter()
+                    .any(|unprotected| entry.file_name() == *unprotected);
+                if !unprotected {
+                    self.collect(&path);
+                }
+            } else {
+                let content = match metadata.is_symlink() {
+                    true => fs::read_link(&path).map(|destination| {
+                        destination
+                            .as_os_str()
+                            .to_string_lossy()
+                            .as_bytes()
+                            .to_vec()
+                    }),
+                    false => fs::read(&path),
+                };
+                // An unreadable source can't be compared, it is reported as modified once readable
+                let hash = content
+                    .map(|content| Sha256::digest(content).to_vec())
+                    .unwrap_or_default();
+                self.files.insert(path, (metadata.permissions(), hash));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protect::SourceSnapshot;
+    use anyhow::Result;
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_report_changed_sources_only() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        fs::create_dir_all(temp.join("sway"))?;
+        fs::create_dir_all(temp.join(".dots"))?;
+        fs::create_dir_all(temp.join(".git"))?;
+        fs::write(temp.join("sway/config"), "bar")?;
+        fs::write(temp.join("zshrc"), "zsh")?;
+        fs::write(temp.join("vimrc"), "vim")?;
+        let dirs = vec![temp.to_path_buf()];
+        let snapshot = SourceSnapshot::take(&dirs);
+
+        // Act
+        // Same size, written within the modification time granularity
+        fs::write(temp.join("sway/config"), "baz")?;
+        fs::write(temp.join("zshrc"), "zsh")?;
+        fs::remove_file(temp.join("vimrc"))?;
+        fs::write(temp.join("hook.log"), "")?;
+        fs::write(temp.join(".dots/zshrc"), "zsh")?;
+        fs::write(temp.join(".git/index"), "")?;
+        let changes = snapshot.changes(&dirs);
+
+        // Assert
+        assert_eq!(
+            changes,
+            vec![
+                format!("{} : created", temp.join("hook.log").display()),
+                format!("{} : modified", temp.join("sway/config").display()),
+                format!("{} : removed", temp.join("vimrc").display()),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,191 @@
+use crate::settings::CONFIG_EXTENSIONS;
+use anyhow::Result;
+use colored::*;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A remote import that does not match its pinned `sha256`, the configuration is not loaded
+#[derive(Debug)]
+pub(crate) struct IntegrityError {
+    url: String,
+    expected: String,
+    actual: String,
+}
+
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Integrity check failed for {}, expected sha256 {} but got {}",
+            self.url, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Fetch a remote import into `$XDG_CACHE/bombadil/imports` and return the cached file path
+pub(crate) fn fetch_import(url: &str, sha256: Option<&str>) -> Result<PathBuf> {
+    let cache_dir = dirs::cache_dir()
+        .ok_or_else(|| anyhow!("$XDG_CACHE does not exist"))?
+        .join("bombadil")
+        .join("imports");
+
+    fetch_import_to(url, sha256, &cache_dir)
+}
+
+/// Remote imports are cached by url, with the extension of the url so yaml and json imports
+/// are parsed as such. When `sha256` is given a matching cached copy is used
+/// without any network access, otherwise the import is downloaded again and the cached copy
+/// is only used as an offline fallback.
+fn fetch_import_to(url: &str, sha256: Option<&str>, cache_dir: &Path) -> Result<PathBuf> {
+    if !(url.starts_with("https://") || url.starts_with("file://")) {
+        return Err(anyhow!("Remote import {} must use https", url));
+    }
+
+    let cache_path = cache_dir.join(format!(
+        "{}.{}",
+        hex_digest(url.as_bytes()),
+        config_extension(url)
+    ));
+
+    if let (Some(expected), Ok(cached)) = (sha256, fs::read(&cache_path)) {
+        if hex_digest(&cached).eq_ignore_ascii_case(expected) {
+            return Ok(cache_path);
+        }
+    }
+
+    match download(url) {
+        Ok(content) => {
+            if let Some(expected) = sha256 {
+                let actual = hex_digest(&content);
+                if !actual.eq_ignore_ascii_case(expected) {
+                    return Err(IntegrityError {
+                        url: url.to_string(),
+                        expected: expected.to_string(),
+                        actual,
+                    }
+                    .into());
+                }
+            }
+
+            fs::create_dir_all(cache_dir)?;
+            fs::write(&cache_path, content)?;
+            Ok(cache_path)
+        }
+        Err(err) if sha256.is_none() && cache_path.exists() => {
+            let warning = format!("{}, using the cached copy of {}", err, url).yellow();
+            warn!("{}", warning);
+            Ok(cache_path)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// The configuration format of `url`, toml when its path has no known extension
+fn config_extension(url: &str) -> &'static str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = path
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+
+    CONFIG_EXTENSIONS
+        .iter()
+        .find(|known| extension.as_deref() == Some(**known))
+        .copied()
+        .unwrap_or("toml")
+}
+
+/// Remote imports need the `curl` command
+fn download(url: &str) -> Result<Vec<u8>> {
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", url])
+        .output()
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => anyhow!(
+                "curl is required to fetch the remote import {}, install it or import a local copy",
+                url
+            ),
+            _ => anyhow!("Unable to run curl to fetch {} : {}", url, err),
+        })?;
+
+    if output.status.success() {
+        Ok(output.stdout)
+    } else {
+        Err(anyhow!(
+            "Unable to fetch {} : {}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+fn hex_digest(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::remote::{config_extension, fetch_import_to, hex_digest, IntegrityError};
+    use anyhow::Result;
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_fetch_and_cache_remote_import() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let remote = temp.join("base.toml");
+        let content = "[settings]\nvars = [ \"vars.toml\" ]\n";
+        fs::write(&remote, content)?;
+        let url = format!("file://{}", remote.display());
+        let sha256 = hex_digest(content.as_bytes());
+
+        // Act
+        let cached = fetch_import_to(&url, Some(&sha256), &temp.join("cache"))?;
+        fs::remove_file(&remote)?;
+        let offline = fetch_import_to(&url, Some(&sha256), &temp.join("cache"))?;
+
+        // Assert
+        assert_eq!(fs::read_to_string(&cached)?, content);
+        assert_eq!(cached, offline);
+        assert_eq!(cached.extension().unwrap(), "toml");
+        Ok(())
+    }
+
+    #[test]
+    fn should_keep_url_extension_of_remote_import() {
+        assert_eq!(config_extension("https://example.com/base.yaml"), "yaml");
+        assert_eq!(
+            config_extension("https://example.com/base.JSON?ref=main"),
+            "json"
+        );
+        assert_eq!(config_extension("https://example.com/v1.2/base"), "toml");
+        assert_eq!(config_extension("https://example.com/raw"), "toml");
+    }
+
+    #[test]
+    fn should_reject_remote_import_with_wrong_hash() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let remote = temp.join("base.toml");
+        fs::write(&remote, "[settings]")?;
+        let url = format!("file://{}", remote.display());
+
+        // Act
+        let result = fetch_import_to(&url, Some("deadbeef"), &temp.join("cache"));
+
+        // Assert
+        assert!(result.unwrap_err().is::<IntegrityError>());
+        assert!(!temp.join("cache").exists());
+        Ok(())
+    }
+}
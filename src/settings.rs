@@ -1,30 +1,300 @@
+use crate::autostart::Autostart;
+use crate::check::ConfigFiles;
 use crate::dots::{Dot, DotOverride};
+use crate::facts::Facts;
+use crate::hook::HookEntry;
+use crate::i18n::t;
+use crate::mimeapps::Defaults;
+use crate::remote::{self, IntegrityError};
 use crate::BOMBADIL_CONFIG;
 use anyhow::Result;
 use colored::Colorize;
 use config::{Config, ConfigError, File};
-use std::collections::HashMap;
+use globset::Glob;
+use std::collections::{BTreeMap, HashMap};
 use std::ops::Not;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Machine local configuration, merged last and never meant to be committed
+const LOCAL_CONFIG: &str = "local.toml";
+/// Supported configuration formats, by order of precedence when several files exist
+pub(crate) const CONFIG_EXTENSIONS: [&str; 4] = ["toml", "yaml", "yml", "json"];
+/// Schema version of the configuration, older configurations are upgraded by `bombadil migrate`
+pub(crate) const CONFIG_VERSION: u32 = 2;
+/// Path of the configuration to use instead of `$XDG_CONFIG/bombadil.toml`
+pub const CONFIG_ENV: &str = "BOMBADIL_CONFIG";
+/// Overrides `dotfiles_dir`
+const DOTFILES_DIR_ENV: &str = "BOMBADIL_DOTFILES_DIR";
+/// Overrides `gpg_user_id`, an empty value disables gpg
+const GPG_USER_ID_ENV: &str = "BOMBADIL_GPG_USER_ID";
+/// Imports nested deeper than this are skipped unless `max_import_depth` is set
+const DEFAULT_MAX_IMPORT_DEPTH: usize = 16;
+
+/// Name of the profile given by `bombadil link --overlay`
+pub const OVERLAY_PROFILE: &str = "@overlay";
 
 /// The Global bombadil configuration
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct Settings {
+    /// Schema version, configurations without one predate versioning
+    #[serde(default)]
+    pub version: Option<u32>,
+
     /// User define dotfiles directory, usually your versioned dotfiles
     pub(crate) dotfiles_dir: PathBuf,
 
     pub gpg_user_id: Option<String>,
 
+    /// How many previous links and replaced files are kept in `.dots`
+    #[serde(default)]
+    pub retention: RetentionSettings,
+
     #[serde(default)]
     pub settings: ActiveProfile,
 
     #[serde(default)]
     pub profiles: HashMap<String, Profile>,
 
+    /// Named sets of var files, the active one is chosen with `bombadil theme set`
+    #[serde(default)]
+    pub themes: HashMap<String, Theme>,
+
     /// Paths to merge with the main configuration
     #[serde(default)]
     pub import: Vec<ImportPath>,
+
+    /// How deep imported files can import other files, the main configuration imports are
+    /// at depth 1
+    #[serde(default)]
+    pub max_import_depth: Option<usize>,
+
+    /// Additional dotfiles repositories, each with its own `bombadil.toml`
+    #[serde(default)]
+    pub source: Vec<SourceRepository>,
+
+    /// Git integration of the dotfiles repository
+    #[serde(default)]
+    pub git: GitSettings,
+
+    /// What was merged from `$XDG_CONFIG/bombadil/local.toml`, if it exists
+    #[serde(skip)]
+    pub(crate) local: Option<LocalProvenance>,
+
+    /// Configuration files merged into these settings, the main configuration first
+    #[serde(skip)]
+    pub(crate) files: Vec<PathBuf>,
+
+    /// Imports and sources that could not be merged, printed by [`Settings::get`]
+    #[serde(skip)]
+    pub(crate) warnings: Vec<String>,
+
+    /// Configuration file declaring each merged entry
+    #[serde(skip)]
+    pub(crate) provenance: Provenance,
+}
+
+/// A configuration file and the line declaring an entry, when it can be found
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct Origin {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+}
+
+/// A hook or var file and where it was declared
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct OriginEntry {
+    pub entry: String,
+    #[serde(flatten)]
+    pub origin: Origin,
+}
+
+/// Where the dots, profiles, hooks and var files of the merged settings were declared. Entries
+/// declared by several files point to the declaration that was kept, hooks and var files are
+/// in the same order as in the settings.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub(crate) struct Provenance {
+    pub dots: BTreeMap<String, Origin>,
+    pub profiles: BTreeMap<String, Origin>,
+    pub prehooks: Vec<OriginEntry>,
+    pub posthooks: Vec<OriginEntry>,
+    pub vars: Vec<OriginEntry>,
+}
+
+impl Provenance {
+    /// The entries declared by the configuration file `path`
+    fn declared(
+        path: &Path,
+        settings: &ActiveProfile,
+        profiles: &HashMap<String, Profile>,
+    ) -> Self {
+        let files = ConfigFiles::read(&[path.to_path_buf()]);
+        let origin = |location: Option<(PathBuf, usize)>| Origin {
+            file: path.to_path_buf(),
+            line: location.map(|(_, line)| line),
+        };
+        let hooks = |hooks: &[HookEntry], key: &str| -> Vec<OriginEntry> {
+            hooks
+                .iter()
+                .map(|hook| {
+                    let entry = match hook {
+                        HookEntry::Command(command) => command.clone(),
+                        HookEntry::Script { interpreter, .. } => interpreter.clone(),
+                        HookEntry::ScriptFile { script, .. } => script.display().to_string(),
+                        HookEntry::Detailed { command, .. } => command.clone(),
+                    };
+                    let location = files.find_value(&entry).or_else(|| files.find_key(key));
+                    OriginEntry {
+                        origin: origin(location),
+                        entry,
+                    }
+                })
+                .collect()
+        };
+
+        Provenance {
+            dots: settings
+                .dots
+                .keys()
+                .map(|key| {
+                    let location = files.find_table(&["settings", "dots", key]);
+                    (key.clone(), origin(location))
+                })
+                .collect(),
+            profiles: profiles
+                .keys()
+                .map(|name| (name.clone(), origin(files.find_table(&["profiles", name]))))
+                .collect(),
+            prehooks: hooks(&settings.prehooks, "prehooks"),
+            posthooks: hooks(&settings.posthooks, "posthooks"),
+            vars: settings
+                .vars
+                .iter()
+                .map(|var_path| {
+                    let entry = var_path.display().to_string();
+                    OriginEntry {
+                        origin: origin(files.find_value(&entry)),
+                        entry,
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Merge the entries of a file merged after the others, like [`Settings::merge`] does
+    fn extend(&mut self, other: Provenance) {
+        self.dots.extend(other.dots);
+        self.profiles.extend(other.profiles);
+        self.prehooks.extend(other.prehooks);
+        self.posthooks.extend(other.posthooks);
+        self.vars.extend(other.vars);
+    }
+
+    /// Merge the entries of a `[[source]]` repository, like [`Settings::merge_sources`] does :
+    /// its hooks and var files come first and its dots and profiles don't override
+    fn extend_source(&mut self, mut other: Provenance) {
+        for (key, origin) in other.dots {
+            self.dots.entry(key).or_insert(origin);
+        }
+        for (name, origin) in other.profiles {
+            self.profiles.entry(name).or_insert(origin);
+        }
+        other.prehooks.append(&mut self.prehooks);
+        self.prehooks = other.prehooks;
+        other.posthooks.append(&mut self.posthooks);
+        self.posthooks = other.posthooks;
+        other.vars.append(&mut self.vars);
+        self.vars = other.vars;
+    }
+}
+
+/// The toml configuration `config` without the dot `key` declared at `line`, one based : its
+/// `[settings.dots.<key>]` table or its inline table. The rest of the file is left as is.
+pub(crate) fn without_dot(config: &str, key: &str, line: usize) -> Result<String> {
+    let unable = || anyhow!("Unable to remove dot `{}`, remove it by hand", key);
+    let lines: Vec<&str> = config.lines().collect();
+    let start = line
+        .checked_sub(1)
+        .filter(|start| *start < lines.len())
+        .ok_or_else(unable)?;
+
+    let header = format!("[settings.dots.{}]", key);
+    let nested = format!("[settings.dots.{}.", key);
+    let (mut start, mut end) = (start, start + 1);
+    if lines[start].trim().starts_with(&header) {
+        end = lines[end..]
+            .iter()
+            .map(|line| line.trim())
+            .position(|line| line.starts_with('[') && !line.starts_with(&nested))
+            .map_or(lines.len(), |next| end + next);
+
+        // The blank line separating the table from the previous one goes with it
+        while end > start + 1 && lines[end - 1].trim().is_empty() {
+            end -= 1;
+        }
+        if start > 0 && lines[start - 1].trim().is_empty() {
+            start -= 1;
+        }
+    }
+
+    let mut without = [&lines[..start], &lines[end..]].concat().join("\n");
+    if config.ends_with('\n') {
+        without.push('\n');
+    }
+
+    // The file is only written back if nothing but the dot was removed
+    let mut expected: toml::Value = toml::from_str(config)?;
+    let removed = expected
+        .get_mut("settings")
+        .and_then(|settings| settings.get_mut("dots"))
+        .and_then(|dots| dots.as_table_mut())
+        .and_then(|dots| dots.remove(key));
+    let mut actual: toml::Value = toml::from_str(&without).map_err(|_| unable())?;
+    prune(&mut expected);
+    prune(&mut actual);
+
+    match removed {
+        Some(_) if actual == expected => Ok(without),
+        _ => Err(unable()),
+    }
+}
+
+/// Remove the empty tables, `[settings.dots]` stays declared once its last dot is removed
+fn prune(value: &mut toml::Value) {
+    if let Some(table) = value.as_table_mut() {
+        table.iter_mut().for_each(|(_, value)| prune(value));
+        table.retain(|_, value| !value.as_table().is_some_and(|table| table.is_empty()));
+    }
+}
+
+/// A `[[source]]` dotfiles repository
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SourceRepository {
+    /// Relative to $HOME or absolute, the repository `bombadil.toml` uses the same format
+    /// as imported files
+    pub dotfiles_dir: PathBuf,
+}
+
+/// The `[git]` section
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct GitSettings {
+    /// Commit the changes made by bombadil commands to the dotfiles repository
+    #[serde(default)]
+    pub auto_commit: bool,
+
+    /// Push commits created by bombadil to the upstream remote
+    #[serde(default)]
+    pub auto_push: bool,
+}
+
+/// Entries coming from the machine local configuration
+#[derive(Debug, Default, Clone)]
+pub(crate) struct LocalProvenance {
+    pub path: PathBuf,
+    pub dots: Vec<String>,
+    pub prehooks: Vec<HookEntry>,
+    pub posthooks: Vec<HookEntry>,
+    pub profiles: Vec<String>,
 }
 
 /// An imported configuration, same as `Settings` but without `dotfiles_dir`
@@ -41,6 +311,23 @@ pub struct ImportedSettings {
     pub import: Vec<ImportPath>,
 }
 
+/// The `[retention]` section
+#[derive(Debug, Deserialize, Serialize, Default, Clone)]
+pub struct RetentionSettings {
+    /// Previous links kept in `.dots`, one when unset
+    #[serde(default)]
+    pub keep_generations: Option<usize>,
+
+    /// Previous links whose replaced files are kept, all the kept links when unset. The files
+    /// replaced by the last link are always kept
+    #[serde(default)]
+    pub keep_backups: Option<usize>,
+
+    /// Previous links older than this many days are discarded, even within `keep_generations`
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
 /// The default profile, containing dot entries, vars and hooks
 #[derive(Debug, Deserialize, Serialize, Default)]
 pub struct ActiveProfile {
@@ -48,120 +335,634 @@ pub struct ActiveProfile {
     #[serde(default)]
     pub dots: HashMap<String, Dot>,
 
+    /// Directory the relative dot targets are linked in instead of $HOME, relative to $HOME or
+    /// absolute, ex: `".config"`
+    #[serde(default)]
+    pub target_base: Option<PathBuf>,
+
     /// Post install hook commands
     #[serde(default)]
-    pub prehooks: Vec<String>,
+    pub prehooks: Vec<HookEntry>,
 
     /// Post install hook commands
     #[serde(default)]
-    pub posthooks: Vec<String>,
+    pub posthooks: Vec<HookEntry>,
+
+    /// Working directory of the hooks without `cwd`, relative to the dotfiles directory or
+    /// absolute, defaults to the dotfiles directory
+    #[serde(default)]
+    pub hook_cwd: Option<PathBuf>,
 
     /// Variables to use in templates
     #[serde(default)]
     pub vars: Vec<PathBuf>,
+
+    /// Warn when linking files that are not committed to the dotfiles git repository
+    #[serde(default)]
+    pub warn_untracked: bool,
+
+    /// Fail links that modified the files of the dotfiles directory, see `bombadil link --write-protect`
+    #[serde(default)]
+    pub write_protect: bool,
+
+    /// Render `__[cmd "..."]__` placeholders with the output of the command
+    #[serde(default)]
+    pub allow_cmd_templates: bool,
+
+    /// Prepend a comment to rendered files telling where they come from
+    #[serde(default)]
+    pub provenance_header: bool,
+
+    /// Extensions of the files that never get a provenance header, ex: `["json", "md"]`
+    #[serde(default)]
+    pub provenance_header_exclude: Vec<String>,
+
+    /// Command used by `bombadil diff`, ex: `delta` or `difft`, defaults to a built-in unified diff
+    #[serde(default)]
+    pub diff_tool: Option<String>,
+
+    /// Command telling about upstream changes found by `bombadil sync`, defaults to `notify-send`
+    #[serde(default)]
+    pub sync_notify: Option<HookEntry>,
+
+    /// Rendered in place of the secrets when linking with `--degraded`
+    #[serde(default)]
+    pub secret_sentinel: Option<String>,
+
+    /// Log every decryption and use of a secret to `$XDG_STATE_HOME/bombadil/secrets_audit.log`
+    #[serde(default)]
+    pub audit_secrets: bool,
+
+    /// XDG autostart entries generated to `$XDG_CONFIG/autostart`
+    #[serde(default)]
+    pub autostart: HashMap<String, Autostart>,
+
+    /// Default applications generated to `$XDG_CONFIG/mimeapps.list`
+    #[serde(default)]
+    pub defaults: Defaults,
 }
 
 /// An named profile meant to override the default one
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 pub struct Profile {
+    /// A short description displayed by `bombadil profile list`
+    #[serde(default)]
+    pub description: Option<String>,
+
     /// A list of symlink to edit
     #[serde(default)]
     pub dots: HashMap<String, DotOverride>,
 
+    /// Autostart entries to add, or to override and disable with `enabled = false`
+    #[serde(default)]
+    pub autostart: HashMap<String, Autostart>,
+
+    /// Default applications overriding the global ones
+    #[serde(default)]
+    pub defaults: Defaults,
+
     /// A list of additional profiles to enable
     #[serde(default)]
     pub extra_profiles: Vec<String>,
 
+    /// Keys of the dots that should not be linked when this profile is enabled
+    #[serde(default)]
+    pub dots_exclude: Vec<String>,
+
+    /// Deploy every dot under this directory, relative to $HOME or absolute,
+    /// when the profile is enabled
+    #[serde(default)]
+    pub target_prefix: Option<PathBuf>,
+
+    /// Dotfiles directory the sources of the profile dots and its var files are relative to,
+    /// relative to $HOME or absolute, ex: a private repository. Defaults to `dotfiles_dir`.
+    #[serde(default)]
+    pub dotfiles_dir: Option<PathBuf>,
+
     /// Pre install hook commands
     #[serde(default)]
-    pub prehooks: Vec<String>,
+    pub prehooks: Vec<HookEntry>,
 
     /// Post install hook commands
     #[serde(default)]
-    pub posthooks: Vec<String>,
+    pub posthooks: Vec<HookEntry>,
+
+    /// `false` replaces the default pre and post install hooks with those of the profile
+    /// instead of running both, defaults to `true`
+    #[serde(default)]
+    pub inherit_hooks: Option<bool>,
+
+    /// Commands run after linking when the profile was not enabled during the previous link
+    #[serde(default)]
+    pub on_profile_activate: Vec<HookEntry>,
+
+    /// Commands run before linking when the profile was enabled during the previous link
+    /// but is not anymore
+    #[serde(default)]
+    pub on_profile_deactivate: Vec<HookEntry>,
 
     /// Variables to use in templates
     #[serde(default)]
     pub vars: Vec<PathBuf>,
+
+    /// Glob pattern matched against the machine hostname, the profile is enabled
+    /// automatically when linking without explicit profiles
+    #[serde(default)]
+    pub hostname: Option<String>,
+
+    /// Operating system matcher, ex: "linux" or "macos"
+    #[serde(default)]
+    pub os: Option<String>,
+
+    /// Linux distribution matcher, ex: "arch" or "debian"
+    #[serde(default)]
+    pub distro: Option<String>,
+
+    /// Desktop session matcher, ex: "sway", "gnome", or the display server "wayland" or "x11"
+    #[serde(default)]
+    pub session: Option<String>,
+
+    /// Locale matcher, ex: "fr_FR" or "de_*"
+    #[serde(default)]
+    pub locale: Option<String>,
+
+    /// Keyboard layout matcher, ex: "fr", any of the configured layouts can match
+    #[serde(default)]
+    pub keyboard: Option<String>,
+}
+
+/// A named set of var files. Unlike profiles, a theme only changes variables and a single one
+/// is active at a time, see `bombadil theme set`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Theme {
+    /// A short description displayed by `bombadil theme list`
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// Var files applied on top of the profile vars
+    #[serde(default)]
+    pub vars: Vec<PathBuf>,
+}
+
+/// A profile given to a single `bombadil link --overlay`, written like a `[profiles.<name>]`
+/// table. It is applied on top of every enabled profile and is not remembered by the next link.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Overlay {
+    #[serde(flatten)]
+    pub profile: Profile,
+
+    /// Variables overriding those of every var file
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+impl Overlay {
+    /// Read the overlay from `path`, or from stdin when `path` is `-`
+    pub fn read(path: &Path) -> Result<Self> {
+        let content = if path == Path::new("-") {
+            let mut content = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+            content
+        } else {
+            std::fs::read_to_string(path).map_err(|err| {
+                anyhow!(t!(
+                    "overlay-read-error",
+                    path = format!("{:?}", path),
+                    error = err
+                ))
+            })?
+        };
+
+        toml::from_str(&content)
+            .map_err(|err| anyhow!("{} {:?} : {}", t!("overlay-format-error").red(), path, err))
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
-pub struct ImportPath {
-    path: PathBuf,
+#[serde(untagged)]
+pub enum ImportPath {
+    /// A file relative to the dotfiles directory or absolute
+    Local { path: PathBuf },
+    /// A file fetched over https, optionally checked against its sha256 hash
+    Remote { url: String, sha256: Option<String> },
+}
+
+impl Profile {
+    /// Resolve the dot sources and the var files of the profile in its own `dotfiles_dir`,
+    /// or in `dotfiles_dir` when it has none
+    pub(crate) fn resolve_in(&mut self, dotfiles_dir: &Path, home_dir: &Path) {
+        let dotfiles_dir = match &self.dotfiles_dir {
+            Some(own_dir) => home_dir.join(own_dir),
+            None => dotfiles_dir.to_path_buf(),
+        };
+
+        self.dots
+            .values_mut()
+            .for_each(|dot| dot.repository = Some(dotfiles_dir.clone()));
+        self.vars = self
+            .vars
+            .iter()
+            .map(|path| dotfiles_dir.join(path))
+            .collect();
+    }
+
+    /// Returns true if the profile declares at least one machine matcher
+    /// and all of them match the given facts
+    pub(crate) fn matches(&self, facts: &Facts) -> bool {
+        let layouts: Vec<Option<String>> =
+            facts.keyboard_layouts.iter().cloned().map(Some).collect();
+        let matchers = [
+            (&self.hostname, vec![&facts.hostname]),
+            (&self.os, vec![&facts.os]),
+            (&self.distro, vec![&facts.distro]),
+            (&self.session, vec![&facts.session, &facts.session_type]),
+            (&self.locale, vec![&facts.locale]),
+            (&self.keyboard, layouts.iter().collect()),
+        ];
+
+        let mut declared = matchers
+            .iter()
+            .filter_map(|(pattern, fact)| pattern.as_ref().map(|pattern| (pattern, fact)))
+            .peekable();
+
+        if declared.peek().is_none() {
+            return false;
+        }
+
+        declared.all(|(pattern, facts)| {
+            facts
+                .iter()
+                .any(|fact| fact.as_ref().is_some_and(|fact| glob_match(pattern, fact)))
+        })
+    }
+}
+
+/// Resolve the profiles declaring their own `dotfiles_dir` in it, the others stay relative to
+/// the dotfiles directory
+fn root_profiles(profiles: &mut HashMap<String, Profile>) -> Result<()> {
+    let mut rooted = profiles
+        .values_mut()
+        .filter(|profile| profile.dotfiles_dir.is_some())
+        .peekable();
+    if rooted.peek().is_none() {
+        return Ok(());
+    }
+
+    let home_dir = dirs::home_dir().ok_or_else(|| anyhow!(t!("home-not-found")))?;
+    rooted.for_each(|profile| profile.resolve_in(&home_dir, &home_dir));
+    Ok(())
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    match Glob::new(pattern) {
+        Ok(glob) => glob.compile_matcher().is_match(value),
+        Err(err) => {
+            let warning = t!("invalid-profile-matcher", pattern = pattern, error = err).yellow();
+            warn!("{}", warning);
+            false
+        }
+    }
+}
+
+/// The bombadil configuration in `dir` : `bombadil.toml`, or the first existing
+/// `bombadil.yaml`, `bombadil.yml` or `bombadil.json`. Defaults to `bombadil.toml`.
+pub(crate) fn config_file(dir: &Path) -> PathBuf {
+    config_files(dir)
+        .into_iter()
+        .find(|path| path.exists())
+        .unwrap_or_else(|| dir.join(BOMBADIL_CONFIG))
+}
+
+/// Every possible bombadil configuration path in `dir`
+pub(crate) fn config_files(dir: &Path) -> Vec<PathBuf> {
+    CONFIG_EXTENSIONS
+        .iter()
+        .map(|extension| dir.join("bombadil").with_extension(extension))
+        .collect()
 }
 
 impl Settings {
     /// Resolve bombadil settings against its standard xdg path :
-    /// `$XDG_CONFIG_DIR/bombadil.toml`
+    /// `$XDG_CONFIG_DIR/bombadil.toml`, or `bombadil.yaml`, `bombadil.yml`, `bombadil.json`.
+    /// `BOMBADIL_CONFIG`, `BOMBADIL_DOTFILES_DIR` and `BOMBADIL_GPG_USER_ID` take precedence.
     pub fn get() -> Result<Self> {
-        match Self::bombadil_config_xdg_path() {
+        Self::get_from(None)
+    }
+
+    /// Same as [`Settings::get`] with the configuration `config` if given, a file or a
+    /// directory containing one, see [`Settings::config_path_from`]
+    pub fn get_from(config: Option<&Path>) -> Result<Self> {
+        let settings = Self::load(config)?;
+        settings
+            .warnings
+            .iter()
+            .for_each(|warning| warn!("{}", warning));
+        Ok(settings)
+    }
+
+    /// Same as [`Settings::get_from`], but the problems found while merging imports and sources
+    /// are kept in `warnings` instead of being printed
+    pub(crate) fn load(config: Option<&Path>) -> Result<Self> {
+        match Self::config_path_from(config) {
             Ok(path) => {
                 if path.exists() {
-                    let mut s = Config::new();
-                    s.merge(File::from(path))?;
-
-                    let mut settings: Result<Settings> = s
-                        .try_into()
-                        .map_err(|err| anyhow!("{} : {}", "Config format error".red(), err));
+                    let mut settings = Self::read(&path);
 
                     if let Ok(settings) = settings.as_mut() {
-                        settings.merge_imports()?;
+                        settings.merge_local(&Self::local_config_xdg_path()?)?;
                     }
 
                     settings
                 } else {
-                    Err(anyhow!(
-                        "Unable to find bombadil config file {}",
-                        path.display()
-                    ))
+                    Err(anyhow!(t!("config-not-found", path = path.display())))
                 }
             }
-            Err(err) => Err(anyhow!("Config error : {}", err)),
+            Err(err) => Err(anyhow!(t!("config-error", error = err))),
+        }
+    }
+
+    /// Read a configuration file and merge its imports and sources, the format is deduced
+    /// from the file extension
+    fn read(path: &Path) -> Result<Self> {
+        let mut s = Config::new();
+        s.merge(File::from(path.to_owned()))?;
+
+        let mut settings: Settings = s.try_into().map_err(|err| {
+            let hint = if err.to_string().contains("unknown field") {
+                format!("\n{}", t!("config-migrate-hint"))
+            } else {
+                String::new()
+            };
+            anyhow!("{} : {}{}", t!("config-format-error").red(), err, hint)
+        })?;
+
+        if settings.version.unwrap_or(1) > CONFIG_VERSION {
+            let warning = t!(
+                "config-newer-version",
+                path = format!("{:?}", path),
+                version = settings.version.unwrap_or(1)
+            );
+            settings.warnings.push(warning.yellow().to_string());
         }
+
+        settings.files.push(path.to_path_buf());
+        settings.provenance = Provenance::declared(path, &settings.settings, &settings.profiles);
+        // Imports are relative to the overridden dotfiles directory
+        settings.apply_env(|name| std::env::var(name).ok());
+        settings.merge_imports()?;
+        root_profiles(&mut settings.profiles)?;
+        settings.merge_sources()?;
+        Ok(settings)
     }
 
+    /// Merge the imports and, depth first, the files they import. Files already merged
+    /// and imports nested deeper than `max_import_depth` are skipped with a warning.
     fn merge_imports(&mut self) -> Result<()> {
-        let import_paths: Vec<PathBuf> = self
-            .import
+        let max_depth = self.max_import_depth.unwrap_or(DEFAULT_MAX_IMPORT_DEPTH);
+        let mut merged: Vec<PathBuf> = self
+            .files
             .iter()
-            .map(|import| import.path.clone())
-            .map(|path| {
-                if path.is_absolute() {
-                    path
-                } else {
-                    self.get_dotfiles_path().unwrap().join(path)
-                }
-            })
+            .map(|path| path.canonicalize().unwrap_or_else(|_| path.clone()))
             .collect();
+        let before = self.files.len();
+
+        let imports = self.import.clone();
+        self.merge_import_list(&imports, 1, max_depth, &mut merged)?;
+
+        debug!(
+            "Merged {} imported configuration files",
+            self.files.len() - before
+        );
+        Ok(())
+    }
+
+    fn merge_import_list(
+        &mut self,
+        imports: &[ImportPath],
+        depth: usize,
+        max_depth: usize,
+        merged: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let mut warnings = vec![];
+        let mut import_paths: Vec<PathBuf> = vec![];
+        for import in imports {
+            match import {
+                ImportPath::Local { path } if path.is_absolute() => import_paths.push(path.clone()),
+                ImportPath::Local { path } => {
+                    import_paths.push(self.get_dotfiles_path().unwrap().join(path))
+                }
+                ImportPath::Remote { url, sha256 } => {
+                    match remote::fetch_import(url, sha256.as_deref()) {
+                        Ok(path) => import_paths.push(path),
+                        // A pinned import that no longer matches its hash fails the configuration
+                        Err(err) if err.is::<IntegrityError>() => {
+                            return Err(anyhow!("{} {}", t!("import-remote-error").red(), err))
+                        }
+                        Err(err) => {
+                            warnings.push(format!("{} {}", t!("import-remote-error").red(), err))
+                        }
+                    }
+                }
+            }
+        }
 
         for path in import_paths.iter() {
-            if path.exists() {
+            if depth > max_depth {
+                let warning = t!(
+                    "import-too-deep",
+                    path = path.display(),
+                    max_depth = max_depth
+                );
+                warnings.push(warning.red().to_string());
+            } else if path.exists() {
+                let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+                if merged.contains(&canonical) {
+                    let warning = t!("import-already-merged", path = path.display());
+                    warnings.push(warning.yellow().to_string());
+                    continue;
+                }
+                merged.push(canonical);
+
                 let mut s = Config::new();
                 s.merge(File::from(path.to_owned()))?;
 
                 let sub_setting = s
                     .try_into::<ImportedSettings>()
-                    .map_err(|err| anyhow!("{} : {}", "Config format error".red(), err));
+                    .map_err(|err| anyhow!("{} : {}", t!("config-format-error").red(), err));
 
                 match sub_setting {
-                    Ok(sub_settings) => self.merge(sub_settings),
-                    Err(err) => {
-                        eprintln!("Error loading settings from : {:?} {}", path, err)
+                    Ok(sub_settings) => {
+                        debug!("Merging import {:?} (depth {})", path, depth);
+                        trace!(
+                            "{:?} : {} dots, {} profiles, {} imports",
+                            path,
+                            sub_settings.settings.dots.len(),
+                            sub_settings.profiles.len(),
+                            sub_settings.import.len()
+                        );
+                        let nested = sub_settings.import.clone();
+                        self.files.push(path.to_owned());
+                        self.merge(path, sub_settings);
+                        self.merge_import_list(&nested, depth + 1, max_depth, merged)?;
                     }
+                    Err(err) => warnings.push(t!(
+                        "config-load-error",
+                        path = format!("{:?}", path),
+                        error = err
+                    )),
                 }
             } else {
-                eprintln!(
+                warnings.push(format!(
                     "{} {}",
-                    "Unable to find bombadil import file".red(),
+                    t!("import-not-found").red(),
                     path.display()
-                );
+                ));
             }
         }
 
+        self.warnings.append(&mut warnings);
         Ok(())
     }
 
-    fn merge(&mut self, sub_settings: ImportedSettings) {
+    /// Merge the `[[source]]` repositories. Entries of the main configuration take precedence
+    /// over the sources, and later sources over earlier ones. Dots, var paths and profiles keep
+    /// track of the repository declaring them.
+    fn merge_sources(&mut self) -> Result<()> {
+        let home_dir = dirs::home_dir().ok_or_else(|| anyhow!(t!("home-not-found")))?;
+        let mut sources = vec![];
+        let mut files = vec![];
+        let mut warnings = vec![];
+
+        for source in self.source.iter() {
+            let dotfiles_dir = home_dir.join(&source.dotfiles_dir);
+            let path = config_file(&dotfiles_dir);
+            if !path.exists() {
+                warnings.push(format!(
+                    "{} {}",
+                    t!("source-config-not-found").red(),
+                    path.display()
+                ));
+                continue;
+            }
+
+            let mut s = Config::new();
+            s.merge(File::from(path.clone()))?;
+            match s.try_into::<ImportedSettings>() {
+                Ok(settings) => {
+                    sources.push((dotfiles_dir, settings));
+                    files.push(path);
+                }
+                Err(err) => warnings.push(t!(
+                    "config-load-error",
+                    path = format!("{:?}", path),
+                    error = format!("{} : {}", t!("config-format-error").red(), err)
+                )),
+            }
+        }
+
+        self.warnings.append(&mut warnings);
+
+        let mut provenance = Provenance::default();
+        for ((_, source), path) in sources.iter().zip(&files) {
+            provenance.extend(Provenance::declared(
+                path,
+                &source.settings,
+                &source.profiles,
+            ));
+        }
+        self.provenance.extend_source(provenance);
+        self.files.append(&mut files);
+
+        let mut vars = vec![];
+        let mut prehooks = vec![];
+        let mut posthooks = vec![];
+        for (dotfiles_dir, source) in sources.iter() {
+            vars.extend(
+                source
+                    .settings
+                    .vars
+                    .iter()
+                    .map(|path| dotfiles_dir.join(path)),
+            );
+            prehooks.extend_from_slice(&source.settings.prehooks);
+            posthooks.extend_from_slice(&source.settings.posthooks);
+        }
+
+        // Var files and hooks merged later override or run after the earlier ones
+        vars.append(&mut self.settings.vars);
+        self.settings.vars = vars;
+        prehooks.append(&mut self.settings.prehooks);
+        self.settings.prehooks = prehooks;
+        posthooks.append(&mut self.settings.posthooks);
+        self.settings.posthooks = posthooks;
+
+        for (dotfiles_dir, source) in sources.into_iter().rev() {
+            for (key, mut dot) in source.settings.dots {
+                dot.repository = Some(dotfiles_dir.clone());
+                self.settings.dots.entry(key).or_insert(dot);
+            }
+
+            for (name, mut profile) in source.profiles {
+                profile.resolve_in(&dotfiles_dir, &home_dir);
+                self.profiles.entry(name).or_insert(profile);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge the machine local configuration last so it can override the shared dots.
+    /// Var paths are relative to the local configuration directory.
+    fn merge_local(&mut self, path: &Path) -> Result<()> {
+        if !path.exists() {
+            return Ok(());
+        }
+
+        let mut s = Config::new();
+        s.merge(File::from(path.to_owned()))?;
+        let mut local_settings = s.try_into::<ImportedSettings>().map_err(|err| {
+            anyhow!(
+                "{} {:?} : {}",
+                t!("local-config-format-error").red(),
+                path,
+                err
+            )
+        })?;
+
+        root_profiles(&mut local_settings.profiles)?;
+        let local_dir = path.parent().unwrap_or_else(|| Path::new("/"));
+        local_settings.settings.vars = local_settings
+            .settings
+            .vars
+            .iter()
+            .map(|var_path| local_dir.join(var_path))
+            .collect();
+
+        let mut dots: Vec<String> = local_settings.settings.dots.keys().cloned().collect();
+        dots.sort();
+        let mut profiles: Vec<String> = local_settings.profiles.keys().cloned().collect();
+        profiles.sort();
+
+        self.files.push(path.to_path_buf());
+        self.local = Some(LocalProvenance {
+            path: path.to_path_buf(),
+            dots,
+            prehooks: local_settings.settings.prehooks.clone(),
+            posthooks: local_settings.settings.posthooks.clone(),
+            profiles,
+        });
+
+        self.merge(path, local_settings);
+        Ok(())
+    }
+
+    /// Merge the settings of the configuration file `path`
+    fn merge(&mut self, path: &Path, sub_settings: ImportedSettings) {
+        self.provenance.extend(Provenance::declared(
+            path,
+            &sub_settings.settings,
+            &sub_settings.profiles,
+        ));
         self.settings
             .prehooks
             .extend_from_slice(&sub_settings.settings.prehooks);
@@ -173,22 +974,68 @@ impl Settings {
             .extend_from_slice(&sub_settings.settings.vars);
         self.import.extend_from_slice(&sub_settings.import);
         self.settings.dots.extend(sub_settings.settings.dots);
+        self.settings
+            .autostart
+            .extend(sub_settings.settings.autostart);
+        self.settings
+            .defaults
+            .merge(&sub_settings.settings.defaults);
         self.profiles.extend(sub_settings.profiles);
     }
 
-    /// Resolve the bombadil XDG settings path : `$XDG_CONFIG_DIR/bombadil.toml
+    /// The configuration in use : `BOMBADIL_CONFIG` if set, a file or a directory containing
+    /// one, otherwise the XDG settings path
+    pub fn config_path() -> Result<PathBuf, ConfigError> {
+        Self::config_path_from(None)
+    }
+
+    /// Same as [`Settings::config_path`], `config` takes precedence over `BOMBADIL_CONFIG`
+    pub fn config_path_from(config: Option<&Path>) -> Result<PathBuf, ConfigError> {
+        let config = config.map(Path::to_path_buf).or_else(|| {
+            std::env::var_os(CONFIG_ENV)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        });
+        match config {
+            Some(path) if path.is_dir() => Ok(config_file(&path)),
+            Some(path) => Ok(path),
+            None => Self::bombadil_config_xdg_path(),
+        }
+    }
+
+    /// Override `dotfiles_dir` and `gpg_user_id` with `BOMBADIL_DOTFILES_DIR` and
+    /// `BOMBADIL_GPG_USER_ID`, `var` looks up an environment variable
+    fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(dotfiles_dir) = var(DOTFILES_DIR_ENV).filter(|dir| !dir.is_empty()) {
+            self.dotfiles_dir = PathBuf::from(dotfiles_dir);
+        }
+
+        if let Some(user_id) = var(GPG_USER_ID_ENV) {
+            self.gpg_user_id = Some(user_id).filter(|user_id| !user_id.is_empty());
+        }
+    }
+
+    /// Resolve the bombadil XDG settings path : `$XDG_CONFIG_DIR/bombadil.toml`
+    /// or the first existing file in another supported format
     pub fn bombadil_config_xdg_path() -> Result<PathBuf, ConfigError> {
         dirs::config_dir()
             .ok_or_else(|| {
                 ConfigError::NotFound("Unable to find `$XDG_CONFIG/bombadil.toml`".into())
             })
-            .map(|path| path.join(BOMBADIL_CONFIG))
+            .map(|path| config_file(&path))
+    }
+
+    /// Resolve the machine local settings path : `$XDG_CONFIG_DIR/bombadil/local.toml`
+    pub fn local_config_xdg_path() -> Result<PathBuf, ConfigError> {
+        dirs::config_dir()
+            .ok_or_else(|| ConfigError::NotFound("Unable to find `$XDG_CONFIG`".into()))
+            .map(|path| path.join("bombadil").join(LOCAL_CONFIG))
     }
 
     pub(crate) fn get_dotfiles_path(&self) -> Result<PathBuf> {
         let home_dir = dirs::home_dir();
         if home_dir.is_none() {
-            return Err(anyhow!("$HOME directory not found"));
+            return Err(anyhow!(t!("home-not-found")));
         }
 
         let path = if self.dotfiles_dir.is_absolute() {
@@ -198,7 +1045,10 @@ impl Settings {
         };
 
         if path.exists().not() {
-            return Err(anyhow!("Dotfiles directory {:?} does not exist", &path));
+            return Err(anyhow!(t!(
+                "dotfiles-dir-not-found",
+                path = format!("{:?}", path)
+            )));
         }
 
         Ok(path)
@@ -207,9 +1057,17 @@ impl Settings {
 
 #[cfg(test)]
 mod tests {
-    use crate::settings::Settings;
+    use crate::dots::Dot;
+    use crate::facts::Facts;
+    use crate::hook::HookEntry;
+    use crate::settings::{
+        config_file, root_profiles, without_dot, Origin, OriginEntry, Profile, Provenance,
+        Settings, SourceRepository,
+    };
     use crate::{Bombadil, BOMBADIL_CONFIG};
+    use std::collections::HashMap;
     use std::ops::Not;
+    use std::path::PathBuf;
     use temp_testdir::TempDir;
 
     #[test]
@@ -234,9 +1092,500 @@ mod tests {
         std::fs::remove_dir_all(dotfiles).unwrap();
     }
 
+    #[test]
+    fn should_read_json_config_with_yaml_import() -> anyhow::Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let config = format!(
+            r#"{{
+  "dotfiles_dir": "{}",
+  "settings": {{ "dots": {{ "zsh": {{ "source": "zshrc", "target": ".zshrc" }} }} }},
+  "import": [ {{ "path": "sway.yaml" }} ]
+}}"#,
+            dotfiles.display()
+        );
+        std::fs::write(dotfiles.join("bombadil.json"), config)?;
+        std::fs::write(
+            dotfiles.join("sway.yaml"),
+            "settings:\n  dots:\n    sway:\n      source: sway\n      target: .config/sway\n",
+        )?;
+
+        // Act
+        let path = config_file(&dotfiles);
+        let settings = Settings::read(&path)?;
+
+        // Assert
+        assert_eq!(path, dotfiles.join("bombadil.json"));
+        assert!(settings.settings.dots.contains_key("zsh"));
+        assert_eq!(
+            settings
+                .settings
+                .dots
+                .get("sway")
+                .map(|dot| dot.target.clone()),
+            Some(PathBuf::from(".config/sway"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_fail_on_pinned_import_mismatch() -> anyhow::Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        std::fs::write(
+            dotfiles.join("base.toml"),
+            "[settings]\nvars = [ \"vars.toml\" ]\n",
+        )?;
+        let config = format!(
+            "dotfiles_dir = \"{}\"\n\n[[import]]\nurl = \"file://{}\"\nsha256 = \"deadbeef\"\n",
+            dotfiles.display(),
+            dotfiles.join("base.toml").display()
+        );
+        std::fs::write(dotfiles.join(BOMBADIL_CONFIG), config)?;
+
+        // Act
+        let settings = Settings::read(&dotfiles.join(BOMBADIL_CONFIG));
+
+        // Assert
+        let err = settings.unwrap_err().to_string();
+        assert!(err.contains("Integrity check failed"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_nested_imports_up_to_max_depth() -> anyhow::Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let import = |name: &str, imports: &[&str]| -> std::io::Result<()> {
+            let mut content = format!(
+                "[settings.dots]\n{name} = {{ source = \"{name}\", target = \"{name}\" }}\n",
+                name = name
+            );
+            for path in imports {
+                content.push_str(&format!("[[import]]\npath = \"{}\"\n", path));
+            }
+            std::fs::write(dotfiles.join(format!("{}.toml", name)), content)
+        };
+        std::fs::write(
+            dotfiles.join(BOMBADIL_CONFIG),
+            format!(
+                "dotfiles_dir = \"{}\"\nmax_import_depth = 2\n[[import]]\npath = \"a.toml\"\n",
+                dotfiles.display()
+            ),
+        )?;
+        import("a", &["b.toml", BOMBADIL_CONFIG])?;
+        import("b", &["c.toml"])?;
+        import("c", &[])?;
+
+        // Act
+        let settings = Settings::read(&dotfiles.join(BOMBADIL_CONFIG))?;
+
+        // Assert
+        let mut dots: Vec<&String> = settings.settings.dots.keys().collect();
+        dots.sort();
+        assert_eq!(dots, vec!["a", "b"]);
+        assert_eq!(
+            settings.files,
+            vec![
+                dotfiles.join(BOMBADIL_CONFIG),
+                dotfiles.join("a.toml"),
+                dotfiles.join("b.toml")
+            ]
+        );
+        assert_eq!(settings.warnings.len(), 2);
+        assert!(settings.warnings[0].contains("c.toml, nested deeper than `max_import_depth` (2)"));
+        assert!(settings.warnings[1].contains("bombadil.toml, already merged"));
+        Ok(())
+    }
+
+    #[test]
+    fn should_remove_a_single_dot_entry() -> anyhow::Result<()> {
+        // Arrange
+        let config = "# Dotfiles\n\
+                      [settings.dots]\n\
+                      sway = { source = \"sway\", target = \".config/sway\" }\n\
+                      zsh = { source = \"zsh\", target = \".zshrc\" }\n\
+                      \n\
+                      [settings.dots.kitty]\n\
+                      source = \"kitty\"\n\
+                      target = { linux = \".config/kitty\" }\n\
+                      \n\
+                      [profiles.work.dots.sway]\n\
+                      source = \"sway-work\"\n";
+
+        // Act
+        let inline = without_dot(config, "sway", 3)?;
+        let table = without_dot(config, "kitty", 6)?;
+        let other_table = without_dot(config, "sway", 10);
+
+        // Assert
+        assert_eq!(
+            inline,
+            config.replace(
+                "sway = { source = \"sway\", target = \".config/sway\" }\n",
+                ""
+            )
+        );
+        assert_eq!(
+            table,
+            config.replace(
+                "[settings.dots.kitty]\nsource = \"kitty\"\ntarget = { linux = \".config/kitty\" }\n\n",
+                ""
+            )
+        );
+        assert!(other_table.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn should_record_where_merged_entries_were_declared() -> anyhow::Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles = temp.canonicalize()?;
+        let config = dotfiles.join(BOMBADIL_CONFIG);
+        let import = dotfiles.join("import.toml");
+        std::fs::write(
+            &config,
+            format!(
+                "dotfiles_dir = \"{}\"\n\
+                 [[import]]\n\
+                 path = \"import.toml\"\n\
+                 [settings]\n\
+                 prehooks = [\"echo main\"]\n\
+                 vars = [\"vars.toml\"]\n\
+                 [settings.dots]\n\
+                 sway = {{ source = \"sway\", target = \".config/sway\" }}\n\
+                 [profiles.work]\n\
+                 vars = [\"work.toml\"]\n",
+                dotfiles.display()
+            ),
+        )?;
+        std::fs::write(
+            &import,
+            "[settings]\n\
+             posthooks = [\"echo import\"]\n\
+             [settings.dots.sway]\n\
+             source = \"sway-import\"\n\
+             target = \".config/sway\"\n\
+             [settings.dots.zsh]\n\
+             source = \"zshrc\"\n\
+             target = \".zshrc\"\n",
+        )?;
+
+        // Act
+        let settings = Settings::read(&config)?;
+
+        // Assert
+        let origin = |file: &PathBuf, line| Origin {
+            file: file.clone(),
+            line: Some(line),
+        };
+        let provenance = settings.provenance;
+        assert_eq!(provenance.dots["sway"], origin(&import, 3));
+        assert_eq!(provenance.dots["zsh"], origin(&import, 6));
+        assert_eq!(provenance.profiles["work"], origin(&config, 9));
+        assert_eq!(
+            provenance.prehooks,
+            vec![OriginEntry {
+                entry: "echo main".to_string(),
+                origin: origin(&config, 5),
+            }]
+        );
+        assert_eq!(provenance.posthooks[0].origin, origin(&import, 2));
+        assert_eq!(provenance.vars[0].entry, "vars.toml");
+        assert_eq!(provenance.vars[0].origin, origin(&config, 6));
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_local_config_last() -> anyhow::Result<()> {
+        // Arrange
+        let mut settings = Settings {
+            version: None,
+            dotfiles_dir: PathBuf::from("dotfiles"),
+            gpg_user_id: None,
+            retention: Default::default(),
+            settings: Default::default(),
+            profiles: Default::default(),
+            themes: Default::default(),
+            import: vec![],
+            max_import_depth: None,
+            source: vec![],
+            git: Default::default(),
+            local: None,
+            files: vec![],
+            warnings: vec![],
+            provenance: Provenance::default(),
+        };
+        let local_path = PathBuf::from("tests/local/local.toml").canonicalize()?;
+
+        // Act
+        settings.merge_local(&local_path)?;
+
+        // Assert
+        let local = settings.local.unwrap();
+        assert_eq!(local.dots, vec!["work_ssh".to_string()]);
+        assert!(settings.settings.dots.contains_key("work_ssh"));
+        assert_eq!(
+            settings.settings.posthooks,
+            vec![HookEntry::Command("echo local".to_string())]
+        );
+        assert_eq!(
+            settings.settings.vars,
+            vec![local_path.parent().unwrap().join("work_vars.toml")]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_merge_sources_with_precedence() -> anyhow::Result<()> {
+        // Arrange
+        let work = PathBuf::from("tests/sources/work").canonicalize()?;
+        let shared = PathBuf::from("tests/sources/shared").canonicalize()?;
+        let mut settings = Settings {
+            version: None,
+            dotfiles_dir: PathBuf::from("dotfiles"),
+            gpg_user_id: None,
+            retention: Default::default(),
+            settings: Default::default(),
+            profiles: Default::default(),
+            themes: Default::default(),
+            import: vec![],
+            max_import_depth: None,
+            source: vec![
+                SourceRepository {
+                    dotfiles_dir: work.clone(),
+                },
+                SourceRepository {
+                    dotfiles_dir: shared.clone(),
+                },
+            ],
+            git: Default::default(),
+            local: None,
+            files: vec![],
+            warnings: vec![],
+            provenance: Provenance::default(),
+        };
+        settings.settings.vars = vec![PathBuf::from("vars.toml")];
+        settings.settings.dots.insert(
+            "zsh".to_string(),
+            Dot {
+                source: PathBuf::from("zsh"),
+                target: PathBuf::from(".zshrc"),
+                ..Default::default()
+            },
+        );
+
+        // Act
+        settings.merge_sources()?;
+
+        // Assert
+        let dots = &settings.settings.dots;
+        assert_eq!(dots["zsh"].repository, None);
+        assert_eq!(dots["ssh"].repository, Some(shared.clone()));
+        assert_eq!(dots["ssh"].source, PathBuf::from("ssh/config"));
+        assert_eq!(dots["git"].repository, Some(shared.clone()));
+        assert_eq!(
+            settings.settings.vars,
+            vec![
+                work.join("vars.toml"),
+                shared.join("vars.toml"),
+                PathBuf::from("vars.toml")
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_resolve_profiles_in_their_dotfiles_dir() -> anyhow::Result<()> {
+        // Arrange
+        let mut profiles: HashMap<String, Profile> = toml::from_str(
+            r#"
+            [work]
+            dotfiles_dir = "/mnt/work-dotfiles"
+            vars = [ "vars.toml" ]
+            dots.ssh = { source = "ssh", target = ".ssh/config" }
+
+            [home]
+            vars = [ "vars.toml" ]
+            dots.ssh = { source = "ssh", target = ".ssh/config" }
+            "#,
+        )?;
+
+        // Act
+        root_profiles(&mut profiles)?;
+
+        // Assert
+        let work = PathBuf::from("/mnt/work-dotfiles");
+        assert_eq!(profiles["work"].dots["ssh"].repository, Some(work.clone()));
+        assert_eq!(profiles["work"].vars, vec![work.join("vars.toml")]);
+        assert_eq!(profiles["home"].dots["ssh"].repository, None);
+        assert_eq!(profiles["home"].vars, vec![PathBuf::from("vars.toml")]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_override_settings_with_environment() {
+        // Arrange
+        let mut settings = Settings {
+            version: None,
+            dotfiles_dir: PathBuf::from("dotfiles"),
+            gpg_user_id: Some("tom@bombadil.org".to_string()),
+            retention: Default::default(),
+            settings: Default::default(),
+            profiles: Default::default(),
+            themes: Default::default(),
+            import: vec![],
+            max_import_depth: None,
+            source: vec![],
+            git: Default::default(),
+            local: None,
+            files: vec![],
+            warnings: vec![],
+            provenance: Provenance::default(),
+        };
+        let env: HashMap<&str, &str> = vec![
+            ("BOMBADIL_DOTFILES_DIR", "/tmp/ci_dotfiles"),
+            ("BOMBADIL_GPG_USER_ID", ""),
+        ]
+        .into_iter()
+        .collect();
+
+        // Act
+        settings.apply_env(|name| env.get(name).map(|value| value.to_string()));
+
+        // Assert
+        assert_eq!(settings.dotfiles_dir, PathBuf::from("/tmp/ci_dotfiles"));
+        assert_eq!(settings.gpg_user_id, None);
+    }
+
     #[test]
     fn should_get_bombadil_path() {
         let path = Settings::bombadil_config_xdg_path();
         assert!(path.is_ok());
     }
+
+    #[test]
+    fn should_match_hostname_glob() {
+        // Arrange
+        let profile = Profile {
+            hostname: Some("thinkpad-*".to_string()),
+            ..Default::default()
+        };
+
+        let laptop = Facts {
+            hostname: Some("thinkpad-x1".to_string()),
+            ..Default::default()
+        };
+
+        let desktop = Facts {
+            hostname: Some("desktop".to_string()),
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert!(profile.matches(&laptop));
+        assert!(profile.matches(&desktop).not());
+    }
+
+    #[test]
+    fn should_not_match_without_matchers() {
+        let profile = Profile::default();
+
+        let facts = Facts {
+            hostname: Some("thinkpad-x1".to_string()),
+            ..Default::default()
+        };
+
+        assert!(profile.matches(&facts).not());
+    }
+
+    #[test]
+    fn should_match_all_declared_matchers() {
+        // Arrange
+        let profile = Profile {
+            os: Some("linux".to_string()),
+            distro: Some("arch".to_string()),
+            ..Default::default()
+        };
+
+        let arch = Facts {
+            os: Some("linux".to_string()),
+            distro: Some("arch".to_string()),
+            ..Default::default()
+        };
+
+        let debian = Facts {
+            os: Some("linux".to_string()),
+            distro: Some("debian".to_string()),
+            ..Default::default()
+        };
+
+        let macos = Facts {
+            os: Some("macos".to_string()),
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert!(profile.matches(&arch));
+        assert!(profile.matches(&debian).not());
+        assert!(profile.matches(&macos).not());
+    }
+
+    #[test]
+    fn should_match_session_or_display_server() {
+        // Arrange
+        let sway = Facts {
+            session: Some("sway".to_string()),
+            session_type: Some("wayland".to_string()),
+            ..Default::default()
+        };
+
+        let gnome_x11 = Facts {
+            session: Some("gnome".to_string()),
+            session_type: Some("x11".to_string()),
+            ..Default::default()
+        };
+
+        let profile = |session: &str| Profile {
+            session: Some(session.to_string()),
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert!(profile("sway").matches(&sway));
+        assert!(profile("wayland").matches(&sway));
+        assert!(profile("wayland").matches(&gnome_x11).not());
+        assert!(profile("{gnome,kde}").matches(&gnome_x11));
+    }
+
+    #[test]
+    fn should_match_locale_and_any_keyboard_layout() {
+        // Arrange
+        let facts = Facts {
+            locale: Some("fr_FR".to_string()),
+            keyboard_layouts: vec!["us".to_string(), "fr".to_string()],
+            ..Default::default()
+        };
+
+        let keyboard = |layout: &str| Profile {
+            keyboard: Some(layout.to_string()),
+            ..Default::default()
+        };
+        let french = Profile {
+            locale: Some("fr_*".to_string()),
+            keyboard: Some("fr".to_string()),
+            ..Default::default()
+        };
+
+        // Act & Assert
+        assert!(keyboard("fr").matches(&facts));
+        assert!(keyboard("us").matches(&facts));
+        assert!(keyboard("de").matches(&facts).not());
+        assert!(keyboard("de").matches(&Facts::default()).not());
+        assert!(french.matches(&facts));
+    }
 }
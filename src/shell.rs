@@ -0,0 +1,297 @@
+use anyhow::Result;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Profile name given to the cli when generating completions, replaced by a query of the
+/// profiles defined at completion time
+pub const PROFILES_PLACEHOLDER: &str = "__BOMBADIL_PROFILES__";
+
+/// Command listing the profile names, one per line
+const COMPLETE_PROFILES: &str = "bombadil complete profiles 2>/dev/null";
+
+/// Shells supported by `bombadil shell-init`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitShell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for InitShell {
+    type Err = anyhow::Error;
+
+    fn from_str(shell: &str) -> Result<Self> {
+        match shell {
+            "bash" => Ok(InitShell::Bash),
+            "zsh" => Ok(InitShell::Zsh),
+            "fish" => Ok(InitShell::Fish),
+            _ => Err(anyhow!(
+                "Unsupported shell `{}`, expected bash, zsh or fish",
+                shell
+            )),
+        }
+    }
+}
+
+/// A snippet defining `theme <profile>...`, linking the dotfiles with the given profiles, and
+/// `dotedit <dot>`, opening the dot source in `$EDITOR`. Both complete the profile and dot
+/// names known when the snippet was generated, it is meant to be evaluated on shell startup.
+pub(crate) fn init_script(
+    shell: InitShell,
+    profiles: &[String],
+    dots: &[(String, PathBuf)],
+) -> String {
+    match shell {
+        InitShell::Bash | InitShell::Zsh => posix_script(shell, profiles, dots),
+        InitShell::Fish => fish_script(profiles, dots),
+    }
+}
+
+fn posix_script(shell: InitShell, profiles: &[String], dots: &[(String, PathBuf)]) -> String {
+    let (name, rc_file, editor) = match shell {
+        InitShell::Zsh => ("zsh", "~/.zshrc", "${=EDITOR:-vi}"),
+        _ => ("bash", "~/.bashrc", "${EDITOR:-vi}"),
+    };
+
+    let cases: String = dots
+        .iter()
+        .map(|(dot, source)| {
+            format!(
+                "        {}) {} {} ;;\n",
+                posix_quote(dot),
+                editor,
+                posix_quote(&source.to_string_lossy())
+            )
+        })
+        .collect();
+
+    let completions = match shell {
+        InitShell::Zsh => format!(
+            "if (( $+functions[compdef] )); then
+    _bombadil_theme() {{ compadd -- {}; }}
+    _bombadil_dotedit() {{ compadd -- {}; }}
+    compdef _bombadil_theme theme
+    compdef _bombadil_dotedit dotedit
+fi
+",
+            words(profiles.iter()),
+            words(dots.iter().map(|(dot, _)| dot)),
+        ),
+        _ => format!(
+            "complete -W {} theme\ncomplete -W {} dotedit\n",
+            posix_quote(&profiles.join(" ")),
+            posix_quote(
+                &dots
+                    .iter()
+                    .map(|(dot, _)| dot.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        ),
+    };
+
+    format!(
+        "# Generated by `bombadil shell-init {name}`, add `eval \"$(bombadil shell-init {name})\"` to {rc_file}
+theme() {{
+    if [ $# -eq 0 ]; then
+        echo \"usage: theme <profile>...\" >&2
+        return 1
+    fi
+    command bombadil link -p \"$@\"
+}}
+
+dotedit() {{
+    case \"$1\" in
+{cases}        *) echo \"dotedit: unknown dot '$1'\" >&2; return 1 ;;
+    esac
+}}
+
+{completions}",
+        name = name,
+        rc_file = rc_file,
+        cases = cases,
+        completions = completions,
+    )
+}
+
+fn fish_script(profiles: &[String], dots: &[(String, PathBuf)]) -> String {
+    let cases: String = dots
+        .iter()
+        .map(|(dot, source)| {
+            format!(
+                "        case {}\n            $editor {}\n",
+                fish_quote(dot),
+                fish_quote(&source.to_string_lossy())
+            )
+        })
+        .collect();
+
+    let dot_names: Vec<&str> = dots.iter().map(|(dot, _)| dot.as_str()).collect();
+
+    format!(
+        "# Generated by `bombadil shell-init fish`, add `bombadil shell-init fish | source` to ~/.config/fish/config.fish
+function theme --description 'Link the dotfiles with the given bombadil profiles'
+    if test (count $argv) -eq 0
+        echo \"usage: theme <profile>...\" >&2
+        return 1
+    end
+    command bombadil link -p $argv
+end
+
+function dotedit --description 'Edit the source of a bombadil dot'
+    set -l editor vi
+    set -q EDITOR; and set editor (string split ' ' -- $EDITOR)
+    switch $argv[1]
+{cases}        case '*'
+            echo \"dotedit: unknown dot '$argv[1]'\" >&2
+            return 1
+    end
+end
+
+complete -c theme -f -a {profiles}
+complete -c dotedit -f -a {dots}
+",
+        cases = cases,
+        profiles = fish_quote(&profiles.join(" ")),
+        dots = fish_quote(&dot_names.join(" ")),
+    )
+}
+
+/// Make the generated completion `script` complete the profiles defined when completing
+/// instead of those defined when the script was generated. Elvish completions do not list
+/// values and are left as is.
+pub fn dynamic_completions(script: &str, shell: &str) -> String {
+    match shell {
+        "bash" => script.replace(
+            &format!("compgen -W \"{}\"", PROFILES_PLACEHOLDER),
+            &format!("compgen -W \"$({})\"", COMPLETE_PROFILES),
+        ),
+        "zsh" => {
+            let script = script.replace(
+                &format!(":({})'", PROFILES_PLACEHOLDER),
+                ":_bombadil_profiles'",
+            );
+            let function = format!(
+                "(( $+functions[_bombadil_profiles] )) ||
+_bombadil_profiles() {{
+    local -a profiles
+    profiles=(${{(f)\"$({})\"}})
+    compadd -a profiles
+}}
+",
+                COMPLETE_PROFILES
+            );
+            match script.split_once('\n') {
+                Some((compdef, rest)) => format!("{}\n\n{}{}", compdef, function, rest),
+                None => script,
+            }
+        }
+        "fish" => script.replace(
+            &format!("-a \"{}\"", PROFILES_PLACEHOLDER),
+            &format!("-a \"({})\"", COMPLETE_PROFILES),
+        ),
+        _ => script.to_string(),
+    }
+}
+
+fn words<'a>(words: impl Iterator<Item = &'a String>) -> String {
+    words
+        .map(|word| posix_quote(word))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Single quote `value` for bash and zsh
+fn posix_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Single quote `value` for fish, where backslashes and quotes are escaped inside quotes
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shell::{dynamic_completions, init_script, InitShell, PROFILES_PLACEHOLDER};
+    use std::path::PathBuf;
+
+    fn dots() -> Vec<(String, PathBuf)> {
+        vec![
+            ("sway".to_string(), PathBuf::from("/home/tom/dotfiles/sway")),
+            (
+                "zsh".to_string(),
+                PathBuf::from("/home/tom/dotfiles/tom's zshrc"),
+            ),
+        ]
+    }
+
+    #[test]
+    fn should_generate_bash_functions() {
+        // Arrange
+        let profiles = vec!["dark".to_string(), "light".to_string()];
+
+        // Act
+        let script = init_script(InitShell::Bash, &profiles, &dots());
+
+        // Assert
+        assert!(script.contains("    command bombadil link -p \"$@\"\n"));
+        assert!(script.contains("        'sway') ${EDITOR:-vi} '/home/tom/dotfiles/sway' ;;\n"));
+        assert!(script.contains("'/home/tom/dotfiles/tom'\\''s zshrc'"));
+        assert!(script.contains("complete -W 'dark light' theme\n"));
+        assert!(script.contains("complete -W 'sway zsh' dotedit\n"));
+    }
+
+    #[test]
+    fn should_generate_zsh_and_fish_completions() {
+        // Arrange
+        let profiles = vec!["dark".to_string()];
+
+        // Act
+        let zsh = init_script(InitShell::Zsh, &profiles, &dots());
+        let fish = init_script(InitShell::Fish, &profiles, &dots());
+
+        // Assert
+        assert!(zsh.contains("${=EDITOR:-vi} '/home/tom/dotfiles/sway'"));
+        assert!(zsh.contains("_bombadil_dotedit() { compadd -- 'sway' 'zsh'; }"));
+        assert!(
+            fish.contains("        case 'sway'\n            $editor '/home/tom/dotfiles/sway'\n")
+        );
+        assert!(fish.contains("$editor '/home/tom/dotfiles/tom\\'s zshrc'"));
+        assert!(fish.contains("complete -c theme -f -a 'dark'\n"));
+    }
+
+    #[test]
+    fn should_query_profiles_when_completing() {
+        // Arrange
+        let bash = format!(
+            "COMPREPLY=($(compgen -W \"{}\" -- \"${{cur}}\"))",
+            PROFILES_PLACEHOLDER
+        );
+        let zsh = format!(
+            "#compdef bombadil\n'*-p+[Profiles]: :({})' \\\n",
+            PROFILES_PLACEHOLDER
+        );
+        let fish = format!(
+            "complete -c bombadil -s p -r -f -a \"{}\"",
+            PROFILES_PLACEHOLDER
+        );
+
+        // Act
+        let bash = dynamic_completions(&bash, "bash");
+        let zsh = dynamic_completions(&zsh, "zsh");
+        let fish = dynamic_completions(&fish, "fish");
+
+        // Assert
+        assert_eq!(
+            bash,
+            "COMPREPLY=($(compgen -W \"$(bombadil complete profiles 2>/dev/null)\" -- \"${cur}\"))"
+        );
+        assert!(zsh.starts_with("#compdef bombadil\n\n(( $+functions[_bombadil_profiles] )) ||\n"));
+        assert!(zsh.ends_with("'*-p+[Profiles]: :_bombadil_profiles' \\\n"));
+        assert_eq!(
+            fish,
+            "complete -c bombadil -s p -r -f -a \"(bombadil complete profiles 2>/dev/null)\""
+        );
+    }
+}
@@ -0,0 +1,188 @@
+use anyhow::Result;
+
+/// The showcase is written between these markers, so it can be regenerated inside a README
+const START_MARKER: &str = "<!-- bombadil showcase start -->";
+const END_MARKER: &str = "<!-- bombadil showcase end -->";
+
+/// A profile or a theme of `bombadil showcase`
+#[derive(Debug, PartialEq)]
+pub(crate) struct ShowcaseEntry {
+    pub name: String,
+    pub description: Option<String>,
+    /// Dots linked with the profile, `None` for themes
+    pub dots: Option<usize>,
+    /// Color variables by name, ex: `("background", "#282a36")`
+    pub colors: Vec<(String, String)>,
+}
+
+/// The profiles and themes of a dotfiles directory, rendered as markdown or html
+#[derive(Debug)]
+pub(crate) struct Showcase {
+    pub profiles: Vec<ShowcaseEntry>,
+    pub themes: Vec<ShowcaseEntry>,
+}
+
+impl Showcase {
+    /// Colors are drawn with GitHub math blocks, GitHub strips the style of html elements
+    pub(crate) fn markdown(&self) -> String {
+        let mut markdown = format!("{}\n", START_MARKER);
+        for (title, entries) in self.sections() {
+            markdown.push_str(&format!("\n## {}\n", title));
+            for entry in entries {
+                markdown.push_str(&format!("\n### {}\n\n", entry.name));
+                if let Some(description) = &entry.description {
+                    markdown.push_str(&format!("{}\n\n", description));
+                }
+                if let Some(dots) = entry.dots {
+                    markdown.push_str(&format!("{}\n\n", count(dots)));
+                }
+                if !entry.colors.is_empty() {
+                    markdown.push_str("| Color | Variable | Value |\n| --- | --- | --- |\n");
+                    for (name, color) in &entry.colors {
+                        markdown.push_str(&format!(
+                            "| $\\color{{{}}}\\blacksquare$ | {} | `{}` |\n",
+                            color,
+                            name.replace('|', "\\|"),
+                            color
+                        ));
+                    }
+                    markdown.push('\n');
+                }
+            }
+        }
+
+        markdown.push_str(END_MARKER);
+        markdown.push('\n');
+        markdown
+    }
+
+    pub(crate) fn html(&self) -> String {
+        let mut html = format!("{}\n<section class=\"bombadil-showcase\">\n", START_MARKER);
+        for (title, entries) in self.sections() {
+            html.push_str(&format!("<h2>{}</h2>\n", title));
+            for entry in entries {
+                html.push_str(&format!("<article>\n<h3>{}</h3>\n", escape(&entry.name)));
+                if let Some(description) = &entry.description {
+                    html.push_str(&format!("<p>{}</p>\n", escape(description)));
+                }
+                if let Some(dots) = entry.dots {
+                    html.push_str(&format!("<p>{}</p>\n", count(dots)));
+                }
+                if !entry.colors.is_empty() {
+                    html.push_str("<ul>\n");
+                    for (name, color) in &entry.colors {
+                        html.push_str(&format!(
+                            "<li><span style=\"display:inline-block;width:1em;height:1em;background:{}\"></span> {} <code>{}</code></li>\n",
+                            color,
+                            escape(name),
+                            color
+                        ));
+                    }
+                    html.push_str("</ul>\n");
+                }
+                html.push_str("</article>\n");
+            }
+        }
+
+        html.push_str(&format!("</section>\n{}\n", END_MARKER));
+        html
+    }
+
+    fn sections(&self) -> Vec<(&'static str, &Vec<ShowcaseEntry>)> {
+        vec![("Profiles", &self.profiles), ("Themes", &self.themes)]
+            .into_iter()
+            .filter(|(_, entries)| !entries.is_empty())
+            .collect()
+    }
+}
+
+/// A `#rgb`, `#rgba`, `#rrggbb` or `#rrggbbaa` color
+pub(crate) fn is_color(value: &str) -> bool {
+    match value.strip_prefix('#') {
+        Some(hex) => {
+            [3, 4, 6, 8].contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit())
+        }
+        None => false,
+    }
+}
+
+/// Replace the showcase between the markers of `document`, ex: a README generated before
+pub(crate) fn embed(document: &str, showcase: &str) -> Result<String> {
+    let start = document.find(START_MARKER);
+    let end = document.find(END_MARKER);
+    match (start, end) {
+        (Some(start), Some(end)) if start < end => Ok(format!(
+            "{}{}{}",
+            &document[..start],
+            showcase.trim_end(),
+            &document[end + END_MARKER.len()..]
+        )),
+        _ => Err(anyhow!(
+            "Unable to find the `{}` and `{}` markers, add them where the showcase goes",
+            START_MARKER,
+            END_MARKER
+        )),
+    }
+}
+
+fn count(dots: usize) -> String {
+    match dots {
+        1 => "1 dot".to_string(),
+        dots => format!("{} dots", dots),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::showcase::{embed, is_color, Showcase, ShowcaseEntry};
+    use anyhow::Result;
+
+    #[test]
+    fn should_render_showcase_between_markers() -> Result<()> {
+        // Arrange
+        let showcase = Showcase {
+            profiles: vec![ShowcaseEntry {
+                name: "work".to_string(),
+                description: Some("Work <laptop>".to_string()),
+                dots: Some(3),
+                colors: vec![("background".to_string(), "#282a36".to_string())],
+            }],
+            themes: vec![],
+        };
+        let readme = "# Dotfiles\n\
+                      <!-- bombadil showcase start -->\nstale\n<!-- bombadil showcase end -->\n\
+                      ## Install\n";
+
+        // Act
+        let markdown = showcase.markdown();
+        let html = showcase.html();
+        let embedded = embed(readme, &markdown)?;
+
+        // Assert
+        assert_eq!(
+            markdown,
+            "<!-- bombadil showcase start -->\n\
+             \n## Profiles\n\
+             \n### work\n\n\
+             Work <laptop>\n\n\
+             3 dots\n\n\
+             | Color | Variable | Value |\n| --- | --- | --- |\n\
+             | $\\color{#282a36}\\blacksquare$ | background | `#282a36` |\n\n\
+             <!-- bombadil showcase end -->\n"
+        );
+        assert!(html.contains("<p>Work &lt;laptop&gt;</p>"));
+        assert!(html.contains("background:#282a36\"></span> background <code>#282a36</code>"));
+        assert_eq!(embedded, format!("# Dotfiles\n{}## Install\n", markdown));
+        assert!(embed("# Dotfiles\n", &markdown).is_err());
+        assert!(is_color("#fff") && is_color("#282a36ff"));
+        assert!(!is_color("#28a36") && !is_color("red") && !is_color("#gggggg"));
+        Ok(())
+    }
+}
@@ -1,19 +1,42 @@
-use crate::{unlink, Bombadil};
+use crate::i18n::t;
+use crate::logger::{self, Outcome};
+use crate::settings::RetentionSettings;
+use crate::templating::DegradedSecrets;
+use crate::{copy_recursively, unlink, Bombadil};
 use anyhow::Result;
 use colored::*;
 use config::Config;
 use config::File;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Debug;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 const STATE_FILE: &str = "previous_state.toml";
+const RUN_MANIFEST_FILE: &str = "run_manifest.toml";
+const PROFILE_STATE_FILE: &str = "state.toml";
+const RESOLVED_VARS_FILE: &str = "resolved_vars.toml";
+const STATE_MANIFEST_FILE: &str = "manifest.toml";
+const JOURNAL_FILE: &str = "journal.toml";
+const INPUT_HASHES_FILE: &str = "input_hashes.toml";
+/// The rendered dots of the link before the last one, in `.dots`
+const PREVIOUS_GENERATION_DIR: &str = ".previous";
+/// Targets replaced by the last link, in `.dots`
+const BACKUP_DIR: &str = ".backup";
 
 #[derive(Debug, Deserialize, Serialize)]
 pub(crate) struct BombadilState {
     #[serde(skip)]
     pub path: PathBuf,
     pub symlinks: Vec<PathBuf>,
+    /// Profiles enabled when the state was written
+    #[serde(default)]
+    pub profiles: Vec<String>,
+    /// Templates rendered with the secret sentinel by `bombadil link --degraded`
+    #[serde(default)]
+    pub degraded: Vec<PathBuf>,
 }
 
 impl BombadilState {
@@ -22,9 +45,12 @@ impl BombadilState {
 
         if state_path.exists() {
             let mut s = Config::new();
-            s.merge(File::from(state_path))?;
-            s.try_into()
-                .map_err(|err| anyhow!("{} : {}", "Previous state format error".red(), err))
+            s.merge(File::from(state_path.clone()))?;
+            let mut state: BombadilState = s
+                .try_into()
+                .map_err(|err| anyhow!("{} : {}", "Previous state format error".red(), err))?;
+            state.path = state_path;
+            Ok(state)
         } else {
             Err(anyhow!(
                 "Unable to find Previous state file {}",
@@ -39,16 +65,20 @@ impl BombadilState {
         Ok(())
     }
 
-    pub fn remove_targets(&self) -> Vec<Result<PathBuf>> {
+    /// Remove the previous targets, but those in `except`
+    pub fn remove_targets(&self, except: &[PathBuf]) -> Vec<Result<PathBuf>> {
         let mut unlink_results = vec![];
 
-        self.symlinks.iter().for_each(|path| {
-            unlink_results.push(
-                unlink(path)
-                    .map(|_| path.to_owned())
-                    .map_err(|err| anyhow!("Failed to unlink dot entry {:?} : {}", path, err)),
-            );
-        });
+        self.symlinks
+            .iter()
+            .filter(|path| !except.contains(path))
+            .for_each(|path| {
+                unlink_results.push(
+                    unlink(path)
+                        .map(|_| path.to_owned())
+                        .map_err(|err| anyhow!("Failed to unlink dot entry {:?} : {}", path, err)),
+                );
+            });
 
         unlink_results
     }
@@ -57,17 +87,992 @@ impl BombadilState {
 impl From<&Bombadil> for BombadilState {
     fn from(current: &Bombadil) -> Self {
         // Since we come from current bombadil config, unwrap is safe
-        let path = current
-            .dotfiles_absolute_path()
-            .unwrap()
-            .join(".dots")
-            .join(STATE_FILE);
-        let symlinks = current
+        let dotfiles_dir = current.dotfiles_absolute_path().unwrap();
+        let path = dotfiles_dir.join(".dots").join(STATE_FILE);
+        let mut symlinks: Vec<PathBuf> = current
             .dots
+            .values()
+            .flat_map(|dot| dot.targets(&dot.dotfiles_dir(&dotfiles_dir)).unwrap())
+            .collect();
+        symlinks.extend(current.autostart_targets());
+        symlinks.extend(current.mimeapps_target());
+
+        Self {
+            path,
+            symlinks,
+            profiles: current.active_profiles.clone(),
+            degraded: current
+                .vars
+                .degraded
+                .as_ref()
+                .map(DegradedSecrets::files)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Progress of the last link operation, written to `.dots/run_manifest.toml` while linking
+/// and kept around when some dots could not be linked so the run can be resumed.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub(crate) struct RunManifest {
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Profiles enabled for this run
+    pub profiles: Vec<String>,
+    /// Dots successfully rendered and linked
+    pub completed: Vec<String>,
+    /// Dots not processed yet
+    pub pending: Vec<String>,
+    /// Whether post install hooks have been run
+    pub posthooks_done: bool,
+    /// Dots that failed to link, with the corresponding error
+    pub failed: Vec<FailedDot>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct FailedDot {
+    pub dot: String,
+    pub error: String,
+}
+
+impl RunManifest {
+    pub fn new(dotfiles_dir: &Path, profiles: Vec<String>, pending: Vec<String>) -> Self {
+        RunManifest {
+            path: Self::manifest_path(dotfiles_dir),
+            profiles,
+            pending,
+            ..Default::default()
+        }
+    }
+
+    pub fn read(dotfiles_dir: &Path) -> Result<Self> {
+        let path = Self::manifest_path(dotfiles_dir);
+        let content = fs::read_to_string(&path).map_err(|err| {
+            anyhow!(
+                "Unable to find run manifest {}, nothing to resume : {}",
+                path.display(),
+                err
+            )
+        })?;
+
+        let mut manifest: RunManifest = toml::from_str(&content)
+            .map_err(|err| anyhow!("{} : {}", "Run manifest format error".red(), err))?;
+        manifest.path = path;
+        Ok(manifest)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let content = toml::to_string(&self)?;
+        fs::write(&self.path, &content)?;
+        Ok(())
+    }
+
+    pub fn remove(&self) -> Result<()> {
+        if self.path.exists() {
+            fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of linking a dot
+    pub fn record(&mut self, dot: &str, result: &Result<()>) {
+        self.pending.retain(|pending| pending != dot);
+        self.failed.retain(|failed| failed.dot != dot);
+
+        match result {
+            Ok(()) => self.completed.push(dot.to_string()),
+            Err(err) => self.failed.push(FailedDot {
+                dot: dot.to_string(),
+                error: err.to_string(),
+            }),
+        }
+    }
+
+    /// Dots that still need to be linked
+    pub fn remaining(&self) -> Vec<String> {
+        self.failed
+            .iter()
+            .map(|failed| failed.dot.clone())
+            .chain(self.pending.iter().cloned())
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.pending.is_empty() && self.posthooks_done
+    }
+
+    /// Print which dots were linked and which ones failed
+    pub fn print_report(&self) {
+        if self.failed.is_empty() {
+            return;
+        }
+
+        error!(
+            "{}",
+            t!(
+                "run-report",
+                completed = self.completed.len(),
+                failed = self.failed.len()
+            )
+            .yellow()
+        );
+
+        self.failed.iter().for_each(|failed| {
+            let line = format!("{} : {}", failed.dot.red(), failed.error);
+            error!("\t{}", logger::outcome(Outcome::Failed, line))
+        });
+
+        error!("{}", t!("run-resume-hint").yellow());
+    }
+
+    fn manifest_path(dotfiles_dir: &Path) -> PathBuf {
+        dotfiles_dir.join(".dots").join(RUN_MANIFEST_FILE)
+    }
+}
+
+/// Profiles explicitly enabled by the last `bombadil link -p ...`, stored in
+/// `$XDG_STATE_HOME/bombadil/state.toml` so a bare `bombadil link` can enable them again.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub(crate) struct ProfileState {
+    /// Dotfiles directory the profiles belong to
+    pub dotfiles_dir: PathBuf,
+    pub profiles: Vec<String>,
+    /// Theme chosen with `bombadil theme set`
+    #[serde(default)]
+    pub theme: Option<String>,
+}
+
+impl ProfileState {
+    pub fn read() -> Result<Self> {
+        Self::read_from(&Self::state_path()?)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        self.write_to(&Self::state_path()?)
+    }
+
+    fn read_from(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Unable to read profile state {} : {}", path.display(), err))?;
+
+        toml::from_str(&content)
+            .map_err(|err| anyhow!("{} : {}", "Profile state format error".red(), err))
+    }
+
+    fn write_to(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = toml::to_string(&self)?;
+        fs::write(path, &content)?;
+        Ok(())
+    }
+
+    fn state_path() -> Result<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| dir.join("bombadil").join(PROFILE_STATE_FILE))
+            .ok_or_else(|| anyhow!("$XDG_STATE_HOME does not exist"))
+    }
+}
+
+/// Every file created by linking, stored in `$XDG_STATE_HOME/bombadil/manifest.toml`.
+/// Unlike [`BombadilState`] it is neither kept in `.dots` nor derived from the configuration,
+/// so the targets of dots removed from the configuration can still be cleaned up.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub(crate) struct StateManifest {
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub created: Vec<CreatedFile>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub(crate) struct CreatedFile {
+    /// Dotfiles directory of the configuration that linked the file
+    pub dotfiles_dir: PathBuf,
+    /// Rendered file the target links to or was copied from
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// The source was copied instead of symlinked
+    #[serde(default)]
+    pub copied: bool,
+    /// sha256 of the target content when it was created
+    pub hash: String,
+}
+
+impl StateManifest {
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| dir.join("bombadil").join(STATE_MANIFEST_FILE))
+            .ok_or_else(|| anyhow!("$XDG_STATE_HOME does not exist"))
+    }
+
+    /// An empty manifest when nothing was linked yet
+    pub fn read(path: &Path) -> Result<Self> {
+        let mut manifest: StateManifest = match fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).map_err(|err| {
+                anyhow!(
+                    "{} {:?} : {}",
+                    "State manifest format error".red(),
+                    path,
+                    err
+                )
+            })?,
+            Err(_) => StateManifest::default(),
+        };
+
+        manifest.path = path.to_path_buf();
+        Ok(manifest)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let content = toml::to_string(&self)?;
+        fs::write(&self.path, &content)?;
+        Ok(())
+    }
+
+    /// Record the `target` linked or copied from `source`
+    pub fn record(&mut self, dotfiles_dir: &Path, source: &Path, target: &Path) -> Result<()> {
+        let copied = !fs::symlink_metadata(target)?.file_type().is_symlink();
+        let file = CreatedFile {
+            dotfiles_dir: dotfiles_dir.to_path_buf(),
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            copied,
+            hash: content_hash(target)?,
+        };
+
+        self.created.retain(|created| created.target != target);
+        self.created.push(file);
+        Ok(())
+    }
+
+    /// Remove the files created for `dotfiles_dir` that are unchanged since they were linked,
+    /// modified ones are left in place. Either way they are no longer recorded.
+    pub fn remove_targets(&mut self, dotfiles_dir: &Path) -> Vec<Result<PathBuf>> {
+        let (files, others): (Vec<CreatedFile>, Vec<CreatedFile>) = self
+            .created
+            .drain(..)
+            .partition(|created| created.dotfiles_dir == dotfiles_dir);
+        self.created = others;
+
+        files
+            .iter()
+            .filter(|file| fs::symlink_metadata(&file.target).is_ok())
+            .map(|file| {
+                if file.is_unchanged() {
+                    unlink(&file.target).map(|_| file.target.clone())
+                } else {
+                    Err(anyhow!(
+                        "{:?} changed since it was linked, left in place",
+                        file.target
+                    ))
+                }
+            })
+            .collect()
+    }
+
+    /// Targets created for `dotfiles_dir`
+    pub fn targets(&self, dotfiles_dir: &Path) -> Vec<PathBuf> {
+        self.created
+            .iter()
+            .filter(|created| created.dotfiles_dir == dotfiles_dir)
+            .map(|created| created.target.clone())
+            .collect()
+    }
+
+    /// Forget the given targets without removing them
+    pub fn forget_targets(&mut self, targets: &[PathBuf]) {
+        self.created
+            .retain(|created| !targets.contains(&created.target));
+    }
+
+    /// Forget the files created for `dotfiles_dir` without removing them
+    pub fn forget(&mut self, dotfiles_dir: &Path) {
+        self.created
+            .retain(|created| created.dotfiles_dir != dotfiles_dir);
+    }
+}
+
+impl CreatedFile {
+    /// The target still links to its source, or still has the content it was copied with
+    fn is_unchanged(&self) -> bool {
+        if self.copied {
+            content_hash(&self.target).is_ok_and(|hash| hash == self.hash)
+        } else {
+            fs::read_link(&self.target).is_ok_and(|destination| destination == self.source)
+        }
+    }
+}
+
+/// sha256 of a file, or of the relative paths and content of the files of a directory
+fn content_hash(path: &Path) -> Result<String> {
+    fn feed(hasher: &mut Sha256, root: &Path, path: &Path) -> Result<()> {
+        // Symlinks kept inside a directory dot are hashed as links, they may loop
+        let is_link =
+            path != root && fs::symlink_metadata(path).is_ok_and(|metadata| metadata.is_symlink());
+        if is_link {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(fs::read_link(path)?.to_string_lossy().as_bytes());
+        } else if path.is_dir() {
+            let mut entries: Vec<PathBuf> = path
+                .read_dir()?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .collect();
+            entries.sort();
+            for entry in entries {
+                feed(hasher, root, &entry)?;
+            }
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(path);
+            hasher.update(relative.to_string_lossy().as_bytes());
+            hasher.update(fs::read(path)?);
+        }
+
+        Ok(())
+    }
+
+    let mut hasher = Sha256::new();
+    feed(&mut hasher, path, path)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Bombadil own files in `.dots`
+pub(crate) fn state_files(dotfiles_dir: &Path) -> Vec<PathBuf> {
+    [
+        STATE_FILE,
+        RUN_MANIFEST_FILE,
+        RESOLVED_VARS_FILE,
+        INPUT_HASHES_FILE,
+    ]
+    .iter()
+    .map(|file| dotfiles_dir.join(".dots").join(file))
+    .collect()
+}
+
+/// The journal and the previous generations kept in `copy_dir`
+pub(crate) fn generation_files(copy_dir: &Path) -> Vec<PathBuf> {
+    [JOURNAL_FILE, PREVIOUS_GENERATION_DIR, BACKUP_DIR]
+        .iter()
+        .map(|file| copy_dir.join(file))
+        .collect()
+}
+
+/// Variable values used by the last link, written to `.dots/resolved_vars.toml`
+/// so a render can be reproduced with `bombadil link --frozen-vars`.
+/// Secrets are stored encrypted, the way they appear in var files.
+#[derive(Debug, Deserialize, Serialize, Default, PartialEq)]
+pub(crate) struct ResolvedVars {
+    pub variables: BTreeMap<String, String>,
+}
+
+impl ResolvedVars {
+    pub fn new(variables: &HashMap<String, String>) -> Self {
+        ResolvedVars {
+            variables: variables
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        }
+    }
+
+    pub fn read(dotfiles_dir: &Path) -> Result<Self> {
+        let path = Self::path(dotfiles_dir);
+        let content = fs::read_to_string(&path).map_err(|err| {
+            anyhow!(
+                "Unable to read recorded vars {}, run `bombadil link` first : {}",
+                path.display(),
+                err
+            )
+        })?;
+
+        toml::from_str(&content)
+            .map_err(|err| anyhow!("{} : {}", "Recorded vars format error".red(), err))
+    }
+
+    pub fn write(&self, dotfiles_dir: &Path) -> Result<()> {
+        let content = toml::to_string(&self)?;
+        fs::write(Self::path(dotfiles_dir), &content)?;
+        Ok(())
+    }
+
+    /// Describe the variables that were added, removed or changed since `previous`
+    pub fn diff(&self, previous: &ResolvedVars) -> Vec<String> {
+        let mut changes: Vec<String> = self
+            .variables
             .iter()
-            .map(|dot| dot.1.target_path().unwrap())
+            .filter_map(|(key, value)| match previous.variables.get(key) {
+                None => Some(format!("{} : added \"{}\"", key, value)),
+                Some(previous) if previous != value => {
+                    Some(format!("{} : \"{}\" => \"{}\"", key, previous, value))
+                }
+                Some(_) => None,
+            })
             .collect();
 
-        Self { path, symlinks }
+        changes.extend(
+            previous
+                .variables
+                .keys()
+                .filter(|key| !self.variables.contains_key(*key))
+                .map(|key| format!("{} : removed", key)),
+        );
+
+        changes
+    }
+
+    fn path(dotfiles_dir: &Path) -> PathBuf {
+        dotfiles_dir.join(".dots").join(RESOLVED_VARS_FILE)
+    }
+}
+
+/// What a link created and replaced, written to `<copy dir>/journal.toml` so `bombadil rollback`
+/// can undo it. The journal moves with its generation of rendered dots.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub(crate) struct LinkJournal {
+    #[serde(skip)]
+    pub path: PathBuf,
+    /// Rendered files and the target linked to them
+    #[serde(default)]
+    pub links: Vec<JournalLink>,
+    /// Targets that existed before the link and were replaced
+    #[serde(default)]
+    pub backups: Vec<Backup>,
+    /// Profiles a bare `bombadil link` enabled before the link
+    #[serde(default)]
+    pub last_profiles: Option<Vec<String>>,
+    /// Seconds since the Unix epoch when the link started, used by `max_age_days`
+    #[serde(default)]
+    pub linked_at: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct JournalLink {
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub(crate) struct Backup {
+    pub target: PathBuf,
+    /// Copy of the target, relative to the copy dir
+    #[serde(default)]
+    pub copy: Option<PathBuf>,
+    /// Destination of the target when it was a symlink
+    #[serde(default)]
+    pub link: Option<PathBuf>,
+}
+
+impl LinkJournal {
+    /// The journal of the generation rendered in `copy_dir`, empty if there is none
+    pub fn read(copy_dir: &Path) -> Result<Self> {
+        let path = copy_dir.join(JOURNAL_FILE);
+        let mut journal: LinkJournal = match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|err| anyhow!("{} {:?} : {}", "Journal format error".red(), path, err))?,
+            Err(_) => LinkJournal::default(),
+        };
+
+        journal.path = path;
+        Ok(journal)
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let content = toml::to_string(&self)?;
+        fs::write(&self.path, &content)?;
+        Ok(())
+    }
+
+    pub fn record(&mut self, links: &[(PathBuf, PathBuf)]) {
+        for (source, target) in links {
+            self.links.retain(|link| &link.target != target);
+            self.links.push(JournalLink {
+                source: source.clone(),
+                target: target.clone(),
+            });
+        }
+    }
+
+    /// Keep a copy of the `targets` about to be replaced, unless they are links to one of the
+    /// `copy_dirs` or were already saved
+    pub fn backup(&mut self, targets: &[PathBuf], copy_dirs: &[PathBuf]) -> Result<()> {
+        let copy_dir = self
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+
+        for target in targets {
+            let metadata = match fs::symlink_metadata(target) {
+                Ok(metadata) => metadata,
+                Err(_) => continue,
+            };
+            if self.backups.iter().any(|backup| &backup.target == target) {
+                continue;
+            }
+
+            let backup = if metadata.file_type().is_symlink() {
+                let destination = fs::read_link(target)?;
+                if copy_dirs.iter().any(|dir| destination.starts_with(dir)) {
+                    continue;
+                }
+                Backup {
+                    target: target.clone(),
+                    copy: None,
+                    link: Some(destination),
+                }
+            } else {
+                let copy = Path::new(BACKUP_DIR).join(self.backups.len().to_string());
+                fs::create_dir_all(copy_dir.join(BACKUP_DIR))?;
+                copy_recursively(target, &copy_dir.join(&copy))?;
+                Backup {
+                    target: target.clone(),
+                    copy: Some(copy),
+                    link: None,
+                }
+            };
+
+            debug!("Saved {:?} before replacing it", target);
+            self.backups.push(backup);
+        }
+
+        self.write()
+    }
+}
+
+impl Backup {
+    /// Put the saved target back, `copy_dir` holds the journal of the backup
+    pub fn restore(&self, copy_dir: &Path) -> Result<()> {
+        if let Some(parent) = self.target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        match (&self.link, &self.copy) {
+            (Some(destination), _) => crate::symlink(destination, &self.target)?,
+            (None, Some(copy)) => copy_recursively(&copy_dir.join(copy), &self.target)?,
+            (None, None) => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Keep `copy_dir` as the previous generation of rendered dots in a new empty `copy_dir`.
+/// Each generation keeps the one before it in its own `.previous`, the generations and
+/// backups outside of the `retention` policy are discarded.
+pub(crate) fn rotate_generation(copy_dir: &Path, retention: &RetentionSettings) -> Result<()> {
+    if !copy_dir.exists() {
+        return Ok(());
+    }
+
+    let rotating = copy_dir.with_extension("rotating");
+    unlink(&rotating)?;
+    fs::rename(copy_dir, &rotating)?;
+    unlink(&rotating.join(RUN_MANIFEST_FILE))?;
+    fs::create_dir_all(copy_dir)?;
+    fs::rename(&rotating, copy_dir.join(PREVIOUS_GENERATION_DIR))?;
+
+    prune_generations(copy_dir, retention)?;
+    Ok(())
+}
+
+/// Where an atomic link renders the dots of `copy_dir`
+pub(crate) fn staging_dir(copy_dir: &Path) -> PathBuf {
+    copy_dir.with_extension("staging")
+}
+
+/// Move the dots rendered in the staging directory of `copy_dir` to `copy_dir`
+pub(crate) fn swap_in_staging(copy_dir: &Path) -> Result<()> {
+    let staging = staging_dir(copy_dir);
+    if !staging.exists() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(copy_dir)?;
+    for entry in staging.read_dir()? {
+        let entry = entry?;
+        let destination = copy_dir.join(entry.file_name());
+        unlink(&destination)?;
+        fs::rename(entry.path(), destination)?;
+    }
+
+    fs::remove_dir(staging)?;
+    Ok(())
+}
+
+/// The previous generation of `copy_dir`, if any
+pub(crate) fn previous_generation(copy_dir: &Path) -> Option<PathBuf> {
+    Some(copy_dir.join(PREVIOUS_GENERATION_DIR)).filter(|previous| previous.is_dir())
+}
+
+/// Replace `copy_dir` with its previous generation
+pub(crate) fn restore_generation(copy_dir: &Path) -> Result<()> {
+    let previous = match previous_generation(copy_dir) {
+        Some(previous) => previous,
+        None => return Ok(()),
+    };
+
+    let restoring = copy_dir.with_extension("restoring");
+    unlink(&restoring)?;
+    fs::rename(previous, &restoring)?;
+    fs::remove_dir_all(copy_dir)?;
+    fs::rename(&restoring, copy_dir)?;
+    Ok(())
+}
+
+/// What [`prune_generations`] discarded
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Pruned {
+    pub generations: usize,
+    pub backups: usize,
+    pub bytes: u64,
+}
+
+impl Pruned {
+    pub fn add(&mut self, other: Pruned) {
+        self.generations += other.generations;
+        self.backups += other.backups;
+        self.bytes += other.bytes;
+    }
+}
+
+/// Discard the previous generations of `copy_dir` beyond `keep_generations` or older than
+/// `max_age_days`, and the replaced files saved by the generations beyond `keep_backups`
+pub(crate) fn prune_generations(copy_dir: &Path, retention: &RetentionSettings) -> Result<Pruned> {
+    let keep_generations = retention.keep_generations.unwrap_or(1);
+    let keep_backups = retention.keep_backups.unwrap_or(keep_generations);
+    let max_age = retention
+        .max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+
+    let mut pruned = Pruned::default();
+    let mut generation = copy_dir.to_path_buf();
+    let mut depth = 0;
+    while let Some(previous) = previous_generation(&generation) {
+        depth += 1;
+        let expired = max_age.is_some_and(|max_age| generation_age(&previous) > max_age);
+        if depth > keep_generations || expired {
+            let mut discarded = Some(previous.clone());
+            while let Some(generation) = discarded {
+                pruned.generations += 1;
+                discarded = previous_generation(&generation);
+            }
+            pruned.bytes += size(&previous);
+            fs::remove_dir_all(&previous)?;
+            break;
+        }
+
+        if depth > keep_backups {
+            pruned.add(prune_backups(&previous)?);
+        }
+        generation = previous;
+    }
+
+    Ok(pruned)
+}
+
+/// Remove the copies of the files replaced by the link of `generation`, the replaced links
+/// take no space and are kept
+fn prune_backups(generation: &Path) -> Result<Pruned> {
+    let backup_dir = generation.join(BACKUP_DIR);
+    if !backup_dir.exists() {
+        return Ok(Pruned::default());
+    }
+
+    let mut journal = LinkJournal::read(generation)?;
+    let before = journal.backups.len();
+    journal.backups.retain(|backup| backup.copy.is_none());
+    let pruned = Pruned {
+        generations: 0,
+        backups: before - journal.backups.len(),
+        bytes: size(&backup_dir),
+    };
+
+    fs::remove_dir_all(backup_dir)?;
+    if journal.path.exists() {
+        journal.write()?;
+    }
+    Ok(pruned)
+}
+
+/// Time since the link of `generation`, generations linked before `linked_at` was recorded
+/// are as old as their directory
+fn generation_age(generation: &Path) -> Duration {
+    let linked_at = LinkJournal::read(generation)
+        .ok()
+        .and_then(|journal| journal.linked_at)
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+        .or_else(|| fs::metadata(generation).and_then(|m| m.modified()).ok());
+
+    linked_at
+        .and_then(|linked_at| SystemTime::now().duration_since(linked_at).ok())
+        .unwrap_or_default()
+}
+
+/// Size of a file or directory in bytes, links are not followed
+pub(crate) fn size(path: &Path) -> u64 {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => path
+            .read_dir()
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .map(|entry| size(&entry.path()))
+                    .sum()
+            })
+            .unwrap_or(0),
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    }
+}
+
+/// Hashes of the dots rendered in a copy dir, written to `<copy dir>/input_hashes.toml`.
+/// A dot whose inputs hash the same on the next link reuses its rendered files, as long as
+/// they still hash the same too.
+#[derive(Debug, Deserialize, Serialize, Default)]
+pub(crate) struct InputHashes {
+    #[serde(skip)]
+    pub path: PathBuf,
+    #[serde(default)]
+    pub dots: BTreeMap<String, DotHashes>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub(crate) struct DotHashes {
+    /// Hash of the dot, its variables and its source files
+    pub inputs: String,
+    /// Hash of the rendered files
+    pub outputs: String,
+}
+
+impl InputHashes {
+    /// The hashes of the dots rendered in `copy_dir`, empty if there are none or they can't
+    /// be read : every dot is rendered again
+    pub fn read(copy_dir: &Path) -> Self {
+        let path = copy_dir.join(INPUT_HASHES_FILE);
+        let mut hashes: InputHashes = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| toml::from_str(&content).ok())
+            .unwrap_or_default();
+
+        hashes.path = path;
+        hashes
+    }
+
+    pub fn write(&self) -> Result<()> {
+        let content = toml::to_string(&self)?;
+        fs::write(&self.path, &content)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::settings::RetentionSettings;
+    use crate::state::{
+        previous_generation, prune_generations, restore_generation, rotate_generation, LinkJournal,
+        ProfileState, Pruned, ResolvedVars, StateManifest,
+    };
+    use anyhow::Result;
+    use std::fs;
+    use std::os::unix;
+    use std::path::PathBuf;
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_persist_profile_state() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let path = temp.join("bombadil").join("state.toml");
+        let state = ProfileState {
+            dotfiles_dir: PathBuf::from("/home/user/dotfiles"),
+            profiles: vec!["sway".to_string(), "work".to_string()],
+            theme: Some("dracula".to_string()),
+        };
+
+        // Act
+        state.write_to(&path)?;
+        let read = ProfileState::read_from(&path)?;
+
+        // Assert
+        assert_eq!(read, state);
+        Ok(())
+    }
+
+    #[test]
+    fn should_only_remove_unchanged_created_files() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let dotfiles_dir = temp.join("dotfiles");
+        fs::create_dir_all(&dotfiles_dir)?;
+        fs::write(dotfiles_dir.join("zshrc"), "zsh")?;
+        fs::write(dotfiles_dir.join("gitconfig"), "git")?;
+        fs::write(dotfiles_dir.join("vimrc"), "vim")?;
+        unix::fs::symlink(dotfiles_dir.join("zshrc"), temp.join(".zshrc"))?;
+        fs::copy(dotfiles_dir.join("gitconfig"), temp.join(".gitconfig"))?;
+        fs::copy(dotfiles_dir.join("vimrc"), temp.join(".vimrc"))?;
+
+        let path = temp.join("state").join("manifest.toml");
+        let mut manifest = StateManifest::read(&path)?;
+        for dot in ["zshrc", "gitconfig", "vimrc"] {
+            let target = temp.join(format!(".{}", dot));
+            manifest.record(&dotfiles_dir, &dotfiles_dir.join(dot), &target)?;
+        }
+        manifest.write()?;
+        fs::write(temp.join(".vimrc"), "edited")?;
+
+        // Act
+        let mut manifest = StateManifest::read(&path)?;
+        let results = manifest.remove_targets(&dotfiles_dir);
+
+        // Assert
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 2);
+        assert!(fs::symlink_metadata(temp.join(".zshrc")).is_err());
+        assert!(!temp.join(".gitconfig").exists());
+        assert_eq!(fs::read_to_string(temp.join(".vimrc"))?, "edited");
+        assert!(manifest.targets(&dotfiles_dir).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn should_diff_resolved_vars() {
+        // Arrange
+        let previous = ResolvedVars {
+            variables: vec![
+                ("red".to_string(), "#ff0000".to_string()),
+                ("host".to_string(), "laptop".to_string()),
+                ("gone".to_string(), "x".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        let current = ResolvedVars {
+            variables: vec![
+                ("red".to_string(), "#ff0000".to_string()),
+                ("host".to_string(), "desktop".to_string()),
+                ("new".to_string(), "y".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        };
+
+        // Act
+        let diff = current.diff(&previous);
+
+        // Assert
+        assert_eq!(
+            diff,
+            vec![
+                "host : \"laptop\" => \"desktop\"".to_string(),
+                "new : added \"y\"".to_string(),
+                "gone : removed".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_keep_one_previous_generation_and_replaced_targets() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let copy_dir = temp.join(".dots");
+        fs::create_dir_all(&copy_dir)?;
+        fs::write(copy_dir.join("zshrc"), "first")?;
+        fs::write(temp.join("user_vimrc"), "mine")?;
+        unix::fs::symlink(copy_dir.join("zshrc"), temp.join(".zshrc"))?;
+        unix::fs::symlink(temp.join("user_vimrc"), temp.join(".vimrc"))?;
+        fs::write(temp.join(".gitconfig"), "user")?;
+
+        // Act
+        rotate_generation(&copy_dir, &RetentionSettings::default())?;
+        fs::write(copy_dir.join("zshrc"), "second")?;
+        rotate_generation(&copy_dir, &RetentionSettings::default())?;
+        fs::write(copy_dir.join("zshrc"), "third")?;
+
+        let mut journal = LinkJournal::read(&copy_dir)?;
+        let targets = vec![
+            temp.join(".zshrc"),
+            temp.join(".vimrc"),
+            temp.join(".gitconfig"),
+        ];
+        journal.backup(&targets, std::slice::from_ref(&copy_dir))?;
+        fs::remove_file(temp.join(".vimrc"))?;
+        fs::remove_file(temp.join(".gitconfig"))?;
+        let journal = LinkJournal::read(&copy_dir)?;
+        for backup in &journal.backups {
+            backup.restore(&copy_dir)?;
+        }
+        restore_generation(&copy_dir)?;
+
+        // Assert
+        assert_eq!(journal.backups.len(), 2);
+        assert_eq!(fs::read_link(temp.join(".vimrc"))?, temp.join("user_vimrc"));
+        assert_eq!(fs::read_to_string(temp.join(".gitconfig"))?, "user");
+        assert_eq!(fs::read_to_string(copy_dir.join("zshrc"))?, "second");
+        assert!(previous_generation(&copy_dir).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn should_prune_generations_and_backups_outside_retention() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let copy_dir = temp.join(".dots");
+        let keep_all = RetentionSettings {
+            keep_generations: Some(10),
+            ..Default::default()
+        };
+        fs::create_dir_all(&copy_dir)?;
+        for link in 1..=4 {
+            fs::write(temp.join(".gitconfig"), format!("user {}", link))?;
+            let mut journal = LinkJournal::read(&copy_dir)?;
+            journal.backup(&[temp.join(".gitconfig")], &[])?;
+            fs::write(copy_dir.join("zshrc"), link.to_string())?;
+            rotate_generation(&copy_dir, &keep_all)?;
+        }
+        let retention = RetentionSettings {
+            keep_generations: Some(2),
+            keep_backups: Some(1),
+            max_age_days: None,
+        };
+
+        // Act
+        let pruned = prune_generations(&copy_dir, &retention)?;
+
+        // Assert
+        let last = previous_generation(&copy_dir).unwrap();
+        let before_last = previous_generation(&last).unwrap();
+        assert_eq!(pruned.generations, 2);
+        assert_eq!(pruned.backups, 1);
+        assert_eq!(fs::read_to_string(last.join("zshrc"))?, "4");
+        assert!(last.join(".backup").exists());
+        assert_eq!(fs::read_to_string(before_last.join("zshrc"))?, "3");
+        assert!(!before_last.join(".backup").exists());
+        assert!(LinkJournal::read(&before_last)?.backups.is_empty());
+        assert!(previous_generation(&before_last).is_none());
+        assert_eq!(prune_generations(&copy_dir, &retention)?, Pruned::default());
+
+        let expired = RetentionSettings {
+            max_age_days: Some(0),
+            ..Default::default()
+        };
+        let mut journal = LinkJournal::read(&last)?;
+        journal.linked_at = Some(0);
+        journal.write()?;
+        assert_eq!(prune_generations(&copy_dir, &expired)?.generations, 2);
+        assert!(previous_generation(&copy_dir).is_none());
+        Ok(())
     }
 }
@@ -0,0 +1,120 @@
+use anyhow::Result;
+use colored::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const WATCH_UNIT: &str = "bombadil-watch.service";
+pub const LINK_UNIT: &str = "bombadil-link.service";
+
+fn exec_start(bombadil: &Path, command: &str, profiles: &[&str]) -> String {
+    let mut exec_start = format!("{} {}", bombadil.display(), command);
+    if !profiles.is_empty() {
+        exec_start.push_str(&format!(" --profiles {}", profiles.join(" ")));
+    }
+
+    exec_start
+}
+
+/// A systemd user unit running `bombadil watch`, `bombadil` is the path
+/// to the bombadil executable
+pub fn watch_unit(bombadil: &Path, profiles: &[&str]) -> String {
+    format!(
+        "[Unit]
+Description=Toml Bombadil dotfiles watcher
+Documentation=https://oknozor.github.io/toml-bombadil/
+
+[Service]
+ExecStart={}
+Restart=on-failure
+RestartSec=5
+
+[Install]
+WantedBy=default.target
+",
+        exec_start(bombadil, "watch", profiles)
+    )
+}
+
+/// A systemd user unit running `bombadil link` once at login, with the profiles of the last
+/// link unless `profiles` are given. A failed link raises a desktop notification.
+pub fn link_unit(bombadil: &Path, profiles: &[&str]) -> String {
+    format!(
+        "[Unit]
+Description=Toml Bombadil dotfiles link
+Documentation=https://oknozor.github.io/toml-bombadil/
+
+[Service]
+Type=oneshot
+ExecStart={}
+ExecStopPost=/bin/sh -c 'test \"$SERVICE_RESULT\" = success || notify-send --urgency=critical \"Toml Bombadil\" \"bombadil link failed, see journalctl --user -u {}\"'
+
+[Install]
+WantedBy=default.target
+",
+        exec_start(bombadil, "link", profiles),
+        LINK_UNIT
+    )
+}
+
+/// Write the unit `name` to `$XDG_CONFIG/systemd/user` and optionally enable and start it
+pub fn install_unit(name: &str, unit: &str, enable: bool) -> Result<PathBuf> {
+    let unit_dir = dirs::config_dir()
+        .ok_or_else(|| anyhow!("$XDG_CONFIG does not exist"))?
+        .join("systemd")
+        .join("user");
+
+    fs::create_dir_all(&unit_dir)?;
+    let unit_path = unit_dir.join(name);
+    fs::write(&unit_path, unit)?;
+    info!("{} {}", "Installed".green(), unit_path.display());
+
+    if enable {
+        systemctl(&["daemon-reload"])?;
+        systemctl(&["enable", "--now", name])?;
+        info!("{} {}", "Enabled".green(), name);
+    }
+
+    Ok(unit_path)
+}
+
+fn systemctl(args: &[&str]) -> Result<()> {
+    let status = Command::new("systemctl")
+        .arg("--user")
+        .args(args)
+        .status()
+        .map_err(|err| anyhow!("Unable to run systemctl, is systemd installed ? {}", err))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("`systemctl --user {}` failed", args.join(" ")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::systemd::{link_unit, watch_unit};
+    use std::path::Path;
+
+    #[test]
+    fn should_generate_watch_unit() {
+        // Act
+        let unit = watch_unit(Path::new("/usr/bin/bombadil"), &["sway", "work"]);
+
+        // Assert
+        assert!(unit.contains("ExecStart=/usr/bin/bombadil watch --profiles sway work\n"));
+        assert!(unit.contains("WantedBy=default.target"));
+    }
+
+    #[test]
+    fn should_generate_link_unit_notifying_failures() {
+        // Act
+        let unit = link_unit(Path::new("/usr/bin/bombadil"), &[]);
+
+        // Assert
+        assert!(unit.contains("Type=oneshot\nExecStart=/usr/bin/bombadil link\n"));
+        assert!(unit.contains("test \"$SERVICE_RESULT\" = success || notify-send"));
+        assert!(unit.contains("journalctl --user -u bombadil-link.service"));
+    }
+}
@@ -1,17 +1,128 @@
+use crate::audit::SecretAudit;
+use crate::facts::wsl_windows_home;
 use crate::gpg::{Gpg, GPG_PREFIX};
+use crate::timings::{RenderProfiler, RenderTiming};
+use crate::WSL_WINDOWS_HOME_VAR;
 use anyhow::Result;
 use colored::Colorize;
 use pest::Parser;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use yaml_rust::{ScanError, Yaml, YamlLoader};
 
 #[derive(Parser)]
 #[grammar = "template.pest"]
 struct BombadilParser;
 
+/// A template using an encrypted variable while secrets are skipped, the dot is not linked
+#[derive(Debug)]
+pub(crate) struct SkippedSecret {
+    path: PathBuf,
+    key: String,
+}
+
+impl fmt::Display for SkippedSecret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} uses the secret `{}`, skipped with `--skip-secrets`",
+            self.path, self.key
+        )
+    }
+}
+
+impl std::error::Error for SkippedSecret {}
+
+/// Renders the secrets that were not decrypted with `sentinel`, and collects the templates
+/// using them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DegradedSecrets {
+    pub sentinel: String,
+    files: Arc<Mutex<Vec<PathBuf>>>,
+}
+
+impl DegradedSecrets {
+    pub(crate) fn new(sentinel: &str) -> Self {
+        DegradedSecrets {
+            sentinel: sentinel.to_string(),
+            files: Arc::default(),
+        }
+    }
+
+    fn record(&self, file: &Path) {
+        if let Ok(mut files) = self.files.lock() {
+            if !files.iter().any(|recorded| recorded == file) {
+                files.push(file.to_path_buf());
+            }
+        }
+    }
+
+    /// Templates rendered with the sentinel, sorted
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        let mut files = self
+            .files
+            .lock()
+            .map(|files| files.clone())
+            .unwrap_or_default();
+        files.sort();
+        files
+    }
+}
+
+/// Values of the encrypted variables read from an unencrypted fixture, so dotfiles repositories
+/// can be checked in CI without gpg keys. Secrets missing from the fixture are collected.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MockSecrets {
+    pub path: PathBuf,
+    values: HashMap<String, String>,
+    missing: Arc<Mutex<Vec<(PathBuf, String)>>>,
+}
+
+impl MockSecrets {
+    pub(crate) fn read(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("Cannot read the mock secrets {:?} : {}", path, err))?;
+        Ok(MockSecrets {
+            path: path.to_path_buf(),
+            values: parse_vars(path, &content)?,
+            missing: Arc::default(),
+        })
+    }
+
+    /// The mock value of `key`, an empty string is rendered for missing ones
+    fn value(&self, key: &str, file: &Path) -> String {
+        match self.values.get(key) {
+            Some(value) => value.clone(),
+            None => {
+                if let Ok(mut missing) = self.missing.lock() {
+                    missing.push((file.to_path_buf(), key.to_string()));
+                }
+                String::new()
+            }
+        }
+    }
+
+    /// Templates and the secrets they use that have no mock value, sorted
+    pub(crate) fn missing(&self) -> Vec<(PathBuf, String)> {
+        let mut missing = self
+            .missing
+            .lock()
+            .map(|missing| missing.clone())
+            .unwrap_or_default();
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+}
+
 #[derive(Clone, Debug, Default)]
 pub(crate) struct Variables {
     /// holds the values defined in template.toml
@@ -19,6 +130,22 @@ pub(crate) struct Variables {
     /// Store decrypted secret value
     /// this might be empty if the var is deserialized without gpg option
     pub secrets: HashMap<String, String>,
+    /// Run the commands of `__[cmd "..."]__` placeholders, see `allow_cmd_templates`
+    pub allow_commands: bool,
+    /// Prepend a "do not edit" comment to rendered files
+    pub provenance_header: bool,
+    /// Extensions of the files rendered without provenance header
+    pub provenance_exclude: Vec<String>,
+    /// Record the render timings of every template, see `--profile-render`
+    pub render_profiler: Option<RenderProfiler>,
+    /// Refuse to render templates using encrypted variables, see `--skip-secrets`
+    pub skip_secrets: bool,
+    /// Render the encrypted variables with a sentinel instead of refusing, see `--degraded`
+    pub degraded: Option<DegradedSecrets>,
+    /// Render the encrypted variables with their mock value, see `check --mock-secrets`
+    pub mock_secrets: Option<MockSecrets>,
+    /// Log every use of the secrets, see `audit_secrets`
+    pub secret_audit: Option<SecretAudit>,
 }
 
 impl Variables {
@@ -36,12 +163,12 @@ impl Variables {
         Ok(out)
     }
 
-    /// Deserialize a toml file struct Variables
+    /// Deserialize a toml or `.env` file struct Variables
     pub(crate) fn from_toml(path: &Path, gpg: Option<&Gpg>) -> Result<Self> {
         let file = File::open(path);
 
         if let Err(err) = file {
-            eprintln!("{} {:?} : {}", "Could not open var file".red(), path, err);
+            warn!("{} {:?} : {}", "Could not open var file".red(), path, err);
             Ok(Self::default())
         } else {
             let mut buf_reader = BufReader::new(file.unwrap());
@@ -51,16 +178,34 @@ impl Variables {
                 .read_to_string(&mut contents)
                 .map_err(|err| anyhow!("Cannot read var file {:?} : {}", &path, err))?;
 
-            let variables: HashMap<String, String> = toml::from_str(&contents)
-                .map_err(|err| anyhow!("parse error in {:?} :  {}", path, err))?;
+            let variables = parse_vars(path, &contents)?;
 
             let vars = if let Some(gpg) = gpg {
                 let secrets = Variables::decrypt_values(&variables, gpg)?;
-                Variables { variables, secrets }
+                Variables {
+                    variables,
+                    secrets,
+                    allow_commands: false,
+                    provenance_header: false,
+                    provenance_exclude: vec![],
+                    render_profiler: None,
+                    skip_secrets: false,
+                    degraded: None,
+                    mock_secrets: None,
+                    secret_audit: None,
+                }
             } else {
                 Variables {
                     variables,
                     secrets: HashMap::default(),
+                    allow_commands: false,
+                    provenance_header: false,
+                    provenance_exclude: vec![],
+                    render_profiler: None,
+                    skip_secrets: false,
+                    degraded: None,
+                    mock_secrets: None,
+                    secret_audit: None,
                 }
             };
 
@@ -68,15 +213,45 @@ impl Variables {
         }
     }
 
+    /// Everything renders depend on besides the templates, in a stable order. Secrets are
+    /// told by their encrypted value, their decrypted value is never part of it.
+    pub(crate) fn fingerprint(&self) -> String {
+        let variables: BTreeMap<&String, &String> = self.variables.iter().collect();
+        let mut secrets: Vec<&String> = self.secrets.keys().collect();
+        secrets.sort();
+        format!(
+            "{:?} {:?} {} {:?} {}",
+            variables, secrets, self.provenance_header, self.provenance_exclude, self.skip_secrets
+        )
+    }
+
     /// Read file in the given path and return its content
     /// with variable replaced by their values.
     pub(crate) fn to_dot(&self, path: &Path) -> Result<String> {
+        let started = Instant::now();
         // Read file content
         let file = File::open(path)?;
         let mut buf_reader = BufReader::new(file);
-        let mut contents = String::new();
-        buf_reader.read_to_string(&mut contents)?;
+        let mut contents = vec![];
+        buf_reader.read_to_end(&mut contents)?;
+        let read = started.elapsed();
 
+        if is_binary(&contents) {
+            return Err(anyhow!("binary file"));
+        }
+
+        self.render(path, &String::from_utf8(contents)?, read)
+    }
+
+    /// Render `contents` generated by bombadil, `path` is where the result is written
+    pub(crate) fn render_content(&self, path: &Path, contents: &str) -> Result<String> {
+        self.render(path, contents, Duration::ZERO)
+    }
+
+    fn render(&self, path: &Path, contents: &str, read: Duration) -> Result<String> {
+        let render_started = Instant::now();
+        let mut placeholders = 0;
+        let mut commands = Duration::ZERO;
         // Merge variable and secret
         let mut variables = self.variables.clone();
 
@@ -85,34 +260,134 @@ impl Variables {
             variables.insert(k.to_owned(), v.to_owned());
         });
 
-        let pairs = BombadilParser::parse(Rule::file, &contents)
+        let parse_started = Instant::now();
+        let pairs = BombadilParser::parse(Rule::file, contents)
             .expect("Unable to parse template file")
             .next()
             .unwrap();
+        let parse = parse_started.elapsed();
 
         let mut output = String::new();
 
         for pair in pairs.into_inner() {
             match pair.as_rule() {
                 Rule::variable => {
-                    let var_name = pair.into_inner().next().unwrap().as_str().trim();
+                    placeholders += 1;
+                    let mut inner = pair.into_inner();
+                    let var_name = inner.next().unwrap().as_str().trim();
+                    let mut value = self.value(&variables, var_name, path)?;
 
-                    let value = variables.get(var_name).cloned().unwrap_or_else(|| {
-                        let err = format!("Undefined variable : {} in {:?}", var_name, path);
-                        eprintln!("{}", err.yellow());
-                        "undefined variable".to_string()
+                    for filter in inner.map(|filter| filter.as_str().trim()) {
+                        match filter {
+                            "indent" => {
+                                let line =
+                                    &output[output.rfind('\n').map_or(0, |start| start + 1)..];
+                                let indentation: String = line
+                                    .chars()
+                                    .take_while(|char| *char == ' ' || *char == '\t')
+                                    .collect();
+                                value = indent(&value, &indentation);
+                            }
+                            _ => {
+                                let err = format!(
+                                    "Unknown filter : {} for {} in {:?}",
+                                    filter, var_name, path
+                                );
+                                warn!("{}", err.yellow());
+                            }
+                        }
+                    }
+
+                    output.push_str(&value);
+                }
+                Rule::command if self.allow_commands => {
+                    placeholders += 1;
+                    let command_started = Instant::now();
+                    let command_line = pair.into_inner().next().unwrap().as_str();
+                    let value = run_command(command_line).unwrap_or_else(|err| {
+                        let err =
+                            format!("Command `{}` failed in {:?} : {}", command_line, path, err);
+                        warn!("{}", err.yellow());
+                        "command failed".to_string()
                     });
+                    commands += command_started.elapsed();
 
                     output.push_str(&value);
                 }
+                Rule::command => {
+                    placeholders += 1;
+                    let warning = format!(
+                        "Command templates are disabled, set `allow_cmd_templates = true` to render {} in {:?}",
+                        pair.as_str(),
+                        path
+                    );
+                    warn!("{}", warning.yellow());
+                    output.push_str(pair.as_str());
+                }
                 Rule::raw_content => output.push_str(pair.as_str()),
                 _ => (),
             }
         }
 
+        if let Some(profiler) = &self.render_profiler {
+            profiler.record(RenderTiming {
+                file: path.to_path_buf(),
+                bytes: contents.len(),
+                placeholders,
+                read,
+                parse,
+                // The parse happens while rendering, it is reported on its own
+                render: render_started.elapsed().saturating_sub(parse),
+                commands,
+            });
+        }
+
         Ok(output)
     }
 
+    /// The value of the placeholder `var_name` in the template `path`, secrets are mocked,
+    /// replaced with the degraded sentinel or audited
+    fn value(
+        &self,
+        variables: &HashMap<String, String>,
+        var_name: &str,
+        path: &Path,
+    ) -> Result<String> {
+        if let (Some(mock), true) = (&self.mock_secrets, self.is_encrypted(var_name)) {
+            return Ok(mock.value(var_name, path));
+        }
+
+        if self.skip_secrets && self.is_encrypted(var_name) {
+            return match &self.degraded {
+                Some(degraded) => {
+                    degraded.record(path);
+                    Ok(degraded.sentinel.clone())
+                }
+                None => Err(SkippedSecret {
+                    path: path.to_path_buf(),
+                    key: var_name.to_string(),
+                }
+                .into()),
+            };
+        }
+
+        if let (Some(audit), true) = (&self.secret_audit, self.secrets.contains_key(var_name)) {
+            audit.rendered(var_name, path);
+        }
+
+        if let (None, WSL_WINDOWS_HOME_VAR) = (variables.get(var_name), var_name) {
+            if let Some(windows_home) = wsl_windows_home() {
+                return Ok(windows_home.display().to_string());
+            }
+        }
+
+        Ok(variables.get(var_name).cloned().unwrap_or_else(|| {
+            let err = format!("Undefined variable : {} in {:?}", var_name, path);
+            warn!("{}", err.yellow());
+            "undefined variable".to_string()
+        }))
+    }
+
     pub(crate) fn resolve_ref(&mut self) {
         // Collect variable references
         let entries: Vec<(String, Option<String>)> = self
@@ -130,11 +405,12 @@ impl Variables {
             }
             None => {
                 let warning = format!("Reference ${} not found in config", &key).yellow();
-                eprintln!("{}", warning);
+                warn!("{}", warning);
             }
         });
     }
 
+    /// Extend the variables, the rendering options are left untouched
     pub(crate) fn extend(&mut self, vars: Variables) {
         self.variables.extend(vars.variables);
         self.secrets.extend(vars.secrets);
@@ -144,6 +420,27 @@ impl Variables {
         self.variables.insert(key.to_string(), value.to_string());
     }
 
+    /// Decrypt the encrypted variables loaded without gpg
+    pub(crate) fn decrypt_secrets(&mut self, gpg: &Gpg) -> Result<()> {
+        let secrets = Variables::decrypt_values(&self.variables, gpg)?;
+        if let Some(audit) = &self.secret_audit {
+            let mut keys: Vec<&String> = secrets.keys().collect();
+            keys.sort();
+            keys.iter().for_each(|key| audit.decrypted(key));
+        }
+        self.secrets.extend(secrets);
+        Ok(())
+    }
+
+    /// True for variables whose value is still encrypted
+    fn is_encrypted(&self, key: &str) -> bool {
+        !self.secrets.contains_key(key)
+            && self
+                .variables
+                .get(key)
+                .is_some_and(|value| value.starts_with(GPG_PREFIX))
+    }
+
     fn decrypt_values(
         vars: &HashMap<String, String>,
         gpg: &Gpg,
@@ -164,9 +461,253 @@ impl Variables {
     }
 }
 
+/// A syntax error in a `.env` var file
+#[derive(Debug)]
+pub(crate) struct EnvError {
+    /// One based
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for EnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {} : {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for EnvError {}
+
+/// The format of a var file, from its name
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum VarFormat {
+    Toml,
+    /// `.env` and `*.env` files, read as `KEY=VALUE` lines
+    Env,
+    Json,
+    Yaml,
+}
+
+impl VarFormat {
+    pub(crate) fn of(path: &Path) -> Self {
+        if path.file_name() == Some(OsStr::new(".env")) {
+            return VarFormat::Env;
+        }
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("env") => VarFormat::Env,
+            Some("json") => VarFormat::Json,
+            Some("yaml" | "yml") => VarFormat::Yaml,
+            _ => VarFormat::Toml,
+        }
+    }
+}
+
+/// A multi-line `block` inserted by `__[name | indent]__` : its common indentation and its last
+/// newline are removed, then every line but the first is indented with `indentation`, the
+/// indentation of the line holding the placeholder
+fn indent(block: &str, indentation: &str) -> String {
+    let block = block.strip_suffix('\n').unwrap_or(block);
+    let common = block
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+
+    block
+        .lines()
+        .enumerate()
+        .map(|(index, line)| match line.get(common..) {
+            _ if line.trim().is_empty() => String::new(),
+            Some(line) if index == 0 => line.to_string(),
+            Some(line) => format!("{}{}", indentation, line),
+            None => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Fonts, images and compiled caches are not templates : they hold a null byte in their
+/// first 8000 bytes, like git checks. Other binary files are rejected as invalid UTF-8.
+fn is_binary(contents: &[u8]) -> bool {
+    contents.iter().take(8000).any(|byte| *byte == 0)
+}
+
+/// Parse the variables of a var file depending on its format, nested json and yaml values
+/// are flattened into dotted keys
+pub(crate) fn parse_vars(path: &Path, content: &str) -> Result<HashMap<String, String>> {
+    let error = |err: &dyn fmt::Display| anyhow!("parse error in {:?} : {}", path, err);
+    match VarFormat::of(path) {
+        VarFormat::Toml => toml::from_str(content).map_err(|err| error(&err)),
+        VarFormat::Env => parse_env(content).map_err(|err| error(&err)),
+        VarFormat::Json => parse_json(content).map_err(|err| error(&err)),
+        VarFormat::Yaml => parse_yaml(content).map_err(|err| error(&err)),
+    }
+}
+
+/// A json object, `{"colors": {"base00": "#181818"}}` gives `colors.base00`, array items
+/// are keyed by their index and null values are skipped
+pub(crate) fn parse_json(content: &str) -> serde_json::Result<HashMap<String, String>> {
+    fn flatten(prefix: String, value: serde_json::Value, variables: &mut HashMap<String, String>) {
+        match value {
+            serde_json::Value::Object(map) => map
+                .into_iter()
+                .for_each(|(key, value)| flatten(join_key(&prefix, &key), value, variables)),
+            serde_json::Value::Array(items) => {
+                items.into_iter().enumerate().for_each(|(idx, value)| {
+                    flatten(join_key(&prefix, &idx.to_string()), value, variables)
+                })
+            }
+            serde_json::Value::String(value) => {
+                variables.insert(prefix, value);
+            }
+            serde_json::Value::Null => {}
+            value => {
+                variables.insert(prefix, value.to_string());
+            }
+        }
+    }
+
+    let mut variables = HashMap::new();
+    flatten(
+        String::new(),
+        serde_json::from_str(content)?,
+        &mut variables,
+    );
+    Ok(variables)
+}
+
+/// The first document of a yaml file, flattened like json
+pub(crate) fn parse_yaml(content: &str) -> std::result::Result<HashMap<String, String>, ScanError> {
+    fn scalar(value: &Yaml) -> Option<String> {
+        match value {
+            Yaml::String(value) | Yaml::Real(value) => Some(value.clone()),
+            Yaml::Integer(value) => Some(value.to_string()),
+            Yaml::Boolean(value) => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    fn flatten(prefix: String, value: &Yaml, variables: &mut HashMap<String, String>) {
+        match value {
+            Yaml::Hash(hash) => hash.iter().for_each(|(key, value)| {
+                if let Some(key) = scalar(key) {
+                    flatten(join_key(&prefix, &key), value, variables)
+                }
+            }),
+            Yaml::Array(items) => items.iter().enumerate().for_each(|(idx, value)| {
+                flatten(join_key(&prefix, &idx.to_string()), value, variables)
+            }),
+            value => {
+                if let Some(value) = scalar(value) {
+                    variables.insert(prefix, value);
+                }
+            }
+        }
+    }
+
+    let mut variables = HashMap::new();
+    if let Some(document) = YamlLoader::load_from_str(content)?.first() {
+        flatten(String::new(), document, &mut variables);
+    }
+    Ok(variables)
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// `KEY=VALUE` lines, optionally prefixed with `export`. Single quoted values are literal,
+/// double quoted values support `\n`, `\t`, `\"` and `\\` escapes, unquoted values are
+/// trimmed and end at ` #` comments.
+pub(crate) fn parse_env(content: &str) -> std::result::Result<HashMap<String, String>, EnvError> {
+    let mut variables = HashMap::new();
+    for (idx, line) in content.lines().enumerate() {
+        let error = |message: &str| EnvError {
+            line: idx + 1,
+            message: message.to_string(),
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| error("expected KEY=VALUE"))?;
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return Err(error(&format!("invalid key `{}`", key)));
+        }
+
+        let value = value.trim();
+        let value = if let Some(quoted) = value.strip_prefix('\'') {
+            let end = quoted
+                .find('\'')
+                .ok_or_else(|| error("unterminated single quote"))?;
+            quoted[..end].to_string()
+        } else if let Some(quoted) = value.strip_prefix('"') {
+            let mut unescaped = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => match chars.next() {
+                        Some('n') => unescaped.push('\n'),
+                        Some('t') => unescaped.push('\t'),
+                        Some(c @ ('"' | '\\' | '$')) => unescaped.push(c),
+                        Some(c) => {
+                            unescaped.push('\\');
+                            unescaped.push(c);
+                        }
+                        None => return Err(error("unterminated double quote")),
+                    },
+                    Some(c) => unescaped.push(c),
+                    None => return Err(error("unterminated double quote")),
+                }
+            }
+            unescaped
+        } else {
+            let end = value.find(" #").unwrap_or(value.len());
+            value[..end].trim_end().to_string()
+        };
+
+        variables.insert(key.to_string(), value);
+    }
+
+    Ok(variables)
+}
+
+/// Run `command_line` with `sh -c` and return its output without the trailing newline
+fn run_command(command_line: &str) -> Result<String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command_line)
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(anyhow!("exited with {}", output.status));
+    }
+
+    let stdout = String::from_utf8(output.stdout)?;
+    Ok(stdout.trim_end_matches('\n').to_string())
+}
+
 #[cfg(test)]
 mod test {
-    use crate::templating::Variables;
+    use crate::templating::{parse_env, parse_vars, DegradedSecrets, MockSecrets, Variables};
+    use crate::timings::RenderProfiler;
     use std::collections::HashMap;
     use std::path::{Path, PathBuf};
 
@@ -178,6 +719,14 @@ mod test {
         let string = Variables {
             variables,
             secrets: Default::default(),
+            allow_commands: false,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
         }
         .to_dot(Path::new("tests/dotfiles_simple/template"))
         .unwrap();
@@ -185,6 +734,213 @@ mod test {
         assert_eq!(string, "color: red_value");
     }
 
+    #[test]
+    fn should_indent_block_variables() -> anyhow::Result<()> {
+        // Arrange
+        let temp = temp_testdir::TempDir::default();
+        let vars = temp.join("vars.toml");
+        std::fs::write(
+            &vars,
+            "keybindings = \"\"\"\n    bindsym $mod+Return exec alacritty\n\n    bindsym $mod+d exec wofi\n\"\"\"\n",
+        )?;
+        let variables = Variables::from_toml(&vars, None)?;
+
+        // Act
+        let sway = variables.render_content(
+            &temp.join("sway"),
+            "mode \"resize\" {\n    __[keybindings | indent]__\n}\n",
+        )?;
+        let i3 = variables.render_content(&temp.join("i3"), "__[keybindings]__")?;
+        let yaml = variables.render_content(
+            &temp.join("ci.yml"),
+            "steps:\n  - run: |\n      __[keybindings|indent]__\nunknown: __[keybindings | upper]__",
+        )?;
+
+        // Assert
+        assert_eq!(
+            sway,
+            "mode \"resize\" {\n    bindsym $mod+Return exec alacritty\n\n    bindsym $mod+d exec wofi\n}\n"
+        );
+        assert_eq!(
+            i3,
+            "    bindsym $mod+Return exec alacritty\n\n    bindsym $mod+d exec wofi\n"
+        );
+        assert_eq!(
+            yaml,
+            format!(
+                "steps:\n  - run: |\n      bindsym $mod+Return exec alacritty\n\n      bindsym $mod+d exec wofi\nunknown: {}",
+                i3
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_record_render_timings() -> anyhow::Result<()> {
+        // Arrange
+        let temp = temp_testdir::TempDir::default();
+        let template = temp.join("many_placeholders");
+        std::fs::write(&template, "color: __[red]__\n".repeat(5000))?;
+        let mut variables = HashMap::new();
+        variables.insert("red".to_string(), "red_value".to_string());
+        let profiler = RenderProfiler::default();
+        let vars = Variables {
+            variables,
+            render_profiler: Some(profiler.clone()),
+            ..Default::default()
+        };
+
+        // Act
+        vars.to_dot(&template)?;
+
+        // Assert
+        let timings = profiler.timings();
+        assert_eq!(timings.len(), 1);
+        assert_eq!(timings[0].file, template);
+        assert_eq!(timings[0].placeholders, 5000);
+        assert_eq!(timings[0].bytes, 17 * 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn should_refuse_templates_using_skipped_secrets() -> anyhow::Result<()> {
+        // Arrange
+        let temp = temp_testdir::TempDir::default();
+        let template = temp.join("netrc");
+        std::fs::write(&template, "password __[pass]__")?;
+        let mut variables = HashMap::new();
+        variables.insert("pass".to_string(), "gpg:hQEMA".to_string());
+        let vars = Variables {
+            variables,
+            skip_secrets: true,
+            ..Default::default()
+        };
+
+        // Act
+        let result = vars.to_dot(&template);
+
+        // Assert
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("uses the secret `pass`, skipped with `--skip-secrets`"));
+        assert_eq!(
+            vars.to_dot(Path::new("tests/dotfiles_simple/template"))?,
+            "color: undefined variable"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_render_skipped_secrets_with_sentinel() -> anyhow::Result<()> {
+        // Arrange
+        let temp = temp_testdir::TempDir::default();
+        let template = temp.join("netrc");
+        std::fs::write(&template, "password __[pass]__ __[pass]__")?;
+        let mut variables = HashMap::new();
+        variables.insert("pass".to_string(), "gpg:hQEMA".to_string());
+        let degraded = DegradedSecrets::new("UNAVAILABLE");
+        let vars = Variables {
+            variables,
+            skip_secrets: true,
+            degraded: Some(degraded.clone()),
+            ..Default::default()
+        };
+
+        // Act
+        let rendered = vars.to_dot(&template)?;
+
+        // Assert
+        assert_eq!(rendered, "password UNAVAILABLE UNAVAILABLE");
+        assert_eq!(degraded.files(), vec![template.to_path_buf()]);
+        Ok(())
+    }
+
+    #[test]
+    fn should_render_mock_secrets() -> anyhow::Result<()> {
+        // Arrange
+        let temp = temp_testdir::TempDir::default();
+        let template = temp.join("netrc");
+        let fixture = temp.join("mock_secrets.toml");
+        std::fs::write(
+            &template,
+            "login __[user]__ password __[pass]__ token __[token]__",
+        )?;
+        std::fs::write(&fixture, "pass = \"hunter2\"")?;
+        let mut variables = HashMap::new();
+        variables.insert("user".to_string(), "tom".to_string());
+        variables.insert("pass".to_string(), "gpg:hQEMA".to_string());
+        variables.insert("token".to_string(), "gpg:hQEMB".to_string());
+        let mock = MockSecrets::read(&fixture)?;
+        let vars = Variables {
+            variables,
+            mock_secrets: Some(mock.clone()),
+            ..Default::default()
+        };
+
+        // Act
+        let rendered = vars.to_dot(&template)?;
+
+        // Assert
+        assert_eq!(rendered, "login tom password hunter2 token ");
+        assert_eq!(
+            mock.missing(),
+            vec![(template.to_path_buf(), "token".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn should_parse_env_files() -> anyhow::Result<()> {
+        // Arrange
+        let content = r#"
+# Generated by direnv
+export EDITOR=nvim
+FONT = Fira Code # the terminal font
+GREETING="hello \"tom\"\nbye"
+LITERAL='$HOME \n'
+EMPTY=
+"#;
+
+        // Act
+        let variables = parse_env(content)?;
+        let error = parse_env("OK=1\nNOT A VARIABLE").unwrap_err();
+
+        // Assert
+        assert_eq!(variables["EDITOR"], "nvim");
+        assert_eq!(variables["FONT"], "Fira Code");
+        assert_eq!(variables["GREETING"], "hello \"tom\"\nbye");
+        assert_eq!(variables["LITERAL"], "$HOME \\n");
+        assert_eq!(variables["EMPTY"], "");
+        assert_eq!(variables.len(), 5);
+        assert_eq!(error.line, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn should_flatten_json_and_yaml_vars() -> anyhow::Result<()> {
+        // Arrange
+        let json = r##"{"scheme": "gruvbox", "colors": {"base00": "#282828", "alpha": 0.9}, "fonts": ["Fira Code", "Noto"], "unset": null}"##;
+        let yaml = "scheme: gruvbox\ncolors:\n  base00: \"#282828\"\n  bold: true\nfonts:\n  - Fira Code\n";
+
+        // Act
+        let from_json = parse_vars(Path::new("theme.json"), json)?;
+        let from_yaml = parse_vars(Path::new("theme.yml"), yaml)?;
+
+        // Assert
+        assert_eq!(from_json["scheme"], "gruvbox");
+        assert_eq!(from_json["colors.base00"], "#282828");
+        assert_eq!(from_json["colors.alpha"], "0.9");
+        assert_eq!(from_json["fonts.1"], "Noto");
+        assert_eq!(from_json.len(), 5);
+        assert_eq!(from_yaml["colors.base00"], "#282828");
+        assert_eq!(from_yaml["colors.bold"], "true");
+        assert_eq!(from_yaml["fonts.0"], "Fira Code");
+        assert_eq!(from_yaml.len(), 4);
+        assert!(parse_vars(Path::new("theme.json"), "{").is_err());
+        Ok(())
+    }
+
     #[test]
     fn should_inject_secret_variables() {
         let mut variables = HashMap::new();
@@ -194,9 +950,20 @@ mod test {
         let mut secrets = HashMap::new();
         secrets.insert("pass".to_string(), "hunter2".to_string());
 
-        let dot_content = Variables { variables, secrets }
-            .to_dot(Path::new("tests/dotfiles_with_secret/template"))
-            .unwrap();
+        let dot_content = Variables {
+            variables,
+            secrets,
+            allow_commands: false,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
+        }
+        .to_dot(Path::new("tests/dotfiles_with_secret/template"))
+        .unwrap();
 
         println!("{}", dot_content);
         assert!(dot_content.contains("color: red_value"));
@@ -208,6 +975,14 @@ mod test {
         let content = Variables {
             variables: HashMap::new(),
             secrets: Default::default(),
+            allow_commands: false,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
         }
         .to_dot(Path::new("tests/dotfiles_non_utf8/ferris.png"));
 
@@ -216,7 +991,7 @@ mod test {
 
     #[test]
     fn should_get_vars_from_toml() {
-        let vars = Variables::from_toml(&Path::new("tests/vars/vars.toml"), None);
+        let vars = Variables::from_toml(Path::new("tests/vars/vars.toml"), None);
 
         assert!(vars.is_ok());
         let vars = vars.unwrap();
@@ -229,7 +1004,7 @@ mod test {
     #[test]
     fn should_get_vars_multiple_path() {
         let vars = Variables::from_paths(
-            &Path::new("tests/vars/"),
+            Path::new("tests/vars/"),
             &[PathBuf::from("vars.toml"), PathBuf::from("meta_vars.toml")],
             None,
         );
@@ -257,11 +1032,30 @@ mod test {
         let mut extends_secrets = HashMap::new();
         extends_secrets.insert("password".to_string(), "hunter3".to_string());
 
-        let mut vars = Variables { variables, secrets };
+        let mut vars = Variables {
+            variables,
+            secrets,
+            allow_commands: false,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
+        };
 
         let extends = Variables {
             variables: extends,
             secrets: extends_secrets,
+            allow_commands: false,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
         };
 
         vars.extend(extends);
@@ -271,4 +1065,30 @@ mod test {
         assert_eq!(vars.variables.get("white"), Some(&"#FFFFFF".to_string()));
         assert_eq!(vars.secrets.get("password"), Some(&"hunter3".to_string()));
     }
+
+    #[test]
+    fn should_inject_command_output_when_allowed() {
+        let vars = |allow_commands| Variables {
+            variables: HashMap::new(),
+            secrets: Default::default(),
+            allow_commands,
+            provenance_header: false,
+            provenance_exclude: vec![],
+            render_profiler: None,
+            skip_secrets: false,
+            degraded: None,
+            mock_secrets: None,
+            secret_audit: None,
+        };
+
+        let allowed = vars(true)
+            .to_dot(Path::new("tests/dotfiles_cmd/template"))
+            .unwrap();
+        let denied = vars(false)
+            .to_dot(Path::new("tests/dotfiles_cmd/template"))
+            .unwrap();
+
+        assert_eq!(allowed, "stamp: built by bombadil\n");
+        assert_eq!(denied, "stamp: __[cmd \"echo built by bombadil\"]__\n");
+    }
 }
@@ -0,0 +1,169 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Time spent rendering a single template
+#[derive(Debug, Clone)]
+pub(crate) struct RenderTiming {
+    pub file: PathBuf,
+    pub bytes: usize,
+    pub placeholders: usize,
+    pub read: Duration,
+    pub parse: Duration,
+    pub render: Duration,
+    /// Spent running `__[cmd "..."]__` placeholders, included in `render`
+    pub commands: Duration,
+}
+
+impl RenderTiming {
+    fn total(&self) -> Duration {
+        self.read + self.parse + self.render
+    }
+}
+
+/// Collects the timings of every rendered template, when linking with `--profile-render`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RenderProfiler {
+    timings: Arc<Mutex<Vec<RenderTiming>>>,
+}
+
+impl RenderProfiler {
+    pub(crate) fn record(&self, timing: RenderTiming) {
+        if let Ok(mut timings) = self.timings.lock() {
+            timings.push(timing);
+        }
+    }
+
+    /// Recorded timings, slowest first
+    pub(crate) fn timings(&self) -> Vec<RenderTiming> {
+        let mut timings = self
+            .timings
+            .lock()
+            .map(|timings| timings.clone())
+            .unwrap_or_default();
+        timings.sort_by(|a, b| b.total().cmp(&a.total()).then(a.file.cmp(&b.file)));
+        timings
+    }
+
+    /// One line per template, slowest first, paths are displayed relative to `root`
+    pub(crate) fn report(&self, root: &Path) -> String {
+        let timings = self.timings();
+        let mut report = String::new();
+        for timing in timings.iter() {
+            let commands = if timing.commands.is_zero() {
+                String::new()
+            } else {
+                format!(", commands {}", millis(timing.commands))
+            };
+
+            report.push_str(&format!(
+                "{:>10}  {} ({} bytes, {} placeholders : read {}, parse {}, render {}{})\n",
+                millis(timing.total()),
+                timing
+                    .file
+                    .strip_prefix(root)
+                    .unwrap_or(&timing.file)
+                    .display(),
+                timing.bytes,
+                timing.placeholders,
+                millis(timing.read),
+                millis(timing.parse),
+                millis(timing.render),
+                commands
+            ));
+        }
+
+        let total: Duration = timings.iter().map(RenderTiming::total).sum();
+        report.push_str(&format!(
+            "Rendered {} templates in {}\n",
+            timings.len(),
+            millis(total)
+        ));
+        report
+    }
+
+    /// Folded stacks in microseconds, one frame per path component then one per render
+    /// phase, ready for `inferno-flamegraph` or `flamegraph.pl`
+    pub(crate) fn folded(&self, root: &Path) -> String {
+        let mut lines = vec![];
+        for timing in self.timings() {
+            let file = timing.file.strip_prefix(root).unwrap_or(&timing.file);
+            let frames: Vec<String> = file
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(name) => Some(name.to_string_lossy().replace(';', "_")),
+                    _ => None,
+                })
+                .collect();
+            let stack = format!("bombadil;{}", frames.join(";"));
+
+            let phases = [
+                ("read", timing.read),
+                ("parse", timing.parse),
+                ("render", timing.render.saturating_sub(timing.commands)),
+                ("commands", timing.commands),
+            ];
+            for (phase, duration) in phases.iter() {
+                if duration.as_micros() > 0 {
+                    lines.push(format!("{};{} {}", stack, phase, duration.as_micros()));
+                }
+            }
+        }
+
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.2}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timings::{RenderProfiler, RenderTiming};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    #[test]
+    fn should_fold_render_stacks() {
+        // Arrange
+        let profiler = RenderProfiler::default();
+        profiler.record(RenderTiming {
+            file: PathBuf::from("/dotfiles/sway/config"),
+            bytes: 2048,
+            placeholders: 12,
+            read: Duration::from_micros(10),
+            parse: Duration::from_micros(300),
+            render: Duration::from_micros(1200),
+            commands: Duration::from_micros(1000),
+        });
+        profiler.record(RenderTiming {
+            file: PathBuf::from("/dotfiles/zshrc"),
+            bytes: 10,
+            placeholders: 0,
+            read: Duration::from_micros(5),
+            parse: Duration::ZERO,
+            render: Duration::ZERO,
+            commands: Duration::ZERO,
+        });
+
+        // Act
+        let folded = profiler.folded(Path::new("/dotfiles"));
+        let report = profiler.report(Path::new("/dotfiles"));
+
+        // Assert
+        assert_eq!(
+            folded,
+            "bombadil;sway;config;commands 1000\n\
+             bombadil;sway;config;parse 300\n\
+             bombadil;sway;config;read 10\n\
+             bombadil;sway;config;render 200\n\
+             bombadil;zshrc;read 5\n"
+        );
+        assert!(report.starts_with(
+            "    1.51ms  sway/config (2048 bytes, 12 placeholders : read 0.01ms, parse 0.30ms, render 1.20ms, commands 1.00ms)\n"
+        ));
+        assert!(report.ends_with("Rendered 2 templates in 1.52ms\n"));
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Directories never watched : rendered dots are written to `.dots` while relinking
+const IGNORED_DIRS: [&str; 2] = [".dots", ".git"];
+
+/// Modification times of every file under a set of paths
+#[derive(Debug, Default)]
+pub(crate) struct Snapshot {
+    files: HashMap<PathBuf, SystemTime>,
+}
+
+impl Snapshot {
+    pub(crate) fn take(roots: &[PathBuf]) -> Self {
+        let mut snapshot = Snapshot::default();
+        roots.iter().for_each(|root| snapshot.visit(root));
+        snapshot
+    }
+
+    fn visit(&mut self, path: &Path) {
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return,
+        };
+
+        if metadata.is_dir() {
+            if is_ignored(path) {
+                return;
+            }
+
+            if let Ok(entries) = path.read_dir() {
+                entries
+                    .filter_map(Result::ok)
+                    .for_each(|entry| self.visit(&entry.path()));
+            }
+        } else if let Ok(modified) = metadata.modified() {
+            self.files.insert(path.to_path_buf(), modified);
+        }
+    }
+
+    /// Returns true if a file under one of `paths` was created, modified or removed since `previous`
+    pub(crate) fn changed(&self, previous: &Snapshot, paths: &[PathBuf]) -> bool {
+        let under_paths = |file: &&PathBuf| paths.iter().any(|path| file.starts_with(path));
+
+        self.files
+            .iter()
+            .filter(|(file, _)| under_paths(file))
+            .any(|(file, modified)| previous.files.get(file) != Some(modified))
+            || previous
+                .files
+                .keys()
+                .filter(under_paths)
+                .any(|file| !self.files.contains_key(file))
+    }
+}
+
+fn is_ignored(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| IGNORED_DIRS.contains(&name))
+        .unwrap_or(false)
+}
+
+/// Watches on the given directories and their subdirectories. Take the snapshot to compare
+/// with once the watcher is created, a change made in between is then reported by [`Watcher::wait`]
+#[cfg(target_os = "linux")]
+pub(crate) struct Watcher {
+    fd: i32,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub(crate) fn new(directories: &[PathBuf]) -> Result<Self> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let fd = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(anyhow!(
+                "Unable to watch files : {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        let mask = libc::IN_CLOSE_WRITE
+            | libc::IN_MODIFY
+            | libc::IN_CREATE
+            | libc::IN_DELETE
+            | libc::IN_MOVED_FROM
+            | libc::IN_MOVED_TO
+            | libc::IN_ATTRIB;
+
+        let mut watched = vec![];
+        directories
+            .iter()
+            .for_each(|directory| collect_directories(directory, &mut watched));
+
+        for directory in watched.iter() {
+            if let Ok(path) = CString::new(directory.as_os_str().as_bytes()) {
+                unsafe { libc::inotify_add_watch(fd, path.as_ptr(), mask) };
+            }
+        }
+
+        Ok(Watcher { fd })
+    }
+
+    /// Block until something changes, the watches are dropped afterwards so newly
+    /// created directories are picked up by the next watcher
+    pub(crate) fn wait(self) -> Result<()> {
+        let mut buffer = [0u8; 4096];
+        let read = unsafe {
+            libc::read(
+                self.fd,
+                buffer.as_mut_ptr() as *mut libc::c_void,
+                buffer.len(),
+            )
+        };
+        if read < 0 {
+            return Err(anyhow!(
+                "Unable to watch files : {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+
+        // Editors usually write files in several steps, let them settle
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// Platforms without inotify fall back to polling
+#[cfg(not(target_os = "linux"))]
+pub(crate) struct Watcher;
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub(crate) fn new(_directories: &[PathBuf]) -> Result<Self> {
+        Ok(Watcher)
+    }
+
+    pub(crate) fn wait(self) -> Result<()> {
+        std::thread::sleep(std::time::Duration::from_secs(1));
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn collect_directories(path: &Path, directories: &mut Vec<PathBuf>) {
+    if !path.is_dir() || is_ignored(path) {
+        return;
+    }
+
+    directories.push(path.to_path_buf());
+    if let Ok(entries) = path.read_dir() {
+        entries
+            .filter_map(Result::ok)
+            .for_each(|entry| collect_directories(&entry.path(), directories));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::watch::Snapshot;
+    use anyhow::Result;
+    use std::fs;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+    use temp_testdir::TempDir;
+
+    #[test]
+    fn should_detect_changes_under_paths() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let sway = temp.join("sway");
+        let alacritty = temp.join("alacritty");
+        fs::create_dir_all(&sway)?;
+        fs::create_dir_all(temp.join(".dots"))?;
+        fs::write(sway.join("config"), "bar")?;
+        fs::write(&alacritty, "font")?;
+        let before = Snapshot::take(&[temp.to_path_buf()]);
+
+        // Act
+        File::options()
+            .write(true)
+            .open(sway.join("config"))?
+            .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+        fs::write(temp.join(".dots").join("rendered"), "ignored")?;
+        let after = Snapshot::take(&[temp.to_path_buf()]);
+
+        // Assert
+        assert!(after.changed(&before, &[sway]));
+        assert!(!after.changed(&before, &[alacritty]));
+        assert!(!after.changed(&before, &[temp.join(".dots")]));
+        Ok(())
+    }
+
+    #[test]
+    fn should_detect_removed_files() -> Result<()> {
+        // Arrange
+        let temp = TempDir::default();
+        let file = temp.join("vars.toml");
+        fs::write(&file, "red = \"#ff0000\"")?;
+        let before = Snapshot::take(&[temp.to_path_buf()]);
+
+        // Act
+        fs::remove_file(&file)?;
+        let after = Snapshot::take(&[temp.to_path_buf()]);
+
+        // Assert
+        assert!(after.changed(&before, &[file]));
+        Ok(())
+    }
+}